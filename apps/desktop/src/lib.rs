@@ -2,9 +2,6 @@ mod ui;
 mod window;
 mod wm;
 
-#[path = "../../terminal/src/term.rs"]
-mod term;
-
 #[path = "../../terminal/src/shell.rs"]
 mod shell;
 
@@ -27,10 +24,7 @@ pub extern "C" fn init() {
         let mut manager = wm::WindowManager::new(w, h);
         
         // Set Default CWD for Desktop Environment
-        // cd only handles relative paths, so navigate step by step
-        ui::exec("cd /");
-        ui::exec("cd local");
-        ui::exec("cd user");
+        ui::exec("cd /local/user");
         
         manager.init();
         
@@ -60,6 +54,8 @@ pub extern "C" fn step() {
                         3 => wm.handle_mouse_down(x, y),
                         4 => wm.handle_mouse_up(),
                         5 => wm.handle_mouse_move(x, y),
+                        6 => wm.handle_fs_changed(),
+                        7 => wm.handle_wheel(x, y, code as i32),
                         _ => {}
                     }
                 } else {
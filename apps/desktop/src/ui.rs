@@ -15,11 +15,33 @@ extern "C" {
     pub fn sys_draw_text(ptr: *const u8, len: usize, x: i32, y: i32, color: i32);
     pub fn sys_fs_list(path_ptr: *const u8, path_len: i32, out_ptr: *mut u8, out_len: i32) -> i32;
     pub fn sys_fs_getcwd(out_ptr: *mut u8, out_len: i32) -> i32;
+    pub fn sys_complete(line_ptr: *const u8, line_len: i32, out_ptr: *mut u8, out_len: i32) -> i32;
+    pub fn sys_fs_open(path_ptr: *const u8, path_len: i32, flags: i32) -> i32;
+    pub fn sys_fs_read(fd: i32, buf_ptr: *mut u8, buf_len: i32) -> i32;
+    pub fn sys_fs_write(fd: i32, buf_ptr: *const u8, buf_len: i32) -> i32;
+    pub fn sys_fs_close(fd: i32) -> i32;
     pub fn sys_exec(cmd_ptr: *const u8, cmd_len: i32, out_ptr: *mut u8, out_len: i32) -> i32;
+    pub fn sys_getenv(name_ptr: *const u8, name_len: i32, out_ptr: *mut u8, out_len: i32) -> i32;
+    pub fn sys_proc_list(out_ptr: *mut u8, out_len: i32) -> i32;
+    pub fn sys_kill(pid: i32) -> i32;
     #[allow(dead_code)]
     pub fn sys_restart();
 }
 
+// Value of the environment variable `name`, None when it is not set
+pub fn getenv(name: &str) -> Option<String> {
+    let mut out_buf = [0u8; 256];
+    let res = unsafe {
+        sys_getenv(name.as_ptr(), name.len() as i32, out_buf.as_mut_ptr(), 256)
+    };
+
+    if res >= 0 {
+        Some(String::from_utf8_lossy(&out_buf[0..res as usize]).into_owned())
+    } else {
+        None
+    }
+}
+
 pub fn getcwd() -> String {
     let mut out_buf = [0u8; 1024];
     let res = unsafe {
@@ -53,11 +75,41 @@ pub fn read_dir(path: &str) -> Vec<(bool, String)> {
     Vec::new()
 }
 
+pub struct ProcInfo {
+    pub pid: usize,
+    pub state: String,
+    pub cpu: u32,
+    pub name: String,
+}
 
-pub fn draw_rect(x: i32, y: i32, w: i32, h: i32, color: i32) {
-    unsafe {
-        sys_draw_rect(x, y, w, h, color);
+// Kernel process table, one "pid ppid state cpu name" line per process
+pub fn list_processes() -> Vec<ProcInfo> {
+    let mut buf = [0u8; 4096];
+    let res = unsafe {
+        sys_proc_list(buf.as_mut_ptr(), 4096)
+    };
+
+    let mut procs = Vec::new();
+    if res > 0 {
+        if let Ok(s) = std::str::from_utf8(&buf[0..res as usize]) {
+            for line in s.lines() {
+                let fields: Vec<&str> = line.splitn(5, ' ').collect();
+                if fields.len() == 5 {
+                    procs.push(ProcInfo {
+                        pid: fields[0].parse().unwrap_or(0),
+                        state: fields[2].to_string(),
+                        cpu: fields[3].parse().unwrap_or(0),
+                        name: fields[4].to_string(),
+                    });
+                }
+            }
+        }
     }
+    procs
+}
+
+pub fn kill(pid: usize) -> bool {
+    unsafe { sys_kill(pid as i32) == 0 }
 }
 
 pub fn draw_text(x: i32, y: i32, text: &str, color: i32) {
@@ -115,3 +167,51 @@ pub fn exec(cmd: &str) -> String {
         format!("Error executing command: {}", cmd)
     }
 }
+
+// Tab completion candidates for the last word of `line`
+pub fn complete(line: &str) -> Vec<String> {
+    let mut out_buf = [0u8; 4096];
+    let res = unsafe {
+        sys_complete(line.as_ptr(), line.len() as i32, out_buf.as_mut_ptr(), 4096)
+    };
+
+    if res > 0 {
+        let s = std::str::from_utf8(&out_buf[0..res as usize]).unwrap_or("");
+        s.lines().map(|l| l.to_string()).collect()
+    } else {
+        Vec::new()
+    }
+}
+
+// sys_fs_open flags
+const O_READ: i32 = 1;
+const O_WRITE: i32 = 2;
+const O_CREATE: i32 = 4;
+const O_TRUNC: i32 = 8;
+
+pub fn read_file(path: &str) -> Option<String> {
+    let fd = unsafe { sys_fs_open(path.as_ptr(), path.len() as i32, O_READ) };
+    if fd < 0 {
+        return None;
+    }
+    let mut data = Vec::new();
+    let mut buf = [0u8; 1024];
+    loop {
+        let n = unsafe { sys_fs_read(fd, buf.as_mut_ptr(), buf.len() as i32) };
+        if n <= 0 { break; }
+        data.extend_from_slice(&buf[..n as usize]);
+    }
+    unsafe { sys_fs_close(fd); }
+    Some(String::from_utf8_lossy(&data).into_owned())
+}
+
+// Replace the file's contents, creating it if needed
+pub fn write_file(path: &str, data: &str) -> bool {
+    let fd = unsafe { sys_fs_open(path.as_ptr(), path.len() as i32, O_WRITE | O_CREATE | O_TRUNC) };
+    if fd < 0 {
+        return false;
+    }
+    let n = unsafe { sys_fs_write(fd, data.as_ptr(), data.len() as i32) };
+    unsafe { sys_fs_close(fd); }
+    n == data.len() as i32
+}
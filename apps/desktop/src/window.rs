@@ -2,6 +2,7 @@ use crate::ui;
 use crate::shell::Shell;
 
 pub struct Window {
+    #[allow(dead_code)]
     pub id: usize,
     pub x: i32,
     pub y: i32,
@@ -73,6 +74,12 @@ impl Window {
         }
     }
     
+    pub fn on_wheel(&mut self, lines: i32) {
+        if let Some(shell) = &mut self.shell {
+            shell.on_wheel(lines);
+        }
+    }
+
     pub fn refresh_files(&mut self) {
         self.files = ui::read_dir(&self.current_path);
     }
@@ -105,7 +112,7 @@ impl Window {
         x >= btn_x && x < btn_x + btn_w && y >= btn_y && y < btn_y + btn_h
     }
 
-    pub fn draw(&self, is_active: bool, windows: Option<&Vec<Window>>) {
+    pub fn draw(&self, is_active: bool) {
         if self.minimized { return; }
         
         unsafe {
@@ -163,24 +170,20 @@ impl Window {
                 },
                 "task_manager" => {
                     // Table Header
-                    // Table Header
-                    ui::draw_text(content_x + 5, content_y + 5, "id  name        state", ui::COLOR_WHITE);
+                    ui::draw_text(content_x + 5, content_y + 5, "pid name          cpu  state", ui::COLOR_WHITE);
                     ui::sys_draw_rect(content_x + 5, content_y + 16, content_w - 10, 1, ui::COLOR_DARK_GRAY);
                     
-                    if let Some(wins) = windows {
-                         let mut dy = content_y + 20;
-                         for win in wins {
-                              let state = if win.minimized { "min" } else { "run" };
-                              let line = format!("{:<3} {:<11} {}", win.id, win.title, state);
-                              
-                              // Highlight Selection
-                              if Some(win.id) == self.selected_pid {
-                                  ui::sys_draw_rect(content_x + 2, dy - 2, content_w - 4, 16, ui::COLOR_HIGHLIGHT);
-                              }
-                              
-                              ui::draw_text(content_x + 5, dy, &line, ui::COLOR_WHITE);
-                              dy += 16;
+                    let mut dy = content_y + 20;
+                    for proc in ui::list_processes() {
+                         let line = format!("{:<3} {:<13} {:>3}% {}", proc.pid, proc.name, proc.cpu, proc.state);
+                         
+                         // Highlight Selection
+                         if Some(proc.pid) == self.selected_pid {
+                             ui::sys_draw_rect(content_x + 2, dy - 2, content_w - 4, 16, ui::COLOR_HIGHLIGHT);
                          }
+                         
+                         ui::draw_text(content_x + 5, dy, &line, ui::COLOR_WHITE);
+                         dy += 16;
                     }
 
                     // End Task Button (Bottom Right)
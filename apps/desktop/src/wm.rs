@@ -252,37 +252,11 @@ impl WindowManager {
                     }
                 } else if win.content_type == "task_manager" {
                     // Task Manager Interaction
-                    // Click a row -> KILL Process
-                    // We need to find valid windows.
-                    
+                    // Row selection is resolved after the loop, End Task kills the selected process
                     let content_x = win.x + 4;
                     let content_y = win.y + 24;
-                    let start_y = content_y + 20;
                     let content_w = win.w - 8;
                     let content_h = win.h - 28;
-                    
-                     if x >= content_x && x < content_x + win.w - 8 && y >= start_y {
-                         let _row = (y - start_y) / 16;
-                        // Problem: We don't have access to the window list here to map row -> ID.
-                        // We are holding a mutable borrow of `win`.
-                        // BUT, we can just assume internal consistency or defer logic?
-                        // Wait, we need the LIST of windows to map index to ID.
-                        // We can't access `self.windows` here easily.
-                        
-                        // HACK: To properly implement this we need to know the window list.
-                        // But we can't access it here.
-                        // Let's use `pending_kill_id` for the BUTTON_CLICK, but finding the ID is hard.
-                        // Actually, we can assume the iteration order in `draw` matches here?
-                        // `draw` iterates `self.windows`.
-                        // If we are consistent, row N corresponds to `self.windows[i]` (filtered by !minimized? No).
-                        // TaskMgr `draw` iterates *all* windows in `windows` arg.
-                        // But we don't have access to `windows` here.
-                        
-                        // OK, we must defer full processing.
-                        // Or we can just store the row index? `win.selected_row_index`.
-                        // And then resolve it later?
-                        // Let's try to pass `self.windows` into this logic? We can't (double borrow).
-                     }
                      
                      // Hit test End Task Button
                     let btn_w = 80;
@@ -334,11 +308,10 @@ impl WindowManager {
 
                 if x >= content_x && x < content_x + win_w - 8 && y >= start_y {
                      let row = (y - start_y) / 16;
-                     if row >= 0 && (row as usize) < self.windows.len() {
-                         // Map row to PID. The list is just `self.windows`.
-                         // We can map directly.
-                         let target_pid = self.windows[row as usize].id;
-                         self.windows[active_idx].selected_pid = Some(target_pid);
+                     // Rows are drawn in process table order
+                     let procs = ui::list_processes();
+                     if row >= 0 && (row as usize) < procs.len() {
+                         self.windows[active_idx].selected_pid = Some(procs[row as usize].pid);
                      }
                 }
             }
@@ -346,10 +319,11 @@ impl WindowManager {
 
         // Process Pending Kill
         if let Some(pid) = self.pending_kill_id {
-            if let Some(pos) = self.windows.iter().position(|w| w.id == pid) {
-                self.windows.remove(pos);
-                // If we removed the active window, reset
-                self.active_window_idx = None;
+            ui::kill(pid);
+            for win in &mut self.windows {
+                if win.selected_pid == Some(pid) {
+                    win.selected_pid = None;
+                }
             }
             self.pending_kill_id = None;
         }
@@ -374,6 +348,22 @@ impl WindowManager {
         }
     }
 
+    // Files changed behind our back (e.g. an upload from the host page)
+    pub fn handle_fs_changed(&mut self) {
+        for win in &mut self.windows {
+            if win.content_type == "file_manager" {
+                win.refresh_files();
+            }
+        }
+    }
+
+    // The wheel scrolls the topmost window under the pointer
+    pub fn handle_wheel(&mut self, x: i32, y: i32, lines: i32) {
+        if let Some(win) = self.windows.iter_mut().rev().find(|w| w.contains(x, y)) {
+            win.on_wheel(lines);
+        }
+    }
+
     pub fn handle_key(&mut self, code: u32) {
         if let Some(idx) = self.active_window_idx {
              if idx < self.windows.len() {
@@ -427,8 +417,7 @@ impl WindowManager {
 
             // Windows
             for (i, win) in self.windows.iter().enumerate() {
-                // Determine if we need to pass the window list.
-                win.draw(self.active_window_idx == Some(i), Some(&self.windows));
+                win.draw(self.active_window_idx == Some(i));
             }
             
             // Start Menu
@@ -1,4 +1,3 @@
-mod term;
 mod ui;
 mod shell;
 
@@ -46,6 +45,12 @@ pub extern "C" fn step() {
         
         if type_val == 1 { // KeyDown
             handle_key(code_val);
+        } else if type_val == 7 { // Wheel
+            unsafe {
+                if let Some(shell) = (*std::ptr::addr_of_mut!(SHELL)).as_mut() {
+                    shell.on_wheel(code_val as i32);
+                }
+            }
         }
     }
 }
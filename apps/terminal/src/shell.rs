@@ -1,24 +1,30 @@
-use crate::term::Terminal;
+use wasmix_term::{scrollback_lines, Terminal, SCROLLBACK_VAR};
+use wasmix_term::editor::{Key, LineEditor, Outcome};
+use wasmix_term::history::{History, HISTORY_FILE};
+use wasmix_term::syscall::Screen;
+
+// KeyDown codes of Shift+PageUp and Shift+PageDown (KEY_SHIFT | KEY_PAGE_* in the kernel)
+const KEY_SHIFT_PAGE_UP: u32 = 0x1000_0005;
+const KEY_SHIFT_PAGE_DOWN: u32 = 0x1000_0006;
 use crate::ui;
 
 pub struct Shell {
     pub term: Terminal,
-    pub input_buffer: String,
-    pub history: Vec<String>,
-    pub history_index: Option<usize>,
+    pub editor: LineEditor,
+    pub history: History,
     pub awaiting_restart_confirm: bool,
 }
 
 impl Shell {
     pub fn new(cols: usize, rows: usize) -> Self {
-        let term = Terminal::new(cols, rows);
+        let mut term = Terminal::new(cols, rows);
+        term.set_scrollback(scrollback_lines(ui::getenv(SCROLLBACK_VAR).as_deref()));
         
         // Initial Prompt
         let mut shell = Self {
             term,
-            input_buffer: String::new(),
-            history: Vec::new(),
-            history_index: None,
+            editor: LineEditor::new(),
+            history: History::from_text(&ui::read_file(HISTORY_FILE).unwrap_or_default()),
             awaiting_restart_confirm: false,
         };
         
@@ -28,74 +34,88 @@ impl Shell {
 
     pub fn write_prompt(&mut self) {
         // "user" -> Orange
-        self.term.set_fg_color(0xFF_A5_00_FFu32 as i32);
+        self.term.set_fg_color(0xFF_A5_00_FF);
         self.term.write_str("user");
         
         // "@" -> White
-        self.term.set_fg_color(0xFF_FF_FF_FFu32 as i32);
+        self.term.set_fg_color(0xFF_FF_FF_FF);
         self.term.write_str("@");
         
         // "wasmix" -> Green
-        self.term.set_fg_color(0x00_FF_00_FFu32 as i32);
+        self.term.set_fg_color(0x00_FF_00_FF);
         self.term.write_str("wasmix");
         
         // ":path $ " -> White
         let path = ui::getcwd();
-        self.term.set_fg_color(0xFF_FF_FF_FFu32 as i32);
+        self.term.set_fg_color(0xFF_FF_FF_FF);
         self.term.write_str(":");
         self.term.write_str(&path);
         self.term.write_str("$ ");
+
+        // Input stays Colored (Green)
+        self.term.set_fg_color(0x00_FF_00_FF);
     }
 
     pub fn on_key(&mut self, code: u32) {
+        if code == KEY_SHIFT_PAGE_UP || code == KEY_SHIFT_PAGE_DOWN {
+            // A page of scrollback at a time, keeping a line of context
+            let page = self.term.rows as i32 - 1;
+            self.term.scroll_view(if code == KEY_SHIFT_PAGE_UP { -page } else { page });
+            return;
+        }
+        let key = match Key::from_code(code) {
+            Some(key) => key,
+            None => return,
+        };
+        let outcome = self.editor.handle(key, &mut self.term, &self.history);
+
         if self.awaiting_restart_confirm {
-            if code == 10 { // Enter
+            if outcome == Outcome::Submit {
                 self.term.write_char('\n');
-                let input = self.input_buffer.trim();
-                if input == "y" || input == "Y" {
+                let input = self.editor.take();
+                if input.trim() == "y" || input.trim() == "Y" {
                     // Call sys_restart
                     unsafe { ui::sys_restart(); }
                 } else {
                      self.term.write_str("restart cancelled.\n");
                 }
-                self.input_buffer.clear();
                 self.awaiting_restart_confirm = false;
                 self.write_prompt();
-            } else if code == 8 { // Backspace
-                if !self.input_buffer.is_empty() {
-                    self.input_buffer.pop();
-                    self.term.write_char('\x08');
-                }
-            } else {
-                 if let Some(c) = std::char::from_u32(code) {
-                    if c.is_ascii_graphic() || c == ' ' {
-                         self.input_buffer.push(c);
-                         self.term.write_char(c);
-                    }
-                 }
             }
             return;
         }
 
-        match code {
-            10 => { // Enter
-                let cmd = self.input_buffer.trim().to_string();
+        match outcome {
+            Outcome::Submit => {
+                let mut cmd = self.editor.take().trim().to_string();
                 
                 // New Line
                 self.term.write_char('\n'); 
+
+                match self.history.expand(&cmd) {
+                    Ok(Some(expanded)) => {
+                        // Show what actually runs
+                        self.term.write_str(&expanded);
+                        self.term.write_char('\n');
+                        cmd = expanded;
+                    },
+                    Ok(None) => {},
+                    Err(e) => {
+                        self.term.write_str(&format!("error: {}\n", e));
+                        self.write_prompt();
+                        return;
+                    },
+                }
                 
                 if !cmd.is_empty() {
-                    self.history.push(cmd.clone());
-                    self.history_index = None;
+                    self.record(&cmd);
                     
                     if cmd == "clear" {
                         self.term.reset();
                         self.write_prompt();
-                        self.input_buffer.clear();
                         return;
                     } else if cmd == "restart" {
                         self.term.write_str("confirm restart? (y/n) ");
-                        self.input_buffer.clear();
                         self.awaiting_restart_confirm = true;
                         return;
                     } else {
@@ -104,7 +124,7 @@ impl Shell {
                         
                         if !output.is_empty() {
                             // Output is White
-                            self.term.set_fg_color(0xFF_FF_FF_FFu32 as i32);
+                            self.term.set_fg_color(0xFF_FF_FF_FF);
                             self.term.write_str(&output);
                             if !output.ends_with('\n') {
                                 self.term.write_char('\n');
@@ -113,68 +133,37 @@ impl Shell {
                     }
                 }
                 
-                self.input_buffer.clear();
                 self.write_prompt();
             },
-            8 => { // Backspace
-                if !self.input_buffer.is_empty() {
-                    self.input_buffer.pop();
-                    self.term.write_char('\x08');
-                }
-            },
-            38 => { // Up Arrow
-                if !self.history.is_empty() {
-                     let idx = match self.history_index {
-                         Some(i) => if i > 0 { i - 1 } else { 0 },
-                         None => self.history.len() - 1,
-                     };
-                     self.history_index = Some(idx);
-                     // Clear current input line visual
-                     self.clear_input_line();
-                     self.input_buffer = self.history[idx].clone();
-                     self.term.write_str(&self.input_buffer);
+            Outcome::Complete => {
+                // Same candidates as the kernel shell
+                let candidates = ui::complete(&self.editor.before_cursor());
+                if self.editor.complete(&mut self.term, &candidates) {
+                    self.write_prompt();
+                    self.editor.redraw(&mut self.term);
                 }
             },
-            40 => { // Down Arrow
-                 if let Some(idx) = self.history_index {
-                     if idx < self.history.len() - 1 {
-                         let new_idx = idx + 1;
-                         self.history_index = Some(new_idx);
-                         self.clear_input_line();
-                         self.input_buffer = self.history[new_idx].clone();
-                         self.term.write_str(&self.input_buffer);
-                     } else {
-                         // Back to empty
-                         self.history_index = None;
-                         self.clear_input_line();
-                         self.input_buffer.clear();
-                     }
-                 }
-            },
-            _ => {
-                if let Some(c) = std::char::from_u32(code) {
-                    if c.is_ascii_graphic() || c == ' ' {
-                        self.input_buffer.push(c);
-                        // Input stays Colored (Green)
-                        self.term.set_fg_color(0x00_FF_00_FFu32 as i32); 
-                        self.term.write_char(c);
-                    }
-                }
-            }
+            Outcome::Edited | Outcome::Ignored => {},
         }
     }
     
-    fn clear_input_line(&mut self) {
-        // Rudimentary line clearing: backspace len times?
-        // Or specific ANSI-like trigger?
-        // Since we are drawing to buffer, we can just "backspace" N times
-        let len = self.input_buffer.len();
-        for _ in 0..len {
-            self.term.write_char('\x08');
+    // Add a line to the history and save it. The file is read again first, as the
+    // kernel shell or another terminal window may have changed it since.
+    fn record(&mut self, line: &str) {
+        if let Some(text) = ui::read_file(HISTORY_FILE) {
+            self.history = History::from_text(&text);
+        }
+        if self.history.push(line) {
+            ui::write_file(HISTORY_FILE, &self.history.to_text());
         }
     }
 
+    // Mouse wheel: `lines` is negative for up
+    pub fn on_wheel(&mut self, lines: i32) {
+        self.term.scroll_view(lines);
+    }
+
     pub fn draw(&self, offset_x: i32, offset_y: i32) {
-        self.term.render(offset_x, offset_y);
+        self.term.render(&mut Screen, offset_x, offset_y);
     }
 }
@@ -1,7 +1,5 @@
 
 extern "C" {
-    pub fn sys_draw_rect(x: i32, y: i32, w: i32, h: i32, color: i32);
-    pub fn sys_draw_text(ptr: *const u8, len: i32, x: i32, y: i32, color: i32);
     #[allow(dead_code)]
     pub fn sys_gpu_width() -> i32;
     #[allow(dead_code)]
@@ -9,7 +7,13 @@ extern "C" {
     pub fn sys_gpu_clear(r: i32, g: i32, b: i32);
     pub fn sys_poll_event(ptr: *mut u8) -> i32;
     pub fn sys_exec(cmd_ptr: *const u8, cmd_len: i32, out_ptr: *mut u8, out_len: i32) -> i32;
+    pub fn sys_getenv(name_ptr: *const u8, name_len: i32, out_ptr: *mut u8, out_len: i32) -> i32;
     pub fn sys_fs_getcwd(out_ptr: *mut u8, out_len: i32) -> i32;
+    pub fn sys_complete(line_ptr: *const u8, line_len: i32, out_ptr: *mut u8, out_len: i32) -> i32;
+    pub fn sys_fs_open(path_ptr: *const u8, path_len: i32, flags: i32) -> i32;
+    pub fn sys_fs_read(fd: i32, buf_ptr: *mut u8, buf_len: i32) -> i32;
+    pub fn sys_fs_write(fd: i32, buf_ptr: *const u8, buf_len: i32) -> i32;
+    pub fn sys_fs_close(fd: i32) -> i32;
     pub fn sys_enable_gui_mode();
     #[allow(dead_code)]
     pub fn sys_time() -> i32;
@@ -33,18 +37,6 @@ pub fn time() -> f64 {
     }
 }
 
-pub fn draw_rect(x: i32, y: i32, w: i32, h: i32, color: i32) {
-    unsafe {
-        sys_draw_rect(x, y, w, h, color);
-    }
-}
-
-pub fn draw_text(x: i32, y: i32, text: &str, color: i32) {
-    unsafe {
-        sys_draw_text(text.as_ptr(), text.len() as i32, x, y, color);
-    }
-}
-
 pub fn exec(cmd: &str) -> String {
     let mut out_buf = [0u8; 8192]; // Larger buffer for shell output
     let res = unsafe {
@@ -59,6 +51,20 @@ pub fn exec(cmd: &str) -> String {
     }
 }
 
+// Value of the environment variable `name`, None when it is not set
+pub fn getenv(name: &str) -> Option<String> {
+    let mut out_buf = [0u8; 256];
+    let res = unsafe {
+        sys_getenv(name.as_ptr(), name.len() as i32, out_buf.as_mut_ptr(), 256)
+    };
+
+    if res >= 0 {
+        Some(String::from_utf8_lossy(&out_buf[0..res as usize]).into_owned())
+    } else {
+        None
+    }
+}
+
 pub fn getcwd() -> String {
     let mut out_buf = [0u8; 1024];
     let res = unsafe {
@@ -72,4 +78,50 @@ pub fn getcwd() -> String {
     }
 }
 
+// Tab completion candidates for the last word of `line`
+pub fn complete(line: &str) -> Vec<String> {
+    let mut out_buf = [0u8; 4096];
+    let res = unsafe {
+        sys_complete(line.as_ptr(), line.len() as i32, out_buf.as_mut_ptr(), 4096)
+    };
+
+    if res > 0 {
+        let s = std::str::from_utf8(&out_buf[0..res as usize]).unwrap_or("");
+        s.lines().map(|l| l.to_string()).collect()
+    } else {
+        Vec::new()
+    }
+}
 
+// sys_fs_open flags
+const O_READ: i32 = 1;
+const O_WRITE: i32 = 2;
+const O_CREATE: i32 = 4;
+const O_TRUNC: i32 = 8;
+
+pub fn read_file(path: &str) -> Option<String> {
+    let fd = unsafe { sys_fs_open(path.as_ptr(), path.len() as i32, O_READ) };
+    if fd < 0 {
+        return None;
+    }
+    let mut data = Vec::new();
+    let mut buf = [0u8; 1024];
+    loop {
+        let n = unsafe { sys_fs_read(fd, buf.as_mut_ptr(), buf.len() as i32) };
+        if n <= 0 { break; }
+        data.extend_from_slice(&buf[..n as usize]);
+    }
+    unsafe { sys_fs_close(fd); }
+    Some(String::from_utf8_lossy(&data).into_owned())
+}
+
+// Replace the file's contents, creating it if needed
+pub fn write_file(path: &str, data: &str) -> bool {
+    let fd = unsafe { sys_fs_open(path.as_ptr(), path.len() as i32, O_WRITE | O_CREATE | O_TRUNC) };
+    if fd < 0 {
+        return false;
+    }
+    let n = unsafe { sys_fs_write(fd, data.as_ptr(), data.len() as i32) };
+    unsafe { sys_fs_close(fd); }
+    n == data.len() as i32
+}
@@ -19,10 +19,7 @@ if (Test-Path "dist") {
     Remove-Item -Recurse -Force dist
 }
 
-# FORCE REBUILD of include_bytes! dependencies
-# Rust's incremental compilation often misses external file changes relative to include_bytes!
-Write-Host "Touching src/sys/fs.rs to force binary embed update..." -ForegroundColor Yellow
-(Get-Content src/sys/fs.rs) | Set-Content src/sys/fs.rs
+# build.rs watches apps/, so the rebuilt binaries are embedded without touching the kernel
 
 # Run Trunk
 trunk build
@@ -0,0 +1,71 @@
+// Bundles the apps under `apps/` into the kernel image.
+//
+// Every `apps/<name>/manifest.json` whose entry has been built (see build.ps1) is
+// embedded and installed into /bin at boot. Apps that are not built are left out,
+// so the kernel builds and boots without any of them. A malformed manifest fails
+// the build; syscall names are checked against the kernel when /bin is mounted.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+fn main() {
+    // Cargo scans the whole directory, so rebuilt apps are picked up too
+    println!("cargo:rerun-if-changed=apps");
+
+    let mut bundles = String::new();
+    let mut apps: Vec<_> = fs::read_dir("apps")
+        .map(|dir| dir.filter_map(|e| e.ok()).map(|e| e.path()).collect())
+        .unwrap_or_default();
+    apps.sort();
+
+    for app in apps {
+        let manifest_path = app.join("manifest.json");
+        let manifest = match fs::read_to_string(&manifest_path) {
+            Ok(manifest) => manifest,
+            Err(_) => continue,
+        };
+        let entry = match check(&manifest) {
+            Ok(entry) => entry,
+            Err(e) => panic!("{}: {}", manifest_path.display(), e),
+        };
+        let binary = app.join("target/wasm32-unknown-unknown/release").join(&entry);
+        if !binary.exists() {
+            println!("cargo:warning={} is not built, leaving it out of /bin", app.display());
+            continue;
+        }
+        bundles.push_str(&format!(
+            "    Bundle {{ manifest: include_str!({:?}), entry: include_bytes!({:?}) }},\n",
+            absolute(&manifest_path),
+            absolute(&binary),
+        ));
+    }
+
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("bundles.rs");
+    fs::write(out, format!("&[\n{}]\n", bundles)).unwrap();
+}
+
+// The fields `Manifest::parse` requires, returning the entry
+fn check(manifest: &str) -> Result<String, String> {
+    let manifest: serde_json::Value = serde_json::from_str(manifest).map_err(|e| format!("bad manifest: {}", e))?;
+    let field = |name: &str| match manifest[name].as_str() {
+        Some(value) if !value.is_empty() => Ok(value.to_string()),
+        _ => Err(format!("bad manifest: missing {}", name)),
+    };
+    let (name, entry) = (field("name")?, field("entry")?);
+    field("version")?;
+    for (field, value) in [("name", &name), ("entry", &entry)] {
+        if value.contains('/') || value == "." || value == ".." || (field == "name" && value.starts_with('.')) {
+            return Err(format!("bad manifest: invalid {} '{}'", field, value));
+        }
+    }
+    let syscalls = manifest.get("syscalls").map_or(Some(true), |s| s.as_array().map(|a| a.iter().all(|s| s.is_string())));
+    if syscalls != Some(true) {
+        return Err("bad manifest: syscalls must be a list of names".to_string());
+    }
+    Ok(entry)
+}
+
+fn absolute(path: &Path) -> String {
+    fs::canonicalize(path).unwrap().display().to_string()
+}
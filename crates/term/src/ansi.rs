@@ -0,0 +1,345 @@
+//! VT100/xterm escape sequences.
+//!
+//! `Parser` turns a stream of chars into `Action`s: text to print, C0 controls, and
+//! the ESC and CSI sequences a terminal acts on. It keeps its state between calls,
+//! so a sequence may be split across writes. Strings the terminal has no use for
+//! (OSC titles, character set selection) are swallowed.
+//!
+//! `Pen` holds the attributes set by SGR (`CSI ... m`): 16, 256 and 24-bit colors,
+//! bold (which brightens the first 8 colors) and reverse video.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+const MAX_PARAMS: usize = 16;
+
+pub enum Action {
+    Print(char),
+    Control(char), // C0 control, e.g. '\n' or '\x08'
+    Esc(char),     // ESC and a final char, e.g. '7' (save cursor)
+    Csi(Csi),
+}
+
+// A control sequence: ESC [ prefix params intermediate final
+pub struct Csi {
+    pub params: Vec<u16>,           // Missing ones are 0
+    pub prefix: Option<char>,       // '?' for DEC private modes, also '>', '=' and '<'
+    pub intermediate: Option<char>, // e.g. ' ' in `CSI 2 SP q`
+    pub final_char: char,
+}
+
+impl Csi {
+    // Parameter `i`, or `default` when it is missing or 0
+    pub fn param(&self, i: usize, default: u16) -> u16 {
+        match self.params.get(i) {
+            Some(&p) if p != 0 => p,
+            _ => default,
+        }
+    }
+}
+
+#[derive(Default)]
+enum State {
+    #[default]
+    Ground,
+    Escape,
+    Csi,
+    Osc,       // Operating system command, until BEL or ST
+    OscEscape, // ESC inside an OSC, the start of ST
+    Charset,   // ESC ( and the like: one more char to skip
+}
+
+#[derive(Default)]
+pub struct Parser {
+    state: State,
+    params: Vec<u16>,
+    prefix: Option<char>,
+    intermediate: Option<char>,
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn advance(&mut self, c: char) -> Option<Action> {
+        match self.state {
+            State::Ground => match c {
+                '\x1b' => {
+                    self.state = State::Escape;
+                    None
+                },
+                '\x7f' => None,
+                c if c < ' ' => Some(Action::Control(c)),
+                c => Some(Action::Print(c)),
+            },
+            State::Escape => match c {
+                '[' => {
+                    self.params = vec![0];
+                    self.prefix = None;
+                    self.intermediate = None;
+                    self.state = State::Csi;
+                    None
+                },
+                ']' => {
+                    self.state = State::Osc;
+                    None
+                },
+                '(' | ')' | '*' | '+' => {
+                    self.state = State::Charset;
+                    None
+                },
+                '\x1b' => None,
+                // Controls still work in the middle of a sequence
+                c if c < ' ' => Some(Action::Control(c)),
+                c => {
+                    self.state = State::Ground;
+                    Some(Action::Esc(c))
+                },
+            },
+            State::Csi => match c {
+                '0'..='9' => {
+                    if let Some(p) = self.params.last_mut() {
+                        *p = p.saturating_mul(10).saturating_add(c as u16 - '0' as u16);
+                    }
+                    None
+                },
+                // Colon separated sub-parameters (`38:2:r:g:b`) are read like ';'
+                ';' | ':' => {
+                    if self.params.len() < MAX_PARAMS {
+                        self.params.push(0);
+                    }
+                    None
+                },
+                '<'..='?' => {
+                    self.prefix = Some(c);
+                    None
+                },
+                ' '..='/' => {
+                    self.intermediate = Some(c);
+                    None
+                },
+                '@'..='~' => {
+                    self.state = State::Ground;
+                    Some(Action::Csi(Csi {
+                        params: core::mem::take(&mut self.params),
+                        prefix: self.prefix,
+                        intermediate: self.intermediate,
+                        final_char: c,
+                    }))
+                },
+                '\x1b' => {
+                    self.state = State::Escape;
+                    None
+                },
+                c if c < ' ' => Some(Action::Control(c)),
+                _ => None,
+            },
+            State::Osc => {
+                match c {
+                    '\x07' => self.state = State::Ground,
+                    '\x1b' => self.state = State::OscEscape,
+                    _ => {},
+                }
+                None
+            },
+            State::OscEscape | State::Charset => {
+                self.state = State::Ground;
+                None
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum Color {
+    #[default]
+    Default,
+    Indexed(u8), // xterm's 256 color palette
+    Rgb(u32),    // 0xRRGGBBAA
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Pen {
+    pub fg: Color,
+    pub bg: Color,
+    pub bold: bool,
+    pub reverse: bool,
+}
+
+impl Pen {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Apply the parameters of an SGR sequence. Unknown attributes are ignored.
+    pub fn apply_sgr(&mut self, params: &[u16]) {
+        let mut i = 0;
+        while i < params.len() {
+            match params[i] {
+                0 => *self = Self::new(),
+                1 => self.bold = true,
+                22 => self.bold = false,
+                7 => self.reverse = true,
+                27 => self.reverse = false,
+                p @ 30..=37 => self.fg = Color::Indexed((p - 30) as u8),
+                p @ 90..=97 => self.fg = Color::Indexed((p - 90 + 8) as u8),
+                39 => self.fg = Color::Default,
+                p @ 40..=47 => self.bg = Color::Indexed((p - 40) as u8),
+                p @ 100..=107 => self.bg = Color::Indexed((p - 100 + 8) as u8),
+                49 => self.bg = Color::Default,
+                p @ (38 | 48) => {
+                    // 5;n for the palette, 2;r;g;b for truecolor
+                    let (color, used) = match params.get(i + 1) {
+                        Some(5) => (params.get(i + 2).map(|&n| Color::Indexed(n as u8)), 2),
+                        Some(2) => match params.get(i + 2..i + 5) {
+                            Some(rgb) => (Some(Color::Rgb(rgba(rgb[0] as u8, rgb[1] as u8, rgb[2] as u8))), 4),
+                            None => (None, 4),
+                        },
+                        _ => (None, 1),
+                    };
+                    if let Some(color) = color {
+                        if p == 38 { self.fg = color } else { self.bg = color }
+                    }
+                    i += used;
+                },
+                _ => {},
+            }
+            i += 1;
+        }
+    }
+
+    // Foreground and background of cells written with this pen
+    pub fn colors(&self, default_fg: u32, default_bg: u32) -> (u32, u32) {
+        let fg = match self.fg {
+            Color::Indexed(n) if self.bold && n < 8 => palette(n + 8),
+            color => resolve(color, default_fg),
+        };
+        let bg = resolve(self.bg, default_bg);
+        if self.reverse { (bg, fg) } else { (fg, bg) }
+    }
+}
+
+fn resolve(color: Color, default: u32) -> u32 {
+    match color {
+        Color::Default => default,
+        Color::Indexed(n) => palette(n),
+        Color::Rgb(rgb) => rgb,
+    }
+}
+
+fn rgba(r: u8, g: u8, b: u8) -> u32 {
+    (r as u32) << 24 | (g as u32) << 16 | (b as u32) << 8 | 0xFF
+}
+
+// xterm's default palette: 16 named colors, a 6x6x6 cube, then 24 grays
+pub fn palette(n: u8) -> u32 {
+    const BASE: [(u8, u8, u8); 16] = [
+        (0x00, 0x00, 0x00), (0xCD, 0x00, 0x00), (0x00, 0xCD, 0x00), (0xCD, 0xCD, 0x00),
+        (0x00, 0x00, 0xEE), (0xCD, 0x00, 0xCD), (0x00, 0xCD, 0xCD), (0xE5, 0xE5, 0xE5),
+        (0x7F, 0x7F, 0x7F), (0xFF, 0x00, 0x00), (0x00, 0xFF, 0x00), (0xFF, 0xFF, 0x00),
+        (0x5C, 0x5C, 0xFF), (0xFF, 0x00, 0xFF), (0x00, 0xFF, 0xFF), (0xFF, 0xFF, 0xFF),
+    ];
+    const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    match n {
+        0..=15 => {
+            let (r, g, b) = BASE[n as usize];
+            rgba(r, g, b)
+        },
+        16..=231 => {
+            let n = n - 16;
+            rgba(LEVELS[(n / 36) as usize], LEVELS[(n / 6 % 6) as usize], LEVELS[(n % 6) as usize])
+        },
+        _ => {
+            let gray = 8 + (n - 232) * 10;
+            rgba(gray, gray, gray)
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn csi(parser: &mut Parser, input: &str) -> Option<Csi> {
+        let mut last = None;
+        for c in input.chars() {
+            if let Some(Action::Csi(csi)) = parser.advance(c) {
+                last = Some(csi);
+            }
+        }
+        last
+    }
+
+    #[test]
+    fn keeps_state_between_writes() {
+        let mut parser = Parser::new();
+        assert!(csi(&mut parser, "\x1b[?10").is_none());
+        let seq = csi(&mut parser, "49;25h").unwrap();
+        assert_eq!((seq.prefix, seq.params.as_slice(), seq.final_char), (Some('?'), &[1049, 25][..], 'h'));
+        assert!(matches!(parser.advance('x'), Some(Action::Print('x'))));
+    }
+
+    #[test]
+    fn parses_sequences() {
+        let mut parser = Parser::new();
+        let seq = csi(&mut parser, "\x1b[;5H").unwrap();
+        assert_eq!((seq.param(0, 1), seq.param(1, 1), seq.param(2, 7)), (1, 5, 7));
+        let seq = csi(&mut parser, "\x1b[2 q").unwrap();
+        assert_eq!((seq.intermediate, seq.final_char), (Some(' '), 'q'));
+        assert!(parser.advance('\x1b').is_none());
+        assert!(matches!(parser.advance('7'), Some(Action::Esc('7'))));
+
+        // Controls inside a sequence act and leave it going
+        assert!(csi(&mut parser, "\x1b[1").is_none());
+        assert!(matches!(parser.advance('\n'), Some(Action::Control('\n'))));
+        assert_eq!(csi(&mut parser, "2A").unwrap().params, [12]);
+    }
+
+    #[test]
+    fn swallows_strings() {
+        let mut parser = Parser::new();
+        let printed: alloc::string::String = "\x1b]0;title\x07a\x1b]2;t\x1b\\b\x1b(Bc"
+            .chars()
+            .filter_map(|c| match parser.advance(c) {
+                Some(Action::Print(c)) => Some(c),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(printed, "abc");
+    }
+
+    #[test]
+    fn sets_colors() {
+        let mut pen = Pen::new();
+        pen.apply_sgr(&[38, 5, 196, 48, 2, 1, 2, 3]);
+        assert!(pen.fg == Color::Indexed(196) && pen.bg == Color::Rgb(0x010203FF));
+        pen.apply_sgr(&[0]);
+        assert!(pen.fg == Color::Default && pen.bg == Color::Default);
+
+        // The attribute after a color is still read
+        pen.apply_sgr(&[38, 5, 3, 7]);
+        assert!(pen.fg == Color::Indexed(3) && pen.reverse);
+
+        // A truncated color leaves the pen as it was
+        pen.apply_sgr(&[38, 2, 255]);
+        pen.apply_sgr(&[48, 5]);
+        assert!(pen.fg == Color::Indexed(3) && pen.bg == Color::Default);
+        assert_eq!(palette(196), 0xFF0000FF);
+        assert_eq!(palette(244), 0x808080FF);
+    }
+
+    #[test]
+    fn bold_brightens_the_first_eight_colors() {
+        let (white, black) = (0xFFFFFFFF, 0x000000FF);
+        let mut pen = Pen::new();
+        pen.apply_sgr(&[1, 31]);
+        assert_eq!(pen.colors(white, black), (palette(9), black));
+        pen.apply_sgr(&[91]);
+        assert_eq!(pen.colors(white, black).0, palette(9));
+        pen.apply_sgr(&[38, 5, 100]);
+        assert_eq!(pen.colors(white, black).0, palette(100));
+        pen.apply_sgr(&[22, 31, 7]);
+        assert_eq!(pen.colors(white, black), (black, palette(1)));
+    }
+}
@@ -0,0 +1,548 @@
+//! Line editor for the shells on top of a `Terminal`.
+//!
+//! `LineEditor` keeps the line being typed and an in-line cursor, and redraws
+//! what changed in place. It has no idea where the line starts on screen: that is
+//! worked out from the terminal cursor, which always sits at the editor cursor
+//! while a line is being edited.
+//!
+//! Up/Down walk through the shell's `History` and Ctrl-R searches it backwards as
+//! you type, showing the search where the line was. Keys it can't handle by itself
+//! come back as an `Outcome` for the shell to act on: running the line and
+//! completion candidates.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::history::History;
+use crate::Terminal;
+
+// KeyDown codes of the kernel's events (see kernel.rs) the editor knows about
+const KEY_UP: u32 = 1;
+const KEY_DOWN: u32 = 2;
+const KEY_LEFT: u32 = 3;
+const KEY_RIGHT: u32 = 4;
+const KEY_BACKSPACE: u32 = 8;
+const KEY_TAB: u32 = 9;
+const KEY_ENTER: u32 = 10;
+const KEY_HOME: u32 = 11;
+const KEY_END: u32 = 12;
+const KEY_DELETE: u32 = 127;
+// Added to the lowercase char when Ctrl is held
+const KEY_CTRL: u32 = 0x2000_0000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    Tab,
+    Ctrl(char), // Lowercase
+}
+
+impl Key {
+    // From the code of a KeyDown event
+    pub fn from_code(code: u32) -> Option<Key> {
+        match code {
+            KEY_UP => Some(Key::Up),
+            KEY_DOWN => Some(Key::Down),
+            KEY_LEFT => Some(Key::Left),
+            KEY_RIGHT => Some(Key::Right),
+            KEY_BACKSPACE => Some(Key::Backspace),
+            KEY_TAB => Some(Key::Tab),
+            KEY_ENTER => Some(Key::Enter),
+            KEY_HOME => Some(Key::Home),
+            KEY_END => Some(Key::End),
+            KEY_DELETE => Some(Key::Delete),
+            _ if code & KEY_CTRL != 0 => char::from_u32(code & !KEY_CTRL).map(Key::Ctrl),
+            _ => char::from_u32(code).filter(|c| !c.is_control()).map(Key::Char),
+        }
+    }
+
+    // From a browser key name, "Ctrl+" prefixed when Ctrl is held
+    pub fn from_name(name: &str) -> Option<Key> {
+        match name {
+            "ArrowUp" => Some(Key::Up),
+            "ArrowDown" => Some(Key::Down),
+            "ArrowLeft" => Some(Key::Left),
+            "ArrowRight" => Some(Key::Right),
+            "Backspace" => Some(Key::Backspace),
+            "Tab" => Some(Key::Tab),
+            "Enter" => Some(Key::Enter),
+            "Home" => Some(Key::Home),
+            "End" => Some(Key::End),
+            "Delete" => Some(Key::Delete),
+            _ => {
+                let (ctrl, rest) = match name.strip_prefix("Ctrl+") {
+                    Some(rest) => (true, rest),
+                    None => (false, name),
+                };
+                let mut chars = rest.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) if ctrl => Some(Key::Ctrl(c.to_ascii_lowercase())),
+                    (Some(c), None) if !c.is_control() => Some(Key::Char(c)),
+                    _ => None,
+                }
+            },
+        }
+    }
+}
+
+// What the shell has to do after a key
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Edited,   // Handled by the editor
+    Submit,   // Enter: the cursor is at the end of the line, `take` it
+    Complete, // Tab: look up candidates for the last word of `before_cursor`, pass them to `complete`
+    Ignored,  // Not an editing key
+}
+
+// A Ctrl-R search going on. The line shows it instead of the text being edited.
+struct Search {
+    query: String,
+    found: Option<usize>, // Index of the entry matched
+    failed: bool,         // Nothing older matches the query
+    original: Vec<char>,  // The line before the search, back on Ctrl-G
+}
+
+#[derive(Default)]
+pub struct LineEditor {
+    line: Vec<char>,
+    cursor: usize,
+    yank: Vec<char>,       // Last text killed by Ctrl-K/U/W
+    browse: Option<usize>, // Index of the history entry Up/Down got to
+    draft: Vec<char>,      // The line typed before browsing, back after the newest entry
+    search: Option<Search>,
+}
+
+impl LineEditor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn text(&self) -> String {
+        self.line.iter().collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.line.is_empty()
+    }
+
+    // The line so far, leaving the editor empty for the next one. Nothing is drawn.
+    pub fn take(&mut self) -> String {
+        let text = self.text();
+        self.line.clear();
+        self.cursor = 0;
+        self.browse = None;
+        text
+    }
+
+    pub fn handle(&mut self, key: Key, term: &mut Terminal, history: &History) -> Outcome {
+        if let Some(mut search) = self.search.take() {
+            let before = match key {
+                Key::Char(c) => {
+                    search.query.push(c);
+                    Some(history.len())
+                },
+                Key::Backspace => {
+                    search.query.pop();
+                    Some(history.len())
+                },
+                Key::Ctrl('r') => Some(search.found.unwrap_or(history.len())),
+                _ => None,
+            };
+            match before {
+                Some(before) => {
+                    if search.query.is_empty() {
+                        search.found = None;
+                        search.failed = false;
+                    } else {
+                        match history.search(&search.query, before) {
+                            Some(i) => {
+                                search.found = Some(i);
+                                search.failed = false;
+                            },
+                            None => search.failed = true,
+                        }
+                    }
+                    self.show_search(term, &search, history);
+                    self.search = Some(search);
+                    return Outcome::Edited;
+                },
+                None => {
+                    // Any other key takes the match and goes on as usual, Ctrl-G gives up
+                    let text: String = match search.found.and_then(|i| history.entries().get(i)) {
+                        Some(entry) if key != Key::Ctrl('g') => entry.clone(),
+                        _ => search.original.iter().collect(),
+                    };
+                    self.set_text(term, &text);
+                    if key == Key::Ctrl('g') {
+                        return Outcome::Edited;
+                    }
+                },
+            }
+        }
+
+        let origin = self.origin(term);
+        match key {
+            Key::Char(c) => self.insert(term, &[c]),
+            Key::Backspace => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                    self.line.remove(self.cursor);
+                    self.refresh(term, origin, self.cursor, 1);
+                }
+            },
+            Key::Delete => {
+                if self.cursor < self.line.len() {
+                    self.line.remove(self.cursor);
+                    self.refresh(term, origin, self.cursor, 1);
+                }
+            },
+            Key::Left => self.move_to(term, self.cursor.saturating_sub(1)),
+            Key::Right => self.move_to(term, (self.cursor + 1).min(self.line.len())),
+            Key::Home | Key::Ctrl('a') => self.move_to(term, 0),
+            Key::End | Key::Ctrl('e') => self.move_to(term, self.line.len()),
+            Key::Ctrl('k') => self.kill(term, self.cursor, self.line.len()),
+            Key::Ctrl('u') => self.kill(term, 0, self.cursor),
+            Key::Ctrl('w') => {
+                // Back over spaces, then over the word before them
+                let mut start = self.cursor;
+                while start > 0 && self.line[start - 1] == ' ' {
+                    start -= 1;
+                }
+                while start > 0 && self.line[start - 1] != ' ' {
+                    start -= 1;
+                }
+                self.kill(term, start, self.cursor);
+            },
+            Key::Ctrl('y') => {
+                let yank = self.yank.clone();
+                self.insert(term, &yank);
+            },
+            Key::Enter => {
+                self.move_to(term, self.line.len());
+                return Outcome::Submit;
+            },
+            Key::Up => {
+                let index = match self.browse {
+                    Some(i) => i.min(history.len()).saturating_sub(1),
+                    None => {
+                        self.draft = self.line.clone();
+                        history.len().wrapping_sub(1)
+                    },
+                };
+                if let Some(entry) = history.entries().get(index) {
+                    self.browse = Some(index);
+                    self.set_text(term, entry);
+                }
+            },
+            Key::Down => {
+                if let Some(i) = self.browse {
+                    match history.entries().get(i + 1) {
+                        Some(entry) => {
+                            self.browse = Some(i + 1);
+                            self.set_text(term, entry);
+                        },
+                        None => {
+                            self.browse = None;
+                            let draft: String = self.draft.iter().collect();
+                            self.set_text(term, &draft);
+                        },
+                    }
+                }
+            },
+            Key::Ctrl('r') => {
+                let search = Search { query: String::new(), found: None, failed: false, original: self.line.clone() };
+                self.show_search(term, &search, history);
+                self.search = Some(search);
+            },
+            Key::Tab => return Outcome::Complete,
+            Key::Ctrl(_) => return Outcome::Ignored,
+        }
+        Outcome::Edited
+    }
+
+    // Replace the whole line, leaving the cursor at its end
+    pub fn set_text(&mut self, term: &mut Terminal, text: &str) {
+        let origin = self.origin(term);
+        let old_len = self.line.len();
+        self.line = text.chars().collect();
+        self.cursor = self.line.len();
+        self.refresh(term, origin, 0, old_len.saturating_sub(self.line.len()));
+    }
+
+    // Draw the whole line again, after the prompt was written somewhere new
+    pub fn redraw(&mut self, term: &mut Terminal) {
+        let origin = term.cursor_y * term.cols + term.cursor_x;
+        self.refresh(term, origin, 0, 0);
+    }
+
+    // The line up to the cursor, whose last word is the one `complete` completes
+    pub fn before_cursor(&self) -> String {
+        self.line[..self.cursor].iter().collect()
+    }
+
+    // How many chars before the cursor `candidates` all start with. The shell knows
+    // where the word ends up beginning (quotes may hold spaces), so its candidates
+    // repeat it as typed.
+    fn typed_len(&self, candidates: &[String]) -> usize {
+        let before = &self.line[..self.cursor];
+        (0..=before.len()).rev()
+            .find(|&n| {
+                let typed = &before[before.len() - n..];
+                candidates.iter().all(|c| c.chars().take(n).eq(typed.iter().copied()))
+            })
+            .unwrap_or(0)
+    }
+
+    // Complete the word before the cursor from `candidates`, the whole words it may
+    // become as they would be typed. A single one is taken with a space after it
+    // (unless it is a directory), several are taken as far as they agree. Returns
+    // true when that didn't get any further and the candidates were listed below the
+    // line instead: the shell then writes its prompt and calls `redraw`.
+    pub fn complete(&mut self, term: &mut Terminal, candidates: &[String]) -> bool {
+        let word_len = self.typed_len(candidates);
+        let mut completion: Vec<char> = match candidates {
+            [] => return false,
+            [only] => {
+                let mut completion: Vec<char> = only.chars().collect();
+                if !only.ends_with('/') {
+                    completion.push(' ');
+                }
+                completion
+            },
+            [first, rest @ ..] => {
+                let mut prefix: Vec<char> = first.chars().collect();
+                for candidate in rest {
+                    let common = prefix.iter().zip(candidate.chars()).take_while(|(a, b)| **a == *b).count();
+                    prefix.truncate(common);
+                }
+                prefix
+            },
+        };
+
+        if completion.len() > word_len {
+            // Only what comes after the typed part is inserted, the rest stays as typed
+            let added = completion.split_off(word_len);
+            self.insert(term, &added);
+            return false;
+        }
+        if candidates.len() == 1 {
+            return false;
+        }
+
+        self.move_to(term, self.line.len());
+        term.write_char('\n');
+        let width = candidates.iter().map(|c| c.chars().count()).max().unwrap_or(0) + 2;
+        let per_line = (term.cols / width).max(1);
+        for (i, candidate) in candidates.iter().enumerate() {
+            term.write_str(candidate);
+            if i % per_line == per_line - 1 || i == candidates.len() - 1 {
+                term.write_char('\n');
+            } else {
+                for _ in candidate.chars().count()..width {
+                    term.write_char(' ');
+                }
+            }
+        }
+        true
+    }
+
+    fn show_search(&mut self, term: &mut Terminal, search: &Search, history: &History) {
+        let found = search.found.and_then(|i| history.entries().get(i)).map_or("", |e| e.as_str());
+        let failed = if search.failed { "failed " } else { "" };
+        let text = format!("({}reverse-i-search)`{}': {}", failed, search.query, found);
+        self.set_text(term, &text);
+    }
+
+    fn insert(&mut self, term: &mut Terminal, chars: &[char]) {
+        let origin = self.origin(term);
+        let at = self.cursor;
+        self.line.splice(at..at, chars.iter().copied());
+        self.cursor += chars.len();
+        self.refresh(term, origin, at, 0);
+    }
+
+    fn kill(&mut self, term: &mut Terminal, start: usize, end: usize) {
+        if start == end {
+            return;
+        }
+        let origin = self.origin(term);
+        self.yank = self.line.drain(start..end).collect();
+        self.cursor = start;
+        self.refresh(term, origin, start, end - start);
+    }
+
+    fn move_to(&mut self, term: &mut Terminal, cursor: usize) {
+        let origin = self.origin(term);
+        self.cursor = cursor;
+        place(term, origin + cursor);
+    }
+
+    // Screen position (row * cols + col) of the start of the line
+    fn origin(&self, term: &Terminal) -> usize {
+        (term.cursor_y * term.cols + term.cursor_x).saturating_sub(self.cursor)
+    }
+
+    // Rewrite the line from `from` on, blank the `erased` cells after its end and put
+    // the terminal cursor back at the editor's. Writing may scroll the screen, so the
+    // origin is worked out again from where the line ends up.
+    fn refresh(&mut self, term: &mut Terminal, origin: usize, from: usize, erased: usize) {
+        place(term, origin + from);
+        for &c in &self.line[from..] {
+            term.write_char(c);
+        }
+        let end = term.cursor_y * term.cols + term.cursor_x;
+        for cell in term.buffer.iter_mut().skip(end).take(erased) {
+            cell.c = ' ';
+        }
+        place(term, end.saturating_sub(self.line.len()) + self.cursor);
+    }
+}
+
+// Move the terminal cursor to a screen position. The cell after the bottom right
+// corner is kept as the pending wrap at the end of the last row.
+fn place(term: &mut Terminal, pos: usize) {
+    let (x, y) = (pos % term.cols, pos / term.cols);
+    if y < term.rows {
+        term.cursor_x = x;
+        term.cursor_y = y;
+    } else {
+        term.cursor_x = term.cols;
+        term.cursor_y = term.rows - 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    // Feed `keys` to an editor on a fresh 20x4 terminal
+    fn type_keys(editor: &mut LineEditor, term: &mut Terminal, history: &History, keys: &[Key]) {
+        for &key in keys {
+            editor.handle(key, term, history);
+        }
+    }
+
+    fn chars(text: &str) -> Vec<Key> {
+        text.chars().map(Key::Char).collect()
+    }
+
+    fn row(term: &Terminal, y: usize) -> String {
+        let line: String = term.buffer[y * term.cols..(y + 1) * term.cols].iter().map(|c| c.c).collect();
+        line.trim_end().to_string()
+    }
+
+    #[test]
+    fn decodes_keys() {
+        assert_eq!(Key::from_code(KEY_TAB), Some(Key::Tab));
+        assert_eq!(Key::from_code(KEY_CTRL | 'r' as u32), Some(Key::Ctrl('r')));
+        assert_eq!(Key::from_code('x' as u32), Some(Key::Char('x')));
+        assert_eq!(Key::from_code(0x1b), None);
+        assert_eq!(Key::from_name("Ctrl+R"), Some(Key::Ctrl('r')));
+        assert_eq!(Key::from_name("ArrowLeft"), Some(Key::Left));
+        assert_eq!(Key::from_name("Shift"), None);
+    }
+
+    #[test]
+    fn edits_in_the_middle_of_the_line() {
+        let (mut editor, mut term, history) = (LineEditor::new(), Terminal::new(20, 4), History::new());
+        type_keys(&mut editor, &mut term, &history, &chars("hllo wrld"));
+        type_keys(&mut editor, &mut term, &history, &[Key::Home, Key::Right, Key::Char('e'), Key::End]);
+        type_keys(&mut editor, &mut term, &history, &[Key::Left, Key::Left, Key::Left, Key::Char('o'), Key::Delete]);
+        assert_eq!(editor.text(), "hello wold");
+        assert_eq!(row(&term, 0), "hello wold");
+        assert_eq!((term.cursor_x, editor.before_cursor().as_str()), (8, "hello wo"));
+
+        type_keys(&mut editor, &mut term, &history, &[Key::Char('r'), Key::End, Key::Backspace, Key::Backspace]);
+        assert_eq!(row(&term, 0), "hello wor");
+        assert_eq!(editor.handle(Key::Enter, &mut term, &history), Outcome::Submit);
+        assert_eq!(editor.take(), "hello wor");
+        assert!(editor.is_empty());
+    }
+
+    #[test]
+    fn wraps_long_lines() {
+        let (mut editor, mut term, history) = (LineEditor::new(), Terminal::new(20, 4), History::new());
+        type_keys(&mut editor, &mut term, &history, &chars("0123456789abcdefghijKLM"));
+        type_keys(&mut editor, &mut term, &history, &[Key::Home, Key::Delete]);
+        assert_eq!(row(&term, 0), "123456789abcdefghijK");
+        assert_eq!(row(&term, 1), "LM");
+        assert_eq!((term.cursor_x, term.cursor_y), (0, 0));
+    }
+
+    #[test]
+    fn kills_and_yanks() {
+        let (mut editor, mut term, history) = (LineEditor::new(), Terminal::new(20, 4), History::new());
+        type_keys(&mut editor, &mut term, &history, &chars("echo one two  "));
+        type_keys(&mut editor, &mut term, &history, &[Key::Ctrl('w')]);
+        assert_eq!(editor.text(), "echo one ");
+        type_keys(&mut editor, &mut term, &history, &[Key::Ctrl('a'), Key::Ctrl('y')]);
+        assert_eq!(editor.text(), "two  echo one ");
+        type_keys(&mut editor, &mut term, &history, &[Key::Ctrl('k')]);
+        assert_eq!(row(&term, 0), "two");
+        type_keys(&mut editor, &mut term, &history, &[Key::Ctrl('u')]);
+        assert_eq!(row(&term, 0), "");
+        assert_eq!(editor.handle(Key::Ctrl('z'), &mut term, &history), Outcome::Ignored);
+    }
+
+    #[test]
+    fn walks_and_searches_history() {
+        let (mut editor, mut term) = (LineEditor::new(), Terminal::new(40, 4));
+        let history = History::from_text("ls /bin\necho hi\ncat notes\n");
+        type_keys(&mut editor, &mut term, &history, &chars("draft"));
+        type_keys(&mut editor, &mut term, &history, &[Key::Up, Key::Up]);
+        assert_eq!(editor.text(), "echo hi");
+        type_keys(&mut editor, &mut term, &history, &[Key::Up, Key::Up, Key::Down]);
+        assert_eq!(editor.text(), "echo hi");
+        type_keys(&mut editor, &mut term, &history, &[Key::Down, Key::Down]);
+        assert_eq!(row(&term, 0), "draft");
+
+        type_keys(&mut editor, &mut term, &history, &[Key::Ctrl('r'), Key::Char('s')]);
+        assert_eq!(row(&term, 0), "(reverse-i-search)`s': cat notes");
+        type_keys(&mut editor, &mut term, &history, &[Key::Ctrl('r')]);
+        assert_eq!(row(&term, 0), "(reverse-i-search)`s': ls /bin");
+        type_keys(&mut editor, &mut term, &history, &[Key::Char('x')]);
+        assert_eq!(row(&term, 0), "(failed reverse-i-search)`sx': ls /bin");
+        // Editing the query searches from the newest entry again
+        type_keys(&mut editor, &mut term, &history, &[Key::Backspace, Key::End]);
+        assert_eq!(editor.text(), "cat notes");
+
+        type_keys(&mut editor, &mut term, &history, &[Key::Ctrl('r'), Key::Char('l'), Key::Ctrl('g')]);
+        assert_eq!(editor.text(), "cat notes");
+    }
+
+    #[test]
+    fn completes_the_word_as_typed() {
+        let (mut editor, mut term, history) = (LineEditor::new(), Terminal::new(40, 6), History::new());
+        type_keys(&mut editor, &mut term, &history, &chars("cat \"my fi"));
+        assert_eq!(editor.handle(Key::Tab, &mut term, &history), Outcome::Complete);
+        assert!(!editor.complete(&mut term, &["\"my file.txt\"".to_string()]));
+        assert_eq!(editor.text(), "cat \"my file.txt\" ");
+
+        // Directories take no space, and the rest of the line stays put
+        editor.set_text(&mut term, "cd do x");
+        type_keys(&mut editor, &mut term, &history, &[Key::Left, Key::Left]);
+        assert!(!editor.complete(&mut term, &["docs/".to_string()]));
+        assert_eq!(row(&term, 0), "cd docs/ x");
+
+        // Several are taken as far as they agree, then listed
+        editor.set_text(&mut term, "ls a");
+        let candidates = vec!["ab\\ c".to_string(), "ab\\ d".to_string()];
+        assert!(!editor.complete(&mut term, &candidates));
+        assert_eq!(editor.text(), "ls ab\\ ");
+        assert!(editor.complete(&mut term, &candidates));
+        assert_eq!(row(&term, 1), "ab\\ c  ab\\ d");
+        assert!(!editor.complete(&mut term, &[]));
+    }
+}
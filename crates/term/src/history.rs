@@ -0,0 +1,219 @@
+//! Command history shared by the shells.
+//!
+//! Entries are kept oldest first and numbered from 1, as `history` lists them. The
+//! shells store them one per line in `HISTORY_FILE`; this module only deals with
+//! the text, so it works the same from the kernel and from a guest.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+pub const HISTORY_FILE: &str = "/local/user/.history";
+
+// Entries kept unless `set_limit` says otherwise, the oldest go first
+pub const DEFAULT_LIMIT: usize = 500;
+
+pub struct History {
+    entries: Vec<String>,
+    limit: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self { entries: Vec::new(), limit: DEFAULT_LIMIT }
+    }
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // One entry per line, as `to_text` writes them
+    pub fn from_text(text: &str) -> Self {
+        let mut history = Self::new();
+        for line in text.lines() {
+            history.push(line);
+        }
+        history
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for entry in &self.entries {
+            text.push_str(entry);
+            text.push('\n');
+        }
+        text
+    }
+
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        self.trim();
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Entry `n`, counting from 1
+    pub fn get(&self, n: usize) -> Option<&str> {
+        n.checked_sub(1).and_then(|i| self.entries.get(i)).map(|e| e.as_str())
+    }
+
+    // Add a line unless it is blank or repeats the last entry. Returns whether it
+    // was added.
+    pub fn push(&mut self, line: &str) -> bool {
+        let line = line.trim();
+        if line.is_empty() || self.entries.last().map(|e| e.as_str()) == Some(line) {
+            return false;
+        }
+        self.entries.push(line.to_string());
+        self.trim();
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn trim(&mut self) {
+        if self.entries.len() > self.limit {
+            let extra = self.entries.len() - self.limit;
+            self.entries.drain(..extra);
+        }
+    }
+
+    // Replace `!!` (the last entry), `!n` (entry n) and `!-n` (the n-th last) in a
+    // line. Returns `None` if there was nothing to expand. A `!` followed by anything
+    // else, or inside single quotes, stays as it is.
+    pub fn expand(&self, line: &str) -> Result<Option<String>, String> {
+        let chars: Vec<char> = line.chars().collect();
+        let mut out = String::new();
+        let mut expanded = false;
+        let mut quoted = false;
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            if c == '\'' {
+                quoted = !quoted;
+            }
+            if c != '!' || quoted {
+                out.push(c);
+                i += 1;
+                continue;
+            }
+
+            let (entry, len) = match chars.get(i + 1) {
+                Some('!') => (self.entries.last().map(|e| e.as_str()), 2),
+                Some(&next) if next.is_ascii_digit() || next == '-' => {
+                    let start = if next == '-' { i + 2 } else { i + 1 };
+                    let end = chars[start..].iter().position(|c| !c.is_ascii_digit()).map_or(chars.len(), |p| start + p);
+                    let digits: String = chars[start..end].iter().collect();
+                    let n = match digits.parse::<usize>() {
+                        Ok(n) => n,
+                        Err(_) => {
+                            // A lone "!-"
+                            out.push(c);
+                            i += 1;
+                            continue;
+                        },
+                    };
+                    let entry = if next == '-' {
+                        self.entries.len().checked_sub(n).and_then(|i| self.entries.get(i)).map(|e| e.as_str())
+                    } else {
+                        self.get(n)
+                    };
+                    (entry, end - i)
+                },
+                _ => {
+                    out.push(c);
+                    i += 1;
+                    continue;
+                },
+            };
+            match entry {
+                Some(entry) => out.push_str(entry),
+                None => {
+                    let event: String = chars[i..i + len].iter().collect();
+                    return Err(format!("{}: event not found", event));
+                },
+            }
+            expanded = true;
+            i += len;
+        }
+        Ok(if expanded { Some(out) } else { None })
+    }
+
+    // The newest entry containing `query` among the first `before` entries
+    pub fn search(&self, query: &str, before: usize) -> Option<usize> {
+        self.entries[..before.min(self.entries.len())].iter().rposition(|e| e.contains(query))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_blanks_and_repeats() {
+        let mut history = History::new();
+        assert!(history.push("  ls  "));
+        assert!(!history.push("ls"));
+        assert!(!history.push("   "));
+        assert!(history.push("pwd"));
+        assert!(history.push("ls"));
+        assert_eq!(history.entries(), ["ls", "pwd", "ls"]);
+        assert_eq!((history.get(1), history.get(3), history.get(0), history.get(4)), (Some("ls"), Some("ls"), None, None));
+    }
+
+    #[test]
+    fn keeps_the_newest_up_to_the_limit() {
+        let mut history = History::new();
+        for i in 0..DEFAULT_LIMIT + 5 {
+            history.push(&format!("echo {}", i));
+        }
+        assert_eq!(history.len(), DEFAULT_LIMIT);
+        assert_eq!(history.get(1), Some("echo 5"));
+        history.set_limit(2);
+        assert_eq!(history.entries(), [format!("echo {}", DEFAULT_LIMIT + 3), format!("echo {}", DEFAULT_LIMIT + 4)]);
+        history.clear();
+        assert!(history.is_empty());
+    }
+
+    #[test]
+    fn round_trips_through_text() {
+        let history = History::from_text("ls\n\nls\ncat a b\n");
+        assert_eq!(history.entries(), ["ls", "cat a b"]);
+        assert_eq!(History::from_text(&history.to_text()).entries(), history.entries());
+    }
+
+    #[test]
+    fn expands_events() {
+        let history = History::from_text("ls /bin\necho hi\ncat notes\n");
+        assert_eq!(history.expand("!! | wc"), Ok(Some("cat notes | wc".into())));
+        assert_eq!(history.expand("!1; !-2"), Ok(Some("ls /bin; echo hi".into())));
+        assert_eq!(history.expand("echo wow! !x '!!' !-"), Ok(None));
+        assert_eq!(history.expand("!4"), Err("!4: event not found".into()));
+        assert_eq!(history.expand("!-9 x"), Err("!-9: event not found".into()));
+        assert_eq!(History::new().expand("!!"), Err("!!: event not found".into()));
+    }
+
+    #[test]
+    fn searches_backwards() {
+        let history = History::from_text("ls /bin\necho hi\nls /tmp\n");
+        assert_eq!(history.search("ls", history.len()), Some(2));
+        assert_eq!(history.search("ls", 2), Some(0));
+        assert_eq!(history.search("ls", 0), None);
+        assert_eq!(history.search("nope", 3), None);
+        assert_eq!(history.search("ls", 99), Some(2));
+    }
+}
@@ -0,0 +1,721 @@
+//! Terminal emulator shared by the kernel console, the terminal app and the
+//! desktop's terminal windows.
+//!
+//! `Terminal` is a grid of cells fed through a VT100/xterm escape sequence parser
+//! (see `ansi`). It draws through a `Backend`, so the kernel can render straight
+//! to the GPU and guests through syscalls (the `syscalls` feature).
+//!
+//! Lines scrolled off the top of the main screen go to a scrollback ring buffer.
+//! `scroll_view` moves the viewport back through it, and any output snaps the
+//! view back to the bottom. Shells size it from `$SCROLLBACK` (`scrollback_lines`).
+//!
+//! `editor` has the line editor the shells read commands with, `history` the
+//! command history it walks through.
+
+#![no_std]
+
+extern crate alloc;
+
+pub mod ansi;
+pub mod editor;
+pub mod history;
+#[cfg(feature = "syscalls")]
+pub mod syscall;
+
+use alloc::collections::VecDeque;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use ansi::{Action, Color, Csi, Parser, Pen};
+
+// Cells are 8x16 pixels, glyphs 8x8 centered in them
+pub const CHAR_W: i32 = 8;
+pub const CHAR_H: i32 = 16;
+
+// Lines of scrollback kept unless `set_scrollback` says otherwise
+pub const DEFAULT_SCROLLBACK: usize = 1000;
+
+// Environment variable the shells read the scrollback size from
+pub const SCROLLBACK_VAR: &str = "SCROLLBACK";
+
+// Scrollback size for a value of `$SCROLLBACK`, the default when unset or not a number
+pub fn scrollback_lines(value: Option<&str>) -> usize {
+    value.and_then(|v| v.trim().parse().ok()).unwrap_or(DEFAULT_SCROLLBACK)
+}
+
+// Where a terminal draws. Colors are 0xRRGGBBAA.
+pub trait Backend {
+    fn fill_rect(&mut self, x: i32, y: i32, w: i32, h: i32, color: u32);
+    fn draw_char(&mut self, x: i32, y: i32, c: char, color: u32);
+}
+
+#[derive(Clone, Copy)]
+pub struct Cell {
+    pub c: char,
+    pub fg: u32,
+    pub bg: u32,
+}
+
+pub struct Terminal {
+    pub rows: usize,
+    pub cols: usize,
+    pub buffer: Vec<Cell>,
+    pub cursor_x: usize, // May be `cols` at the end of a line, until the next char wraps
+    pub cursor_y: usize,
+    pub cursor_visible: bool,
+    pub default_fg: u32,
+    pub default_bg: u32,
+    pen: Pen,
+    parser: Parser,
+    scroll_top: usize, // Scroll region, inclusive rows
+    scroll_bottom: usize,
+    saved_cursor: (usize, usize, Pen), // ESC 7 / CSI s
+    main_screen: Option<(Vec<Cell>, usize, usize)>, // Kept while the alternate screen is up
+    scrollback: VecDeque<Vec<Cell>>, // Oldest line first
+    scrollback_limit: usize,
+    view_offset: usize, // Lines the viewport is scrolled back, 0 at the bottom
+}
+
+impl Terminal {
+    pub fn new(cols: usize, rows: usize) -> Self {
+        let default_fg = 0xFF_FF_FF_FF; // White
+        let default_bg = 0x00_00_00_FF; // Black
+        let buffer = vec![Cell { c: ' ', fg: default_fg, bg: default_bg }; cols * rows];
+        Self {
+            rows,
+            cols,
+            buffer,
+            cursor_x: 0,
+            cursor_y: 0,
+            default_fg,
+            default_bg,
+            cursor_visible: true,
+            pen: Pen::new(),
+            parser: Parser::new(),
+            scroll_top: 0,
+            scroll_bottom: rows - 1,
+            saved_cursor: (0, 0, Pen::new()),
+            main_screen: None,
+            scrollback: VecDeque::new(),
+            scrollback_limit: DEFAULT_SCROLLBACK,
+            view_offset: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.cursor_x = 0;
+        self.cursor_y = 0;
+        self.view_offset = 0;
+        self.scroll_top = 0;
+        self.scroll_bottom = self.rows - 1;
+        let blank = self.blank();
+        self.buffer.fill(blank);
+    }
+
+    pub fn show_cursor(&mut self, visible: bool) {
+        self.cursor_visible = visible;
+    }
+
+    pub fn write_str(&mut self, s: &str) {
+        for c in s.chars() {
+            self.write_char(c);
+        }
+    }
+
+    // Feed one char through the escape sequence parser
+    pub fn write_char(&mut self, c: char) {
+        let action = match self.parser.advance(c) {
+            Some(action) => action,
+            None => return,
+        };
+        // New output is shown where it lands
+        self.view_offset = 0;
+        match action {
+            Action::Print(c) => self.print(c),
+            Action::Control(c) => self.control(c),
+            Action::Esc(c) => self.esc(c),
+            Action::Csi(csi) => self.csi(&csi),
+        }
+    }
+
+    // Keep at most `lines` of scrollback, 0 to keep none
+    pub fn set_scrollback(&mut self, lines: usize) {
+        self.scrollback_limit = lines;
+        while self.scrollback.len() > lines {
+            self.scrollback.pop_front();
+        }
+        self.view_offset = self.view_offset.min(self.scrollback.len());
+    }
+
+    // Move the viewport by `lines`: back into the scrollback when negative, towards
+    // the live screen when positive
+    pub fn scroll_view(&mut self, lines: i32) {
+        let offset = self.view_offset as i64 - lines as i64;
+        self.view_offset = offset.clamp(0, self.scrollback.len() as i64) as usize;
+    }
+
+    pub fn view_offset(&self) -> usize {
+        self.view_offset
+    }
+
+    // An empty cell in the current colors, for erasing
+    fn blank(&self) -> Cell {
+        let (fg, bg) = self.pen.colors(self.default_fg, self.default_bg);
+        Cell { c: ' ', fg, bg }
+    }
+
+    fn print(&mut self, c: char) {
+        if self.cursor_x >= self.cols {
+            self.cursor_x = 0;
+            self.line_feed();
+        }
+        let (fg, bg) = self.pen.colors(self.default_fg, self.default_bg);
+        let idx = self.cursor_y * self.cols + self.cursor_x;
+        self.buffer[idx] = Cell { c, fg, bg };
+        self.cursor_x += 1;
+    }
+
+    fn control(&mut self, c: char) {
+        match c {
+            '\n' => {
+                self.cursor_x = 0;
+                self.line_feed();
+            },
+            '\r' => self.cursor_x = 0,
+            '\t' => self.cursor_x = ((self.cursor_x / 8 + 1) * 8).min(self.cols - 1),
+            '\x08' => { // Backspace
+                if self.cursor_x > 0 {
+                    self.cursor_x -= 1;
+                } else if self.cursor_y > 0 {
+                    self.cursor_y -= 1;
+                    self.cursor_x = self.cols - 1;
+                }
+                // Clear the character
+                let idx = self.cursor_y * self.cols + self.cursor_x;
+                self.buffer[idx].c = ' ';
+            },
+            _ => {},
+        }
+    }
+
+    fn esc(&mut self, c: char) {
+        match c {
+            '7' => self.saved_cursor = (self.cursor_x, self.cursor_y, self.pen),
+            '8' => {
+                let (x, y, pen) = self.saved_cursor;
+                self.move_to(x, y);
+                self.pen = pen;
+            },
+            'D' => self.line_feed(),
+            'E' => {
+                self.cursor_x = 0;
+                self.line_feed();
+            },
+            'M' => self.reverse_line_feed(),
+            'c' => {
+                self.leave_alt_screen();
+                self.pen = Pen::new();
+                self.scrollback.clear();
+                self.reset();
+            },
+            _ => {},
+        }
+    }
+
+    fn csi(&mut self, csi: &Csi) {
+        if csi.intermediate.is_some() {
+            return;
+        }
+        if csi.prefix == Some('?') {
+            let set = match csi.final_char {
+                'h' => true,
+                'l' => false,
+                _ => return,
+            };
+            for mode in &csi.params {
+                match mode {
+                    25 => self.cursor_visible = set,
+                    47 | 1047 | 1049 if set => self.enter_alt_screen(),
+                    47 | 1047 | 1049 => self.leave_alt_screen(),
+                    _ => {},
+                }
+            }
+            return;
+        }
+        if csi.prefix.is_some() {
+            return;
+        }
+
+        let n = csi.param(0, 1) as usize;
+        let (x, y) = (self.cursor_x.min(self.cols - 1), self.cursor_y);
+        match csi.final_char {
+            'A' => self.move_to(x, y.saturating_sub(n)),
+            'B' => self.move_to(x, y + n),
+            'C' => self.move_to(x + n, y),
+            'D' => self.move_to(x.saturating_sub(n), y),
+            'E' => self.move_to(0, y + n),
+            'F' => self.move_to(0, y.saturating_sub(n)),
+            'G' | '`' => self.move_to(n - 1, y),
+            'd' => self.move_to(x, n - 1),
+            'H' | 'f' => self.move_to(csi.param(1, 1) as usize - 1, n - 1),
+            'J' => {
+                let start = y * self.cols + x;
+                match csi.param(0, 0) {
+                    0 => self.erase(start, self.buffer.len()),
+                    1 => self.erase(0, start + 1),
+                    2 => self.erase(0, self.buffer.len()),
+                    _ => self.scrollback.clear(),
+                }
+            },
+            'K' => {
+                let line = y * self.cols;
+                match csi.param(0, 0) {
+                    0 => self.erase(line + x, line + self.cols),
+                    1 => self.erase(line, line + x + 1),
+                    _ => self.erase(line, line + self.cols),
+                }
+            },
+            'X' => {
+                let start = y * self.cols + x;
+                self.erase(start, start + n.min(self.cols - x));
+            },
+            '@' | 'P' => {
+                // Insert or delete chars, shifting the rest of the line
+                let line = y * self.cols;
+                let n = n.min(self.cols - x);
+                let row = &mut self.buffer[line + x..line + self.cols];
+                if csi.final_char == '@' {
+                    row.rotate_right(n);
+                    self.erase(line + x, line + x + n);
+                } else {
+                    row.rotate_left(n);
+                    self.erase(line + self.cols - n, line + self.cols);
+                }
+            },
+            // Lines are inserted and deleted inside the scroll region only
+            'L' if (self.scroll_top..=self.scroll_bottom).contains(&y) => {
+                self.scroll_down(y, self.scroll_bottom, n);
+                self.cursor_x = 0;
+            },
+            'M' if (self.scroll_top..=self.scroll_bottom).contains(&y) => {
+                self.scroll_up(y, self.scroll_bottom, n);
+                self.cursor_x = 0;
+            },
+            'S' => self.scroll_up(self.scroll_top, self.scroll_bottom, n),
+            'T' => self.scroll_down(self.scroll_top, self.scroll_bottom, n),
+            'r' => {
+                let top = csi.param(0, 1) as usize - 1;
+                let bottom = (csi.param(1, self.rows as u16) as usize).min(self.rows) - 1;
+                if top < bottom {
+                    self.scroll_top = top;
+                    self.scroll_bottom = bottom;
+                    self.move_to(0, 0);
+                }
+            },
+            's' => self.saved_cursor = (self.cursor_x, self.cursor_y, self.pen),
+            'u' => {
+                let (x, y, _) = self.saved_cursor;
+                self.move_to(x, y);
+            },
+            'm' => self.pen.apply_sgr(&csi.params),
+            _ => {},
+        }
+    }
+
+    fn move_to(&mut self, x: usize, y: usize) {
+        self.cursor_x = x.min(self.cols - 1);
+        self.cursor_y = y.min(self.rows - 1);
+    }
+
+    // Blank the cells in `start..end` of the buffer
+    fn erase(&mut self, start: usize, end: usize) {
+        let blank = self.blank();
+        let end = end.min(self.buffer.len());
+        if start < end {
+            self.buffer[start..end].fill(blank);
+        }
+    }
+
+    // Down a line, scrolling when the cursor is at the bottom of the scroll region
+    fn line_feed(&mut self) {
+        if self.cursor_y == self.scroll_bottom {
+            // Only lines leaving the whole main screen are worth keeping
+            if self.scroll_top == 0 && self.scroll_bottom == self.rows - 1 && self.main_screen.is_none() {
+                self.push_scrollback();
+            }
+            self.scroll_up(self.scroll_top, self.scroll_bottom, 1);
+        } else if self.cursor_y < self.rows - 1 {
+            self.cursor_y += 1;
+        }
+    }
+
+    fn push_scrollback(&mut self) {
+        if self.scrollback_limit == 0 {
+            return;
+        }
+        if self.scrollback.len() == self.scrollback_limit {
+            self.scrollback.pop_front();
+        }
+        self.scrollback.push_back(self.buffer[..self.cols].to_vec());
+    }
+
+    fn reverse_line_feed(&mut self) {
+        if self.cursor_y == self.scroll_top {
+            self.scroll_down(self.scroll_top, self.scroll_bottom, 1);
+        } else if self.cursor_y > 0 {
+            self.cursor_y -= 1;
+        }
+    }
+
+    // Move rows `top..=bottom` up by `n`, blanking the rows that open up at the bottom
+    fn scroll_up(&mut self, top: usize, bottom: usize, n: usize) {
+        let n = n.min(bottom + 1 - top);
+        let (start, end) = (top * self.cols, (bottom + 1) * self.cols);
+        self.buffer[start..end].rotate_left(n * self.cols);
+        self.erase(end - n * self.cols, end);
+    }
+
+    fn scroll_down(&mut self, top: usize, bottom: usize, n: usize) {
+        let n = n.min(bottom + 1 - top);
+        let (start, end) = (top * self.cols, (bottom + 1) * self.cols);
+        self.buffer[start..end].rotate_right(n * self.cols);
+        self.erase(start, start + n * self.cols);
+    }
+
+    // Full screen programs draw on a screen of their own, the shell's comes back after
+    fn enter_alt_screen(&mut self) {
+        if self.main_screen.is_none() {
+            let blank = self.blank();
+            let main = core::mem::replace(&mut self.buffer, vec![blank; self.cols * self.rows]);
+            self.main_screen = Some((main, self.cursor_x, self.cursor_y));
+            self.move_to(0, 0);
+        }
+    }
+
+    fn leave_alt_screen(&mut self) {
+        if let Some((main, x, y)) = self.main_screen.take() {
+            self.buffer = main;
+            self.cursor_x = x;
+            self.cursor_y = y;
+        }
+    }
+
+    // Out-of-band color changes, the same as a truecolor SGR
+    pub fn set_fg_color(&mut self, color: u32) {
+        self.pen.fg = Color::Rgb(color);
+    }
+
+    pub fn set_bg_color(&mut self, color: u32) {
+        self.pen.bg = Color::Rgb(color);
+    }
+
+    pub fn render<B: Backend>(&self, out: &mut B, offset_x: i32, offset_y: i32) {
+        // Scrolled back, the top rows come from the scrollback
+        let history = self.scrollback.len() - self.view_offset;
+        for y in 0..self.rows {
+            let row = match y.checked_sub(self.view_offset) {
+                Some(live) => &self.buffer[live * self.cols..(live + 1) * self.cols],
+                None => &self.scrollback[history + y][..],
+            };
+            for (x, cell) in row.iter().enumerate() {
+                let draw_x = offset_x + x as i32 * CHAR_W;
+                let draw_y = offset_y + y as i32 * CHAR_H;
+
+                // Draw background only if opaque
+                if (cell.bg & 0xFF) != 0 {
+                    out.fill_rect(draw_x, draw_y, CHAR_W, CHAR_H, cell.bg);
+                }
+                // Draw char centered vertically in the 16px cell (offset +4)
+                if cell.c != ' ' {
+                    out.draw_char(draw_x, draw_y + 4, cell.c, cell.fg);
+                }
+            }
+        }
+
+        // Vertical bar cursor: 4px wide, 14px high, leaving 1px top and bottom
+        if self.cursor_visible && self.cursor_y + self.view_offset < self.rows {
+            let cx = offset_x + self.cursor_x as i32 * CHAR_W;
+            let cy = offset_y + (self.cursor_y + self.view_offset) as i32 * CHAR_H;
+            out.fill_rect(cx, cy + 1, 4, 14, 0xFF_FF_FF_FF);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+
+    // The screen as text, one trimmed line per row
+    fn lines(term: &Terminal) -> Vec<String> {
+        term.buffer.chunks(term.cols)
+            .map(|row| row.iter().map(|cell| cell.c).collect::<String>().trim_end().into())
+            .collect()
+    }
+
+    // Records what `render` draws
+    #[derive(Default)]
+    struct Recorder {
+        chars: Vec<(i32, i32, char)>,
+        rects: Vec<(i32, i32, i32, i32, u32)>,
+    }
+
+    impl Backend for Recorder {
+        fn fill_rect(&mut self, x: i32, y: i32, w: i32, h: i32, color: u32) {
+            self.rects.push((x, y, w, h, color));
+        }
+
+        fn draw_char(&mut self, x: i32, y: i32, c: char, _color: u32) {
+            self.chars.push((x, y, c));
+        }
+    }
+
+    #[test]
+    fn wraps_at_the_last_column() {
+        let mut term = Terminal::new(4, 3);
+        term.write_str("abcd");
+        // The cursor waits past the end until the next char
+        assert_eq!((term.cursor_x, term.cursor_y), (4, 0));
+        term.write_str("ef\tg");
+        assert_eq!(lines(&term), ["abcd", "ef g", ""]);
+        term.write_str("\r\x08\x08x");
+        assert_eq!(lines(&term), ["abx", "ef g", ""]);
+    }
+
+    #[test]
+    fn saves_the_cursor_and_pen() {
+        let mut term = Terminal::new(10, 3);
+        term.write_str("\x1b[2;3H\x1b[32m\x1b7\x1b[0m\x1b[H\x1b8a");
+        assert_eq!((term.cursor_x, term.cursor_y), (3, 1));
+        assert_eq!(term.buffer[12].fg, ansi::palette(2));
+        term.write_str("\x1b[1;5H\x1b[s\x1b[3;1H\x1b[ub");
+        assert_eq!(lines(&term), ["    b", "  a", ""]);
+    }
+
+    #[test]
+    fn erases_and_resets() {
+        let mut term = Terminal::new(4, 3);
+        term.write_str("abcd\nefgh\nijkl\x1b[2;2H\x1b[K");
+        assert_eq!(lines(&term), ["abcd", "e", "ijkl"]);
+        term.write_str("\x1b[1K\x1b[1J");
+        assert_eq!(lines(&term), ["", "", "ijkl"]);
+        term.write_str("\x1b[31m\x1b[?25l\x1bc");
+        assert_eq!(lines(&term), ["", "", ""]);
+        assert_eq!((term.cursor_x, term.cursor_y), (0, 0));
+        term.write_str("x");
+        assert_eq!(term.buffer[0].fg, term.default_fg);
+    }
+
+    #[test]
+    fn renders_cells_and_cursor() {
+        let mut term = Terminal::new(4, 2);
+        term.write_str("a\x1b[41mb");
+        let mut out = Recorder::default();
+        term.render(&mut out, 100, 10);
+        assert_eq!(out.chars, [(100, 14, 'a'), (108, 14, 'b')]);
+        assert!(out.rects.contains(&(108, 10, CHAR_W, CHAR_H, ansi::palette(1))));
+        assert_eq!(out.rects.last(), Some(&(116, 11, 4, 14, 0xFFFFFFFF)));
+
+        term.show_cursor(false);
+        let mut out = Recorder::default();
+        term.render(&mut out, 0, 0);
+        assert_eq!(out.rects.len(), 8);
+    }
+
+    #[test]
+    fn applies_sequences_split_across_writes() {
+        let mut term = Terminal::new(10, 3);
+        term.write_str("\x1b[3");
+        term.write_str("1mA\x1b[");
+        term.write_str("0mB");
+        assert_eq!(lines(&term)[0], "AB");
+        assert_eq!(term.buffer[0].fg, ansi::palette(1));
+        assert_eq!(term.buffer[1].fg, term.default_fg);
+    }
+
+    #[test]
+    fn colors_cells() {
+        let mut term = Terminal::new(10, 3);
+        term.write_str("\x1b[38;5;21ma\x1b[38;2;1;2;3mb\x1b[38;2;9mc\x1b[1;34md");
+        let fg: Vec<u32> = term.buffer[..4].iter().map(|cell| cell.fg).collect();
+        assert_eq!(fg, [ansi::palette(21), 0x010203FF, 0x010203FF, ansi::palette(12)]);
+
+        // Erasing uses the background of the pen
+        term.write_str("\x1b[44m\x1b[2K");
+        assert!(term.buffer[..10].iter().all(|cell| cell.c == ' ' && cell.bg == ansi::palette(4)));
+    }
+
+    #[test]
+    fn clamps_cursor_movement_to_the_screen() {
+        let mut term = Terminal::new(10, 5);
+        term.write_str("\x1b[99;99H");
+        assert_eq!((term.cursor_x, term.cursor_y), (9, 4));
+        term.write_str("\x1b[0;0H");
+        assert_eq!((term.cursor_x, term.cursor_y), (0, 0));
+        term.write_str("\x1b[3;4H\x1b[9A");
+        assert_eq!((term.cursor_x, term.cursor_y), (3, 0));
+        term.write_str("\x1b[20C\x1b[20B");
+        assert_eq!((term.cursor_x, term.cursor_y), (9, 4));
+        term.write_str("\x1b[20D\x1b[2A");
+        assert_eq!((term.cursor_x, term.cursor_y), (0, 2));
+    }
+
+    #[test]
+    fn scrolls_only_the_region() {
+        let mut term = Terminal::new(10, 5);
+        term.write_str("0\n1\n2\n3\n4");
+        term.write_str("\x1b[2;4r");
+        assert_eq!((term.cursor_x, term.cursor_y), (0, 0));
+        term.write_str("\x1b[4;1H\nx");
+        assert_eq!(lines(&term), ["0", "2", "3", "x", "4"]);
+
+        // Reverse index at the top of the region scrolls it down
+        term.write_str("\x1b[2;1H\x1bM");
+        assert_eq!(lines(&term), ["0", "", "2", "3", "4"]);
+
+        // The whole screen again
+        term.write_str("\x1b[r\x1b[5;1H\n");
+        assert_eq!(lines(&term), ["", "2", "3", "4", ""]);
+    }
+
+    #[test]
+    fn restores_the_main_screen() {
+        let mut term = Terminal::new(10, 3);
+        term.write_str("main\n$ ls");
+        term.write_str("\x1b[?1049h");
+        assert_eq!(lines(&term), ["", "", ""]);
+        assert_eq!((term.cursor_x, term.cursor_y), (0, 0));
+        term.write_str("\x1b[2;3Halt\x1b[?1049h");
+        assert_eq!(lines(&term), ["", "  alt", ""]);
+        term.write_str("\x1b[?1049l");
+        assert_eq!(lines(&term), ["main", "$ ls", ""]);
+        assert_eq!((term.cursor_x, term.cursor_y), (4, 1));
+    }
+
+    #[test]
+    fn edits_the_last_column() {
+        let mut term = Terminal::new(5, 2);
+        term.write_str("abcde\x1b[@");
+        assert_eq!(lines(&term)[0], "abcd");
+        term.write_str("\x1b[Hvwxyz\x1b[3P");
+        assert_eq!(lines(&term)[0], "vwxy");
+        term.write_str("\x1b[Habcde\x1b[9X");
+        assert_eq!(lines(&term)[0], "abcd");
+
+        // Anywhere else the rest of the line shifts
+        term.write_str("\x1b[2G\x1b[2@");
+        assert_eq!(lines(&term)[0], "a  bc");
+        term.write_str("\x1b[P");
+        assert_eq!(lines(&term)[0], "a bc");
+        assert_eq!(lines(&term)[1], "");
+    }
+
+    // `n` numbered lines, each ending in a line feed
+    fn feed(term: &mut Terminal, from: usize, n: usize) {
+        for i in from..from + n {
+            term.write_str(&alloc::format!("{}\n", i));
+        }
+    }
+
+    fn history(term: &Terminal) -> Vec<String> {
+        term.scrollback.iter().map(|row| row.iter().map(|cell| cell.c).collect::<String>().trim_end().into()).collect()
+    }
+
+    #[test]
+    fn drops_the_oldest_lines_past_the_limit() {
+        let mut term = Terminal::new(4, 2);
+        term.set_scrollback(3);
+        feed(&mut term, 0, 6);
+        assert_eq!(history(&term), ["2", "3", "4"]);
+        term.set_scrollback(1);
+        assert_eq!(history(&term), ["4"]);
+        term.set_scrollback(0);
+        feed(&mut term, 6, 2);
+        assert!(history(&term).is_empty());
+    }
+
+    #[test]
+    fn clamps_the_view() {
+        let mut term = Terminal::new(4, 2);
+        feed(&mut term, 0, 4);
+        assert_eq!(history(&term), ["0", "1", "2"]);
+        term.scroll_view(-2);
+        assert_eq!(term.view_offset(), 2);
+        term.scroll_view(-10);
+        assert_eq!(term.view_offset(), 3);
+        term.scroll_view(1);
+        assert_eq!(term.view_offset(), 2);
+        term.scroll_view(10);
+        assert_eq!(term.view_offset(), 0);
+
+        // Shrinking the scrollback pulls the view in with it
+        term.scroll_view(-3);
+        term.set_scrollback(1);
+        assert_eq!(term.view_offset(), 1);
+    }
+
+    #[test]
+    fn output_snaps_the_view_to_the_bottom() {
+        let mut term = Terminal::new(4, 2);
+        feed(&mut term, 0, 4);
+        term.scroll_view(-2);
+        // Half a sequence is not output yet
+        term.write_str("\x1b[");
+        assert_eq!(term.view_offset(), 2);
+        term.write_str("m");
+        assert_eq!(term.view_offset(), 0);
+    }
+
+    #[test]
+    fn keeps_only_lines_leaving_the_main_screen() {
+        let mut term = Terminal::new(4, 3);
+        term.write_str("\x1b[?1049h");
+        feed(&mut term, 0, 5);
+        term.write_str("\x1b[?1049l");
+        assert!(history(&term).is_empty());
+
+        for region in ["\x1b[2;3r", "\x1b[1;2r"] {
+            term.write_str(region);
+            term.write_str("\x1b[3;1H");
+            feed(&mut term, 0, 5);
+            assert!(history(&term).is_empty());
+        }
+
+        term.write_str("\x1b[r\x1b[3;1H");
+        feed(&mut term, 0, 1);
+        assert_eq!(history(&term).len(), 1);
+    }
+
+    #[test]
+    fn renders_history_rows() {
+        let mut term = Terminal::new(4, 3);
+        feed(&mut term, 0, 4);
+        term.write_str("\x1b[H");
+        term.scroll_view(-1);
+        let mut out = Recorder::default();
+        term.render(&mut out, 0, 0);
+        // The newest history line on top, the screen and its cursor a row further down
+        assert_eq!(out.chars, [(0, 4, '1'), (0, 20, '2'), (0, 36, '3')]);
+        assert_eq!(out.rects.last(), Some(&(0, 17, 4, 14, 0xFFFFFFFF)));
+
+        // All the way back, and once the cursor's row is off the bottom it is not drawn
+        term.scroll_view(-5);
+        let mut out = Recorder::default();
+        term.render(&mut out, 0, 0);
+        assert_eq!(out.chars, [(0, 4, '0'), (0, 20, '1'), (0, 36, '2')]);
+        assert_eq!(out.rects.last(), Some(&(0, 33, 4, 14, 0xFFFFFFFF)));
+        term.write_str("\x1b[3;1H");
+        term.scroll_view(-1);
+        let mut out = Recorder::default();
+        term.render(&mut out, 0, 0);
+        assert!(!out.rects.iter().any(|&(_, _, w, ..)| w == 4));
+    }
+
+    #[test]
+    fn reads_the_size_from_the_environment() {
+        assert_eq!(scrollback_lines(Some("50")), 50);
+        assert_eq!(scrollback_lines(Some(" 0 ")), 0);
+        assert_eq!(scrollback_lines(Some("lots")), DEFAULT_SCROLLBACK);
+        assert_eq!(scrollback_lines(None), DEFAULT_SCROLLBACK);
+    }
+}
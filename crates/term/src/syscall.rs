@@ -0,0 +1,20 @@
+//! Backend for guests, drawing through the kernel's syscalls.
+
+extern "C" {
+    fn sys_draw_rect(x: i32, y: i32, w: i32, h: i32, color: i32);
+    fn sys_draw_text(ptr: *const u8, len: i32, x: i32, y: i32, color: i32);
+}
+
+pub struct Screen;
+
+impl crate::Backend for Screen {
+    fn fill_rect(&mut self, x: i32, y: i32, w: i32, h: i32, color: u32) {
+        unsafe { sys_draw_rect(x, y, w, h, color as i32) }
+    }
+
+    fn draw_char(&mut self, x: i32, y: i32, c: char, color: u32) {
+        let mut buf = [0u8; 4];
+        let text = c.encode_utf8(&mut buf);
+        unsafe { sys_draw_text(text.as_ptr(), text.len() as i32, x, y, color as i32) }
+    }
+}
@@ -14,11 +14,24 @@
  * copies or substantial portions of the Software.
  */
 
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::host::Host;
+use crate::sys::fs::FileSystem;
+use crate::sys::vfs::MountStatus;
 use crate::term::Terminal;
 
+// How long POST waits for the persistent disk before booting without it
+const DISK_TIMEOUT_TICKS: u64 = 300;
+
+// Problems POST lists before summing up the rest
+const FSCK_SHOWN: usize = 5;
+
 pub enum BiosState {
     PowerOn,
     MemoryTest,
+    DiskCheck(u64), // Tick the disk load was first polled
     WaitingForInput,
     Setup,
     Booting,
@@ -29,38 +42,54 @@ pub struct Bios {
     state: BiosState,
     ticks: u64,
     pub boot_target: String,
+    pub check_disk: bool, // Run fsck (with repairs) as part of POST
+    host: Rc<dyn Host>,
 }
 
 impl Bios {
-    pub fn new() -> Self {
-        let mut target = "/bin/terminal.wasm".to_string();
-        
-        // Try to load from localStorage
-        if let Some(window) = web_sys::window() {
-            if let Ok(Some(storage)) = window.local_storage() {
-                if let Ok(Some(stored)) = storage.get_item("wasmix_boot_target") {
-                    target = stored;
-                }
-            }
-        }
+    pub fn new(host: Rc<dyn Host>) -> Self {
+        // Try to load from host storage
+        let target = host.storage_get("wasmix_boot_target")
+            .unwrap_or_else(|| "/bin/terminal.wasm".to_string());
+        let check_disk = host.storage_get("wasmix_check_disk").is_some_and(|v| v == "on");
 
         Self {
             state: BiosState::PowerOn,
             ticks: 0,
             boot_target: target,
+            check_disk,
+            host,
         }
     }
     
     fn save_boot_target(&self) {
-        if let Some(window) = web_sys::window() {
-            if let Ok(Some(storage)) = window.local_storage() {
-                let _ = storage.set_item("wasmix_boot_target", &self.boot_target);
-            }
+        self.host.storage_set("wasmix_boot_target", &self.boot_target);
+    }
+
+    fn save_check_disk(&self) {
+        self.host.storage_set("wasmix_check_disk", if self.check_disk { "on" } else { "off" });
+    }
+
+    // Optional POST step: check the filesystems and repair what is found
+    fn check_filesystems(&self, term: &mut Terminal, fs: &RefCell<FileSystem>) {
+        let mut fs = fs.borrow_mut();
+        let report = fs.fsck(true);
+        fs.sync();
+        let mut lines = report.lines();
+        let summary = lines.pop().unwrap_or_default();
+        let hidden = lines.len().saturating_sub(FSCK_SHOWN);
+        lines.truncate(FSCK_SHOWN);
+        for line in lines {
+            term.write_str(&format!("fsck: {}\n", line));
         }
+        if hidden > 0 {
+            term.write_str(&format!("fsck: ... and {} more\n", hidden));
+        }
+        term.write_str(&format!("fsck: {}\n", summary));
     }
 
     // Returns true when BIOS is done and Kernel should start
-    pub fn step(&mut self, term: &mut Terminal, bus: &mut super::hw::bus::Bus, input_op: Option<String>) -> bool {
+    pub fn step(&mut self, term: &mut Terminal, bus: &mut super::hw::bus::Bus, fs: &RefCell<FileSystem>, input_op: Option<String>) -> bool {
         self.ticks += 1;
 
         match self.state {
@@ -83,16 +112,40 @@ impl Bios {
                      let mem = (progress * 16384.0) as u32;
                      
                      if mem >= 16384 {
-                         let target_name = if self.boot_target.contains("terminal") { "terminal" } else { "desktop" };
-                         let msg = format!("\rmemory test: {} kb ok\n\nbooting {}...\n", 16384, target_name);
+                         let msg = format!("\rmemory test: {} kb ok\n", 16384);
                          term.write_str(&msg);
-                         self.state = BiosState::WaitingForInput;
+                         term.write_str("local disk: loading...");
+                         self.state = BiosState::DiskCheck(self.ticks);
                      } else {
                          let msg = format!("\rmemory test: {} kb ok", mem);
                          term.write_str(&msg);
                      }
                 }
             },
+            BiosState::DiskCheck(started) => {
+                // The host reads the disk asynchronously (IndexedDB), wait for it here
+                let status = fs.borrow_mut().poll_disk();
+                let msg = match status {
+                    Some(MountStatus::Ready(inodes)) => format!("\rlocal disk: {} inodes ok\n", inodes),
+                    // The disk is left as it is, so the data can still be copied out
+                    Some(MountStatus::Recovery(inodes, reason)) => format!(
+                        "\rlocal disk: {}\nlocal disk: {} inodes recovered, mounted read-only\n", reason, inodes),
+                    Some(MountStatus::Failed(e)) => format!("\rlocal disk: error ({}), changes will not be saved\n", e),
+                    None if self.ticks - started > DISK_TIMEOUT_TICKS => {
+                        fs.borrow_mut().abort_load("timed out");
+                        "\rlocal disk: not responding, changes will not be saved\n".to_string()
+                    },
+                    None => return false,
+                };
+                term.write_str(&msg);
+                if self.check_disk {
+                    self.check_filesystems(term, fs);
+                }
+
+                let target_name = if self.boot_target.contains("terminal") { "terminal" } else { "desktop" };
+                term.write_str(&format!("\nbooting {}...\n", target_name));
+                self.state = BiosState::WaitingForInput;
+            },
             BiosState::WaitingForInput => {
                 // If any key pressed, go to Setup
                 if input_op.is_some() {
@@ -117,6 +170,8 @@ impl Bios {
                     }
                     
                     term.write_str("press [1] or [2] to select.\n");
+                    term.write_str(&format!("press [3] to toggle disk check at boot ({}).\n",
+                        if self.check_disk { "on" } else { "off" }));
                     return false;
                 }
 
@@ -149,6 +204,10 @@ impl Bios {
                         term.write_str("\nselected: desktop\nbooting...");
                         self.state = BiosState::Booting;
                          self.ticks = 0;
+                    } else if key == "3" {
+                        self.check_disk = !self.check_disk;
+                        self.save_check_disk();
+                        term.write_str(&format!("\ndisk check at boot: {}\n", if self.check_disk { "on" } else { "off" }));
                     }
                 }
             },
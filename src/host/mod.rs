@@ -0,0 +1,39 @@
+//! Host services the kernel needs from whatever is running it.
+//!
+//! The browser build uses `web::WebHost` (performance.now, localStorage,
+//! IndexedDB, console). `native::NativeHost` keeps everything in memory so the
+//! machine can boot and run programs under `cargo test` or any native binary.
+
+pub mod web;
+pub mod native;
+
+pub use native::NativeHost;
+pub use web::WebHost;
+
+/// `(inode, record)` pairs of the persistent disk.
+pub type DiskRecords = Vec<(u32, Vec<u8>)>;
+
+pub trait Host {
+    /// Monotonic clock in milliseconds.
+    fn now(&self) -> f64;
+    /// Wall-clock time in milliseconds since the unix epoch.
+    fn wall_clock_ms(&self) -> f64;
+    /// Human readable wall-clock date, as printed by `date`.
+    fn date_string(&self) -> String;
+
+    fn storage_get(&self, key: &str) -> Option<String>;
+    fn storage_set(&self, key: &str, value: &str);
+    fn storage_remove(&self, key: &str);
+
+    /// Start reading the persistent disk (one record per inode). The records
+    /// are handed over by `disk_poll` once the read has finished.
+    fn disk_load(&self);
+    /// `None` while the load started by `disk_load` is still running.
+    fn disk_poll(&self) -> Option<Result<DiskRecords, String>>;
+    /// Store and delete records in one transaction.
+    fn disk_write(&self, puts: DiskRecords, deletes: Vec<u32>);
+    /// Remove every record.
+    fn disk_clear(&self);
+
+    fn log(&self, msg: &str);
+}
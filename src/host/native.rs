@@ -0,0 +1,99 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use super::{DiskRecords, Host};
+
+// In-memory backend for running the machine outside a browser.
+// Storage lives only as long as the host; log lines are kept for inspection.
+// Disk loads complete immediately.
+pub struct NativeHost {
+    start: Instant,
+    storage: RefCell<HashMap<String, String>>,
+    disk: RefCell<BTreeMap<u32, Vec<u8>>>,
+    pub logs: RefCell<Vec<String>>,
+}
+
+impl NativeHost {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            storage: RefCell::new(HashMap::new()),
+            disk: RefCell::new(BTreeMap::new()),
+            logs: RefCell::new(Vec::new()),
+        }
+    }
+}
+
+impl Default for NativeHost {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Host for NativeHost {
+    fn now(&self) -> f64 {
+        self.start.elapsed().as_secs_f64() * 1000.0
+    }
+
+    fn wall_clock_ms(&self) -> f64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as f64)
+            .unwrap_or(0.0)
+    }
+
+    fn date_string(&self) -> String {
+        let secs = (self.wall_clock_ms() / 1000.0) as i64;
+        let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+
+        // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02} utc", year, month, day, rem / 3600, (rem / 60) % 60, rem % 60)
+    }
+
+    fn storage_get(&self, key: &str) -> Option<String> {
+        self.storage.borrow().get(key).cloned()
+    }
+
+    fn storage_set(&self, key: &str, value: &str) {
+        self.storage.borrow_mut().insert(key.to_string(), value.to_string());
+    }
+
+    fn storage_remove(&self, key: &str) {
+        self.storage.borrow_mut().remove(key);
+    }
+
+    fn disk_load(&self) {}
+
+    fn disk_poll(&self) -> Option<Result<DiskRecords, String>> {
+        Some(Ok(self.disk.borrow().iter().map(|(k, v)| (*k, v.clone())).collect()))
+    }
+
+    fn disk_write(&self, puts: DiskRecords, deletes: Vec<u32>) {
+        let mut disk = self.disk.borrow_mut();
+        for (key, value) in puts {
+            disk.insert(key, value);
+        }
+        for key in deletes {
+            disk.remove(&key);
+        }
+    }
+
+    fn disk_clear(&self) {
+        self.disk.borrow_mut().clear();
+    }
+
+    fn log(&self, msg: &str) {
+        self.logs.borrow_mut().push(msg.to_string());
+    }
+}
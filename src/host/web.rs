@@ -0,0 +1,203 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{IdbDatabase, IdbObjectStore, IdbOpenDbRequest, IdbRequest, IdbTransactionMode};
+
+use super::{DiskRecords, Host};
+
+const DB_NAME: &str = "wasmix";
+const DB_VERSION: u32 = 1;
+const INODE_STORE: &str = "inodes";
+
+// IndexedDB connection, shared with the request callbacks
+#[derive(Default)]
+struct Disk {
+    db: Option<IdbDatabase>,
+    loaded: Option<Result<DiskRecords, String>>,
+}
+
+// Browser backend: everything goes through web_sys
+pub struct WebHost {
+    disk: Rc<RefCell<Disk>>,
+}
+
+impl WebHost {
+    pub fn new() -> Self {
+        Self { disk: Rc::new(RefCell::new(Disk::default())) }
+    }
+
+    fn storage() -> Option<web_sys::Storage> {
+        let window = web_sys::window()?;
+        window.local_storage().ok().flatten()
+    }
+
+    fn inode_store(&self, mode: IdbTransactionMode) -> Option<IdbObjectStore> {
+        let disk = self.disk.borrow();
+        let tx = disk.db.as_ref()?.transaction_with_str_and_mode(INODE_STORE, mode).ok()?;
+        tx.object_store(INODE_STORE).ok()
+    }
+
+    fn open(disk: Rc<RefCell<Disk>>) -> Result<(), JsValue> {
+        let factory = web_sys::window()
+            .and_then(|w| w.indexed_db().ok().flatten())
+            .ok_or_else(|| JsValue::from_str("indexeddb not available"))?;
+        let req = factory.open_with_u32(DB_NAME, DB_VERSION)?;
+
+        let on_upgrade = Closure::<dyn FnMut(web_sys::Event)>::new(|event: web_sys::Event| {
+            let req: Option<IdbOpenDbRequest> = event.target().and_then(|t| t.dyn_into().ok());
+            if let Some(db) = req.and_then(|r| r.result().ok()).and_then(|r| r.dyn_into::<IdbDatabase>().ok()) {
+                let _ = db.create_object_store(INODE_STORE);
+            }
+        });
+        req.set_onupgradeneeded(Some(on_upgrade.as_ref().unchecked_ref()));
+        on_upgrade.forget();
+
+        let ok_disk = disk.clone();
+        let req_ok = req.clone();
+        let on_success = Closure::<dyn FnMut()>::new(move || {
+            match req_ok.result().ok().and_then(|r| r.dyn_into::<IdbDatabase>().ok()) {
+                Some(db) => {
+                    ok_disk.borrow_mut().db = Some(db);
+                    if let Err(e) = Self::read_all(ok_disk.clone()) {
+                        ok_disk.borrow_mut().loaded = Some(Err(Self::describe(&e)));
+                    }
+                },
+                None => ok_disk.borrow_mut().loaded = Some(Err("indexeddb open failed".to_string())),
+            }
+        });
+        req.set_onsuccess(Some(on_success.as_ref().unchecked_ref()));
+        on_success.forget();
+
+        let on_error = Closure::<dyn FnMut()>::new(move || {
+            disk.borrow_mut().loaded = Some(Err("indexeddb open failed".to_string()));
+        });
+        req.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+        on_error.forget();
+        Ok(())
+    }
+
+    // Fetch keys and values in one transaction, so they come back in matching order
+    fn read_all(disk: Rc<RefCell<Disk>>) -> Result<(), JsValue> {
+        let store = {
+            let d = disk.borrow();
+            let db = d.db.as_ref().ok_or_else(|| JsValue::from_str("no database"))?;
+            db.transaction_with_str(INODE_STORE)?.object_store(INODE_STORE)?
+        };
+        let keys_req = store.get_all_keys()?;
+        let values_req = store.get_all()?;
+
+        let values_ok = values_req.clone();
+        let ok_disk = disk.clone();
+        let on_success = Closure::<dyn FnMut()>::new(move || {
+            let records = Self::collect_records(&keys_req, &values_ok);
+            ok_disk.borrow_mut().loaded = Some(records);
+        });
+        values_req.set_onsuccess(Some(on_success.as_ref().unchecked_ref()));
+        on_success.forget();
+
+        let on_error = Closure::<dyn FnMut()>::new(move || {
+            disk.borrow_mut().loaded = Some(Err("indexeddb read failed".to_string()));
+        });
+        values_req.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+        on_error.forget();
+        Ok(())
+    }
+
+    fn collect_records(keys: &IdbRequest, values: &IdbRequest) -> Result<DiskRecords, String> {
+        let keys: js_sys::Array = keys.result().ok().and_then(|k| k.dyn_into().ok()).ok_or("indexeddb read failed")?;
+        let values: js_sys::Array = values.result().ok().and_then(|v| v.dyn_into().ok()).ok_or("indexeddb read failed")?;
+        Ok(keys.iter().zip(values.iter())
+            .filter_map(|(k, v)| {
+                let key = k.as_f64()? as u32;
+                let bytes = v.dyn_into::<js_sys::Uint8Array>().ok()?.to_vec();
+                Some((key, bytes))
+            })
+            .collect())
+    }
+
+    fn describe(e: &JsValue) -> String {
+        e.as_string().unwrap_or_else(|| "indexeddb error".to_string())
+    }
+}
+
+impl Default for WebHost {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Host for WebHost {
+    fn now(&self) -> f64 {
+        web_sys::window()
+            .and_then(|w| w.performance())
+            .map(|p| p.now())
+            .unwrap_or(0.0)
+    }
+
+    fn wall_clock_ms(&self) -> f64 {
+        js_sys::Date::now()
+    }
+
+    fn date_string(&self) -> String {
+        let date = js_sys::Date::new_0();
+        String::from(date.to_string())
+    }
+
+    fn storage_get(&self, key: &str) -> Option<String> {
+        Self::storage()?.get_item(key).ok().flatten()
+    }
+
+    fn storage_set(&self, key: &str, value: &str) {
+        if let Some(storage) = Self::storage() {
+            let _ = storage.set_item(key, value);
+        }
+    }
+
+    fn storage_remove(&self, key: &str) {
+        if let Some(storage) = Self::storage() {
+            let _ = storage.remove_item(key);
+        }
+    }
+
+    fn disk_load(&self) {
+        self.disk.borrow_mut().loaded = None;
+        let opened = self.disk.borrow().db.is_some();
+        let res = if opened {
+            Self::read_all(self.disk.clone())
+        } else {
+            Self::open(self.disk.clone())
+        };
+        if let Err(e) = res {
+            self.disk.borrow_mut().loaded = Some(Err(Self::describe(&e)));
+        }
+    }
+
+    fn disk_poll(&self) -> Option<Result<DiskRecords, String>> {
+        self.disk.borrow_mut().loaded.take()
+    }
+
+    fn disk_write(&self, puts: DiskRecords, deletes: Vec<u32>) {
+        let store = match self.inode_store(IdbTransactionMode::Readwrite) {
+            Some(s) => s,
+            None => return,
+        };
+        for (key, value) in puts {
+            let _ = store.put_with_key(&js_sys::Uint8Array::from(value.as_slice()), &JsValue::from(key));
+        }
+        for key in deletes {
+            let _ = store.delete(&JsValue::from(key));
+        }
+    }
+
+    fn disk_clear(&self) {
+        if let Some(store) = self.inode_store(IdbTransactionMode::Readwrite) {
+            let _ = store.clear();
+        }
+    }
+
+    fn log(&self, msg: &str) {
+        web_sys::console::log_1(&msg.into());
+    }
+}
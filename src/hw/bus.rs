@@ -3,10 +3,54 @@ use std::cell::RefCell;
 use super::ram::Ram;
 use super::gpu::Gpu;
 
-#[allow(dead_code)]
+// Physical address map
+// 0x00000000 - RAM size  -> RAM
+// 0xA0000000 - + VRAM    -> GPU framebuffer (RGBA, row major)
+// 0xF0000000 - 0xF000FFFF -> Device registers (32-bit, see `Device`)
+// Anything else raises a `BusFault` instead of reading as zero.
+pub const RAM_BASE: u32 = 0x0000_0000;
+pub const VRAM_BASE: u32 = 0xA000_0000;
+pub const MMIO_BASE: u32 = 0xF000_0000;
+pub const MMIO_SIZE: u32 = 0x0001_0000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BusFault {
+    Unmapped(u32),
+    Misaligned(u32),
+}
+
+impl std::fmt::Display for BusFault {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BusFault::Unmapped(addr) => write!(f, "unmapped address {:08x}", addr),
+            BusFault::Misaligned(addr) => write!(f, "misaligned access at {:08x}", addr),
+        }
+    }
+}
+
+/// A peripheral exposing 32-bit registers inside the device window.
+/// Offsets are relative to the device's own base and always word aligned.
+pub trait Device {
+    fn read_reg(&mut self, offset: u32) -> u32;
+    fn write_reg(&mut self, offset: u32, val: u32);
+}
+
+struct MappedDevice {
+    base: u32,
+    size: u32,
+    device: Rc<RefCell<dyn Device>>,
+}
+
+enum Region {
+    Ram(usize),
+    Vram(usize),
+    Device(usize, u32), // index into devices, offset
+}
+
 pub struct Bus {
     pub ram: Rc<RefCell<Ram>>,
     pub gpu: Rc<RefCell<Gpu>>,
+    devices: Vec<MappedDevice>,
 }
 
 impl Bus {
@@ -14,10 +58,216 @@ impl Bus {
         Self {
             ram,
             gpu,
+            devices: Vec::new(),
+        }
+    }
+
+    /// Map a device at `MMIO_BASE + offset`. Fails if the range leaves the
+    /// device window or overlaps RAM, VRAM or another device.
+    #[allow(dead_code)]
+    pub fn map_device(&mut self, offset: u32, size: u32, device: Rc<RefCell<dyn Device>>) -> Result<(), String> {
+        if size == 0 || offset.checked_add(size).is_none_or(|end| end > MMIO_SIZE) {
+            return Err("device range outside mmio window".to_string());
+        }
+        let base = MMIO_BASE + offset;
+        let (start, end) = (base as u64, base as u64 + size as u64);
+        // Memory large enough to reach the window would shadow the device
+        let ram_end = RAM_BASE as u64 + self.ram.borrow().size as u64;
+        if start < ram_end {
+            return Err("device range overlaps ram".to_string());
+        }
+        let vram_end = VRAM_BASE as u64 + self.gpu.borrow().buffer.len() as u64;
+        if start < vram_end && (VRAM_BASE as u64) < end {
+            return Err("device range overlaps vram".to_string());
+        }
+        for d in &self.devices {
+            if base < d.base + d.size && d.base < base + size {
+                return Err(format!("device range overlaps device at {:08x}", d.base));
+            }
+        }
+        self.devices.push(MappedDevice { base, size, device });
+        Ok(())
+    }
+
+    // Resolve an access of `width` bytes to a region, checking bounds and alignment.
+    fn decode(&self, addr: u32, width: u32) -> Result<Region, BusFault> {
+        if !addr.is_multiple_of(width) {
+            return Err(BusFault::Misaligned(addr));
+        }
+        let end = addr as u64 + width as u64;
+
+        let ram_size = self.ram.borrow().size as u64;
+        if end <= RAM_BASE as u64 + ram_size {
+            return Ok(Region::Ram((addr - RAM_BASE) as usize));
+        }
+
+        let vram_size = self.gpu.borrow().buffer.len() as u64;
+        if addr >= VRAM_BASE && end <= VRAM_BASE as u64 + vram_size {
+            return Ok(Region::Vram((addr - VRAM_BASE) as usize));
+        }
+
+        if addr >= MMIO_BASE && end <= MMIO_BASE as u64 + MMIO_SIZE as u64 {
+            for (i, d) in self.devices.iter().enumerate() {
+                if addr >= d.base && end <= d.base as u64 + d.size as u64 {
+                    return Ok(Region::Device(i, addr - d.base));
+                }
+            }
+        }
+
+        Err(BusFault::Unmapped(addr))
+    }
+
+    fn read(&self, addr: u32, buf: &mut [u8]) -> Result<(), BusFault> {
+        match self.decode(addr, buf.len() as u32)? {
+            Region::Ram(off) => buf.copy_from_slice(&self.ram.borrow().mem[off..off + buf.len()]),
+            Region::Vram(off) => buf.copy_from_slice(&self.gpu.borrow().buffer[off..off + buf.len()]),
+            Region::Device(i, off) => {
+                // Narrow reads see the matching bytes of the 32-bit register
+                let word = self.devices[i].device.borrow_mut().read_reg(off & !3).to_le_bytes();
+                let start = (off & 3) as usize;
+                buf.copy_from_slice(&word[start..start + buf.len()]);
+            },
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, addr: u32, buf: &[u8]) -> Result<(), BusFault> {
+        match self.decode(addr, buf.len() as u32)? {
+            Region::Ram(off) => self.ram.borrow_mut().mem[off..off + buf.len()].copy_from_slice(buf),
+            Region::Vram(off) => self.gpu.borrow_mut().buffer[off..off + buf.len()].copy_from_slice(buf),
+            Region::Device(i, off) => {
+                // Narrow writes are read-modify-write on the 32-bit register
+                let mut device = self.devices[i].device.borrow_mut();
+                let reg = off & !3;
+                let mut word = device.read_reg(reg).to_le_bytes();
+                let start = (off & 3) as usize;
+                word[start..start + buf.len()].copy_from_slice(buf);
+                device.write_reg(reg, u32::from_le_bytes(word));
+            },
+        }
+        Ok(())
+    }
+
+    pub fn read_u8(&self, addr: u32) -> Result<u8, BusFault> {
+        let mut buf = [0u8; 1];
+        self.read(addr, &mut buf)?;
+        Ok(buf[0])
+    }
+
+    pub fn read_u16(&self, addr: u32) -> Result<u16, BusFault> {
+        let mut buf = [0u8; 2];
+        self.read(addr, &mut buf)?;
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    pub fn read_u32(&self, addr: u32) -> Result<u32, BusFault> {
+        let mut buf = [0u8; 4];
+        self.read(addr, &mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    pub fn write_u8(&mut self, addr: u32, val: u8) -> Result<(), BusFault> {
+        self.write(addr, &[val])
+    }
+
+    pub fn write_u16(&mut self, addr: u32, val: u16) -> Result<(), BusFault> {
+        self.write(addr, &val.to_le_bytes())
+    }
+
+    pub fn write_u32(&mut self, addr: u32, val: u32) -> Result<(), BusFault> {
+        self.write(addr, &val.to_le_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Four registers, remembering the last write
+    #[derive(Default)]
+    struct Regs {
+        regs: [u32; 4],
+        writes: Vec<(u32, u32)>,
+    }
+
+    impl Device for Regs {
+        fn read_reg(&mut self, offset: u32) -> u32 {
+            self.regs[offset as usize / 4]
         }
+
+        fn write_reg(&mut self, offset: u32, val: u32) {
+            self.regs[offset as usize / 4] = val;
+            self.writes.push((offset, val));
+        }
+    }
+
+    // 64 KB of RAM and a 16x16 framebuffer
+    fn bus() -> Bus {
+        Bus::new(Rc::new(RefCell::new(Ram::new(64 * 1024))), Rc::new(RefCell::new(Gpu::new(16, 16))))
+    }
+
+    #[test]
+    fn faults_outside_mapped_memory() {
+        let mut bus = bus();
+        bus.write_u32(0xFFFC, 0x11223344).unwrap();
+        assert_eq!(bus.read_u32(0xFFFC), Ok(0x11223344));
+        assert_eq!(bus.read_u8(0x1_0000), Err(BusFault::Unmapped(0x1_0000)));
+        assert_eq!(bus.read_u32(0x5000_0000), Err(BusFault::Unmapped(0x5000_0000)));
+
+        bus.write_u32(VRAM_BASE + 1020, 0xFF0000FF).unwrap();
+        assert_eq!(bus.gpu.borrow().buffer[1020..1024], [0xFF, 0, 0, 0xFF]);
+        assert_eq!(bus.write_u8(VRAM_BASE + 1024, 0), Err(BusFault::Unmapped(VRAM_BASE + 1024)));
+
+        // The device window with nothing mapped
+        assert_eq!(bus.read_u32(MMIO_BASE), Err(BusFault::Unmapped(MMIO_BASE)));
+        assert_eq!(bus.read_u32(0xFFFF_FFFC), Err(BusFault::Unmapped(0xFFFF_FFFC)));
+    }
+
+    #[test]
+    fn faults_on_misaligned_access() {
+        let mut bus = bus();
+        assert_eq!(bus.read_u16(1), Err(BusFault::Misaligned(1)));
+        assert_eq!(bus.read_u32(2), Err(BusFault::Misaligned(2)));
+        assert_eq!(bus.write_u16(VRAM_BASE + 3, 0), Err(BusFault::Misaligned(VRAM_BASE + 3)));
+        assert_eq!(bus.write_u32(0x5000_0001, 0), Err(BusFault::Misaligned(0x5000_0001)));
+        assert!(bus.read_u8(3).is_ok() && bus.read_u16(2).is_ok());
+        assert_eq!(BusFault::Misaligned(2).to_string(), "misaligned access at 00000002");
+    }
+
+    #[test]
+    fn routes_narrow_accesses_to_device_registers() {
+        let mut bus = bus();
+        let regs = Rc::new(RefCell::new(Regs::default()));
+        bus.map_device(0x100, 16, regs.clone()).unwrap();
+
+        bus.write_u32(MMIO_BASE + 0x104, 0x11223344).unwrap();
+        assert_eq!(regs.borrow().regs[1], 0x11223344);
+        assert_eq!(bus.read_u8(MMIO_BASE + 0x105), Ok(0x33));
+        assert_eq!(bus.read_u16(MMIO_BASE + 0x106), Ok(0x1122));
+
+        // Narrow writes keep the rest of the register
+        bus.write_u8(MMIO_BASE + 0x106, 0xAA).unwrap();
+        bus.write_u16(MMIO_BASE + 0x10C, 0xBEEF).unwrap();
+        assert_eq!(regs.borrow().regs, [0, 0x11AA3344, 0, 0xBEEF]);
+        assert_eq!(regs.borrow().writes, [(4, 0x11223344), (4, 0x11AA3344), (12, 0xBEEF)]);
+
+        assert_eq!(bus.read_u32(MMIO_BASE + 0x110), Err(BusFault::Unmapped(MMIO_BASE + 0x110)));
+        assert_eq!(bus.read_u32(MMIO_BASE + 0xFC), Err(BusFault::Unmapped(MMIO_BASE + 0xFC)));
+    }
+
+    #[test]
+    fn refuses_overlapping_devices() {
+        let mut bus = bus();
+        let regs = Rc::new(RefCell::new(Regs::default()));
+        bus.map_device(0x100, 16, regs.clone()).unwrap();
+        assert!(bus.map_device(0x10C, 16, regs.clone()).unwrap_err().contains("overlaps device"));
+        assert!(bus.map_device(0xFFF0, 32, regs.clone()).is_err());
+        assert!(bus.map_device(0x200, 0, regs.clone()).is_err());
+        bus.map_device(0x110, 16, regs.clone()).unwrap();
+
+        // RAM reaching into the window, without allocating all of it
+        bus.ram.borrow_mut().size = (MMIO_BASE + 0x1000) as usize;
+        assert_eq!(bus.map_device(0x800, 16, regs.clone()).unwrap_err(), "device range overlaps ram");
+        bus.map_device(0x1000, 16, regs).unwrap();
     }
-    
-    // Future: read/write mapping
-    // 0x00000000 - 0x0FFFFFFF -> RAM (256MB window commonly, we use what we have)
-    // 0xA0000000 - ... -> VRAM
 }
@@ -1,10 +1,185 @@
 #![allow(dead_code)]
-use super::bus::Bus;
+//! wasmix-32 instruction set.
+//!
+//! Every instruction is one little-endian 32-bit word fetched from `pc`.
+//! The top 6 bits select the opcode, the rest is one of three layouts:
+//!
+//! ```text
+//!  31    26 25   21 20   16 15   11 10         0
+//! [ opcode ][  rd  ][  rs  ][  rt  ][  unused   ]   r-type
+//! [ opcode ][  rd  ][  rs  ][       imm16       ]   i-type
+//! [ opcode ][            imm26 (signed)         ]   j-type
+//! ```
+//!
+//! `r0` always reads as zero. By convention `r2` holds return values,
+//! `r4`-`r7` hold arguments, `r29` is the stack pointer and `r31` the
+//! link register written by `jal`/`jalr`.
+//!
+//! | op   | name  | type | effect                                         |
+//! |------|-------|------|------------------------------------------------|
+//! | 0x00 | halt  | -    | stop the core                                  |
+//! | 0x01 | nop   | -    | nothing                                        |
+//! | 0x02 | add   | r    | rd = rs + rt                                   |
+//! | 0x03 | sub   | r    | rd = rs - rt                                   |
+//! | 0x04 | and   | r    | rd = rs & rt                                   |
+//! | 0x05 | or    | r    | rd = rs \| rt                                  |
+//! | 0x06 | xor   | r    | rd = rs ^ rt                                   |
+//! | 0x07 | sll   | r    | rd = rs << (rt & 31)                           |
+//! | 0x08 | srl   | r    | rd = rs >> (rt & 31) (logical)                 |
+//! | 0x09 | sra   | r    | rd = rs >> (rt & 31) (arithmetic)              |
+//! | 0x0A | slt   | r    | rd = (rs as i32) < (rt as i32)                 |
+//! | 0x0B | sltu  | r    | rd = rs < rt                                   |
+//! | 0x0C | mul   | r    | rd = rs * rt (low 32 bits)                     |
+//! | 0x0D | divu  | r    | rd = rs / rt (`0xFFFFFFFF` when rt is zero)    |
+//! | 0x0E | remu  | r    | rd = rs % rt (rs when rt is zero)              |
+//! | 0x10 | addi  | i    | rd = rs + sext(imm)                            |
+//! | 0x11 | andi  | i    | rd = rs & zext(imm)                            |
+//! | 0x12 | ori   | i    | rd = rs \| zext(imm)                           |
+//! | 0x13 | xori  | i    | rd = rs ^ zext(imm)                            |
+//! | 0x14 | slti  | i    | rd = (rs as i32) < sext(imm)                   |
+//! | 0x15 | slli  | i    | rd = rs << (imm & 31)                          |
+//! | 0x16 | srli  | i    | rd = rs >> (imm & 31) (logical)                |
+//! | 0x17 | srai  | i    | rd = rs >> (imm & 31) (arithmetic)             |
+//! | 0x18 | lui   | i    | rd = imm << 16                                 |
+//! | 0x20 | lw    | i    | rd = mem32[rs + sext(imm)]                     |
+//! | 0x21 | lh    | i    | rd = sext(mem16[rs + sext(imm)])               |
+//! | 0x22 | lhu   | i    | rd = zext(mem16[rs + sext(imm)])               |
+//! | 0x23 | lb    | i    | rd = sext(mem8[rs + sext(imm)])                |
+//! | 0x24 | lbu   | i    | rd = zext(mem8[rs + sext(imm)])                |
+//! | 0x28 | sw    | i    | mem32[rs + sext(imm)] = rd                     |
+//! | 0x29 | sh    | i    | mem16[rs + sext(imm)] = rd                     |
+//! | 0x2A | sb    | i    | mem8[rs + sext(imm)] = rd                      |
+//! | 0x30 | beq   | i    | if rd == rs: pc += sext(imm) << 2              |
+//! | 0x31 | bne   | i    | if rd != rs: pc += sext(imm) << 2              |
+//! | 0x32 | blt   | i    | if (rd as i32) < (rs as i32): branch           |
+//! | 0x33 | bge   | i    | if (rd as i32) >= (rs as i32): branch          |
+//! | 0x34 | bltu  | i    | if rd < rs: branch                             |
+//! | 0x35 | bgeu  | i    | if rd >= rs: branch                            |
+//! | 0x38 | jal   | j    | r31 = pc + 4; pc += sext(imm26) << 2           |
+//! | 0x39 | jalr  | i    | rd = pc + 4; pc = (rs + sext(imm)) & !3        |
+//! | 0x3F | sys   | i    | trap to the kernel with syscall number `imm`   |
+//!
+//! Branch and jump offsets are relative to the address of the branch itself.
+//! Every memory access goes through `Bus`; halfword and word accesses must be
+//! naturally aligned, and a faulting access halts the core with `Trap::BusFault`.
+
+use super::bus::{Bus, BusFault};
+
+pub mod op {
+    pub const HALT: u32 = 0x00;
+    pub const NOP: u32 = 0x01;
+    pub const ADD: u32 = 0x02;
+    pub const SUB: u32 = 0x03;
+    pub const AND: u32 = 0x04;
+    pub const OR: u32 = 0x05;
+    pub const XOR: u32 = 0x06;
+    pub const SLL: u32 = 0x07;
+    pub const SRL: u32 = 0x08;
+    pub const SRA: u32 = 0x09;
+    pub const SLT: u32 = 0x0A;
+    pub const SLTU: u32 = 0x0B;
+    pub const MUL: u32 = 0x0C;
+    pub const DIVU: u32 = 0x0D;
+    pub const REMU: u32 = 0x0E;
+    pub const ADDI: u32 = 0x10;
+    pub const ANDI: u32 = 0x11;
+    pub const ORI: u32 = 0x12;
+    pub const XORI: u32 = 0x13;
+    pub const SLTI: u32 = 0x14;
+    pub const SLLI: u32 = 0x15;
+    pub const SRLI: u32 = 0x16;
+    pub const SRAI: u32 = 0x17;
+    pub const LUI: u32 = 0x18;
+    pub const LW: u32 = 0x20;
+    pub const LH: u32 = 0x21;
+    pub const LHU: u32 = 0x22;
+    pub const LB: u32 = 0x23;
+    pub const LBU: u32 = 0x24;
+    pub const SW: u32 = 0x28;
+    pub const SH: u32 = 0x29;
+    pub const SB: u32 = 0x2A;
+    pub const BEQ: u32 = 0x30;
+    pub const BNE: u32 = 0x31;
+    pub const BLT: u32 = 0x32;
+    pub const BGE: u32 = 0x33;
+    pub const BLTU: u32 = 0x34;
+    pub const BGEU: u32 = 0x35;
+    pub const JAL: u32 = 0x38;
+    pub const JALR: u32 = 0x39;
+    pub const SYS: u32 = 0x3F;
+}
+
+// Register conventions
+pub const REG_RET: usize = 2;
+pub const REG_ARG0: usize = 4;
+pub const REG_SP: usize = 29;
+pub const REG_LINK: usize = 31;
+
+// Encoders, handy for building native images by hand
+pub fn encode_r(opcode: u32, rd: u32, rs: u32, rt: u32) -> u32 {
+    (opcode << 26) | ((rd & 31) << 21) | ((rs & 31) << 16) | ((rt & 31) << 11)
+}
+
+pub fn encode_i(opcode: u32, rd: u32, rs: u32, imm: u16) -> u32 {
+    (opcode << 26) | ((rd & 31) << 21) | ((rs & 31) << 16) | imm as u32
+}
+
+pub fn encode_j(opcode: u32, offset: i32) -> u32 {
+    (opcode << 26) | (offset as u32 & 0x03FF_FFFF)
+}
+
+/// Decoded form of a single instruction word.
+#[derive(Clone, Copy, Debug)]
+pub struct Instruction {
+    pub opcode: u32,
+    pub rd: usize,
+    pub rs: usize,
+    pub rt: usize,
+    pub imm: u16,
+    pub imm26: u32,
+}
+
+impl Instruction {
+    pub fn decode(word: u32) -> Self {
+        Self {
+            opcode: word >> 26,
+            rd: ((word >> 21) & 31) as usize,
+            rs: ((word >> 16) & 31) as usize,
+            rt: ((word >> 11) & 31) as usize,
+            imm: (word & 0xFFFF) as u16,
+            imm26: word & 0x03FF_FFFF,
+        }
+    }
+
+    fn simm(&self) -> u32 {
+        self.imm as i16 as i32 as u32
+    }
+
+    fn zimm(&self) -> u32 {
+        self.imm as u32
+    }
+
+    fn simm26(&self) -> u32 {
+        // Sign extend from bit 25
+        (((self.imm26 << 6) as i32) >> 6) as u32
+    }
+}
+
+/// Reasons the core stops executing and hands control back to the machine.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Trap {
+    Halt,
+    Syscall(u32),
+    IllegalInstruction { pc: u32, word: u32 },
+    BusFault { pc: u32, fault: BusFault },
+}
 
 pub struct Cpu {
     pub regs: [u32; 32], // General Purpose Registers (r0-r31)
     pub pc: u32,         // Program Counter
     pub version: u8,
+    pub halted: bool,
+    pub cycles: u64,
 }
 
 impl Cpu {
@@ -13,20 +188,285 @@ impl Cpu {
             regs: [0; 32],
             pc: 0,
             version: 1,
+            halted: true, // Nothing to run until an image is loaded
+            cycles: 0,
+        }
+    }
+
+    pub fn reset(&mut self, entry: u32, stack_top: u32) {
+        self.regs = [0; 32];
+        self.regs[REG_SP] = stack_top;
+        self.pc = entry;
+        self.halted = false;
+    }
+
+    fn set_reg(&mut self, idx: usize, val: u32) {
+        if idx != 0 {
+            self.regs[idx] = val;
+        }
+    }
+
+    // Single step of the CPU: fetch, decode, execute.
+    // Returns a trap when the kernel needs to take over.
+    pub fn step(&mut self, bus: &mut Bus) -> Option<Trap> {
+        if self.halted {
+            return Some(Trap::Halt);
+        }
+
+        let pc = self.pc;
+        match self.execute(bus, pc) {
+            Ok(trap) => {
+                self.cycles += 1;
+                trap
+            },
+            Err(fault) => {
+                // Faulting instructions do not retire
+                self.halted = true;
+                Some(Trap::BusFault { pc, fault })
+            },
         }
     }
 
-    // Single step of the CPU
-    pub fn step(&mut self, _bus: &mut Bus) { // Underscore to suppress unused for now
-        // Fetch instruction (simulated)
-        
-        // For Phase 1: this is a dummy stepper.
-        // The real "logic" is currently essentially "interrupt driven" by the Shell 
-        // passing through lib.rs tick() -> cpu_step() -> shell logic.
-        
-        // Once we move Shell logic to run on the CPU loop, we will look at [PC]
-        // and execute an instruction.
-        
-        self.pc += 4; // Increment PC
+    fn execute(&mut self, bus: &mut Bus, pc: u32) -> Result<Option<Trap>, BusFault> {
+        let word = bus.read_u32(pc)?;
+        let ins = Instruction::decode(word);
+        let mut next_pc = pc.wrapping_add(4);
+        let mut trap = None;
+
+        let rs = self.regs[ins.rs];
+        let rt = self.regs[ins.rt];
+        let rd_val = self.regs[ins.rd];
+
+        match ins.opcode {
+            op::HALT => {
+                self.halted = true;
+                trap = Some(Trap::Halt);
+            },
+            op::NOP => {},
+
+            // ALU (register)
+            op::ADD => self.set_reg(ins.rd, rs.wrapping_add(rt)),
+            op::SUB => self.set_reg(ins.rd, rs.wrapping_sub(rt)),
+            op::AND => self.set_reg(ins.rd, rs & rt),
+            op::OR => self.set_reg(ins.rd, rs | rt),
+            op::XOR => self.set_reg(ins.rd, rs ^ rt),
+            op::SLL => self.set_reg(ins.rd, rs << (rt & 31)),
+            op::SRL => self.set_reg(ins.rd, rs >> (rt & 31)),
+            op::SRA => self.set_reg(ins.rd, ((rs as i32) >> (rt & 31)) as u32),
+            op::SLT => self.set_reg(ins.rd, ((rs as i32) < (rt as i32)) as u32),
+            op::SLTU => self.set_reg(ins.rd, (rs < rt) as u32),
+            op::MUL => self.set_reg(ins.rd, rs.wrapping_mul(rt)),
+            op::DIVU => self.set_reg(ins.rd, rs.checked_div(rt).unwrap_or(u32::MAX)),
+            op::REMU => self.set_reg(ins.rd, rs.checked_rem(rt).unwrap_or(rs)),
+
+            // ALU (immediate)
+            op::ADDI => self.set_reg(ins.rd, rs.wrapping_add(ins.simm())),
+            op::ANDI => self.set_reg(ins.rd, rs & ins.zimm()),
+            op::ORI => self.set_reg(ins.rd, rs | ins.zimm()),
+            op::XORI => self.set_reg(ins.rd, rs ^ ins.zimm()),
+            op::SLTI => self.set_reg(ins.rd, ((rs as i32) < (ins.simm() as i32)) as u32),
+            op::SLLI => self.set_reg(ins.rd, rs << (ins.imm & 31)),
+            op::SRLI => self.set_reg(ins.rd, rs >> (ins.imm & 31)),
+            op::SRAI => self.set_reg(ins.rd, ((rs as i32) >> (ins.imm & 31)) as u32),
+            op::LUI => self.set_reg(ins.rd, ins.zimm() << 16),
+
+            // Loads
+            op::LW | op::LH | op::LHU | op::LB | op::LBU => {
+                let addr = rs.wrapping_add(ins.simm());
+                let val = match ins.opcode {
+                    op::LW => bus.read_u32(addr)?,
+                    op::LH => bus.read_u16(addr)? as i16 as i32 as u32,
+                    op::LHU => bus.read_u16(addr)? as u32,
+                    op::LB => bus.read_u8(addr)? as i8 as i32 as u32,
+                    _ => bus.read_u8(addr)? as u32,
+                };
+                self.set_reg(ins.rd, val);
+            },
+
+            // Stores
+            op::SW | op::SH | op::SB => {
+                let addr = rs.wrapping_add(ins.simm());
+                match ins.opcode {
+                    op::SW => bus.write_u32(addr, rd_val)?,
+                    op::SH => bus.write_u16(addr, rd_val as u16)?,
+                    _ => bus.write_u8(addr, rd_val as u8)?,
+                }
+            },
+
+            // Branches (compare rd against rs)
+            op::BEQ | op::BNE | op::BLT | op::BGE | op::BLTU | op::BGEU => {
+                let taken = match ins.opcode {
+                    op::BEQ => rd_val == rs,
+                    op::BNE => rd_val != rs,
+                    op::BLT => (rd_val as i32) < (rs as i32),
+                    op::BGE => (rd_val as i32) >= (rs as i32),
+                    op::BLTU => rd_val < rs,
+                    _ => rd_val >= rs,
+                };
+                if taken {
+                    next_pc = pc.wrapping_add(ins.simm() << 2);
+                }
+            },
+
+            // Calls
+            op::JAL => {
+                self.set_reg(REG_LINK, pc.wrapping_add(4));
+                next_pc = pc.wrapping_add(ins.simm26() << 2);
+            },
+            op::JALR => {
+                next_pc = rs.wrapping_add(ins.simm()) & !3;
+                self.set_reg(ins.rd, pc.wrapping_add(4));
+            },
+
+            op::SYS => {
+                trap = Some(Trap::Syscall(ins.zimm()));
+            },
+
+            _ => {
+                self.halted = true;
+                trap = Some(Trap::IllegalInstruction { pc, word });
+            },
+        }
+
+        if !self.halted {
+            self.pc = next_pc;
+        }
+        Ok(trap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hw::gpu::Gpu;
+    use crate::hw::ram::Ram;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    const BASE: u32 = 0x100;
+
+    // A bus with 64 KB of RAM holding `program` at BASE, and a cpu about to run it
+    fn load(program: &[u32]) -> (Cpu, Bus) {
+        let ram = Rc::new(RefCell::new(Ram::new(64 * 1024)));
+        let gpu = Rc::new(RefCell::new(Gpu::new(4, 4)));
+        let mut bus = Bus::new(ram, gpu);
+        for (i, word) in program.iter().enumerate() {
+            bus.write_u32(BASE + i as u32 * 4, *word).unwrap();
+        }
+        let mut cpu = Cpu::new();
+        cpu.reset(BASE, 64 * 1024);
+        (cpu, bus)
+    }
+
+    // Step until the core traps, returning the trap
+    fn run(cpu: &mut Cpu, bus: &mut Bus) -> Trap {
+        for _ in 0..1000 {
+            if let Some(trap) = cpu.step(bus) {
+                return trap;
+            }
+        }
+        panic!("program did not trap");
+    }
+
+    #[test]
+    fn alu_and_immediates() {
+        let (mut cpu, mut bus) = load(&[
+            encode_i(op::ADDI, 1, 0, 7),
+            encode_i(op::ADDI, 2, 0, (-3i16) as u16),
+            encode_r(op::ADD, 3, 1, 2),
+            encode_r(op::MUL, 4, 1, 2),
+            encode_r(op::SLT, 5, 2, 1),
+            encode_r(op::SLTU, 6, 2, 1),
+            encode_i(op::LUI, 7, 0, 0x1234),
+            encode_i(op::ORI, 7, 7, 0x5678),
+            encode_r(op::DIVU, 8, 1, 0),
+            encode_i(op::SRAI, 9, 2, 1),
+            encode_i(op::ADDI, 0, 0, 5), // r0 stays zero
+            encode_i(op::HALT, 0, 0, 0),
+        ]);
+        assert_eq!(run(&mut cpu, &mut bus), Trap::Halt);
+        assert_eq!(cpu.regs[3], 4);
+        assert_eq!(cpu.regs[4], (-21i32) as u32);
+        assert_eq!(cpu.regs[5], 1);
+        assert_eq!(cpu.regs[6], 0);
+        assert_eq!(cpu.regs[7], 0x1234_5678);
+        assert_eq!(cpu.regs[8], u32::MAX);
+        assert_eq!(cpu.regs[9], (-2i32) as u32);
+        assert_eq!(cpu.regs[0], 0);
+        assert_eq!(cpu.cycles, 12);
+    }
+
+    #[test]
+    fn loads_and_stores() {
+        let (mut cpu, mut bus) = load(&[
+            encode_i(op::ADDI, 1, 0, 0x2000),
+            encode_i(op::ADDI, 2, 0, (-2i16) as u16),
+            encode_i(op::SW, 2, 1, 0),
+            encode_i(op::LB, 3, 1, 0),
+            encode_i(op::LBU, 4, 1, 0),
+            encode_i(op::LHU, 5, 1, 2),
+            encode_i(op::SB, 0, 1, 0),
+            encode_i(op::LW, 6, 1, 0),
+            encode_i(op::HALT, 0, 0, 0),
+        ]);
+        assert_eq!(run(&mut cpu, &mut bus), Trap::Halt);
+        assert_eq!(cpu.regs[3], (-2i32) as u32);
+        assert_eq!(cpu.regs[4], 0xFE);
+        assert_eq!(cpu.regs[5], 0xFFFF);
+        assert_eq!(cpu.regs[6], 0xFFFF_FF00);
+    }
+
+    #[test]
+    fn branches_loop() {
+        // Sum 1..=10
+        let (mut cpu, mut bus) = load(&[
+            encode_i(op::ADDI, 1, 0, 10),
+            encode_r(op::ADD, 2, 2, 1),
+            encode_i(op::ADDI, 1, 1, (-1i16) as u16),
+            encode_i(op::BNE, 1, 0, (-2i16) as u16),
+            encode_i(op::HALT, 0, 0, 0),
+        ]);
+        assert_eq!(run(&mut cpu, &mut bus), Trap::Halt);
+        assert_eq!(cpu.regs[2], 55);
+    }
+
+    #[test]
+    fn calls_and_returns() {
+        let (mut cpu, mut bus) = load(&[
+            encode_j(op::JAL, 3),
+            encode_i(op::ADDI, 2, 2, 1),
+            encode_i(op::HALT, 0, 0, 0),
+            encode_i(op::ADDI, 2, 0, 40), // The callee
+            encode_i(op::JALR, 0, REG_LINK as u32, 0),
+        ]);
+        assert_eq!(run(&mut cpu, &mut bus), Trap::Halt);
+        assert_eq!(cpu.regs[2], 41);
+        assert_eq!(cpu.regs[REG_LINK], BASE + 4);
+    }
+
+    #[test]
+    fn syscalls_trap_and_resume() {
+        let (mut cpu, mut bus) = load(&[
+            encode_i(op::SYS, 0, 0, 2),
+            encode_i(op::HALT, 0, 0, 0),
+        ]);
+        assert_eq!(run(&mut cpu, &mut bus), Trap::Syscall(2));
+        assert_eq!(cpu.pc, BASE + 4);
+        assert_eq!(run(&mut cpu, &mut bus), Trap::Halt);
+    }
+
+    #[test]
+    fn faults_halt_the_core() {
+        let (mut cpu, mut bus) = load(&[
+            encode_i(op::ADDI, 1, 0, 2),
+            encode_i(op::LW, 2, 1, 0),
+        ]);
+        let trap = run(&mut cpu, &mut bus);
+        assert_eq!(trap, Trap::BusFault { pc: BASE + 4, fault: BusFault::Misaligned(2) });
+        assert!(cpu.halted);
+        assert_eq!(cpu.pc, BASE + 4);
+
+        let (mut cpu, mut bus) = load(&[0x3E << 26]); // Unassigned opcode
+        assert!(matches!(run(&mut cpu, &mut bus), Trap::IllegalInstruction { pc: BASE, .. }));
     }
 }
@@ -2,6 +2,7 @@ use std::rc::Rc;
 use std::cell::RefCell;
 use std::collections::VecDeque;
 
+use crate::host::Host;
 use crate::hw;
 use crate::sys;
 use crate::term;
@@ -14,6 +15,8 @@ pub enum EventType {
     MouseDown = 3,
     MouseUp = 4,
     MouseMove = 5,
+    FsChanged = 6, // Files were added from outside, views should reload
+    Wheel = 7,     // Mouse wheel at x, y; code is the lines moved as an i32, negative for up
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -24,6 +27,34 @@ pub struct SystemEvent {
     pub y: i32,
 }
 
+// KeyDown codes of keys without a char. The arrows are 1-4, Backspace, Tab and
+// Enter are 8, 9 and 10.
+pub const KEY_PAGE_UP: u32 = 5;
+pub const KEY_PAGE_DOWN: u32 = 6;
+pub const KEY_HOME: u32 = 11;
+pub const KEY_END: u32 = 12;
+pub const KEY_DELETE: u32 = 127;
+// Added to the code of one of those keys when Shift is held, and to a (lowercase)
+// char when Ctrl is. Both are above the last char, so codes never clash.
+pub const KEY_SHIFT: u32 = 0x1000_0000;
+pub const KEY_CTRL: u32 = 0x2000_0000;
+
+// Syscall numbers for native (wasmix-32) guest programs, passed in the `sys` immediate.
+// Arguments come in r4-r7, results go back in r2.
+pub const NATIVE_SYS_EXIT: u32 = 0;
+pub const NATIVE_SYS_PUTCHAR: u32 = 1;
+pub const NATIVE_SYS_WRITE: u32 = 2;
+pub const NATIVE_SYS_TICKS: u32 = 3;
+
+// A native image starts with this magic word, followed by its code. The whole image
+// is loaded at NATIVE_LOAD_ADDR and runs from the word after the magic. The stack
+// grows down from the top of RAM.
+pub const NATIVE_MAGIC: &[u8; 4] = b"WX32";
+pub const NATIVE_LOAD_ADDR: u32 = 0x0000_1000;
+
+// Where files uploaded while the desktop is running end up
+pub const UPLOAD_DIR: &str = "/local/user/desktop";
+
 pub enum MachineState {
     Bios,
     Kernel,
@@ -33,6 +64,7 @@ pub struct Machine {
     pub cpu: hw::cpu::Cpu,
     pub bus: hw::bus::Bus, // Holds Rcs
     pub bios: bios::Bios,
+    pub host: Rc<dyn Host>,
     
     // Peripherals / Firmware
     pub term: Rc<RefCell<term::Terminal>>,
@@ -41,6 +73,7 @@ pub struct Machine {
     pub wasm: sys::wasm::WasmRuntime,
     
     // Timing and State
+    pub cycles_per_tick: u32,
     pub tick_count: u64,
     pub last_time: f64,
     pub accumulator: f64,
@@ -56,18 +89,20 @@ pub struct Machine {
 }
 
 impl Machine {
-    pub fn new() -> Self {
+    pub fn new(host: Rc<dyn Host>) -> Self {
         // Hardware Init
         let ram = Rc::new(RefCell::new(hw::ram::Ram::new(16 * 1024 * 1024))); // 16 MB RAM
         let gpu = Rc::new(RefCell::new(hw::gpu::Gpu::new(512, 512))); // VRAM
         let bus = hw::bus::Bus::new(ram.clone(), gpu.clone());
         let cpu = hw::cpu::Cpu::new();
-        let bios = bios::Bios::new();
+        let bios = bios::Bios::new(host.clone());
         
         // Firmware/Software Init
-        let term = Rc::new(RefCell::new(term::Terminal::new(64, 32)));
-        let shell = Rc::new(RefCell::new(sys::shell::Shell::new()));
-        let fs = Rc::new(RefCell::new(sys::fs::FileSystem::new(10))); // 10 MB disk
+        let mut console = term::Terminal::new(64, 32);
+        console.show_cursor(false); // Hidden for the BIOS
+        let term = Rc::new(RefCell::new(console));
+        let shell = Rc::new(RefCell::new(sys::shell::Shell::new(host.clone())));
+        let fs = Rc::new(RefCell::new(sys::fs::FileSystem::new(10, host.clone()))); // 10 MB disk
         
         // Shared State
         let gui_mode = Rc::new(RefCell::new(false));
@@ -82,10 +117,11 @@ impl Machine {
             events.clone(),
             fs.clone(),
             should_reset.clone(),
-            shell.clone()
+            shell.clone(),
+            host.clone(),
         );
         
-        let now = web_sys::window().unwrap().performance().unwrap().now();
+        let now = host.now();
 
         Self {
             cpu,
@@ -94,6 +130,7 @@ impl Machine {
             shell,
             fs,
             wasm,
+            cycles_per_tick: 10_000,
             tick_count: 0,
             last_time: now,
             accumulator: 0.0,
@@ -102,6 +139,7 @@ impl Machine {
             last_sec_time: now,
             state: MachineState::Bios,
             bios,
+            host,
             gui_mode,
             should_reset,
             events,
@@ -109,17 +147,127 @@ impl Machine {
     }
 
     pub fn reboot(&mut self) {
-         let new_machine = Self::new();
+         let new_machine = Self::new(self.host.clone());
          *self = new_machine;
     }
 
+    // Store a file handed in by the host page (drag and drop or a file picker). It
+    // lands on the desktop while the GUI is up, otherwise in the shell's directory.
+    pub fn upload(&mut self, name: &str, data: &[u8]) -> Result<String, String> {
+        let name = name.rsplit(['/', '\\']).next().unwrap_or_default();
+        if name.is_empty() || name == "." || name == ".." {
+            return Err(format!("'{}': invalid file name", name));
+        }
+        let dir = if *self.gui_mode.borrow() { UPLOAD_DIR.to_string() } else { self.fs.borrow().cwd() };
+        let path = format!("{}/{}", dir.trim_end_matches('/'), name);
+
+        let mut fs = self.fs.borrow_mut();
+        let existed = fs.stat(&path).is_ok();
+        if let Err(e) = fs.write_file(&path, data) {
+            // Don't leave an empty file behind when the disk is full
+            if !existed {
+                let _ = fs.remove_entry(&path);
+            }
+            return Err(e);
+        }
+        // Uploaded programs should run without a chmod
+        if name.ends_with(".wasm") {
+            fs.chmod(&path, 0o755)?;
+        }
+        fs.sync();
+        drop(fs);
+
+        self.events.borrow_mut().push_back(SystemEvent {
+            event_type: EventType::FsChanged,
+            code: 0,
+            x: 0,
+            y: 0,
+        });
+        Ok(path)
+    }
+
     pub fn tick_process(&mut self) {
         self.wasm.tick();
+        // Write back whatever changed on /local this frame
+        self.fs.borrow_mut().sync();
+    }
+
+    // Copy a native wasmix-32 image into RAM and start the CPU after its magic.
+    pub fn load_native(&mut self, image: &[u8]) -> Result<(), String> {
+        if !image.starts_with(NATIVE_MAGIC) {
+            return Err("not a wasmix-32 image".to_string());
+        }
+        if !self.cpu.halted {
+            return Err("cpu is busy".to_string());
+        }
+        let ram_size = self.bus.ram.borrow().size;
+        let start = NATIVE_LOAD_ADDR as usize;
+        if start + image.len() > ram_size {
+            return Err("image too large".to_string());
+        }
+        self.bus.ram.borrow_mut().mem[start..start + image.len()].copy_from_slice(image);
+        self.cpu.reset(NATIVE_LOAD_ADDR + NATIVE_MAGIC.len() as u32, ram_size as u32);
+        Ok(())
+    }
+
+    // Run up to `cycles_per_tick` instructions, servicing traps as they come.
+    fn run_cpu(&mut self) {
+        for _ in 0..self.cycles_per_tick {
+            match self.cpu.step(&mut self.bus) {
+                None => {},
+                Some(hw::cpu::Trap::Halt) => break,
+                Some(hw::cpu::Trap::Syscall(num)) => self.native_syscall(num),
+                Some(hw::cpu::Trap::IllegalInstruction { pc, word }) => {
+                    let msg = format!("cpu: illegal instruction {:08x} at {:08x}\n", word, pc);
+                    self.term.borrow_mut().write_str(&msg);
+                    break;
+                },
+                Some(hw::cpu::Trap::BusFault { pc, fault }) => {
+                    let msg = format!("cpu: bus fault at {:08x}: {}\n", pc, fault);
+                    self.term.borrow_mut().write_str(&msg);
+                    break;
+                },
+            }
+        }
+    }
+
+    fn native_syscall(&mut self, num: u32) {
+        let arg0 = self.cpu.regs[hw::cpu::REG_ARG0];
+        let arg1 = self.cpu.regs[hw::cpu::REG_ARG0 + 1];
+        match num {
+            NATIVE_SYS_EXIT => {
+                self.cpu.halted = true;
+            },
+            NATIVE_SYS_PUTCHAR => {
+                if let Some(c) = char::from_u32(arg0) {
+                    self.term.borrow_mut().write_char(c);
+                }
+            },
+            NATIVE_SYS_WRITE => {
+                let bytes: Result<Vec<u8>, _> = (0..arg1)
+                    .map(|i| self.bus.read_u8(arg0.wrapping_add(i)))
+                    .collect();
+                match bytes {
+                    Ok(bytes) => {
+                        self.term.borrow_mut().write_str(&String::from_utf8_lossy(&bytes));
+                        self.cpu.regs[hw::cpu::REG_RET] = arg1;
+                    },
+                    Err(_) => self.cpu.regs[hw::cpu::REG_RET] = u32::MAX,
+                }
+            },
+            NATIVE_SYS_TICKS => {
+                self.cpu.regs[hw::cpu::REG_RET] = self.tick_count as u32;
+            },
+            _ => {
+                // Unknown syscall: report failure to the guest
+                self.cpu.regs[hw::cpu::REG_RET] = u32::MAX;
+            },
+        }
     }
 
     pub fn step(&mut self, _input_op: Option<String>) {
-         // CPU Cycle
-        self.cpu.step(&mut self.bus);
+         // CPU Cycles
+        self.run_cpu();
         
         self.tick_count += 1;
         self.frames_buffer += 1; // Count cycle for FPS
@@ -135,7 +283,7 @@ impl Machine {
                 // We need to pass bus.
                 
                 let mut term = self.term.borrow_mut();
-                if self.bios.step(&mut term, &mut self.bus, _input_op) {
+                if self.bios.step(&mut term, &mut self.bus, &self.fs, _input_op) {
                     // Handoff to Kernel
                     self.state = MachineState::Kernel;
                     
@@ -153,9 +301,14 @@ impl Machine {
                     let target = &self.bios.boot_target;
                     
                     if let Err(e) = self.wasm.load_from_path(target) {
-                        web_sys::console::log_1(&format!("Failed to boot {}: {}", target, e).into());
-                        // Fallback?
+                        self.host.log(&format!("Failed to boot {}: {}", target, e));
+                        // Fall back to the kernel's own shell, e.g. when built without apps
                         term.write_str(&format!("boot error: {}\n", e));
+                        term.write_str("starting the built-in shell\n\n");
+                        drop(term);
+                        let mut shell = self.shell.borrow_mut();
+                        shell.update_prompt(&self.fs);
+                        shell.draw_prompt(&self.term);
                     }
                     
                     // If we booted Terminal (or Desktop which uses Term for logs), we're good.
@@ -169,6 +322,13 @@ impl Machine {
                      self.reboot();
                      return;
                  }
+
+                // A native image `exec` found, it starts running on the next step
+                if let Some(image) = self.wasm.take_native() {
+                    if let Err(e) = self.load_native(&image) {
+                        self.term.borrow_mut().write_str(&format!("exec: {}\n", e));
+                    }
+                }
                  
                 // Process Input (Interrupts) - NOW handled by Machine.events + Shell on_key?
                 // `step` received `input_op` (legacy from lib.rs InputQueue).
@@ -177,7 +337,7 @@ impl Machine {
                 // Let's pop from events if it's a KeyDown? Use `events` directly?
                 // Shell needs to consume events.
                 
-                let events_guard = self.events.borrow_mut();
+                let mut events_guard = self.events.borrow_mut();
                 if let Some(event) = events_guard.front().cloned() {
                     // Check if it is a key event to pass to shell?
                     // Or does shell process the queue? 
@@ -216,6 +376,17 @@ impl Machine {
                          }
                     }
                 }
+                // The wheel scrolls the console through its scrollback (apps take it in GUI mode)
+                if !*self.gui_mode.borrow() {
+                    let mut term = self.term.borrow_mut();
+                    events_guard.retain(|e| match e.event_type {
+                        EventType::Wheel => {
+                            term.scroll_view(e.code as i32);
+                            false
+                        },
+                        _ => true,
+                    });
+                }
                 drop(events_guard); // Release borrow
                 
                 // Legacy Shell Input (Text Mode) - Can be removed if we fully deprecate internal shell
@@ -229,13 +400,12 @@ impl Machine {
                              // let mut gpu = self.bus.gpu.borrow_mut(); // Removed to avoid RefCell panic
                              let mut shell = self.shell.borrow_mut();
                              
-                             let mut events = self.events.borrow_mut();
-                             
-                             shell.on_key(&key, &term, &fs, &self.wasm, &mut events, self.tick_count, self.real_fps)
+                             // No events borrow held here - programs started from the shell read input through it
+                             shell.on_key(&key, &term, &fs, &self.wasm, self.tick_count, self.real_fps)
                          };
                          
                          if should_reboot {
-                             web_sys::console::log_1(&"system rebooting...".into());
+                             self.host.log("system rebooting...");
                              self.reboot(); 
                          }
                      }
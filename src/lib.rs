@@ -10,6 +10,7 @@ mod sys;
 mod term;
 
 mod bios;
+pub mod host;
 pub mod kernel;
 
 static INIT: Once = Once::new();
@@ -29,13 +30,14 @@ pub fn init_os() {
             *q.borrow_mut() = Some(VecDeque::new());
         });
         
-        let machine = kernel::Machine::new();
+        let host: std::rc::Rc<dyn host::Host> = std::rc::Rc::new(host::WebHost::new());
+        let machine = kernel::Machine::new(host.clone());
 
         MACHINE.with(|m| {
             *m.borrow_mut() = Some(machine);
         });
         
-        web_sys::console::log_1(&"virtual machine initialized".into());
+        host.log("virtual machine initialized");
     });
 }
 
@@ -44,7 +46,7 @@ pub fn tick() {
     MACHINE.with(|m| {
         let mut borrow = m.borrow_mut();
         if let Some(machine) = borrow.as_mut() {
-            let now = web_sys::window().unwrap().performance().unwrap().now();
+            let now = machine.host.now();
             let frame_time = now - machine.last_time;
             machine.last_time = now;
             
@@ -84,7 +86,9 @@ pub fn tick() {
             if !gui_mode {
                 let term = machine.term.borrow_mut();
                 let mut gpu = machine.bus.gpu.borrow_mut();
-                term.render(&mut gpu, 4, 0);
+                // Fill the left padding with the background to prevent artifacts
+                gpu.fill_rect(0, 0, 4, term.rows as i32 * 16, term.default_bg);
+                term.render(&mut *gpu, 4, 0);
             }
         }
     });
@@ -111,17 +115,26 @@ pub fn get_video_buffer_ptr() -> *const u8 {
 }
 
 #[wasm_bindgen]
-pub fn on_keydown(key: String, _ctrl: bool, _alt: bool, _meta: bool) {
+pub fn on_keydown(key: String, ctrl: bool, _alt: bool, _meta: bool, shift: bool) {
     MACHINE.with(|m| {
         if let Ok(mut borrow) = m.try_borrow_mut() {
             if let Some(machine) = borrow.as_mut() {
                 let code = match key.as_str() {
                     "Enter" => 10,
                     "Backspace" => 8,
+                    "Tab" => 9,
+                    "Home" => kernel::KEY_HOME,
+                    "End" => kernel::KEY_END,
+                    "Delete" => kernel::KEY_DELETE,
                     "ArrowUp" => 1, // Non-standard mapping
                     "ArrowDown" => 2,
                     "ArrowLeft" => 3,
                     "ArrowRight" => 4,
+                    "PageUp" if shift => kernel::KEY_PAGE_UP | kernel::KEY_SHIFT,
+                    "PageDown" if shift => kernel::KEY_PAGE_DOWN | kernel::KEY_SHIFT,
+                    "PageUp" => kernel::KEY_PAGE_UP,
+                    "PageDown" => kernel::KEY_PAGE_DOWN,
+                    k if k.len() == 1 && ctrl => kernel::KEY_CTRL | k.to_ascii_lowercase().chars().next().unwrap() as u32,
                     k if k.len() == 1 => k.chars().next().unwrap() as u32,
                     _ => 0,
                 };
@@ -137,6 +150,11 @@ pub fn on_keydown(key: String, _ctrl: bool, _alt: bool, _meta: bool) {
     });
     
     // Keep legacy input queue for Shell
+    let key = match key.as_str() {
+        "PageUp" | "PageDown" if shift => format!("Shift+{}", key),
+        k if k.len() == 1 && ctrl => format!("Ctrl+{}", k.to_ascii_lowercase()),
+        _ => key,
+    };
     INPUT_QUEUE.with(|q| {
         if let Some(queue) = q.borrow_mut().as_mut() {
             queue.push_back(key);
@@ -192,6 +210,23 @@ pub fn on_mouseup(x: i32, y: i32, button: i32) {
     });
 }
 
+// `lines` is how far the wheel moved, negative for up
+#[wasm_bindgen]
+pub fn on_wheel(x: i32, y: i32, lines: i32) {
+    MACHINE.with(|m| {
+        if let Ok(mut borrow) = m.try_borrow_mut() {
+            if let Some(machine) = borrow.as_mut() {
+                machine.events.borrow_mut().push_back(kernel::SystemEvent {
+                    event_type: kernel::EventType::Wheel,
+                    code: lines as u32,
+                    x,
+                    y,
+                });
+            }
+        }
+    });
+}
+
 #[wasm_bindgen]
 pub fn on_mousemove(x: i32, y: i32) {
     MACHINE.with(|m| {
@@ -206,4 +241,56 @@ pub fn on_mousemove(x: i32, y: i32) {
             }
         }
     });
-}
\ No newline at end of file
+}
+
+// Tar archive of a path in the VM filesystem, for the page to offer as a download
+#[wasm_bindgen]
+pub fn export_fs_tar(path: String) -> Result<Vec<u8>, JsValue> {
+    MACHINE.with(|m| {
+        let borrow = m.borrow();
+        let machine = borrow.as_ref().ok_or_else(|| JsValue::from_str("machine not initialized"))?;
+        let archive = machine.fs.borrow().export_tar(&path);
+        archive.map_err(|e| JsValue::from_str(&e))
+    })
+}
+
+// Extract an uploaded tar archive into a directory of the VM filesystem
+#[wasm_bindgen]
+pub fn import_fs_tar(path: String, bytes: Vec<u8>) -> Result<usize, JsValue> {
+    MACHINE.with(|m| {
+        let borrow = m.borrow();
+        let machine = borrow.as_ref().ok_or_else(|| JsValue::from_str("machine not initialized"))?;
+        let mut fs = machine.fs.borrow_mut();
+        let count = fs.import_tar(&path, &bytes).map_err(|e| JsValue::from_str(&e))?;
+        fs.sync();
+        Ok(count)
+    })
+}
+
+// Consistency check of the VM filesystem, as `fsck` prints it. With `repair` the
+// problems found are fixed and written back.
+#[wasm_bindgen]
+pub fn check_fs(repair: bool) -> Result<String, JsValue> {
+    MACHINE.with(|m| {
+        let borrow = m.borrow();
+        let machine = borrow.as_ref().ok_or_else(|| JsValue::from_str("machine not initialized"))?;
+        let mut fs = machine.fs.borrow_mut();
+        let report = fs.fsck(repair);
+        if repair {
+            fs.sync();
+        }
+        Ok(report.lines().join("\n"))
+    })
+}
+
+// A file dropped on the canvas or picked with a file input. Returns where it was stored.
+#[wasm_bindgen]
+pub fn upload_file(name: String, bytes: Vec<u8>) -> Result<String, JsValue> {
+    MACHINE.with(|m| {
+        let mut borrow = m.borrow_mut();
+        let machine = borrow.as_mut().ok_or_else(|| JsValue::from_str("machine not initialized"))?;
+        let path = machine.upload(&name, &bytes).map_err(|e| JsValue::from_str(&e))?;
+        machine.host.log(&format!("uploaded {}", path));
+        Ok(path)
+    })
+}
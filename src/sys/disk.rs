@@ -0,0 +1,390 @@
+//! On-disk format of the persistent `/local` tree.
+//!
+//! Every file and directory under `/local` is one record in the host's disk store
+//! (IndexedDB in the browser), keyed by its inode number. `/local` itself is
+//! `ROOT_INO`. A record is a little-endian u32 CRC-32 of the rest of the record, a
+//! u32 header length, a json header (the node kind and, for directories, the entry
+//! names with their inodes), then the raw file content. Names live in the parent's
+//! entries, so a rename inside `/local` only rewrites the directories involved.
+//!
+//! Record `SUPERBLOCK_INO` holds the magic, the format version and its own CRC.
+//! Older formats are migrated one version at a time on load:
+//!
+//! - 0: the whole tree as one json blob in localStorage (`LEGACY_STORAGE_KEY`)
+//! - 1: one record per inode, no superblock and no checksums
+//! - 2: the current format
+//!
+//! `LocalFs` marks inodes dirty as it changes them, and `sync` writes just those
+//! records back.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use serde::{Serialize, Deserialize};
+
+use crate::sys::fs::NodeType;
+use crate::sys::tmpfs::Node;
+
+pub const ROOT_INO: u32 = 1;
+pub const SUPERBLOCK_INO: u32 = 0;
+
+pub const FORMAT_VERSION: u32 = 2;
+const MAGIC: &[u8; 4] = b"WXFS";
+
+pub type Records = HashMap<u32, Vec<u8>>;
+
+// Where legacy builds kept the whole tree as one json blob in localStorage
+pub const LEGACY_STORAGE_KEY: &str = "wasmix_fs_local";
+
+// Fields after `entries` came later; records without them get the defaults
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+struct Header {
+    dir: bool,
+    entries: Vec<(String, u32)>, // Directories only
+    symlink: bool, // Content is the target path
+    mode: Option<u32>,
+    uid: u32,
+    gid: u32,
+    created: f64,
+    modified: f64,
+    accessed: f64,
+}
+
+// CRC-32 (IEEE), bit by bit: records are small and written a few at a time
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn with_crc(body: Vec<u8>) -> Vec<u8> {
+    let mut out = Vec::with_capacity(4 + body.len());
+    out.extend_from_slice(&crc32(&body).to_le_bytes());
+    out.extend_from_slice(&body);
+    out
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> Option<u32> {
+    let b = bytes.get(at..at + 4)?;
+    Some(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+pub fn superblock() -> Vec<u8> {
+    let mut body = MAGIC.to_vec();
+    body.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    let crc = crc32(&body);
+    body.extend_from_slice(&crc.to_le_bytes());
+    body
+}
+
+// Format version of a set of records. Records without a superblock are version 1.
+pub fn format_version(records: &Records) -> Result<u32, String> {
+    let bytes = match records.get(&SUPERBLOCK_INO) {
+        Some(bytes) => bytes,
+        None => return Ok(1),
+    };
+    if bytes.len() != 12 || &bytes[0..4] != MAGIC {
+        return Err("bad superblock".to_string());
+    }
+    if read_u32(bytes, 8) != Some(crc32(&bytes[0..8])) {
+        return Err("superblock checksum mismatch".to_string());
+    }
+    read_u32(bytes, 4).ok_or_else(|| "bad superblock".to_string())
+}
+
+// Bring records of version `from` up to `FORMAT_VERSION`, one version at a time
+pub fn migrate(records: &mut Records, from: u32) -> Result<(), String> {
+    for version in from..FORMAT_VERSION {
+        match version {
+            1 => migrate_v1(records),
+            _ => return Err(format!("no migration from format version {}", version)),
+        }
+    }
+    records.insert(SUPERBLOCK_INO, superblock());
+    Ok(())
+}
+
+// 0 -> 1: split the legacy json tree into one record per inode. Legacy trees have
+// no metadata, so nodes get the defaults of new ones stamped `now`.
+pub fn migrate_v0(json: &str, now: f64) -> Result<Records, String> {
+    let mut root: Node = serde_json::from_str(json).map_err(|e| format!("legacy tree: {}", e))?;
+    upgrade_legacy(&mut root, now);
+    let mut next_ino = ROOT_INO + 1;
+    let mut unused = BTreeSet::new();
+    for child in root.children.values_mut() {
+        adopt(child, &mut next_ino, &mut unused);
+    }
+    root.ino = ROOT_INO;
+    let mut records = Records::new();
+    collect_v1(&root, &mut records);
+    Ok(records)
+}
+
+fn upgrade_legacy(node: &mut Node, now: f64) {
+    node.mode = match node.node_type {
+        NodeType::Directory => 0o755,
+        _ => 0o644,
+    };
+    node.stamp(now);
+    for child in node.children.values_mut() {
+        upgrade_legacy(child, now);
+    }
+}
+
+fn collect_v1(node: &Node, records: &mut Records) {
+    records.insert(node.ino, encode_v1(node));
+    for child in node.children.values() {
+        collect_v1(child, records);
+    }
+}
+
+// 1 -> 2: checksum every record
+fn migrate_v1(records: &mut Records) {
+    for bytes in records.values_mut() {
+        *bytes = with_crc(std::mem::take(bytes));
+    }
+}
+
+pub fn encode(node: &Node) -> Vec<u8> {
+    with_crc(encode_v1(node))
+}
+
+// A record without its checksum, as version 1 stored it
+fn encode_v1(node: &Node) -> Vec<u8> {
+    let header = Header {
+        dir: matches!(node.node_type, NodeType::Directory),
+        entries: node.children.iter().map(|(name, child)| (name.clone(), child.ino)).collect(),
+        symlink: matches!(node.node_type, NodeType::Symlink),
+        mode: Some(node.mode),
+        uid: node.uid,
+        gid: node.gid,
+        created: node.created,
+        modified: node.modified,
+        accessed: node.accessed.get(),
+    };
+    let header = serde_json::to_vec(&header).unwrap_or_default();
+    let mut out = Vec::with_capacity(4 + header.len() + node.content.len());
+    out.extend_from_slice(&(header.len() as u32).to_le_bytes());
+    out.extend_from_slice(&header);
+    out.extend_from_slice(&node.content);
+    out
+}
+
+fn decode(bytes: &[u8]) -> Result<(Header, &[u8]), String> {
+    let crc = read_u32(bytes, 0).ok_or("record too short")?;
+    if crc != crc32(&bytes[4..]) {
+        return Err("checksum mismatch".to_string());
+    }
+    let len = read_u32(bytes, 4).ok_or("record too short")? as usize;
+    let header = bytes.get(8..8 + len).ok_or("record too short")?;
+    let header: Header = serde_json::from_slice(header).map_err(|e| format!("bad record header: {}", e))?;
+    Ok((header, &bytes[8 + len..]))
+}
+
+// An entry `build_tree` had to leave out
+pub struct Damage {
+    pub path: Vec<String>, // Below /local
+    pub ino: u32,
+    pub error: String,
+}
+
+impl fmt::Display for Damage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = self.path.last().map_or("local", |n| n.as_str());
+        write!(f, "inode {} ({}): {}", self.ino, name, self.error)
+    }
+}
+
+// The `/local` tree as far as its records could be read
+pub struct Tree {
+    pub root: Node,
+    pub damage: Vec<Damage>,
+    pub orphans: Vec<u32>, // Records no directory links to
+}
+
+// Rebuild the `/local` tree from current-format records, starting at `ROOT_INO`.
+// Damaged entries are left out and described in `damage`, so whatever is still
+// readable can be recovered.
+pub fn build_tree(records: &Records) -> Tree {
+    let mut visited = BTreeSet::new();
+    let mut damage = Vec::new();
+    let root = build_node(records, ROOT_INO, &mut Vec::new(), &mut visited, &mut damage)
+        .unwrap_or_else(|| {
+            let mut root = Node::new_dir("local");
+            root.ino = ROOT_INO;
+            root
+        });
+    let orphans = records.keys()
+        .filter(|&&ino| ino != SUPERBLOCK_INO && !visited.contains(&ino))
+        .copied()
+        .collect::<BTreeSet<u32>>()
+        .into_iter()
+        .collect();
+    Tree { root, damage, orphans }
+}
+
+fn build_node(records: &Records, ino: u32, path: &mut Vec<String>, visited: &mut BTreeSet<u32>, damage: &mut Vec<Damage>) -> Option<Node> {
+    if !visited.insert(ino) {
+        damage.push(Damage { path: path.clone(), ino, error: "linked twice".to_string() });
+        return None;
+    }
+    let decoded = records.get(&ino)
+        .ok_or_else(|| "missing".to_string())
+        .and_then(|bytes| decode(bytes));
+    let (header, content) = match decoded {
+        Ok(decoded) => decoded,
+        Err(error) => {
+            damage.push(Damage { path: path.clone(), ino, error });
+            return None;
+        },
+    };
+    let name = path.last().map_or("local", |n| n.as_str());
+    let mut node = if header.dir {
+        let mut dir = Node::new_dir(name);
+        for (child_name, child_ino) in header.entries {
+            path.push(child_name);
+            let child = build_node(records, child_ino, path, visited, damage);
+            let child_name = path.pop().unwrap_or_default();
+            if let Some(child) = child {
+                dir.children.insert(child_name, child);
+            }
+        }
+        dir
+    } else if header.symlink {
+        Node::new_symlink(name, &String::from_utf8_lossy(content))
+    } else {
+        Node::new_file(name, content.to_vec())
+    };
+    node.ino = ino;
+    if let Some(mode) = header.mode {
+        node.mode = mode;
+    }
+    node.uid = header.uid;
+    node.gid = header.gid;
+    node.created = header.created;
+    node.modified = header.modified;
+    node.accessed.set(header.accessed);
+    Some(node)
+}
+
+// Give a subtree fresh inode numbers and mark all of it dirty
+pub fn adopt(node: &mut Node, next_ino: &mut u32, dirty: &mut BTreeSet<u32>) {
+    node.ino = *next_ino;
+    *next_ino += 1;
+    dirty.insert(node.ino);
+    for child in node.children.values_mut() {
+        adopt(child, next_ino, dirty);
+    }
+}
+
+pub fn collect_inos(node: &Node, out: &mut BTreeSet<u32>) {
+    out.insert(node.ino);
+    for child in node.children.values() {
+        collect_inos(child, out);
+    }
+}
+
+pub fn max_ino(node: &Node) -> u32 {
+    node.children.values().map(max_ino).fold(node.ino, u32::max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // /local with a directory `docs` (inode 2) holding `a.txt` (inode 3)
+    fn records() -> Records {
+        let mut file = Node::new_file("a.txt", b"hello".to_vec());
+        file.ino = 3;
+        file.mode = 0o600;
+        let mut docs = Node::new_dir("docs");
+        docs.ino = 2;
+        docs.children.insert("a.txt".to_string(), file);
+        let mut root = Node::new_dir("local");
+        root.ino = ROOT_INO;
+        root.children.insert("docs".to_string(), docs);
+
+        let mut records = Records::new();
+        collect_v1(&root, &mut records);
+        migrate_v1(&mut records);
+        records.insert(SUPERBLOCK_INO, superblock());
+        records
+    }
+
+    #[test]
+    fn computes_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn builds_the_tree_from_records() {
+        let records = records();
+        assert_eq!(format_version(&records), Ok(FORMAT_VERSION));
+        let tree = build_tree(&records);
+        assert!(tree.damage.is_empty() && tree.orphans.is_empty());
+        let root = tree.root;
+        let file = &root.children["docs"].children["a.txt"];
+        assert_eq!((file.ino, file.mode, file.content.as_slice()), (3, 0o600, b"hello".as_slice()));
+        assert_eq!(max_ino(&root), 3);
+        assert_eq!(encode(file), records[&3]);
+    }
+
+    #[test]
+    fn leaves_out_damaged_records() {
+        let mut records = records();
+        records.get_mut(&3).unwrap()[10] ^= 1;
+        let tree = build_tree(&records);
+        assert!(tree.root.children["docs"].children.is_empty());
+        assert_eq!(tree.damage.len(), 1);
+        assert_eq!(tree.damage[0].path, ["docs", "a.txt"]);
+        assert_eq!(tree.damage[0].to_string(), "inode 3 (a.txt): checksum mismatch");
+
+        records.remove(&3);
+        assert_eq!(build_tree(&records).damage[0].to_string(), "inode 3 (a.txt): missing");
+        records.insert(3, vec![1, 2]);
+        assert_eq!(build_tree(&records).damage[0].to_string(), "inode 3 (a.txt): record too short");
+
+        // Children of a damaged directory are left without a parent
+        records.insert(3, encode(&Node::new_file("a.txt", Vec::new())));
+        records.insert(9, encode(&Node::new_file("lost", Vec::new())));
+        records.get_mut(&2).unwrap()[10] ^= 1;
+        let tree = build_tree(&records);
+        assert_eq!(tree.damage[0].to_string(), "inode 2 (docs): checksum mismatch");
+        assert_eq!(tree.orphans, [3, 9]);
+    }
+
+    #[test]
+    fn checks_the_superblock() {
+        let mut records = records();
+        records.get_mut(&SUPERBLOCK_INO).unwrap()[4] = 9;
+        assert_eq!(format_version(&records).unwrap_err(), "superblock checksum mismatch");
+        records.insert(SUPERBLOCK_INO, b"nope".to_vec());
+        assert_eq!(format_version(&records).unwrap_err(), "bad superblock");
+        records.remove(&SUPERBLOCK_INO);
+        assert_eq!(format_version(&records), Ok(1));
+    }
+
+    #[test]
+    fn migrates_old_formats() {
+        let json = r#"{ "name": "local", "node_type": "Directory", "size": 0, "content": [], "children": {
+            "notes.txt": { "name": "notes.txt", "node_type": "File", "size": 2, "content": [104, 105], "children": {} } } }"#;
+        let mut records = migrate_v0(json, 1000.0).unwrap();
+        assert_eq!(format_version(&records), Ok(1));
+        // Version 1 records have no checksum to check
+        assert!(!build_tree(&records).damage.is_empty());
+
+        migrate(&mut records, 1).unwrap();
+        assert_eq!(format_version(&records), Ok(FORMAT_VERSION));
+        let tree = build_tree(&records);
+        assert!(tree.damage.is_empty());
+        let notes = &tree.root.children["notes.txt"];
+        assert_eq!((notes.content.as_slice(), notes.mode, notes.modified), (b"hi".as_slice(), 0o644, 1000.0));
+        assert!(migrate_v0("not json", 0.0).is_err());
+    }
+}
@@ -0,0 +1,173 @@
+//! Environment variables of a shell and the programs it starts.
+//!
+//! Every shell has its own `Env`, set with `export` and `unset`, and programs get a
+//! copy when they start (WASI `environ_get`, `sys_getenv`). Bare command names are
+//! looked up along `$PATH`.
+
+use std::collections::BTreeMap;
+
+use crate::sys::fs::FileSystem;
+use crate::sys::pkg;
+
+pub const DEFAULT_PATH: &str = "/bin:/local/bin";
+
+#[derive(Clone)]
+pub struct Env {
+    vars: BTreeMap<String, String>,
+    pub status: i32, // Exit status of the last command, `$?`
+}
+
+impl Default for Env {
+    fn default() -> Self {
+        let mut vars = BTreeMap::new();
+        for (name, value) in [("PATH", DEFAULT_PATH), ("HOME", "/local/user"), ("USER", "user")] {
+            vars.insert(name.to_string(), value.to_string());
+        }
+        Self { vars, status: 0 }
+    }
+}
+
+impl Env {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.vars.get(name).map(|v| v.as_str())
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) {
+        self.vars.insert(name.to_string(), value.to_string());
+    }
+
+    pub fn unset(&mut self, name: &str) {
+        self.vars.remove(name);
+    }
+
+    // What a `$NAME` in a command line stands for, empty when unset
+    pub fn lookup(&self, name: &str) -> String {
+        if name == "?" {
+            return self.status.to_string();
+        }
+        self.get(name).unwrap_or_default().to_string()
+    }
+
+    // "NAME=value" pairs sorted by name, as `env` prints them and programs get them
+    pub fn pairs(&self) -> Vec<String> {
+        self.vars.iter().map(|(name, value)| format!("{}={}", name, value)).collect()
+    }
+}
+
+// Where the program `name` is: a path when it has a '/', otherwise the first
+// `<dir>/<name>` or `<dir>/<name>.wasm` along $PATH, then an installed package
+pub fn find_program(fs: &FileSystem, env: &Env, name: &str) -> Option<String> {
+    if name.contains('/') {
+        return Some(name.to_string());
+    }
+    if name.is_empty() {
+        return None;
+    }
+    for dir in env.get("PATH").unwrap_or_default().split(':').filter(|d| !d.is_empty()) {
+        let dir = dir.trim_end_matches('/');
+        for candidate in [format!("{}/{}", dir, name), format!("{}/{}.wasm", dir, name)] {
+            if matches!(fs.stat(&candidate), Ok(stat) if stat.is_executable()) {
+                return Some(candidate);
+            }
+        }
+    }
+    pkg::find(fs, name)
+}
+
+// Names of the programs along $PATH, without ".wasm", for completion
+pub fn programs(fs: &FileSystem, env: &Env) -> Vec<String> {
+    let mut names = Vec::new();
+    for dir in env.get("PATH").unwrap_or_default().split(':').filter(|d| !d.is_empty()) {
+        if let Ok(entries) = fs.read_dir(dir) {
+            for (name, stat) in entries {
+                if stat.is_executable() {
+                    names.push(name.strip_suffix(".wasm").unwrap_or(&name).to_string());
+                }
+            }
+        }
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::native::NativeHost;
+    use std::rc::Rc;
+
+    // A filesystem with `files` as (path, mode) and $PATH set to `path`
+    fn setup(files: &[(&str, u32)], path: &str) -> (FileSystem, Env) {
+        let mut fs = FileSystem::new(1, Rc::new(NativeHost::new()));
+        while fs.poll_disk().is_none() {}
+        for &(file, mode) in files {
+            fs.mkdir_all(&file[..file.rfind('/').unwrap()]).unwrap();
+            fs.write_file(file, b"").unwrap();
+            fs.chmod(file, mode).unwrap();
+        }
+        let mut env = Env::new();
+        env.set("PATH", path);
+        (fs, env)
+    }
+
+    #[test]
+    fn takes_the_first_match_along_path() {
+        let (fs, env) = setup(&[("/tmp/a/x", 0o755), ("/tmp/b/x", 0o755), ("/tmp/a/y.wasm", 0o755), ("/tmp/b/y", 0o755)],
+                              "/tmp/b:/tmp/a");
+        assert_eq!(find_program(&fs, &env, "x").as_deref(), Some("/tmp/b/x"));
+        assert_eq!(find_program(&fs, &env, "y").as_deref(), Some("/tmp/b/y"));
+        assert_eq!(find_program(&fs, &env, "nope"), None);
+        assert_eq!(find_program(&fs, &env, ""), None);
+        // Paths are taken as they are
+        assert_eq!(find_program(&fs, &env, "./nope").as_deref(), Some("./nope"));
+    }
+
+    #[test]
+    fn falls_back_to_the_wasm_suffix() {
+        let (fs, env) = setup(&[("/tmp/a/y.wasm", 0o755), ("/tmp/a/z", 0o755), ("/tmp/a/z.wasm", 0o755)], "/tmp/a");
+        assert_eq!(find_program(&fs, &env, "y").as_deref(), Some("/tmp/a/y.wasm"));
+        assert_eq!(find_program(&fs, &env, "y.wasm").as_deref(), Some("/tmp/a/y.wasm"));
+        assert_eq!(find_program(&fs, &env, "z").as_deref(), Some("/tmp/a/z"));
+    }
+
+    #[test]
+    fn skips_files_that_are_not_executable() {
+        let (fs, env) = setup(&[("/tmp/a/x", 0o644), ("/tmp/a/x.wasm", 0o644), ("/tmp/b/x.wasm", 0o755)], "/tmp/a:/tmp/b");
+        assert_eq!(find_program(&fs, &env, "x").as_deref(), Some("/tmp/b/x.wasm"));
+        assert_eq!(programs(&fs, &env), ["x"]);
+    }
+
+    #[test]
+    fn ignores_empty_entries_and_trailing_slashes() {
+        let (fs, env) = setup(&[("/tmp/a/x", 0o755)], "::/tmp/a/:");
+        assert_eq!(find_program(&fs, &env, "x").as_deref(), Some("/tmp/a/x"));
+        assert_eq!(programs(&fs, &env), ["x"]);
+
+        let (fs, env) = setup(&[("/tmp/a/x", 0o755)], "");
+        assert_eq!(find_program(&fs, &env, "x"), None);
+    }
+
+    #[test]
+    fn falls_back_to_installed_packages() {
+        let (mut fs, env) = setup(&[("/tmp/a/p", 0o755), ("/local/apps/q/q.wasm", 0o755)], "/tmp/a");
+        let manifest = r#"{ "name": "q", "version": "1", "entry": "q.wasm" }"#;
+        fs.write_file("/local/apps/q/manifest.json", manifest.as_bytes()).unwrap();
+        assert_eq!(find_program(&fs, &env, "q").as_deref(), Some("/local/apps/q/q.wasm"));
+        assert_eq!(find_program(&fs, &env, "p").as_deref(), Some("/tmp/a/p"));
+        assert_eq!(find_program(&fs, &env, "apps"), None);
+    }
+
+    #[test]
+    fn expands_status_and_unset_variables() {
+        let mut env = Env::new();
+        env.status = 127;
+        env.set("GREETING", "hi");
+        assert_eq!((env.lookup("?"), env.lookup("GREETING"), env.lookup("NOPE")), ("127".into(), "hi".into(), String::new()));
+        env.unset("GREETING");
+        assert_eq!(env.lookup("GREETING"), "");
+        assert!(env.pairs().starts_with(&["HOME=/local/user".to_string()]));
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+
+// Open flags for sys_fs_open (may be or'ed together)
+pub const O_READ: i32 = 1;
+pub const O_WRITE: i32 = 2;
+pub const O_CREATE: i32 = 4;
+pub const O_TRUNC: i32 = 8;
+pub const O_APPEND: i32 = 16;
+
+// Whence values for sys_fs_seek
+pub const SEEK_SET: i32 = 0;
+pub const SEEK_CUR: i32 = 1;
+pub const SEEK_END: i32 = 2;
+
+// 0-2 are reserved for stdio, 3 and 4 are the "/" and "." preopens used by wasi
+// guests. "." is the directory the process was started in.
+pub const PREOPEN_FD: i32 = 3;
+pub const CWD_FD: i32 = 4;
+const FIRST_FD: i32 = 5;
+
+// Name a preopened descriptor is announced to wasi guests under
+pub fn preopen_name(fd: i32) -> Option<&'static str> {
+    match fd {
+        PREOPEN_FD => Some("/"),
+        CWD_FD => Some("."),
+        _ => None,
+    }
+}
+
+pub struct OpenFile {
+    pub path: String, // Absolute, resolved at open time
+    pub flags: i32,
+    pub pos: usize,
+    pub is_dir: bool,
+}
+
+impl OpenFile {
+    pub fn file(path: String, flags: i32) -> Self {
+        Self { path, flags, pos: 0, is_dir: false }
+    }
+
+    pub fn dir(path: String) -> Self {
+        Self { path, flags: O_READ, pos: 0, is_dir: true }
+    }
+
+    pub fn readable(&self) -> bool {
+        self.flags & O_READ != 0
+    }
+
+    pub fn writable(&self) -> bool {
+        self.flags & (O_WRITE | O_APPEND) != 0
+    }
+}
+
+// Per-process file descriptor table
+pub struct FdTable {
+    files: HashMap<i32, OpenFile>,
+    next_fd: i32,
+}
+
+impl FdTable {
+    pub fn new(cwd: &str) -> Self {
+        let mut files = HashMap::new();
+        files.insert(PREOPEN_FD, OpenFile::dir("/".to_string()));
+        files.insert(CWD_FD, OpenFile::dir(cwd.to_string()));
+        Self {
+            files,
+            next_fd: FIRST_FD,
+        }
+    }
+
+    pub fn insert(&mut self, file: OpenFile) -> i32 {
+        let fd = self.next_fd;
+        self.next_fd += 1;
+        self.files.insert(fd, file);
+        fd
+    }
+
+    pub fn get(&self, fd: i32) -> Option<&OpenFile> {
+        self.files.get(&fd)
+    }
+
+    pub fn get_mut(&mut self, fd: i32) -> Option<&mut OpenFile> {
+        self.files.get_mut(&fd)
+    }
+
+    pub fn remove(&mut self, fd: i32) -> Option<OpenFile> {
+        self.files.remove(&fd)
+    }
+
+    // Move `from` onto `to`, closing whatever `to` held
+    pub fn renumber(&mut self, from: i32, to: i32) -> bool {
+        match self.files.remove(&from) {
+            Some(file) => {
+                self.files.insert(to, file);
+                true
+            },
+            None => false,
+        }
+    }
+}
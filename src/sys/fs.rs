@@ -1,315 +1,620 @@
-use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::rc::Rc;
 use serde::{Serialize, Deserialize};
 
+use crate::host::Host;
+use crate::sys::imagefs::ImageFs;
+use crate::sys::localfs::LocalFs;
+use crate::sys::pkg;
+use crate::sys::tar;
+use crate::sys::tmpfs::{TmpFs, TMPFS_SIZE};
+use crate::sys::vfs::{FsckReport, MountStatus, Vfs, READ_ONLY};
+
 
 #[derive(Clone, Serialize, Deserialize)]
 #[allow(dead_code)]
 pub enum NodeType {
     File,
     Directory,
+    Symlink,
 }
 
-#[derive(Clone, Serialize, Deserialize)]
-#[allow(dead_code)]
-pub struct Node {
-    pub name: String,
+pub struct Stat {
     pub node_type: NodeType,
-    pub size: usize, // Bytes
-    pub children: HashMap<String, Node>, // For directories
-    pub content: Vec<u8>, // For files
+    pub size: usize,
+    pub mode: u32, // Permission bits
+    pub uid: u32,
+    pub gid: u32,
+    pub created: f64, // Wall clock ms
+    pub modified: f64,
+    pub accessed: f64,
 }
 
-impl Node {
-    pub fn new_dir(name: &str) -> Self {
-        Self {
-            name: name.to_string(),
-            node_type: NodeType::Directory,
-            size: 0,
-            children: HashMap::new(),
-            content: Vec::new(),
-        }
+impl Stat {
+    pub fn is_executable(&self) -> bool {
+        matches!(self.node_type, NodeType::File) && self.mode & 0o111 != 0
     }
+}
 
-    #[allow(dead_code)]
-    pub fn new_file(name: &str, content: Vec<u8>) -> Self {
-        Self {
-            name: name.to_string(),
-            node_type: NodeType::File,
-            size: content.len(),
-            children: HashMap::new(),
-            content,
-        }
-    }
+// Symlinks followed while resolving one path before giving up on a loop
+const MAX_SYMLINKS: usize = 40;
+
+struct Mount {
+    path: Vec<String>,
+    fs: Box<dyn Vfs>,
+}
+
+pub struct MountInfo {
+    pub path: String,
+    pub fs_type: &'static str,
+    pub read_only: bool,
+    pub used: usize, // Bytes
+    pub capacity: usize,
 }
 
 pub struct FileSystem {
-    pub root: Node,
+    mounts: Vec<Mount>, // The root filesystem is always first
     pub current_path: Vec<String>,
-    pub total_space: usize,
-    pub used_space: usize,
+    disk_size: usize, // Quota of the localfs store, in bytes
+    host: Rc<dyn Host>,
 }
 
 impl FileSystem {
-    pub fn new(size_mb: usize) -> Self {
+    // `disk_mb` is the size of the persistent local disk
+    pub fn new(disk_mb: usize, host: Rc<dyn Host>) -> Self {
         let mut fs = Self {
-            root: Node::new_dir("/"),
+            mounts: vec![Mount { path: Vec::new(), fs: Box::new(TmpFs::new(host.clone(), TMPFS_SIZE)) }],
             current_path: Vec::new(),
-            total_space: size_mb * 1024 * 1024,
-            used_space: 0,
+            disk_size: disk_mb * 1024 * 1024,
+            host,
         };
-        // Initialize standard directories
-        fs.mkdir_internal("local").unwrap();
-        fs.mkdir_internal("tmp").unwrap();
-        fs.mkdir_internal("bin").unwrap();
-        
-        // Initialize User Directories
-        fs.current_path.push("local".to_string());
-        fs.mkdir_internal("user").unwrap();
-        
-        fs.current_path.push("user".to_string());
-        fs.mkdir_internal("desktop").unwrap();
-        
-        // Return to root
-        fs.current_path.clear();
-
-        // Preload hello.wasm
-        let hello_wasm = include_bytes!(r"../../apps/hello/target/wasm32-unknown-unknown/release/hello.wasm");
-        if let Some(bin) = fs.root.children.get_mut("bin") {
-             bin.children.insert("hello.wasm".to_string(), Node::new_file("hello.wasm", hello_wasm.to_vec()));
-        }
-
-        // Preload math.wasm
-        let math_wasm = include_bytes!(r"../../apps/math/target/wasm32-unknown-unknown/release/math.wasm");
-         if let Some(bin) = fs.root.children.get_mut("bin") {
-             bin.children.insert("math.wasm".to_string(), Node::new_file("math.wasm", math_wasm.to_vec()));
-        }
-
-        // Preload desktop.wasm
-        let desktop_wasm = include_bytes!(r"../../apps/desktop/target/wasm32-unknown-unknown/release/desktop.wasm");
-         if let Some(bin) = fs.root.children.get_mut("bin") {
-             bin.children.insert("desktop.wasm".to_string(), Node::new_file("desktop.wasm", desktop_wasm.to_vec()));
-        }
-
-        // Preload terminal.wasm
-        let terminal_wasm = include_bytes!(r"../../apps/terminal/target/wasm32-unknown-unknown/release/terminal.wasm");
-         if let Some(bin) = fs.root.children.get_mut("bin") {
-             bin.children.insert("terminal.wasm".to_string(), Node::new_file("terminal.wasm", terminal_wasm.to_vec()));
-        }
-        
-        // Load persistent storage for "local"
-        fs.load_local_disk();
-        
+        // Initialize standard directories and mount their filesystems
+        for (dir, kind) in [("/bin", "image"), ("/tmp", "tmpfs"), ("/local", "localfs")] {
+            fs.mkdir(dir).unwrap();
+            fs.mount(kind, dir).unwrap();
+        }
+
+        // Initialize User Directories. If the local disk already has them this only
+        // touches the placeholder tree, which the load replaces.
+        let _ = fs.mkdir("/local/user");
+        let _ = fs.mkdir("/local/user/desktop");
+
         fs
     }
 
-    // Helper to get window.localStorage
-    fn get_storage() -> Option<web_sys::Storage> {
-        let window = web_sys::window()?;
-        window.local_storage().ok().flatten()
-    }
-    
-    fn save_local_disk(&self) {
-        if let Some(storage) = Self::get_storage() {
-            if let Some(local_node) = self.root.children.get("local") {
-                if let Ok(json) = serde_json::to_string(local_node) {
-                    let _ = storage.set_item("wasmix_fs_local", &json);
-                }
+    // Finish mounting filesystems whose storage loads asynchronously (the local disk
+    // in the browser). `None` while still loading, otherwise the total entries
+    // loaded, or the first mount that failed or came up in recovery.
+    pub fn poll_disk(&mut self) -> Option<MountStatus> {
+        let mut total = 0;
+        for mount in &mut self.mounts {
+            match mount.fs.poll()? {
+                MountStatus::Ready(n) => total += n,
+                status => return Some(status),
             }
         }
+        Some(MountStatus::Ready(total))
     }
 
-    fn load_local_disk(&mut self) {
-        if let Some(storage) = Self::get_storage() {
-             if let Ok(Some(json)) = storage.get_item("wasmix_fs_local") {
-                 if let Ok(node) = serde_json::from_str::<Node>(&json) {
-                     self.root.children.insert("local".to_string(), node);
-                 }
-             }
+    pub fn abort_load(&mut self, reason: &str) {
+        for mount in &mut self.mounts {
+            mount.fs.abort_load(reason);
         }
     }
 
-    // Internal mkdir without saving (used during init)
-    fn mkdir_internal(&mut self, path: &str) -> Result<(), String> {
-        if self.used_space + 4096 > self.total_space { 
-            return Err("disk full".to_string());
+    pub fn sync(&mut self) {
+        for mount in &mut self.mounts {
+            mount.fs.sync();
+        }
+    }
+
+    // Check every mount for inconsistent entries, repairing them if `repair` is
+    // set. Paths in the report are absolute.
+    pub fn fsck(&mut self, repair: bool) -> FsckReport {
+        let mut total = FsckReport::default();
+        for mount in &mut self.mounts {
+            let report = mount.fs.fsck(repair);
+            total.checked += report.checked;
+            for mut problem in report.problems {
+                problem.path.splice(0..0, mount.path.iter().cloned());
+                total.problems.push(problem);
+            }
         }
+        total
+    }
 
-        let path_clone = self.current_path.clone();
-        let target_dir = self.resolve_mut_dir(&path_clone)?;
-        if target_dir.children.contains_key(path) {
-            return Err("directory exists".to_string());
+    // Mount a new `kind` filesystem on an existing directory
+    pub fn mount(&mut self, kind: &str, path: &str) -> Result<(), String> {
+        let parts = self.resolve_path(path);
+        if self.mounts.iter().any(|m| m.path == parts) {
+            return Err(format!("'{}' is already a mount point", path));
+        }
+        if !self.is_dir(path) {
+            return Err(format!("'{}' is not a directory", path));
         }
+        let fs: Box<dyn Vfs> = match kind {
+            "tmpfs" => Box::new(TmpFs::new(self.host.clone(), TMPFS_SIZE)),
+            "image" => {
+                // Apps bundled into the kernel, by their entry name
+                for error in pkg::bundle_errors() {
+                    self.host.log(&format!("/bin: {}", error));
+                }
+                let bundled = pkg::bundled();
+                let files: Vec<(&str, &[u8])> = bundled.iter().map(|(m, entry)| (m.entry.as_str(), *entry)).collect();
+                Box::new(ImageFs::new(&files, self.host.clone()))
+            },
+            // One store per host, so it can only be mounted once
+            "localfs" if self.mounts.iter().any(|m| m.fs.fs_type() == "localfs") => return Err("localfs is already mounted".to_string()),
+            "localfs" => Box::new(LocalFs::new(self.host.clone(), self.disk_size)),
+            _ => return Err(format!("unknown filesystem type '{}'", kind)),
+        };
+        self.mounts.push(Mount { path: parts, fs });
+        Ok(())
+    }
 
-        target_dir.children.insert(path.to_string(), Node::new_dir(path));
-        self.used_space += 4096;
+    pub fn umount(&mut self, path: &str) -> Result<(), String> {
+        let parts = self.resolve_path(path);
+        if parts.is_empty() {
+            return Err("cannot unmount the root filesystem".to_string());
+        }
+        let idx = self.mounts.iter().position(|m| m.path == parts).ok_or_else(|| format!("'{}' is not a mount point", path))?;
+        let nested = self.mounts.iter().any(|m| m.path.len() > parts.len() && m.path.starts_with(&parts));
+        if nested || self.current_path.starts_with(&parts) {
+            return Err("target is busy".to_string());
+        }
+        let mut mount = self.mounts.remove(idx);
+        mount.fs.sync();
         Ok(())
     }
 
+    pub fn mounts(&self) -> Vec<MountInfo> {
+        let mut list: Vec<MountInfo> = self.mounts.iter()
+            .map(|m| MountInfo {
+                path: Self::join_path(&m.path),
+                fs_type: m.fs.fs_type(),
+                read_only: m.fs.read_only(),
+                used: m.fs.used(),
+                capacity: m.fs.capacity(),
+            })
+            .collect();
+        list.sort_by(|a, b| a.path.cmp(&b.path));
+        list
+    }
+
+    // Mount point of the filesystem holding `path`
+    pub fn mount_of(&self, path: &str) -> Result<String, String> {
+        let parts = self.resolve_path(path);
+        if self.stat_parts(&parts).is_err() {
+            return Err(format!("'{}': no such file or directory", path));
+        }
+        let (idx, _) = self.route(&parts);
+        Ok(Self::join_path(&self.mounts[idx].path))
+    }
+
+    // Index of the mount holding `parts` (the longest matching mount point) and
+    // the path inside it
+    fn route(&self, parts: &[String]) -> (usize, Vec<String>) {
+        let (idx, mount) = self.mounts.iter().enumerate()
+            .filter(|(_, m)| parts.starts_with(&m.path))
+            .max_by_key(|(_, m)| m.path.len())
+            .unwrap_or((0, &self.mounts[0]));
+        (idx, parts[mount.path.len()..].to_vec())
+    }
+
+    fn vfs(&self, parts: &[String]) -> (&dyn Vfs, Vec<String>) {
+        let (idx, rel) = self.route(parts);
+        (self.mounts[idx].fs.as_ref(), rel)
+    }
+
+    fn vfs_mut(&mut self, parts: &[String]) -> (&mut dyn Vfs, Vec<String>) {
+        let (idx, rel) = self.route(parts);
+        (self.mounts[idx].fs.as_mut(), rel)
+    }
+
+    fn is_mount_point(&self, parts: &[String]) -> bool {
+        self.mounts.iter().any(|m| m.path.starts_with(parts))
+    }
+
     pub fn mkdir(&mut self, path: &str) -> Result<(), String> {
-        let res = self.mkdir_internal(path);
-        if res.is_ok() {
-            // If we are in /local or root creating local (handled by internal but general check)
-            // Simplest check: check if modified path affects local
-            // Ideally we check if absolute path starts with /local
-            // But strict path checking is complex here.
-            // Let's just save all the time for MVP safety or check if current path is empty (root) and target is local? No user won't create local.
-            // Check if we are inside local
-            if self.is_inside_local() {
-                self.save_local_disk();
+        let parts = self.resolve_entry(path);
+        let (fs, rel) = self.vfs_mut(&parts);
+        fs.mkdir(&rel)
+    }
+
+    // Create `path` and any missing directories above it, like `mkdir -p`
+    pub fn mkdir_all(&mut self, path: &str) -> Result<(), String> {
+        let parts = self.resolve_path(path);
+        for i in 1..=parts.len() {
+            let current = Self::join_path(&parts[..i]);
+            if !self.is_dir(&current) {
+                self.mkdir(&current)?;
             }
         }
-        res
+        Ok(())
     }
 
     pub fn create_file(&mut self, path: &str) -> Result<(), String> {
-        if self.used_space + 10 > self.total_space { 
-            return Err("Disk full".to_string());
+        // A dangling symlink is created through, like open(O_CREAT) does
+        let parts = self.resolve_path(path);
+        let (fs, rel) = self.vfs_mut(&parts);
+        fs.create(&rel)
+    }
+
+    // Create a symlink at `path` pointing to `target`, which need not exist
+    pub fn symlink(&mut self, target: &str, path: &str) -> Result<(), String> {
+        let parts = self.resolve_entry(path);
+        let (fs, rel) = self.vfs_mut(&parts);
+        fs.symlink(&rel, target)
+    }
+
+    pub fn readlink(&self, path: &str) -> Result<String, String> {
+        let (fs, rel) = self.vfs(&self.resolve_entry(path));
+        fs.readlink(&rel)
+    }
+
+    pub fn chmod(&mut self, path: &str, mode: u32) -> Result<(), String> {
+        let parts = self.resolve_path(path);
+        let (fs, rel) = self.vfs_mut(&parts);
+        fs.chmod(&rel, mode)
+    }
+
+    pub fn chown(&mut self, path: &str, uid: u32, gid: u32) -> Result<(), String> {
+        let parts = self.resolve_path(path);
+        let (fs, rel) = self.vfs_mut(&parts);
+        fs.chown(&rel, uid, gid)
+    }
+
+    pub fn remove_entry(&mut self, path: &str) -> Result<(), String> {
+        let target = self.resolve_entry(path);
+        if target.is_empty() {
+            return Err("invalid path".to_string());
         }
 
-        let path_clone = self.current_path.clone();
-        let target_dir = self.resolve_mut_dir(&path_clone)?;
-        if target_dir.children.contains_key(path) {
-             return Err("file or directory exists".to_string());
+        // Refuse to pull the cwd out from under the shell
+        if self.current_path.starts_with(&target) {
+            return Err("cannot remove current directory".to_string());
+        }
+        if self.is_mount_point(&target) {
+            return Err("cannot remove a mount point".to_string());
         }
 
-        target_dir.children.insert(path.to_string(), Node::new_file(path, Vec::new()));
-        self.used_space += 10; 
-        
-        if self.is_inside_local() {
-            self.save_local_disk();
+        let (fs, rel) = self.vfs_mut(&target);
+        fs.unlink(&rel)
+    }
+    
+    // Move or rename an entry. An existing file at the destination is replaced.
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<(), String> {
+        let src = self.resolve_entry(from);
+        let dst = self.resolve_entry(to);
+        if src.is_empty() || dst.is_empty() {
+            return Err("invalid path".to_string());
         }
-        
-        Ok(())
+
+        if src == dst {
+            return Ok(());
+        }
+        if dst.starts_with(&src) {
+            return Err("cannot move a directory into itself".to_string());
+        }
+        if self.current_path.starts_with(&src) {
+            return Err("cannot move current directory".to_string());
+        }
+        if self.is_mount_point(&src) {
+            return Err("cannot move a mount point".to_string());
+        }
+
+        let (src_mount, src_rel) = self.route(&src);
+        let (dst_mount, dst_rel) = self.route(&dst);
+        if src_mount != dst_mount {
+            // Across filesystems: copy, then remove the original
+            if self.mounts[src_mount].fs.read_only() {
+                return Err(READ_ONLY.to_string());
+            }
+            self.copy_parts(&src, &dst, true, from)?;
+            return self.remove_entry(from);
+        }
+
+        self.mounts[src_mount].fs.rename(&src_rel, &dst_rel)
     }
 
-    pub fn remove_entry(&mut self, path: &str) -> Result<(), String> {
-        let path_clone = self.current_path.clone();
-        let target_dir = self.resolve_mut_dir(&path_clone)?;
-        
-        if let Some(node) = target_dir.children.remove(path) {
-             // Reclaim space (Simplified)
-             self.used_space = self.used_space.saturating_sub(node.size);
-             if let NodeType::Directory = node.node_type {
-                 self.used_space = self.used_space.saturating_sub(4096);
-             }
-             
-             if self.is_inside_local() {
-                 self.save_local_disk();
-             }
-             Ok(())
-        } else {
-             Err("file or directory not found".to_string())
+    // Copy a file, or with `recursive` a whole directory tree. An existing file at the
+    // destination is replaced.
+    pub fn copy(&mut self, from: &str, to: &str, recursive: bool) -> Result<(), String> {
+        let src = self.resolve_path(from);
+        let dst = self.resolve_path(to);
+        self.copy_parts(&src, &dst, recursive, from)
+    }
+
+    fn copy_parts(&mut self, src: &[String], dst: &[String], recursive: bool, from: &str) -> Result<(), String> {
+        let (dst_name, dst_parent) = dst.split_last().ok_or("invalid path")?;
+
+        let stat = self.stat_parts(src).map_err(|_| "file or directory not found")?;
+        if let NodeType::Directory = stat.node_type {
+            if !recursive {
+                return Err(format!("'{}' is a directory (use -r)", from));
+            }
+            if dst.starts_with(src) {
+                return Err("cannot copy a directory into itself".to_string());
+            }
+        }
+        if src == dst {
+            return Err("source and destination are the same file".to_string());
+        }
+
+        let freed = match self.read_dir_parts(dst_parent) {
+            Ok(entries) => match entries.into_iter().find(|(name, _)| name == dst_name) {
+                Some((_, existing)) => match (&existing.node_type, &stat.node_type) {
+                    (NodeType::Directory, _) => return Err("destination is a directory".to_string()),
+                    (_, NodeType::Directory) => return Err("cannot overwrite a file with a directory".to_string()),
+                    _ => existing.size,
+                },
+                None => 0,
+            },
+            Err(_) => return Err("destination directory not found".to_string()),
+        };
+        // Check up front so a copy that cannot fit leaves nothing half written
+        let (dst_mount, _) = self.route(dst);
+        let target = &self.mounts[dst_mount].fs;
+        if self.content_size(src) > (target.capacity() + freed).saturating_sub(target.used()) {
+            return Err("disk full".to_string());
         }
+        self.copy_tree(src, dst)
     }
-    
-    fn is_inside_local(&self) -> bool {
-        // If current path starts with "local"
-        if !self.current_path.is_empty() && self.current_path[0] == "local" {
-            return true;
+
+    // Copy entry by entry, so it works between any two filesystems. Permission bits
+    // are kept and symlinks inside directories are copied as links.
+    fn copy_tree(&mut self, src: &[String], dst: &[String]) -> Result<(), String> {
+        let stat = self.stat_parts(src)?;
+        match stat.node_type {
+            NodeType::File => {
+                let data = {
+                    let (fs, rel) = self.vfs(src);
+                    fs.read(&rel, 0, stat.size)?
+                };
+                let (fs, rel) = self.vfs_mut(dst);
+                match fs.lookup(&rel) {
+                    Some(_) => fs.truncate(&rel, 0)?,
+                    None => fs.create(&rel)?,
+                }
+                fs.write(&rel, 0, &data)?;
+                fs.chmod(&rel, stat.mode)?;
+            },
+            NodeType::Symlink => {
+                let target = {
+                    let (fs, rel) = self.vfs(src);
+                    fs.readlink(&rel)?
+                };
+                let (fs, rel) = self.vfs_mut(dst);
+                if fs.lookup(&rel).is_some() {
+                    fs.unlink(&rel)?;
+                }
+                fs.symlink(&rel, &target)?;
+            },
+            NodeType::Directory => {
+                let (fs, rel) = self.vfs_mut(dst);
+                fs.mkdir(&rel)?;
+                fs.chmod(&rel, stat.mode)?;
+                for (name, _) in self.read_dir_parts(src)? {
+                    let mut child_src = src.to_vec();
+                    child_src.push(name.clone());
+                    let mut child_dst = dst.to_vec();
+                    child_dst.push(name);
+                    self.copy_tree(&child_src, &child_dst)?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    // Bytes of content in an entry and everything under it
+    fn content_size(&self, parts: &[String]) -> usize {
+        match self.stat_parts(parts) {
+            Ok(Stat { node_type: NodeType::Directory, .. }) => {
+                let entries = self.read_dir_parts(parts).unwrap_or_default();
+                entries.into_iter().map(|(name, _)| {
+                    let mut child = parts.to_vec();
+                    child.push(name);
+                    self.content_size(&child)
+                }).sum()
+            },
+            Ok(stat) => stat.size,
+            Err(_) => 0,
         }
-        false
     }
 
     pub fn cd(&mut self, path: &str) -> Result<(), String> {
-        if path == "/" {
-            self.current_path.clear();
-            return Ok(());
+        let target = self.resolve_path(path);
+        match self.stat_parts(&target) {
+            Ok(Stat { node_type: NodeType::Directory, .. }) => {
+                self.current_path = target;
+                Ok(())
+            },
+            Ok(_) => Err("not a directory".to_string()),
+            Err(_) => Err("directory not found".to_string()),
         }
-        if path == ".." {
-            self.current_path.pop();
-            return Ok(());
+    }
+
+    // Absolute path of the current directory ("/" at the root)
+    pub fn cwd(&self) -> String {
+        Self::join_path(&self.current_path)
+    }
+
+    pub fn join_path(parts: &[String]) -> String {
+        format!("/{}", parts.join("/"))
+    }
+
+    pub fn list_dir(&self, path: &str) -> Result<Vec<String>, String> {
+        Ok(self.read_dir(path)?.into_iter().map(|(name, _)| name).collect())
+    }
+
+    // Directory entries with their metadata, sorted by name
+    pub fn read_dir(&self, path: &str) -> Result<Vec<(String, Stat)>, String> {
+        self.read_dir_parts(&self.resolve_path(path))
+    }
+
+    fn read_dir_parts(&self, parts: &[String]) -> Result<Vec<(String, Stat)>, String> {
+        let (fs, rel) = self.vfs(parts);
+        fs.readdir(&rel)
+    }
+
+    pub fn read_file(&self, path: &str) -> Result<Vec<u8>, String> {
+        let parts = self.resolve_path(path);
+        match self.stat_parts(&parts) {
+            Ok(stat) => match stat.node_type {
+                NodeType::File => {
+                    let (fs, rel) = self.vfs(&parts);
+                    fs.read(&rel, 0, stat.size)
+                },
+                NodeType::Directory => Err(format!("'{}' is a directory", path)),
+                NodeType::Symlink => Err(format!("'{}': too many levels of symbolic links", path)),
+            },
+            Err(_) => Err(format!("file '{}' not found", path)),
         }
+    }
 
-        // Check if directory exists in current directory
-        // Validation logic
-        // We need to resolve the current directory first to check children
-        // We can't borrow self mutable and immutable at same time easily if we strictly use helpers
-        // But resolve_dir is using &self, correct.
-        
-        let path_clone = self.current_path.clone(); // Clone to avoid borrow conflict if we need mut self later, though here strictly we need to check existence first.
-        
-        let dir_node = self.resolve_dir(&path_clone).ok_or("Current path invalid")?;
-        
-        if let Some(child) = dir_node.children.get(path) {
-            if let NodeType::Directory = child.node_type {
-                 self.current_path.push(path.to_string());
-                 Ok(())
-            } else {
-                Err("not a directory".to_string())
-            }
-        } else {
-             Err("directory not found".to_string())
+    // Contents of a file that may be run: it must have an execute bit set
+    pub fn read_executable(&self, path: &str) -> Result<Vec<u8>, String> {
+        match self.stat(path) {
+            Ok(stat) if stat.is_executable() => self.read_file(path),
+            Ok(Stat { node_type: NodeType::File, .. }) => Err(format!("'{}': permission denied", path)),
+            _ => self.read_file(path),
         }
     }
 
-    pub fn list_dir(&self) -> Vec<String> {
-        let dir = self.resolve_dir(&self.current_path).unwrap_or(&self.root);
-        let mut names: Vec<String> = dir.children.keys().cloned().collect();
-        names.sort();
-        names
+    pub fn stat(&self, path: &str) -> Result<Stat, String> {
+        self.stat_parts(&self.resolve_path(path))
+    }
+
+    // Like `stat`, but a symlink is described itself rather than what it points to
+    pub fn lstat(&self, path: &str) -> Result<Stat, String> {
+        self.stat_parts(&self.resolve_entry(path))
     }
 
+    fn stat_parts(&self, parts: &[String]) -> Result<Stat, String> {
+        let (fs, rel) = self.vfs(parts);
+        fs.stat(&rel)
+    }
+
+    // Read up to `len` bytes starting at `offset`. Reading past the end returns nothing.
+    pub fn read_at(&self, path: &str, offset: usize, len: usize) -> Result<Vec<u8>, String> {
+        let (fs, rel) = self.vfs(&self.resolve_path(path));
+        fs.read(&rel, offset, len)
+    }
+
+    // Write `data` at `offset`, growing the file (zero filled) as needed
+    pub fn write_at(&mut self, path: &str, offset: usize, data: &[u8]) -> Result<usize, String> {
+        let parts = self.resolve_path(path);
+        let stat = self.stat_parts(&parts).map_err(|_| "file not found")?;
+        match stat.node_type {
+            NodeType::File => {},
+            NodeType::Directory => return Err("is a directory".to_string()),
+            NodeType::Symlink => return Err("too many levels of symbolic links".to_string()),
+        }
+
+        let (fs, rel) = self.vfs_mut(&parts);
+        fs.write(&rel, offset, data)
+    }
+
+    pub fn truncate(&mut self, path: &str, len: usize) -> Result<(), String> {
+        let parts = self.resolve_path(path);
+        let (fs, rel) = self.vfs_mut(&parts);
+        fs.truncate(&rel, len)
+    }
+
+    // Replace a file's content, creating it if needed
+    pub fn write_file(&mut self, path: &str, data: &[u8]) -> Result<(), String> {
+        if self.stat(path).is_err() {
+            self.create_file(path)?;
+        }
+        self.truncate(path, 0)?;
+        self.write_at(path, 0, data).map(|_| ())
+    }
+
+    // Add to the end of a file, creating it if needed
+    pub fn append_file(&mut self, path: &str, data: &[u8]) -> Result<(), String> {
+        let end = match self.stat(path) {
+            Ok(stat) => stat.size,
+            Err(_) => { self.create_file(path)?; 0 },
+        };
+        self.write_at(path, end, data).map(|_| ())
+    }
+
+    // Tar archive of the entry at `path` and everything under it
+    pub fn export_tar(&self, path: &str) -> Result<Vec<u8>, String> {
+        tar::pack(self, path)
+    }
+
+    // Extract a tar archive into the directory `dest`, returning the entry count
+    pub fn import_tar(&mut self, dest: &str, data: &[u8]) -> Result<usize, String> {
+        tar::unpack(self, dest, data)
+    }
+
+    pub fn is_dir(&self, path: &str) -> bool {
+        matches!(self.stat(path), Ok(Stat { node_type: NodeType::Directory, .. }))
+    }
+
+    // Resolve `path` and return the matching entry's path, expanding a trailing '*'
+    // in the last component against the entries of its parent directory.
     pub fn match_entry(&self, pattern: &str) -> Option<String> {
-        let dir = self.resolve_dir(&self.current_path).unwrap_or(&self.root);
-        
         // Direct match first
-        if dir.children.contains_key(pattern) {
+        if self.stat(pattern).is_ok() {
             return Some(pattern.to_string());
         }
 
         // Wildcard match
-        if pattern.ends_with('*') {
-            let prefix = &pattern[..pattern.len()-1];
-            for name in dir.children.keys() {
-                if name.starts_with(prefix) {
-                    return Some(name.clone());
-                }
-            }
-        }
-        
-        None
+        let prefix = pattern.strip_suffix('*')?;
+        let (dir_part, name_prefix) = match prefix.rfind('/') {
+            Some(idx) => (&prefix[..idx + 1], &prefix[idx + 1..]),
+            None => ("", prefix),
+        };
+        let entries = self.read_dir(dir_part).ok()?;
+        let (name, _) = entries.iter().find(|(name, _)| name.starts_with(name_prefix))?;
+        Some(format!("{}{}", dir_part, name))
     }
-    
-    // Helpers
-    pub fn resolve_dir(&self, path: &[String]) -> Option<&Node> {
-        let mut current_node = &self.root;
-        for part in path {
-            if let Some(node) = current_node.children.get(part) {
-                if let NodeType::Directory = node.node_type {
-                    current_node = node;
-                } else {
-                    return None;
-                }
-            } else {
-                return None;
+
+    // Path resolution
+    //
+    // Turns an absolute or cwd-relative path into normalized components, following
+    // symlinks. Handles ".", "..", repeated and trailing slashes; ".." at the root
+    // stays at the root.
+    pub fn resolve_path(&self, path: &str) -> Vec<String> {
+        self.walk(path, true)
+    }
+
+    // Resolve the directory part of `path` but not its last component, for
+    // operations on a symlink itself (rm, mv, lstat) or on a name being created
+    pub fn resolve_entry(&self, path: &str) -> Vec<String> {
+        self.walk(path, false)
+    }
+
+    fn walk(&self, path: &str, follow_last: bool) -> Vec<String> {
+        let mut parts = if path.starts_with('/') { Vec::new() } else { self.current_path.clone() };
+        let mut pending: VecDeque<String> = path.split('/').map(String::from).collect();
+        let mut links = 0;
+        while let Some(part) = pending.pop_front() {
+            match part.as_str() {
+                "" | "." => continue,
+                ".." => { parts.pop(); continue; },
+                _ => parts.push(part),
             }
-        }
-        Some(current_node)
-    }
-
-    fn resolve_mut_dir(&mut self, path: &[String]) -> Result<&mut Node, String> {
-        let mut current_node = &mut self.root;
-        for part in path {
-            // This requires re-borrowing which is tricky in a loop.
-            // A common pattern for simple trees is to keep path or use indexes/Arena.
-            // For MVP with deep recursion, we can just "drill down" if the borrow checker allows, 
-            // but loop borrow is hard.
-            // REWRITE: Simple "flat" map of paths? Or just support 1 level deep for MVP?
-            // To properly do tree traversal mutably: define a recursive helper or use Unsafe / Raw pointers.
-            // Or... since this is single threaded WASM, we can be a bit sloppy, but let's be safe.
-            // Alternative: `get_mut` on HashMap returns mutable ref, we can chain them?
-            // `current_node = current_node.children.get_mut(part)` works if we don't hold the previous ref.
-            
-            if let Some(node) = current_node.children.get_mut(part) {
-                if let NodeType::Directory = node.node_type {
-                    current_node = node;
-                } else {
-                    return Err(format!("{} is not a directory", part));
+            let last = pending.iter().all(|p| p.is_empty() || p == ".");
+            if (last && !follow_last) || links == MAX_SYMLINKS {
+                continue;
+            }
+            let (fs, rel) = self.vfs(&parts);
+            if let Some(NodeType::Symlink) = fs.lookup(&rel) {
+                if let Ok(target) = fs.readlink(&rel) {
+                    links += 1;
+                    parts.pop();
+                    if target.starts_with('/') {
+                        parts.clear();
+                    }
+                    for part in target.split('/').rev() {
+                        pending.push_front(part.to_string());
+                    }
                 }
-            } else {
-                return Err(format!("Directory {} not found", part));
             }
         }
-        Ok(current_node)
+        parts
     }
 }
@@ -0,0 +1,110 @@
+//! Read-only filesystem built into the kernel. `/bin` is mounted from it, so the
+//! bundled apps cannot be deleted or overwritten.
+
+use std::collections::BTreeSet;
+use std::rc::Rc;
+
+use crate::host::Host;
+use crate::sys::fs::{NodeType, Stat};
+use crate::sys::tmpfs::{Node, TmpFs};
+use crate::sys::vfs::{FsckReport, Vfs, READ_ONLY};
+
+pub struct ImageFs {
+    tree: TmpFs,
+}
+
+impl ImageFs {
+    // A flat image of executables, from `(name, content)` pairs
+    pub fn new(files: &[(&str, &[u8])], host: Rc<dyn Host>) -> Self {
+        let mut tree = TmpFs::new(host, usize::MAX);
+        let now = tree.now();
+        for (name, content) in files {
+            let mut node = Node::new_file(name, content.to_vec());
+            node.mode = 0o755;
+            node.stamp(now);
+            tree.root.children.insert(name.to_string(), node);
+        }
+        tree.recount();
+        Self { tree }
+    }
+}
+
+impl Vfs for ImageFs {
+    fn fs_type(&self) -> &'static str {
+        "image"
+    }
+
+    fn read_only(&self) -> bool {
+        true
+    }
+
+    fn used(&self) -> usize {
+        self.tree.used()
+    }
+
+    // The image is always full
+    fn capacity(&self) -> usize {
+        self.tree.used()
+    }
+
+    fn lookup(&self, path: &[String]) -> Option<NodeType> {
+        self.tree.lookup(path)
+    }
+
+    fn stat(&self, path: &[String]) -> Result<Stat, String> {
+        self.tree.stat(path)
+    }
+
+    fn readdir(&self, path: &[String]) -> Result<Vec<(String, Stat)>, String> {
+        self.tree.readdir(path)
+    }
+
+    fn read(&self, path: &[String], offset: usize, len: usize) -> Result<Vec<u8>, String> {
+        self.tree.read(path, offset, len)
+    }
+
+    fn write(&mut self, _path: &[String], _offset: usize, _data: &[u8]) -> Result<usize, String> {
+        Err(READ_ONLY.to_string())
+    }
+
+    fn truncate(&mut self, _path: &[String], _len: usize) -> Result<(), String> {
+        Err(READ_ONLY.to_string())
+    }
+
+    fn mkdir(&mut self, _path: &[String]) -> Result<(), String> {
+        Err(READ_ONLY.to_string())
+    }
+
+    fn create(&mut self, _path: &[String]) -> Result<(), String> {
+        Err(READ_ONLY.to_string())
+    }
+
+    fn symlink(&mut self, _path: &[String], _target: &str) -> Result<(), String> {
+        Err(READ_ONLY.to_string())
+    }
+
+    fn readlink(&self, path: &[String]) -> Result<String, String> {
+        self.tree.readlink(path)
+    }
+
+    fn chmod(&mut self, _path: &[String], _mode: u32) -> Result<(), String> {
+        Err(READ_ONLY.to_string())
+    }
+
+    fn chown(&mut self, _path: &[String], _uid: u32, _gid: u32) -> Result<(), String> {
+        Err(READ_ONLY.to_string())
+    }
+
+    fn unlink(&mut self, _path: &[String]) -> Result<(), String> {
+        Err(READ_ONLY.to_string())
+    }
+
+    fn rename(&mut self, _from: &[String], _to: &[String]) -> Result<(), String> {
+        Err(READ_ONLY.to_string())
+    }
+
+    // Problems are reported, but the image stays as it was built
+    fn fsck(&mut self, _repair: bool) -> FsckReport {
+        self.tree.check(false, &mut BTreeSet::new())
+    }
+}
@@ -0,0 +1,526 @@
+//! Persistent filesystem mounted at `/local`.
+//!
+//! The tree lives in memory like a tmpfs; every node also has an inode number, and
+//! the host's disk store keeps one record per inode (see `disk`). Changes mark
+//! inodes dirty and `sync` writes just those back.
+//!
+//! Records in an older format are migrated on load. Damaged records, or a format
+//! newer than this kernel knows, put the mount in read-only recovery: what can be
+//! read is there to copy out, and nothing is written back over the disk. What the
+//! load left out, and records no directory links to, show up in `fsck`, which
+//! deletes the unlinked ones when it may repair.
+
+use std::collections::BTreeSet;
+use std::rc::Rc;
+
+use crate::host::{DiskRecords, Host};
+use crate::sys::disk::{self, Damage, Records, Tree, FORMAT_VERSION, ROOT_INO, SUPERBLOCK_INO};
+use crate::sys::fs::{NodeType, Stat};
+use crate::sys::tmpfs::{Node, TmpFs};
+use crate::sys::vfs::{FsckReport, MountStatus, Problem, Vfs, READ_ONLY};
+
+// Nothing is written until the disk has been read, so a slow load cannot be
+// overwritten by the defaults.
+#[derive(Clone, PartialEq, Debug)]
+pub enum DiskState {
+    Loading,
+    Ready,
+    ReadOnly(String), // Recovery: the disk could not be fully read, and is left alone
+    Offline(String),  // Load failed, the tree lives in memory only
+}
+
+pub struct LocalFs {
+    tree: TmpFs,
+    host: Rc<dyn Host>,
+    pub disk: DiskState,
+    next_ino: u32,
+    dirty: BTreeSet<u32>,   // Inodes to write on the next sync
+    deleted: BTreeSet<u32>, // Inodes to delete on the next sync
+    write_superblock: bool,
+    loaded: usize, // Inodes in the tree when the load finished
+    damage: Vec<Damage>, // Entries the load left out
+    orphans: Vec<u32>,   // Records found on load that nothing links to
+}
+
+impl LocalFs {
+    // Start reading the disk. Hosts that answer right away (native) are ready on
+    // return, otherwise `poll` finishes the load later.
+    pub fn new(host: Rc<dyn Host>, capacity: usize) -> Self {
+        let mut tree = TmpFs::new(host.clone(), capacity);
+        tree.root.ino = ROOT_INO;
+        let mut fs = Self {
+            tree,
+            host,
+            disk: DiskState::Loading,
+            next_ino: ROOT_INO + 1,
+            dirty: BTreeSet::new(),
+            deleted: BTreeSet::new(),
+            write_superblock: false,
+            loaded: 0,
+            damage: Vec::new(),
+            orphans: Vec::new(),
+        };
+        fs.host.disk_load();
+        fs.poll();
+        fs
+    }
+
+    fn load(&mut self, records: DiskRecords) {
+        let mut records: Records = records.into_iter().collect();
+        let mut legacy = false;
+        if records.is_empty() {
+            match self.host.storage_get(disk::LEGACY_STORAGE_KEY) {
+                // Data from a build that kept /local in localStorage
+                Some(json) => match disk::migrate_v0(&json, self.tree.now()) {
+                    Ok(converted) => {
+                        records = converted;
+                        legacy = true;
+                    },
+                    // The blob stays where it is, for a build that can read it
+                    Err(e) => return self.recover(&records, format!("corrupt: {}", e)),
+                },
+                // First boot: the placeholder tree becomes the disk
+                None => {
+                    let root = &mut self.tree.root;
+                    for child in root.children.values_mut() {
+                        disk::adopt(child, &mut self.next_ino, &mut self.dirty);
+                    }
+                    self.dirty.insert(ROOT_INO);
+                    self.write_superblock = true;
+                    self.disk = DiskState::Ready;
+                    self.sync();
+                    return;
+                },
+            }
+        }
+
+        let version = match disk::format_version(&records) {
+            Ok(version) if version > FORMAT_VERSION => {
+                let reason = format!("format version {} is newer than this kernel's ({})", version, FORMAT_VERSION);
+                return self.recover(&records, reason);
+            },
+            Ok(version) => version,
+            Err(e) => return self.recover(&records, format!("corrupt: {}", e)),
+        };
+        if let Err(e) = disk::migrate(&mut records, version) {
+            return self.recover(&records, e);
+        }
+
+        let tree = disk::build_tree(&records);
+        if let Some(first) = tree.damage.first() {
+            let reason = match tree.damage.len() {
+                1 => format!("corrupt: {}", first),
+                n => format!("corrupt: {} (and {} more)", first, n - 1),
+            };
+            return self.recover(&records, reason);
+        }
+        self.set_tree(tree);
+        self.disk = DiskState::Ready;
+
+        if version < FORMAT_VERSION {
+            // Rewrite everything in the current format
+            self.dirty = self.inos(&[]);
+            self.write_superblock = true;
+            self.sync();
+            if legacy {
+                self.host.storage_remove(disk::LEGACY_STORAGE_KEY);
+            }
+        }
+    }
+
+    // Mount read-only with whatever can still be read. Nothing is written to the
+    // disk in this state, so the damaged records stay as they are.
+    fn recover(&mut self, records: &Records, reason: String) {
+        self.set_tree(disk::build_tree(records));
+        self.disk = DiskState::ReadOnly(reason);
+    }
+
+    fn set_tree(&mut self, tree: Tree) {
+        // Orphans keep their inodes until deleted, so their records aren't reused
+        let max_orphan = tree.orphans.iter().copied().max().unwrap_or(0);
+        self.next_ino = self.next_ino.max(disk::max_ino(&tree.root).max(max_orphan) + 1);
+        self.damage = tree.damage;
+        self.orphans = tree.orphans;
+        self.tree.root = tree.root;
+        self.tree.recount();
+        // Changes made to the placeholder tree while loading are gone with it
+        self.dirty.clear();
+        self.deleted.clear();
+    }
+
+    fn writable(&self) -> Result<(), String> {
+        match self.disk {
+            DiskState::ReadOnly(_) => Err(READ_ONLY.to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    fn collect_dirty(node: &Node, dirty: &BTreeSet<u32>, out: &mut DiskRecords) {
+        if dirty.contains(&node.ino) {
+            out.push((node.ino, disk::encode(node)));
+        }
+        for child in node.children.values() {
+            Self::collect_dirty(child, dirty, out);
+        }
+    }
+
+    // The entry at `path` changed: a file's content or a directory's entries
+    fn mark_dirty(&mut self, path: &[String]) {
+        if let Some(node) = self.tree.node(path) {
+            self.dirty.insert(node.ino);
+        }
+    }
+
+    // A new subtree appeared at `path`
+    fn adopt(&mut self, path: &[String]) {
+        if let Some(node) = self.tree.node_mut(path) {
+            disk::adopt(node, &mut self.next_ino, &mut self.dirty);
+        }
+    }
+
+    // Inodes of the subtree at `path`, for forgetting once it is gone
+    fn inos(&self, path: &[String]) -> BTreeSet<u32> {
+        let mut inos = BTreeSet::new();
+        if let Some(node) = self.tree.node(path) {
+            disk::collect_inos(node, &mut inos);
+        }
+        inos
+    }
+
+    fn forget(&mut self, inos: BTreeSet<u32>) {
+        for ino in inos {
+            self.dirty.remove(&ino);
+            self.deleted.insert(ino);
+        }
+    }
+
+    // Every node needs an inode of its own, or two entries would share one record.
+    // Repairs give the later ones a fresh inode, and rewrite their directory.
+    fn check_inos(&mut self, path: &mut Vec<String>, seen: &mut BTreeSet<u32>, repair: bool, report: &mut FsckReport) {
+        let mut names: Vec<String> = match self.tree.node(path) {
+            Some(node) => node.children.keys().cloned().collect(),
+            None => return,
+        };
+        names.sort();
+        for name in names {
+            path.push(name);
+            let ino = self.tree.node(path).map(|n| n.ino).unwrap_or_default();
+            let message = match ino {
+                ROOT_INO | SUPERBLOCK_INO => Some(format!("has the reserved inode {}", ino)),
+                _ if !seen.insert(ino) => Some(format!("inode {} is used twice", ino)),
+                _ => None,
+            };
+            if let Some(message) = message {
+                if repair {
+                    let fresh = self.next_ino;
+                    self.next_ino += 1;
+                    seen.insert(fresh);
+                    if let Some(node) = self.tree.node_mut(path) {
+                        node.ino = fresh;
+                    }
+                    self.dirty.insert(fresh);
+                    self.mark_dirty(Self::parent(path));
+                }
+                report.problems.push(Problem { path: path.clone(), message, fixed: repair });
+            }
+            self.check_inos(path, seen, repair, report);
+            path.pop();
+        }
+    }
+
+    fn parent(path: &[String]) -> &[String] {
+        &path[..path.len().saturating_sub(1)]
+    }
+}
+
+impl Vfs for LocalFs {
+    fn fs_type(&self) -> &'static str {
+        "localfs"
+    }
+
+    fn read_only(&self) -> bool {
+        matches!(self.disk, DiskState::ReadOnly(_))
+    }
+
+    // Usage is recounted from the loaded records, so it may exceed a smaller quota
+    fn used(&self) -> usize {
+        self.tree.used()
+    }
+
+    fn capacity(&self) -> usize {
+        self.tree.capacity()
+    }
+
+    fn lookup(&self, path: &[String]) -> Option<NodeType> {
+        self.tree.lookup(path)
+    }
+
+    fn stat(&self, path: &[String]) -> Result<Stat, String> {
+        self.tree.stat(path)
+    }
+
+    fn readdir(&self, path: &[String]) -> Result<Vec<(String, Stat)>, String> {
+        self.tree.readdir(path)
+    }
+
+    // Access times change in memory only, and reach the disk with the node's next write
+    fn read(&self, path: &[String], offset: usize, len: usize) -> Result<Vec<u8>, String> {
+        self.tree.read(path, offset, len)
+    }
+
+    fn write(&mut self, path: &[String], offset: usize, data: &[u8]) -> Result<usize, String> {
+        self.writable()?;
+        let n = self.tree.write(path, offset, data)?;
+        self.mark_dirty(path);
+        Ok(n)
+    }
+
+    fn truncate(&mut self, path: &[String], len: usize) -> Result<(), String> {
+        self.writable()?;
+        self.tree.truncate(path, len)?;
+        self.mark_dirty(path);
+        Ok(())
+    }
+
+    fn mkdir(&mut self, path: &[String]) -> Result<(), String> {
+        self.writable()?;
+        self.tree.mkdir(path)?;
+        self.adopt(path);
+        self.mark_dirty(Self::parent(path));
+        Ok(())
+    }
+
+    fn create(&mut self, path: &[String]) -> Result<(), String> {
+        self.writable()?;
+        self.tree.create(path)?;
+        self.adopt(path);
+        self.mark_dirty(Self::parent(path));
+        Ok(())
+    }
+
+    fn symlink(&mut self, path: &[String], target: &str) -> Result<(), String> {
+        self.writable()?;
+        self.tree.symlink(path, target)?;
+        self.adopt(path);
+        self.mark_dirty(Self::parent(path));
+        Ok(())
+    }
+
+    fn readlink(&self, path: &[String]) -> Result<String, String> {
+        self.tree.readlink(path)
+    }
+
+    fn chmod(&mut self, path: &[String], mode: u32) -> Result<(), String> {
+        self.writable()?;
+        self.tree.chmod(path, mode)?;
+        self.mark_dirty(path);
+        Ok(())
+    }
+
+    fn chown(&mut self, path: &[String], uid: u32, gid: u32) -> Result<(), String> {
+        self.writable()?;
+        self.tree.chown(path, uid, gid)?;
+        self.mark_dirty(path);
+        Ok(())
+    }
+
+    fn unlink(&mut self, path: &[String]) -> Result<(), String> {
+        self.writable()?;
+        let inos = self.inos(path);
+        self.tree.unlink(path)?;
+        self.forget(inos);
+        self.mark_dirty(Self::parent(path));
+        Ok(())
+    }
+
+    // Moves keep their inodes, so only the directories involved are rewritten
+    fn rename(&mut self, from: &[String], to: &[String]) -> Result<(), String> {
+        self.writable()?;
+        let replaced = self.inos(to);
+        self.tree.rename(from, to)?;
+        self.forget(replaced);
+        self.mark_dirty(Self::parent(from));
+        self.mark_dirty(Self::parent(to));
+        Ok(())
+    }
+
+    // Called from every `sync`, so the tree is only walked once, when the load ends
+    fn poll(&mut self) -> Option<MountStatus> {
+        if self.disk == DiskState::Loading {
+            match self.host.disk_poll() {
+                None => return None,
+                Some(Ok(records)) => self.load(records),
+                Some(Err(e)) => self.disk = DiskState::Offline(e),
+            }
+            self.loaded = self.inos(&[]).len();
+        }
+        match &self.disk {
+            DiskState::Offline(e) => Some(MountStatus::Failed(e.clone())),
+            DiskState::ReadOnly(reason) => Some(MountStatus::Recovery(self.loaded, reason.clone())),
+            _ => Some(MountStatus::Ready(self.loaded)),
+        }
+    }
+
+    fn abort_load(&mut self, reason: &str) {
+        if self.disk == DiskState::Loading {
+            self.disk = DiskState::Offline(reason.to_string());
+        }
+    }
+
+    // A disk in recovery is checked but left alone
+    fn fsck(&mut self, repair: bool) -> FsckReport {
+        let repair = repair && self.writable().is_ok();
+        let mut fixed = BTreeSet::new();
+        let mut report = self.tree.check(repair, &mut fixed);
+        self.dirty.extend(fixed);
+        self.check_inos(&mut Vec::new(), &mut BTreeSet::new(), repair, &mut report);
+
+        // Findings of the load, which only recovery leaves in place
+        for damage in &self.damage {
+            let message = format!("inode {}: {}, left out on load", damage.ino, damage.error);
+            report.problems.push(Problem { path: damage.path.clone(), message, fixed: false });
+        }
+        for &ino in &self.orphans {
+            let message = format!("inode {} is not linked from any directory", ino);
+            report.problems.push(Problem { path: Vec::new(), message, fixed: repair });
+        }
+        if repair {
+            self.deleted.extend(self.orphans.drain(..));
+        }
+        report
+    }
+
+    // Write back the inodes changed since the last sync, in one transaction
+    fn sync(&mut self) {
+        self.poll();
+        let idle = self.dirty.is_empty() && self.deleted.is_empty() && !self.write_superblock;
+        if self.disk != DiskState::Ready || idle {
+            return;
+        }
+        let mut puts = Vec::new();
+        if self.write_superblock {
+            puts.push((SUPERBLOCK_INO, disk::superblock()));
+            self.write_superblock = false;
+        }
+        Self::collect_dirty(&self.tree.root, &self.dirty, &mut puts);
+        let deletes: Vec<u32> = self.deleted.iter().copied().collect();
+        self.host.disk_write(puts, deletes);
+        self.dirty.clear();
+        self.deleted.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::native::NativeHost;
+
+    const CAPACITY: usize = 1 << 20;
+
+    fn path(p: &str) -> Vec<String> {
+        p.split('/').filter(|s| !s.is_empty()).map(String::from).collect()
+    }
+
+    // A fresh disk holding /docs/a.txt, with the host it lives on
+    fn disk() -> (Rc<NativeHost>, LocalFs) {
+        let host = Rc::new(NativeHost::new());
+        let mut fs = LocalFs::new(host.clone(), CAPACITY);
+        fs.mkdir(&path("docs")).unwrap();
+        fs.create(&path("docs/a.txt")).unwrap();
+        fs.write(&path("docs/a.txt"), 0, b"hello").unwrap();
+        fs.sync();
+        (host, fs)
+    }
+
+    fn ino(fs: &LocalFs, p: &str) -> u32 {
+        fs.tree.node(&path(p)).unwrap().ino
+    }
+
+    #[test]
+    fn persists_across_reloads() {
+        let (host, mut fs) = disk();
+        fs.rename(&path("docs/a.txt"), &path("b.txt")).unwrap();
+        fs.sync();
+
+        let mut fs = LocalFs::new(host, CAPACITY);
+        assert_eq!(fs.disk, DiskState::Ready);
+        assert_eq!(fs.read(&path("b.txt"), 0, 100).unwrap(), b"hello");
+        assert!(fs.readdir(&path("docs")).unwrap().is_empty());
+        assert_eq!(fs.poll(), Some(MountStatus::Ready(3)));
+    }
+
+    #[test]
+    fn sync_writes_only_changes() {
+        let (host, mut fs) = disk();
+        let file = ino(&fs, "docs/a.txt");
+        // Nothing is dirty, so a record lost behind the kernel's back stays lost
+        host.disk_write(Vec::new(), vec![file]);
+        fs.sync();
+        assert!(LocalFs::new(host.clone(), CAPACITY).disk != DiskState::Ready);
+
+        fs.write(&path("docs/a.txt"), 5, b"!").unwrap();
+        fs.sync();
+        let mut fs = LocalFs::new(host, CAPACITY);
+        assert_eq!(fs.read(&path("docs/a.txt"), 0, 100).unwrap(), b"hello!");
+        assert_eq!(fs.poll(), Some(MountStatus::Ready(3)));
+    }
+
+    #[test]
+    fn migrates_the_legacy_tree() {
+        let host = Rc::new(NativeHost::new());
+        host.storage_set(disk::LEGACY_STORAGE_KEY, r#"{ "name": "local", "node_type": "Directory", "size": 0,
+            "content": [], "children": { "old.txt": { "name": "old.txt", "node_type": "File", "size": 2,
+            "content": [104, 105], "children": {} } } }"#);
+        let fs = LocalFs::new(host.clone(), CAPACITY);
+        assert_eq!(fs.disk, DiskState::Ready);
+        assert!(host.storage_get(disk::LEGACY_STORAGE_KEY).is_none());
+
+        let fs = LocalFs::new(host, CAPACITY);
+        assert_eq!(fs.read(&path("old.txt"), 0, 100).unwrap(), b"hi");
+    }
+
+    #[test]
+    fn recovers_read_only_from_damage() {
+        let (host, fs) = disk();
+        let file = ino(&fs, "docs/a.txt");
+        host.disk_write(vec![(file, b"garbage".to_vec())], Vec::new());
+
+        let mut fs = LocalFs::new(host.clone(), CAPACITY);
+        let reason = format!("corrupt: inode {} (a.txt): checksum mismatch", file);
+        assert_eq!(fs.disk, DiskState::ReadOnly(reason.clone()));
+        assert_eq!(fs.poll(), Some(MountStatus::Recovery(2, reason)));
+        assert_eq!(fs.mkdir(&path("new")).unwrap_err(), READ_ONLY);
+        fs.sync();
+        assert_eq!(LocalFs::new(host.clone(), CAPACITY).tree.node(&path("docs")).map(|n| n.children.len()), Some(0));
+
+        // fsck reports what the load left out, without repairing
+        let lines = fs.fsck(true).lines();
+        assert_eq!(lines[0], format!("/docs/a.txt: inode {}: checksum mismatch, left out on load", file));
+        assert_eq!(lines.last().unwrap(), "2 entries checked, 1 problems, 0 fixed");
+
+        // A newer format is left alone too
+        host.disk_write(vec![(SUPERBLOCK_INO, b"WXFS\x09\0\0\0".to_vec())], Vec::new());
+        let fs = LocalFs::new(host, CAPACITY);
+        assert!(matches!(fs.disk, DiskState::ReadOnly(_)));
+    }
+
+    #[test]
+    fn fsck_deletes_unlinked_records() {
+        let (host, fs) = disk();
+        let lost = ino(&fs, "docs/a.txt") + 10;
+        host.disk_write(vec![(lost, disk::encode(&Node::new_file("lost", b"x".to_vec())))], Vec::new());
+
+        let mut fs = LocalFs::new(host.clone(), CAPACITY);
+        assert_eq!(fs.disk, DiskState::Ready);
+        assert!(fs.next_ino > lost);
+        let report = fs.fsck(false);
+        assert_eq!(report.lines()[0], format!("/: inode {} is not linked from any directory", lost));
+        assert!(!report.problems[0].fixed);
+
+        assert!(fs.fsck(true).problems[0].fixed);
+        fs.sync();
+        assert!(fs.fsck(false).problems.is_empty());
+        let mut fs = LocalFs::new(host, CAPACITY);
+        assert!(fs.fsck(false).problems.is_empty());
+    }
+}
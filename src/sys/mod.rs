@@ -1,4 +1,16 @@
 pub mod shell;
 pub mod fs;
 pub mod wasm;
+pub mod fd;
+pub mod wasi;
+pub mod process;
+pub mod parser;
+pub mod disk;
 
+pub mod vfs;
+pub mod tmpfs;
+pub mod imagefs;
+pub mod localfs;
+pub mod tar;
+pub mod pkg;
+pub mod env;
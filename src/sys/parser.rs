@@ -0,0 +1,438 @@
+//! Shell command-line parser.
+//!
+//! A line is a list of pipelines joined by `;`, `&&` or `||`. A pipeline is one or
+//! more commands joined by `|`, and each command is a list of words with optional
+//! `<`, `>` and `>>` redirections.
+//!
+//! Quoting follows sh: single quotes keep everything literal, double quotes allow
+//! `\"`, `\\` and `\$` escapes, and outside quotes a backslash escapes any character.
+//! Quoted and unquoted parts next to each other form one word (`a"b c"'d'` is `ab cd`).
+//!
+//! `$NAME`, `${NAME}` and `$?` outside single quotes are kept in the word as
+//! references, which `Word::expand` fills in when the command runs. Unlike sh the
+//! value is never split into several words.
+//!
+//! `partial` reads a line that is still being typed, for tab completion: the same
+//! tokens, except that the line may end inside a quote.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RedirectKind {
+    In,     // < file
+    Out,    // > file
+    Append, // >> file
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Part {
+    Text(String),
+    Var(String), // Name of a variable, "?" for the last status
+}
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Word {
+    pub parts: Vec<Part>,
+}
+
+impl Word {
+    fn push(&mut self, c: char) {
+        match self.parts.last_mut() {
+            Some(Part::Text(text)) => text.push(c),
+            _ => self.parts.push(Part::Text(c.to_string())),
+        }
+    }
+
+    // The word with its references replaced by what `lookup` gives for them
+    pub fn expand(&self, lookup: impl Fn(&str) -> String) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => out.push_str(text),
+                Part::Var(name) => out.push_str(&lookup(name)),
+            }
+        }
+        out
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Redirect {
+    pub kind: RedirectKind,
+    pub target: Word,
+}
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Command {
+    pub argv: Vec<Word>,
+    pub redirects: Vec<Redirect>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Pipeline {
+    pub commands: Vec<Command>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Connector {
+    Seq, // ; always runs the next pipeline
+    And, // && runs it if the last one succeeded
+    Or,  // || runs it if the last one failed
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct CommandList {
+    pub first: Pipeline,
+    pub rest: Vec<(Connector, Pipeline)>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Word(Word),
+    Pipe,
+    Seq,
+    And,
+    Or,
+    Redirect(RedirectKind),
+}
+
+fn describe(token: &Token) -> &'static str {
+    match token {
+        Token::Word(_) => "word",
+        Token::Pipe => "|",
+        Token::Seq => ";",
+        Token::And => "&&",
+        Token::Or => "||",
+        Token::Redirect(RedirectKind::In) => "<",
+        Token::Redirect(RedirectKind::Out) => ">",
+        Token::Redirect(RedirectKind::Append) => ">>",
+    }
+}
+
+// Quoting in effect at some point of a line
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Quote {
+    None,
+    Single,
+    Double,
+}
+
+// The word a line being typed ends in, empty when it ends in a blank or an operator
+#[derive(Clone, PartialEq, Debug)]
+pub struct Partial {
+    pub start: usize,  // Byte offset of the word in the line
+    pub word: Word,    // As far as it goes, without its quotes
+    pub quote: Quote,  // Left open at the end of the line
+    pub command: bool, // The word names the command to run
+}
+
+type Chars<'a> = std::iter::Peekable<std::str::CharIndices<'a>>;
+
+// After a `$`: read the reference it starts into `word`, or keep the `$` as text
+// when no name follows
+fn variable(chars: &mut Chars, word: &mut Word) -> Result<(), String> {
+    let name = match chars.peek().map(|&(_, c)| c) {
+        Some('?') => {
+            chars.next();
+            "?".to_string()
+        },
+        Some('{') => {
+            chars.next();
+            let mut name = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '}')) => break,
+                    Some((_, c)) => name.push(c),
+                    None => return Err("syntax error: unterminated ${".to_string()),
+                }
+            }
+            if !is_name(&name) && name != "?" {
+                return Err(format!("syntax error: bad substitution ${{{}}}", name));
+            }
+            name
+        },
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            let mut name = String::new();
+            while let Some(&(_, c)) = chars.peek() {
+                if !(c.is_ascii_alphanumeric() || c == '_') {
+                    break;
+                }
+                name.push(c);
+                chars.next();
+            }
+            name
+        },
+        _ => {
+            word.push('$');
+            return Ok(());
+        },
+    };
+    word.parts.push(Part::Var(name));
+    Ok(())
+}
+
+// Whether `name` can be a variable: letters, digits and `_`, not starting with a digit
+pub fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// A line split into tokens, each with the byte offset it starts at
+struct Scan {
+    tokens: Vec<(usize, Token)>,
+    open: Option<Quote>, // Set when the line ends inside its last word
+}
+
+// With `partial` the line may end inside a quote, which is left open in `Scan::open`
+fn scan(input: &str, partial: bool) -> Result<Scan, String> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    let mut word = Word::default();
+    let mut start = None; // Of the word being read, kept for quoted empty strings too
+    let mut quote = Quote::None;
+
+    while let Some((at, c)) = chars.next() {
+        let next = chars.peek().map(|&(_, c)| c);
+        let op = match c {
+            '|' if next == Some('|') => { chars.next(); Some(Token::Or) },
+            '|' => Some(Token::Pipe),
+            '&' if next == Some('&') => { chars.next(); Some(Token::And) },
+            '&' => return Err("syntax error: background jobs (&) are not supported".to_string()),
+            ';' => Some(Token::Seq),
+            '<' => Some(Token::Redirect(RedirectKind::In)),
+            '>' if next == Some('>') => { chars.next(); Some(Token::Redirect(RedirectKind::Append)) },
+            '>' => Some(Token::Redirect(RedirectKind::Out)),
+            _ => None,
+        };
+
+        if op.is_some() || c.is_whitespace() {
+            if let Some(start) = start.take() {
+                tokens.push((start, Token::Word(std::mem::take(&mut word))));
+            }
+            if let Some(op) = op {
+                tokens.push((at, op));
+            }
+            continue;
+        }
+
+        start.get_or_insert(at);
+        match c {
+            '\'' => loop {
+                match chars.next() {
+                    Some((_, '\'')) => break,
+                    Some((_, ch)) => word.push(ch),
+                    None if partial => { quote = Quote::Single; break },
+                    None => return Err("syntax error: unterminated single quote".to_string()),
+                }
+            },
+            '"' => loop {
+                match chars.next() {
+                    Some((_, '"')) => break,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, ch @ ('"' | '\\' | '$'))) => word.push(ch),
+                        Some((_, ch)) => { word.push('\\'); word.push(ch); },
+                        None if partial => { quote = Quote::Double; break },
+                        None => return Err("syntax error: unterminated double quote".to_string()),
+                    },
+                    Some((_, '$')) => variable(&mut chars, &mut word)?,
+                    Some((_, ch)) => word.push(ch),
+                    None if partial => { quote = Quote::Double; break },
+                    None => return Err("syntax error: unterminated double quote".to_string()),
+                }
+            },
+            // A trailing backslash has nothing to escape and stays literal
+            '\\' => word.push(chars.next().map_or('\\', |(_, ch)| ch)),
+            '$' => variable(&mut chars, &mut word)?,
+            _ => word.push(c),
+        }
+    }
+    let open = start.map(|start| {
+        tokens.push((start, Token::Word(word)));
+        quote
+    });
+    Ok(Scan { tokens, open })
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    Ok(scan(input, false)?.tokens.into_iter().map(|(_, token)| token).collect())
+}
+
+// The word `line` ends in, as far as it has been typed
+pub fn partial(line: &str) -> Result<Partial, String> {
+    let mut scan = scan(line, true)?;
+    let (start, word, quote) = match scan.open {
+        Some(quote) => match scan.tokens.pop() {
+            Some((start, Token::Word(word))) => (start, word, quote),
+            _ => unreachable!("an open line ends in a word"),
+        },
+        None => (line.len(), Word::default(), Quote::None),
+    };
+    // A command starts the line and follows `|`, `;`, `&&` and `||`
+    let command = matches!(scan.tokens.last(), None | Some((_, Token::Pipe | Token::Seq | Token::And | Token::Or)));
+    Ok(Partial { start, word, quote, command })
+}
+
+// Parse a command line. Returns `None` for a blank line.
+pub fn parse(input: &str) -> Result<Option<CommandList>, String> {
+    let tokens = tokenize(input)?;
+    if tokens.is_empty() {
+        return Ok(None);
+    }
+
+    let mut pipelines: Vec<(Connector, Pipeline)> = Vec::new();
+    let mut connector = Connector::Seq;
+    let mut commands: Vec<Command> = Vec::new();
+    let mut current = Command::default();
+    let mut iter = tokens.into_iter().peekable();
+
+    while let Some(token) = iter.next() {
+        match token {
+            Token::Word(w) => current.argv.push(w),
+            Token::Redirect(kind) => match iter.next() {
+                Some(Token::Word(target)) => current.redirects.push(Redirect { kind, target }),
+                Some(other) => return Err(format!("syntax error near '{}'", describe(&other))),
+                None => return Err("syntax error: missing redirection target".to_string()),
+            },
+            Token::Pipe | Token::Seq | Token::And | Token::Or => {
+                if current.argv.is_empty() {
+                    return Err(format!("syntax error near '{}'", describe(&token)));
+                }
+                commands.push(std::mem::take(&mut current));
+                if token == Token::Pipe {
+                    if iter.peek().is_none() {
+                        return Err("syntax error: missing command after '|'".to_string());
+                    }
+                    continue;
+                }
+                pipelines.push((connector, Pipeline { commands: std::mem::take(&mut commands) }));
+                connector = match token {
+                    Token::And => Connector::And,
+                    Token::Or => Connector::Or,
+                    _ => Connector::Seq,
+                };
+                if iter.peek().is_none() && connector != Connector::Seq {
+                    return Err(format!("syntax error: missing command after '{}'", describe(&token)));
+                }
+            },
+        }
+    }
+
+    // A trailing `;` is allowed and leaves nothing pending
+    if !current.argv.is_empty() {
+        commands.push(current);
+        pipelines.push((connector, Pipeline { commands }));
+    } else if !current.redirects.is_empty() {
+        return Err("syntax error: redirection without a command".to_string());
+    }
+
+    let mut pipelines = pipelines.into_iter();
+    let first = match pipelines.next() {
+        Some((_, p)) => p,
+        None => return Ok(None),
+    };
+    Ok(Some(CommandList { first, rest: pipelines.collect() }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The one command of a line, e.g. `ls -l > out`
+    fn command(input: &str) -> Command {
+        let list = parse(input).unwrap().unwrap();
+        assert!(list.rest.is_empty());
+        assert_eq!(list.first.commands.len(), 1);
+        list.first.commands.into_iter().next().unwrap()
+    }
+
+    // Words with references shown as <NAME>
+    fn argv(cmd: &Command) -> Vec<String> {
+        cmd.argv.iter().map(|w| w.expand(|name| format!("<{}>", name))).collect()
+    }
+
+    #[test]
+    fn blank_lines_parse_to_nothing() {
+        assert_eq!(parse("").unwrap(), None);
+        assert_eq!(parse("   \t").unwrap(), None);
+    }
+
+    #[test]
+    fn joins_quoted_and_unquoted_parts() {
+        assert_eq!(argv(&command(r#"echo a"b c"'d' '' "#)), ["echo", "ab cd", ""]);
+        assert_eq!(argv(&command(r#"echo 'it''s $HOME' "say \"hi\" \n""#)), ["echo", "its $HOME", r#"say "hi" \n"#]);
+        assert_eq!(argv(&command(r"echo a\ b \| trailing\")), ["echo", "a b", "|", "trailing\\"]);
+    }
+
+    #[test]
+    fn keeps_variable_references() {
+        let cmd = command(r#"echo $HOME/x "${USER}s" $? '$NOPE' \$LIT $ 1$"#);
+        assert_eq!(argv(&cmd), ["echo", "<HOME>/x", "<USER>s", "<?>", "$NOPE", "$LIT", "$", "1$"]);
+        assert_eq!(cmd.argv[1].parts, [Part::Var("HOME".to_string()), Part::Text("/x".to_string())]);
+        assert!(parse("echo ${unterminated").is_err());
+        assert!(parse("echo ${1x}").is_err());
+    }
+
+    #[test]
+    fn collects_redirections() {
+        let cmd = command("sort < in.txt > out.txt -r >> log");
+        assert_eq!(argv(&cmd), ["sort", "-r"]);
+        let targets: Vec<_> = cmd.redirects.iter()
+            .map(|r| (r.kind, r.target.expand(|_| String::new())))
+            .collect();
+        assert_eq!(targets, [
+            (RedirectKind::In, "in.txt".to_string()),
+            (RedirectKind::Out, "out.txt".to_string()),
+            (RedirectKind::Append, "log".to_string()),
+        ]);
+        assert_eq!(argv(&command("echo a>b")), ["echo", "a"]);
+    }
+
+    #[test]
+    fn splits_pipelines_and_lists() {
+        let list = parse("cat a | grep x | wc; ls && echo ok || echo failed;").unwrap().unwrap();
+        assert_eq!(list.first.commands.iter().map(argv).collect::<Vec<_>>(), [
+            vec!["cat", "a"], vec!["grep", "x"], vec!["wc"],
+        ]);
+        let rest: Vec<_> = list.rest.iter()
+            .map(|(c, p)| (*c, p.commands.iter().map(argv).collect::<Vec<_>>()))
+            .collect();
+        assert_eq!(rest, [
+            (Connector::Seq, vec![vec!["ls".to_string()]]),
+            (Connector::And, vec![vec!["echo".to_string(), "ok".to_string()]]),
+            (Connector::Or, vec![vec!["echo".to_string(), "failed".to_string()]]),
+        ]);
+    }
+
+    #[test]
+    fn reports_syntax_errors() {
+        for line in [";", "| ls", "ls |", "ls &&", "ls ||", "ls ;; ls", "ls && | wc", "ls >", "ls > | wc",
+                     "> out", "ls &", "echo 'open", "echo \"open"] {
+            assert!(parse(line).is_err(), "{:?} should not parse", line);
+        }
+        assert_eq!(parse("ls | | wc").unwrap_err(), "syntax error near '|'");
+        assert_eq!(parse("cat >").unwrap_err(), "syntax error: missing redirection target");
+    }
+
+    #[test]
+    fn finds_the_word_being_typed() {
+        fn word(line: &str) -> (&str, String, Quote, bool) {
+            let p = partial(line).unwrap();
+            (&line[p.start..], p.word.expand(|name| format!("<{}>", name)), p.quote, p.command)
+        }
+        assert_eq!(word(""), ("", String::new(), Quote::None, true));
+        assert_eq!(word("ec"), ("ec", "ec".to_string(), Quote::None, true));
+        assert_eq!(word("ls | gr"), ("gr", "gr".to_string(), Quote::None, true));
+        assert_eq!(word("ls -l "), ("", String::new(), Quote::None, false));
+        assert_eq!(word("cat > my\\ fi"), ("my\\ fi", "my fi".to_string(), Quote::None, false));
+        assert_eq!(word("cat \"my fi"), ("\"my fi", "my fi".to_string(), Quote::Double, false));
+        assert_eq!(word("cat 'a b'c'd "), ("'a b'c'd ", "a bcd ".to_string(), Quote::Single, false));
+        assert_eq!(word("cd $HOME/d"), ("$HOME/d", "<HOME>/d".to_string(), Quote::None, false));
+        assert_eq!(word("ls && \"\""), ("\"\"", String::new(), Quote::None, true));
+        assert!(partial("echo ${HO").is_err());
+    }
+
+    #[test]
+    fn recognizes_names() {
+        assert!(is_name("PATH") && is_name("_x1"));
+        assert!(!is_name("") && !is_name("1x") && !is_name("a-b"));
+    }
+}
@@ -0,0 +1,259 @@
+//! Packages: a program plus a manifest describing it.
+//!
+//! A package is a tar archive with `manifest.json` at its root, or inside a single
+//! top-level directory as `tar -c <dir>` makes it, next to the entry module and
+//! any files the program needs:
+//!
+//! ```json
+//! { "name": "hello", "version": "0.1.0", "entry": "hello.wasm",
+//!   "icon": "[hi]", "syscalls": ["sys_print"] }
+//! ```
+//!
+//! `syscalls` has to list every call the entry module imports from `env`; a module
+//! importing anything else is refused when the package is installed.
+//!
+//! Apps bundled at build time (see build.rs) are served from the read-only /bin.
+//! `install` unpacks other packages under /local/apps/<name>, where they persist.
+
+use serde::{Serialize, Deserialize};
+
+use crate::kernel::NATIVE_MAGIC;
+use crate::sys::fs::FileSystem;
+use crate::sys::tar::{self, EntryKind};
+use crate::sys::wasm::SYSCALLS;
+
+pub const MANIFEST: &str = "manifest.json";
+pub const APPS_DIR: &str = "/local/apps";
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub name: String,
+    pub version: String,
+    pub entry: String, // Module to run, in the package root
+    #[serde(default)]
+    pub icon: String, // Short text label for launchers, e.g. "[>_]"
+    #[serde(default)]
+    pub syscalls: Vec<String>, // Kernel calls the program imports
+}
+
+impl Manifest {
+    pub fn parse(json: &str) -> Result<Self, String> {
+        let manifest: Self = serde_json::from_str(json).map_err(|e| format!("bad manifest: {}", e))?;
+        for (field, value) in [("name", &manifest.name), ("entry", &manifest.entry)] {
+            if value.is_empty() || value.contains('/') || value == "." || value == ".." {
+                return Err(format!("bad manifest: invalid {} '{}'", field, value));
+            }
+        }
+        // Dot names are left to install's staging directories
+        if manifest.name.starts_with('.') {
+            return Err(format!("bad manifest: invalid name '{}'", manifest.name));
+        }
+        if manifest.version.is_empty() {
+            return Err("bad manifest: missing version".to_string());
+        }
+        if let Some(missing) = manifest.syscalls.iter().find(|s| !SYSCALLS.contains(&s.as_str())) {
+            return Err(format!("'{}' needs syscall '{}', which this kernel does not provide", manifest.name, missing));
+        }
+        Ok(manifest)
+    }
+
+    // Check the compiled entry module against `syscalls`. Native wasmix-32 images
+    // import nothing and pass as they are.
+    pub fn check_module(&self, entry: &[u8]) -> Result<(), String> {
+        if entry.starts_with(NATIVE_MAGIC) {
+            return Ok(());
+        }
+        let module = wasmi::Module::new(&wasmi::Engine::default(), entry)
+            .map_err(|e| format!("'{}': bad entry module: {}", self.name, e))?;
+        let undeclared = module.imports()
+            .find(|import| import.module() == "env" && !self.syscalls.iter().any(|s| s == import.name()));
+        match undeclared {
+            Some(import) => Err(format!("'{}' imports syscall '{}', which its manifest does not declare", self.name, import.name())),
+            None => Ok(()),
+        }
+    }
+}
+
+// An app embedded into the kernel by build.rs
+struct Bundle {
+    manifest: &'static str,
+    entry: &'static [u8],
+}
+
+const BUNDLES: &[Bundle] = include!(concat!(env!("OUT_DIR"), "/bundles.rs"));
+
+// Bundled apps with their entry module. Empty when the kernel was built without apps.
+// Apps whose manifest this kernel rejects are left out (see `bundle_errors`).
+pub fn bundled() -> Vec<(Manifest, &'static [u8])> {
+    BUNDLES.iter()
+        .filter_map(|b| Manifest::parse(b.manifest).ok().map(|m| (m, b.entry)))
+        .collect()
+}
+
+// Why bundled apps are left out of /bin or would fail to run: manifests naming
+// syscalls this kernel lacks and entries importing undeclared ones. build.rs has
+// already refused malformed manifests.
+pub fn bundle_errors() -> Vec<String> {
+    BUNDLES.iter()
+        .filter_map(|b| Manifest::parse(b.manifest).and_then(|m| m.check_module(b.entry)).err())
+        .collect()
+}
+
+// Unpack a package archive into /local/apps/<name>, replacing an installed version
+pub fn install(fs: &mut FileSystem, archive: &[u8]) -> Result<Manifest, String> {
+    let mut entries = tar::read(archive)?;
+
+    // The shallowest manifest marks the package root
+    let root = entries.iter()
+        .filter(|e| e.path.last().is_some_and(|name| name == MANIFEST))
+        .map(|e| e.path[..e.path.len() - 1].to_vec())
+        .min_by_key(|root| root.len())
+        .ok_or("not a package: no manifest.json")?;
+    entries.retain(|e| e.path.starts_with(&root) && e.path.len() > root.len());
+    for entry in &mut entries {
+        entry.path.drain(..root.len());
+    }
+
+    let manifest = entries.iter()
+        .find_map(|e| match &e.kind {
+            EntryKind::File(content) if e.path == [MANIFEST] => Some(content),
+            _ => None,
+        })
+        .ok_or("not a package: manifest.json is not a file")?;
+    let manifest = Manifest::parse(&String::from_utf8_lossy(manifest))?;
+    let entry = entries.iter()
+        .find_map(|e| match &e.kind {
+            EntryKind::File(content) if e.path == [manifest.entry.clone()] => Some(content),
+            _ => None,
+        })
+        .ok_or_else(|| format!("'{}': entry '{}' is not in the package", manifest.name, manifest.entry))?;
+    manifest.check_module(entry)?;
+
+    if !fs.is_dir(APPS_DIR) {
+        fs.mkdir(APPS_DIR)?;
+    }
+    // Unpack next to the installed version and swap it in only once the new one is
+    // complete, so a failed upgrade (a full disk, say) leaves the old one running
+    let dir = format!("{}/{}", APPS_DIR, manifest.name);
+    let staging = format!("{}/.{}.new", APPS_DIR, manifest.name);
+    if fs.lstat(&staging).is_ok() {
+        fs.remove_entry(&staging)?;
+    }
+    fs.mkdir(&staging)?;
+    let entry = format!("{}/{}", staging, manifest.entry);
+    let res = tar::extract(fs, &staging, &entries)
+        .and_then(|_| fs.stat(&entry))
+        // The entry has to be runnable, whatever mode the archive gave it
+        .and_then(|stat| fs.chmod(&entry, stat.mode | 0o111))
+        .and_then(|_| match fs.lstat(&dir) {
+            Ok(_) => fs.remove_entry(&dir),
+            Err(_) => Ok(()),
+        })
+        .and_then(|_| fs.rename(&staging, &dir));
+    if let Err(e) = res {
+        // Don't leave half a package behind
+        let _ = fs.remove_entry(&staging);
+        return Err(e);
+    }
+    Ok(manifest)
+}
+
+pub fn remove(fs: &mut FileSystem, name: &str) -> Result<(), String> {
+    if name.contains('/') || manifest(fs, name).is_none() {
+        return Err(format!("package '{}' is not installed", name));
+    }
+    fs.remove_entry(&format!("{}/{}", APPS_DIR, name))
+}
+
+// Packages under /local/apps, by name
+pub fn installed(fs: &FileSystem) -> Vec<Manifest> {
+    fs.list_dir(APPS_DIR)
+        .unwrap_or_default()
+        .iter()
+        .filter(|name| !name.starts_with('.'))
+        .filter_map(|name| manifest(fs, name))
+        .collect()
+}
+
+fn manifest(fs: &FileSystem, name: &str) -> Option<Manifest> {
+    let json = fs.read_file(&format!("{}/{}/{}", APPS_DIR, name, MANIFEST)).ok()?;
+    Manifest::parse(&String::from_utf8_lossy(&json)).ok()
+}
+
+// Path of the entry module of the installed package `name`
+pub fn find(fs: &FileSystem, name: &str) -> Option<String> {
+    if name.contains('/') {
+        return None;
+    }
+    manifest(fs, name).map(|m| format!("{}/{}/{}", APPS_DIR, name, m.entry))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::native::NativeHost;
+    use std::rc::Rc;
+
+    const PRINTER: &str = r#"
+        (module
+            (import "env" "sys_print" (func (param i32 i32)))
+            (import "wasi_snapshot_preview1" "sched_yield" (func (result i32)))
+            (memory (export "memory") 1)
+            (func (export "_start")))
+    "#;
+
+    fn manifest(syscalls: &[&str]) -> Manifest {
+        let json = serde_json::json!({ "name": "p", "version": "1", "entry": "p.wasm", "syscalls": syscalls });
+        Manifest::parse(&json.to_string()).unwrap()
+    }
+
+    // A package archive of `p` with the given manifest syscalls and entry module
+    fn package(fs: &mut FileSystem, syscalls: &[&str], wat: &str) -> Vec<u8> {
+        let json = serde_json::json!({ "name": "p", "version": "1", "entry": "p.wasm", "syscalls": syscalls });
+        let _ = fs.remove_entry("/tmp/p");
+        fs.mkdir("/tmp/p").unwrap();
+        fs.write_file("/tmp/p/manifest.json", json.to_string().as_bytes()).unwrap();
+        fs.write_file("/tmp/p/p.wasm", &wat::parse_str(wat).unwrap()).unwrap();
+        tar::pack(fs, "/tmp/p").unwrap()
+    }
+
+    #[test]
+    fn validates_manifests() {
+        assert!(Manifest::parse(r#"{ "name": "a/b", "version": "1", "entry": "a.wasm" }"#).is_err());
+        assert!(Manifest::parse(r#"{ "name": "a", "version": "", "entry": "a.wasm" }"#).is_err());
+        assert!(Manifest::parse(r#"{ "name": "a", "version": "1", "entry": "a.wasm", "syscalls": ["sys_nope"] }"#)
+            .err().unwrap().contains("sys_nope"));
+    }
+
+    #[test]
+    fn checks_env_imports_against_syscalls() {
+        let module = wat::parse_str(PRINTER).unwrap();
+        assert!(manifest(&["sys_print"]).check_module(&module).is_ok());
+        assert_eq!(manifest(&["sys_time"]).check_module(&module).unwrap_err(),
+                   "'p' imports syscall 'sys_print', which its manifest does not declare");
+        assert!(manifest(&[]).check_module(b"not wasm").unwrap_err().contains("bad entry module"));
+        assert!(manifest(&[]).check_module(NATIVE_MAGIC).is_ok());
+    }
+
+    #[test]
+    fn installs_only_declared_packages() {
+        let mut fs = FileSystem::new(1, Rc::new(NativeHost::new()));
+        while fs.poll_disk().is_none() {}
+
+        let archive = package(&mut fs, &[], PRINTER);
+        assert!(install(&mut fs, &archive).err().unwrap().contains("sys_print"));
+        assert!(installed(&fs).is_empty());
+
+        let archive = package(&mut fs, &["sys_print"], PRINTER);
+        assert_eq!(install(&mut fs, &archive).unwrap().name, "p");
+        assert_eq!(find(&fs, "p").as_deref(), Some("/local/apps/p/p.wasm"));
+        remove(&mut fs, "p").unwrap();
+        assert!(find(&fs, "p").is_none());
+    }
+
+    #[test]
+    fn bundled_apps_declare_their_syscalls() {
+        assert_eq!(bundle_errors(), Vec::<String>::new());
+        assert_eq!(bundled().len(), BUNDLES.len());
+    }
+}
@@ -0,0 +1,256 @@
+//! Process table for wasm guests.
+//!
+//! Every loaded program gets a PID. Programs that export `step` stay in the table
+//! and are stepped round-robin from `Machine::tick_process`; the rest exit as soon
+//! as `init`/`_start` returns. A process that exits while its parent is still
+//! alive stays behind as a zombie holding its status until the parent calls
+//! `sys_wait`. Children of the kernel (PID 0) are reaped immediately.
+//!
+//! The foreground process is the last one to call `sys_enable_gui_mode`. It alone
+//! receives input events and may draw; when it exits the previous one takes over.
+//!
+//! Guests run on a fuel-metered engine. Each `step` gets `STEP_FUEL_QUOTA` and
+//! `init` gets `INIT_FUEL_QUOTA`; running dry kills the process. A wasi `_start`
+//! does all its work in one call, so it runs in slices instead: every yield or
+//! stdio/file transfer (`sched_yield`, `poll_oneoff`, `fd_read`, `fd_write`) tops
+//! it back up to `INIT_FUEL_QUOTA`, and only a slice without any runs dry. The fuel burnt is
+//! charged to the process, and `cpu` is its share of the step quota over the last
+//! `CPU_WINDOW_TICKS` ticks.
+
+use std::collections::BTreeMap;
+use wasmi::{Instance, Store};
+
+use crate::sys::wasm::WasmContext;
+
+pub type Pid = u32;
+
+pub const KERNEL_PID: Pid = 0;
+
+// Fuel (roughly one unit per wasm instruction) a process may burn per call
+pub const STEP_FUEL_QUOTA: u64 = 2_000_000;
+pub const INIT_FUEL_QUOTA: u64 = 50_000_000;
+pub const CPU_WINDOW_TICKS: u64 = 60;
+
+// Exit status of killed processes (128 + SIGKILL, as in a unix shell)
+pub const EXIT_KILLED: i32 = 137;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ProcessState {
+    Running,
+    Sleeping,    // Waiting in sys_wait for a child to exit
+    Zombie(i32), // Exited, status kept until the parent waits
+}
+
+impl ProcessState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProcessState::Running => "running",
+            ProcessState::Sleeping => "sleeping",
+            ProcessState::Zombie(_) => "zombie",
+        }
+    }
+}
+
+// A live guest instance
+pub struct Task {
+    pub store: Store<WasmContext>,
+    pub instance: Instance,
+}
+
+pub struct Process {
+    pub pid: Pid,
+    pub parent: Pid,
+    pub name: String,
+    pub state: ProcessState,
+    pub fuel: u64,     // Total fuel consumed
+    fuel_window: u64,  // Consumed in the current cpu window
+    fuel_last: u64,    // Consumed in the last complete window
+    // None while init is running, while the scheduler is stepping it, or after exit
+    task: Option<Task>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ProcessInfo {
+    pub pid: Pid,
+    pub parent: Pid,
+    pub name: String,
+    pub state: ProcessState,
+    pub fuel: u64,
+    pub cpu: u32, // Percent of the step quota used over the last window
+}
+
+pub enum WaitResult {
+    Exited(Pid, i32),
+    Pending,  // A matching child is still alive, the caller is now sleeping
+    NoChild,
+}
+
+pub struct ProcessTable {
+    procs: BTreeMap<Pid, Process>,
+    next_pid: Pid,
+    last_scheduled: Pid,
+    ticks: u64,
+    foreground: Vec<Pid>, // Stack, top is the current foreground
+}
+
+impl ProcessTable {
+    pub fn new() -> Self {
+        Self {
+            procs: BTreeMap::new(),
+            next_pid: 1,
+            last_scheduled: KERNEL_PID,
+            ticks: 0,
+            foreground: Vec::new(),
+        }
+    }
+
+    // Register a process before its init runs, so it already has a PID
+    pub fn create(&mut self, parent: Pid, name: &str) -> Pid {
+        let pid = self.next_pid;
+        self.next_pid += 1;
+        self.procs.insert(pid, Process {
+            pid,
+            parent,
+            name: name.to_string(),
+            state: ProcessState::Running,
+            fuel: 0,
+            fuel_window: 0,
+            fuel_last: 0,
+            task: None,
+        });
+        pid
+    }
+
+    // Hand a task (back) to the table. Dropped if the process exited meanwhile.
+    pub fn attach(&mut self, pid: Pid, task: Task) {
+        if let Some(p) = self.procs.get_mut(&pid) {
+            if !matches!(p.state, ProcessState::Zombie(_)) {
+                p.task = Some(task);
+            }
+        }
+    }
+
+    pub fn take_task(&mut self, pid: Pid) -> Option<Task> {
+        self.procs.get_mut(&pid).and_then(|p| p.task.take())
+    }
+
+    pub fn charge(&mut self, pid: Pid, fuel: u64) {
+        if let Some(p) = self.procs.get_mut(&pid) {
+            p.fuel += fuel;
+            p.fuel_window += fuel;
+        }
+    }
+
+    // Runnable PIDs for this tick, starting after the one that went first last time
+    pub fn schedule(&mut self) -> Vec<Pid> {
+        self.ticks += 1;
+        if self.ticks.is_multiple_of(CPU_WINDOW_TICKS) {
+            for p in self.procs.values_mut() {
+                p.fuel_last = p.fuel_window;
+                p.fuel_window = 0;
+            }
+        }
+
+        let runnable: Vec<Pid> = self.procs.values()
+            .filter(|p| p.state == ProcessState::Running && p.task.is_some())
+            .map(|p| p.pid)
+            .collect();
+        let split = runnable.iter().position(|pid| *pid > self.last_scheduled).unwrap_or(0);
+        let order: Vec<Pid> = runnable[split..].iter().chain(&runnable[..split]).copied().collect();
+        if let Some(first) = order.first() {
+            self.last_scheduled = *first;
+        }
+        order
+    }
+
+    pub fn name(&self, pid: Pid) -> Option<&str> {
+        self.procs.get(&pid).map(|p| p.name.as_str())
+    }
+
+    pub fn exit(&mut self, pid: Pid, status: i32) {
+        let parent = match self.procs.get_mut(&pid) {
+            Some(p) if !matches!(p.state, ProcessState::Zombie(_)) => {
+                p.task = None;
+                p.state = ProcessState::Zombie(status);
+                p.parent
+            },
+            _ => return,
+        };
+        self.foreground.retain(|p| *p != pid);
+
+        // Orphans go to the kernel, which reaps the ones that already exited
+        let orphans: Vec<Pid> = self.procs.values().filter(|p| p.parent == pid).map(|p| p.pid).collect();
+        for child in orphans {
+            let zombie = matches!(self.procs[&child].state, ProcessState::Zombie(_));
+            if zombie {
+                self.procs.remove(&child);
+            } else if let Some(p) = self.procs.get_mut(&child) {
+                p.parent = KERNEL_PID;
+            }
+        }
+
+        match self.procs.get_mut(&parent) {
+            Some(p) if parent != KERNEL_PID => {
+                if p.state == ProcessState::Sleeping {
+                    p.state = ProcessState::Running;
+                }
+            },
+            _ => {
+                self.procs.remove(&pid);
+            },
+        }
+    }
+
+    // `pid` < 0 waits for any child
+    pub fn wait(&mut self, parent: Pid, pid: i32) -> WaitResult {
+        let children: Vec<&Process> = self.procs.values()
+            .filter(|p| p.parent == parent && (pid < 0 || p.pid == pid as Pid))
+            .collect();
+        if children.is_empty() {
+            return WaitResult::NoChild;
+        }
+        let exited = children.iter().find_map(|p| match p.state {
+            ProcessState::Zombie(status) => Some((p.pid, status)),
+            _ => None,
+        });
+        match exited {
+            Some((child, status)) => {
+                self.procs.remove(&child);
+                WaitResult::Exited(child, status)
+            },
+            None => {
+                if let Some(p) = self.procs.get_mut(&parent) {
+                    p.state = ProcessState::Sleeping;
+                }
+                WaitResult::Pending
+            },
+        }
+    }
+
+    pub fn foreground(&self) -> Option<Pid> {
+        self.foreground.last().copied()
+    }
+
+    pub fn set_foreground(&mut self, pid: Pid) {
+        if self.foreground() != Some(pid) {
+            self.foreground.retain(|p| *p != pid);
+            self.foreground.push(pid);
+        }
+    }
+
+    // Whether `pid` may take input and draw
+    pub fn owns_display(&self, pid: Pid) -> bool {
+        self.foreground().is_none_or(|fg| fg == pid)
+    }
+
+    pub fn list(&self) -> Vec<ProcessInfo> {
+        self.procs.values().map(|p| ProcessInfo {
+            pid: p.pid,
+            parent: p.parent,
+            name: p.name.clone(),
+            state: p.state,
+            fuel: p.fuel,
+            cpu: (p.fuel_last * 100 / (STEP_FUEL_QUOTA * CPU_WINDOW_TICKS)) as u32,
+        }).collect()
+    }
+}
@@ -1,10 +1,22 @@
 
 
+use std::rc::Rc;
+
+use crate::host::Host;
+use crate::sys::fs::{NodeType, Stat};
+use crate::sys::parser::{self, Connector, Pipeline, RedirectKind};
+use crate::sys::env::{self, Env};
+use crate::sys::pkg;
+use wasmix_term::editor::{Key, LineEditor, Outcome};
+use wasmix_term::history::{History, HISTORY_FILE};
+
 pub struct Shell {
-    input_buffer: String,
+    host: Rc<dyn Host>,
+    editor: LineEditor,
     current_path: String,
-    history: Vec<String>,
-    history_index: Option<usize>,
+    history: History,
+    history_loaded: bool, // Read from HISTORY_FILE yet
+    env: Env,
     waiting_for_reset: bool,
     awaiting_restart_confirm: bool,
 }
@@ -22,30 +34,176 @@ pub enum CmdResult {
     Clear,
 }
 
+// Streams of a builtin: piped or redirected input (`None` when it comes from the
+// terminal) and what it printed. Errors go to `err`, which is never piped.
+struct Io {
+    stdin: Option<Vec<u8>>,
+    out: String,
+    err: String,
+    status: Option<i32>, // Exit status of a program that ran, for `$?`
+}
+
+impl Io {
+    fn error(&mut self, msg: &str) -> CmdResult {
+        self.err.push_str(&format!("error: {}\n", msg));
+        CmdResult::Error
+    }
+
+    fn usage(&mut self, msg: &str) -> CmdResult {
+        self.err.push_str(&format!("usage: {}\n", msg));
+        CmdResult::Error
+    }
+}
+
+// Split leading single-letter flags ("-l", "-pv") from the operands. Flags end at the
+// first operand or at "--"; a lone "-" is an operand. Fails on a flag not in `allowed`.
+fn split_flags<'a>(args: &[&'a str], allowed: &str) -> Result<(Vec<char>, Vec<&'a str>), String> {
+    let mut flags = Vec::new();
+    let mut i = 0;
+    while let Some(arg) = args.get(i) {
+        if *arg == "--" {
+            i += 1;
+            break;
+        }
+        match arg.strip_prefix('-') {
+            Some(letters) if !letters.is_empty() => {
+                for c in letters.chars() {
+                    if !allowed.contains(c) {
+                        return Err(format!("invalid option -- '{}'", c));
+                    }
+                    flags.push(c);
+                }
+            },
+            _ => break,
+        }
+        i += 1;
+    }
+    Ok((flags, args[i..].to_vec()))
+}
+
 const COMMANDS: &[CommandDef] = &[
     CommandDef { name: "help", desc: "show this help" },
     CommandDef { name: "clear", desc: "clear screen" },
-    CommandDef { name: "ls", desc: "list files" },
+    CommandDef { name: "ls", desc: "list files (-l for details)" },
     CommandDef { name: "cd", desc: "change directory" },
-    CommandDef { name: "mkdir", desc: "create directory" },
+    CommandDef { name: "mkdir", desc: "create directory (-p for parents)" },
     CommandDef { name: "touch", desc: "create file" },
     CommandDef { name: "rm", desc: "remove file/dir" },
-    CommandDef { name: "df", desc: "disk usage" },
+    CommandDef { name: "cat", desc: "print files (or stdin)" },
+    CommandDef { name: "stat", desc: "show file metadata" },
+    CommandDef { name: "chmod", desc: "change permissions" },
+    CommandDef { name: "chown", desc: "change owner" },
+    CommandDef { name: "ln", desc: "create symlink (-s)" },
+    CommandDef { name: "cp", desc: "copy file (-r for dirs)" },
+    CommandDef { name: "mv", desc: "move/rename file or dir" },
+    CommandDef { name: "tar", desc: "create (-c) or extract (-x) archive" },
+    CommandDef { name: "pkg", desc: "list, install or remove packages" },
+    CommandDef { name: "echo", desc: "print text" },
+    CommandDef { name: "export", desc: "set environment variables" },
+    CommandDef { name: "unset", desc: "remove environment variables" },
+    CommandDef { name: "env", desc: "list environment variables" },
+    CommandDef { name: "write", desc: "write text (or stdin) to file" },
+    CommandDef { name: "head", desc: "first lines of file" },
+    CommandDef { name: "tail", desc: "last lines of file" },
+    CommandDef { name: "df", desc: "disk usage per mount [path]" },
+    CommandDef { name: "fsck", desc: "check filesystems [-r to repair]" },
+    CommandDef { name: "mount", desc: "list or add mounts" },
+    CommandDef { name: "umount", desc: "remove a mount" },
     CommandDef { name: "sysinfo", desc: "system information" },
     // CommandDef { name: "restart", desc: "restart system" }, // Handled by app shell or deprecated
     CommandDef { name: "uptime", desc: "system uptime" },
     CommandDef { name: "date", desc: "real world time" },
+    CommandDef { name: "history", desc: "list past commands [n], -c to clear" },
     CommandDef { name: "reset", desc: "factory reset (wipe data)" },
-    CommandDef { name: "exec", desc: "execute wasm binary" },
+    CommandDef { name: "exec", desc: "execute wasm or wasmix-32 binary" },
+    CommandDef { name: "ps", desc: "list processes" },
+    CommandDef { name: "kill", desc: "terminate a process" },
 ];
 
+// Tab completion candidates for the word `line` (the line up to the cursor) ends in:
+// builtins and programs along $PATH where a command goes, paths anywhere else.
+// Candidates are whole words as they would be typed, each one going on from the word
+// as it was typed (quotes, escapes and variables included). Directories end in "/",
+// anything else closes a quote left open.
+pub fn complete(line: &str, fs: &crate::sys::fs::FileSystem, env: &Env) -> Vec<String> {
+    let partial = match parser::partial(line) {
+        Ok(partial) => partial,
+        Err(_) => return Vec::new(),
+    };
+    let word = partial.word.expand(|name| env.lookup(name));
+
+    let mut candidates: Vec<String> = if partial.command && !word.contains('/') {
+        COMMANDS.iter().map(|c| c.name.to_string())
+            .chain(env::programs(fs, env))
+            .filter(|name| name.starts_with(&word))
+            .collect()
+    } else {
+        let (dir, prefix) = match word.rfind('/') {
+            Some(i) => (&word[..i + 1], &word[i + 1..]),
+            None => ("", word.as_str()),
+        };
+        match fs.list_dir(dir) {
+            Ok(names) => names.into_iter()
+                // Dot files only when asked for
+                .filter(|name| name.starts_with(prefix) && (prefix.starts_with('.') || !name.starts_with('.')))
+                .map(|name| {
+                    let path = format!("{}{}", dir, name);
+                    if fs.is_dir(&path) { format!("{}/", path) } else { path }
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    };
+    candidates.sort();
+    candidates.dedup();
+
+    let typed = &line[partial.start..];
+    candidates.iter()
+        .map(|candidate| {
+            let rest = quote(&candidate[word.len()..], partial.quote);
+            let close = match partial.quote {
+                _ if candidate.ends_with('/') => "",
+                parser::Quote::Single => "'",
+                parser::Quote::Double => "\"",
+                parser::Quote::None => "",
+            };
+            format!("{}{}{}", typed, rest, close)
+        })
+        .collect()
+}
+
+// `text` escaped to be typed inside `quote`
+fn quote(text: &str, quote: parser::Quote) -> String {
+    let mut out = String::new();
+    for c in text.chars() {
+        match quote {
+            parser::Quote::None if c.is_whitespace() || "'\"\\$|&;<>".contains(c) => out.push('\\'),
+            parser::Quote::Double if "\"\\$".contains(c) => out.push('\\'),
+            // Nothing is special inside single quotes, a quote has to leave them
+            parser::Quote::Single if c == '\'' => {
+                out.push_str("'\\''");
+                continue;
+            },
+            _ => {},
+        }
+        out.push(c);
+    }
+    out
+}
+
 impl Shell {
-    pub fn new() -> Self {
+    pub fn new(host: Rc<dyn Host>) -> Self {
+        Self::with_env(host, Env::new())
+    }
+
+    pub fn with_env(host: Rc<dyn Host>, env: Env) -> Self {
         Self {
-            input_buffer: String::new(),
+            host,
+            editor: LineEditor::new(),
             current_path: "~".to_string(),
-            history: Vec::new(),
-            history_index: None,
+            history: History::new(),
+            history_loaded: false,
+            env,
             waiting_for_reset: false,
             awaiting_restart_confirm: false,
         }
@@ -82,403 +240,669 @@ impl Shell {
     }
 
     pub fn update_prompt(&mut self, fs: &std::rc::Rc<std::cell::RefCell<crate::sys::fs::FileSystem>>) {
-        let path = {
-            let fs_guard = fs.borrow();
-            if fs_guard.current_path.is_empty() {
-                "~".to_string()
-            } else {
-                 // Join path
-                 let mut p = String::from("/");
-                 for part in &fs_guard.current_path {
-                     p.push_str(part);
-                     p.push('/');
-                 }
-                 // Remove trailing slash if len > 1
-                 if p.len() > 1 { p.pop(); }
-                 p
-            }
-        };
-        self.current_path = path;
+        let path = fs.borrow().cwd();
+        self.current_path = if path == "/" { "~".to_string() } else { path };
     }
 
-    pub fn on_key(&mut self, key: &str, term: &std::rc::Rc<std::cell::RefCell<crate::term::Terminal>>, fs: &std::rc::Rc<std::cell::RefCell<crate::sys::fs::FileSystem>>, wasm: &crate::sys::wasm::WasmRuntime, events: &mut std::collections::VecDeque<crate::kernel::SystemEvent>, ticks: u64, hz: f64) -> bool {
+    pub fn on_key(&mut self, key: &str, term: &std::rc::Rc<std::cell::RefCell<crate::term::Terminal>>, fs: &std::rc::Rc<std::cell::RefCell<crate::sys::fs::FileSystem>>, wasm: &crate::sys::wasm::WasmRuntime, ticks: u64, hz: f64) -> bool {
+        if key == "Shift+PageUp" || key == "Shift+PageDown" {
+            // A page of scrollback at a time, keeping a line of context
+            let mut term = term.borrow_mut();
+            let page = term.rows as i32 - 1;
+            term.scroll_view(if key == "Shift+PageUp" { -page } else { page });
+            return false;
+        }
+        let key = match Key::from_name(key) {
+            Some(key) => key,
+            None => return false,
+        };
+        self.history(fs);
+        let outcome = self.editor.handle(key, &mut term.borrow_mut(), &self.history);
+
         // Handle confirmation dialog
         if self.awaiting_restart_confirm {
-            if key == "Enter" {
+            if outcome == Outcome::Submit {
                 term.borrow_mut().write_char('\n');
-                let input = self.input_buffer.trim();
-                
-                if input == "y" || input == "Y" {
+                let input = self.editor.take();
+                self.awaiting_restart_confirm = false;
+                if input.trim() == "y" || input.trim() == "Y" {
                     term.borrow_mut().write_str("restarting system...\n");
-                    self.input_buffer.clear();
-                    self.awaiting_restart_confirm = false;
                     return true; // Trigger RESTART (mapped to Re-boot/start enum)
                 } else {
                     term.borrow_mut().write_str("restart cancelled.\n");
-                    self.input_buffer.clear();
-                    self.awaiting_restart_confirm = false;
                     self.draw_prompt(term);
-                    return false;
-                }
-            } else if key == "Backspace" {
-                 if !self.input_buffer.is_empty() {
-                    self.input_buffer.pop();
-                    term.borrow_mut().write_char('\x08'); 
-                }
-                return false;
-            } else if key.len() == 1 {
-                self.input_buffer.push_str(key);
-                term.borrow_mut().write_str(key);
-                return false;
+                }
             }
             return false;
         }
 
         if self.waiting_for_reset {
-            if key == "Enter" {
+            if outcome == Outcome::Submit {
                 term.borrow_mut().write_char('\n');
-                let input = self.input_buffer.trim();
-                if input == "y" || input == "Y" {
+                let input = self.editor.take();
+                self.waiting_for_reset = false;
+                if input.trim() == "y" || input.trim() == "Y" {
                     term.borrow_mut().write_str("resetting to factory defaults...\n");
-                    
-                    // Clear LocalStorage
-                    if let Some(window) = web_sys::window() {
-                        if let Ok(Some(storage)) = window.local_storage() {
-                            let _ = storage.remove_item("wasmix_fs_local");
-                        }
-                    }
 
-                    self.input_buffer.clear();
-                    self.waiting_for_reset = false;
+                    // Clear persisted disk
+                    self.host.disk_clear();
                     return true; // Trigger REBOOT
                 } else {
                     term.borrow_mut().write_str("reset cancelled.\n");
-                    self.input_buffer.clear();
-                    self.waiting_for_reset = false;
                     self.draw_prompt(term);
-                    return false;
-                }
-            } else if key == "Backspace" {
-                 if !self.input_buffer.is_empty() {
-                    self.input_buffer.pop();
-                    term.borrow_mut().write_char('\x08'); 
-                }
-                return false;
-            } else if key.len() == 1 {
-                self.input_buffer.push_str(key);
-                term.borrow_mut().write_str(key);
-                return false;
+                }
             }
             return false;
         }
 
-        if key == "Enter" {
-            term.borrow_mut().write_char('\n');
-            if !self.input_buffer.trim().is_empty() {
-                self.history.push(self.input_buffer.clone());
-                self.history_index = None;
-            }
-            
-            let restart = self.execute_command(term, fs, Some(wasm), events, ticks, hz);
-            self.input_buffer.clear();
-            if !self.awaiting_restart_confirm && !self.waiting_for_reset && !restart {
-                 self.draw_prompt(term);
-            }
-            return restart;
-        } else if key == "Backspace" {
-            if !self.input_buffer.is_empty() {
-                self.input_buffer.pop();
-                term.borrow_mut().write_char('\x08'); 
-            }
-        } else if key == "ArrowUp" {
-            if !self.history.is_empty() {
-                let new_index = match self.history_index {
-                    Some(i) => if i > 0 { i - 1 } else { 0 },
-                    None => self.history.len() - 1,
-                };
-                self.history_index = Some(new_index);
+        match outcome {
+            Outcome::Submit => {
+                term.borrow_mut().write_char('\n');
+                let mut line = self.editor.take();
+                match self.history.expand(&line) {
+                    Ok(Some(expanded)) => {
+                        // Show what actually runs
+                        term.borrow_mut().write_str(&format!("{}\n", expanded));
+                        line = expanded;
+                    },
+                    Ok(None) => {},
+                    Err(e) => {
+                        term.borrow_mut().write_str(&format!("error: {}\n", e));
+                        self.draw_prompt(term);
+                        return false;
+                    },
+                }
+                self.record(&line, fs);
 
-                // Clear current line
-                for _ in 0..self.input_buffer.len() {
-                    term.borrow_mut().write_char('\x08');
+                let restart = self.execute_command(&line, term, fs, Some(wasm), ticks, hz);
+                if !self.awaiting_restart_confirm && !self.waiting_for_reset && !restart {
+                    self.draw_prompt(term);
                 }
-                
-                self.input_buffer = self.history[new_index].clone();
-                term.borrow_mut().write_str(&self.input_buffer);
-            }
-        } else if key == "ArrowDown" {
-            if let Some(i) = self.history_index {
-                // Clear current line
-                for _ in 0..self.input_buffer.len() {
-                    term.borrow_mut().write_char('\x08');
+                return restart;
+            },
+            Outcome::Complete => {
+                let candidates = complete(&self.editor.before_cursor(), &fs.borrow(), &self.env);
+                let listed = self.editor.complete(&mut term.borrow_mut(), &candidates);
+                if listed {
+                    self.draw_prompt(term);
+                    self.editor.redraw(&mut term.borrow_mut());
                 }
+            },
+            Outcome::Edited | Outcome::Ignored => {},
+        }
+        false
+    }
 
-                if i < self.history.len() - 1 {
-                    let new_index = i + 1;
-                    self.history_index = Some(new_index);
-                    self.input_buffer = self.history[new_index].clone();
-                } else {
-                    self.history_index = None;
-                    self.input_buffer.clear();
-                }
-                term.borrow_mut().write_str(&self.input_buffer);
+
+    pub fn env(&self) -> &Env {
+        &self.env
+    }
+
+    // The history, read from HISTORY_FILE the first time it is needed
+    fn history(&mut self, fs: &std::rc::Rc<std::cell::RefCell<crate::sys::fs::FileSystem>>) -> &mut History {
+        if !self.history_loaded {
+            self.history_loaded = true;
+            if let Ok(data) = fs.borrow().read_file(HISTORY_FILE) {
+                self.history = History::from_text(&String::from_utf8_lossy(&data));
             }
-        } else if key.len() == 1 {
-            self.input_buffer.push_str(key);
-            term.borrow_mut().write_str(key);
         }
-        false
+        &mut self.history
     }
 
-    
-    pub fn execute_command(&mut self, term: &std::rc::Rc<std::cell::RefCell<crate::term::Terminal>>, fs: &std::rc::Rc<std::cell::RefCell<crate::sys::fs::FileSystem>>, wasm: Option<&crate::sys::wasm::WasmRuntime>, events: &mut std::collections::VecDeque<crate::kernel::SystemEvent>, ticks: u64, hz: f64) -> bool {
-        let full_input = self.input_buffer.trim().to_string(); 
-        if full_input.is_empty() {
-             return false;
+    // Add a line to the history and save it. The file is read again first, as
+    // other shells (terminal windows, `history -c`) may have changed it since.
+    fn record(&mut self, line: &str, fs: &std::rc::Rc<std::cell::RefCell<crate::sys::fs::FileSystem>>) {
+        self.history_loaded = false;
+        if self.history(fs).push(line) {
+            let text = self.history.to_text();
+            // Nowhere to keep it on a read-only disk, it stays in memory then
+            let _ = fs.borrow_mut().write_file(HISTORY_FILE, text.as_bytes());
         }
+    }
 
-        let commands: Vec<&str> = full_input.split("&&").collect();
-        
-        for cmd_str in commands {
-            let (res, output) = self.run_one_command(cmd_str.trim(), fs, wasm, events, ticks, hz);
-            
-            term.borrow_mut().write_str(&output);
-
-            match res {
-                CmdResult::Success => continue,
-                CmdResult::Error => break, 
-                // CmdResult::Reboot => return true,
-                CmdResult::Clear => term.borrow_mut().reset(),
-            }
+    pub fn execute_command(&mut self, line: &str, term: &std::rc::Rc<std::cell::RefCell<crate::term::Terminal>>, fs: &std::rc::Rc<std::cell::RefCell<crate::sys::fs::FileSystem>>, wasm: Option<&crate::sys::wasm::WasmRuntime>, ticks: u64, hz: f64) -> bool {
+        let (output, clear) = self.run_line(line.trim(), fs, wasm, ticks, hz);
+        let mut term = term.borrow_mut();
+        if clear {
+            term.reset();
         }
+        // `export SCROLLBACK=<lines>` resizes the console's scrollback
+        term.set_scrollback(wasmix_term::scrollback_lines(self.env.get(wasmix_term::SCROLLBACK_VAR)));
+        term.write_str(&output);
         false
     }
     
-    pub fn execute_string(&mut self, full_input: &str, fs: &std::rc::Rc<std::cell::RefCell<crate::sys::fs::FileSystem>>, wasm: Option<&crate::sys::wasm::WasmRuntime>, events: &mut std::collections::VecDeque<crate::kernel::SystemEvent>, ticks: u64, hz: f64) -> (String, bool) {
-        let mut full_output = String::new();
-        if full_input.trim().is_empty() {
-             return (full_output, false);
-        }
-        
-        let commands: Vec<&str> = full_input.split("&&").collect();
-        let should_reboot = false;
-        
-        for cmd_str in commands {
-            // Note: pass dummy term? Or does execute_string NOT print to term?
-            // Actually, run_one_command returns output. We aggregate it.
-            // But we can't pass `term` because we don't have it here. This function returns String.
-            // That's fine.
-            let (res, output) = self.run_one_command(cmd_str.trim(), fs, wasm, events, ticks, hz);
-            full_output.push_str(&output);
-
-            match res {
-                CmdResult::Success => continue,
-                CmdResult::Error => break, 
-                // CmdResult::Reboot => {
-                //    full_output.push_str("System Rebooting...\n");
-                //    should_reboot = true;
-                // },
-                CmdResult::Clear => {}, 
+    /// Runs a command line and returns what it printed instead of writing to a terminal (used by sys_exec)
+    pub fn execute_string(&mut self, full_input: &str, fs: &std::rc::Rc<std::cell::RefCell<crate::sys::fs::FileSystem>>, wasm: Option<&crate::sys::wasm::WasmRuntime>, ticks: u64, hz: f64) -> (String, bool) {
+        let (output, _clear) = self.run_line(full_input, fs, wasm, ticks, hz);
+        (output, false)
+    }
+
+    // Parse and run a whole line. Returns the terminal output and whether `clear` ran,
+    // in which case the output only holds what was printed after it.
+    fn run_line(&mut self, input: &str, fs: &std::rc::Rc<std::cell::RefCell<crate::sys::fs::FileSystem>>, wasm: Option<&crate::sys::wasm::WasmRuntime>, ticks: u64, hz: f64) -> (String, bool) {
+        let mut out = String::new();
+        let mut clear = false;
+        let list = match parser::parse(input) {
+            Ok(Some(list)) => list,
+            Ok(None) => return (out, clear),
+            Err(e) => {
+                out.push_str(&e);
+                out.push('\n');
+                self.env.status = 2;
+                return (out, clear);
+            }
+        };
+
+        let mut ok = self.run_pipeline(&list.first, fs, wasm, ticks, hz, &mut out, &mut clear);
+        for (connector, pipeline) in &list.rest {
+            let run = match connector {
+                Connector::Seq => true,
+                Connector::And => ok,
+                Connector::Or => !ok,
+            };
+            if run {
+                ok = self.run_pipeline(pipeline, fs, wasm, ticks, hz, &mut out, &mut clear);
             }
         }
-        (full_output, should_reboot)
+        (out, clear)
     }
-    
-    /// Variant that takes events as Rc<RefCell<...>> to avoid holding borrows across nested wasm calls
-    pub fn execute_string_rc(&mut self, full_input: &str, fs: &std::rc::Rc<std::cell::RefCell<crate::sys::fs::FileSystem>>, wasm: Option<&crate::sys::wasm::WasmRuntime>, events_rc: &std::rc::Rc<std::cell::RefCell<std::collections::VecDeque<crate::kernel::SystemEvent>>>, ticks: u64, hz: f64) -> (String, bool) {
-        let mut full_output = String::new();
-        if full_input.trim().is_empty() {
-             return (full_output, false);
-        }
-        
-        let commands: Vec<&str> = full_input.split("&&").collect();
-        let should_reboot = false;
-        
-        for cmd_str in commands {
-            let cmd_str = cmd_str.trim();
-            
-            // Special handling for exec: don't hold events borrow since wasm load may trigger syscalls
-            let is_exec = cmd_str.starts_with("exec ");
-            
-            let (res, output) = if is_exec {
-                // For exec, call run_one_command_rc which handles the Rc internally
-                self.run_one_command_rc(cmd_str, fs, wasm, events_rc, ticks, hz)
+
+    // Run each command with the previous one's stdout as its stdin. Succeeds if the last
+    // command did, as in sh.
+    #[allow(clippy::too_many_arguments)]
+    fn run_pipeline(&mut self, pipeline: &Pipeline, fs: &std::rc::Rc<std::cell::RefCell<crate::sys::fs::FileSystem>>, wasm: Option<&crate::sys::wasm::WasmRuntime>, ticks: u64, hz: f64, out: &mut String, clear: &mut bool) -> bool {
+        let last = pipeline.commands.len() - 1;
+        let mut piped: Option<Vec<u8>> = None;
+        let mut result = CmdResult::Success;
+
+        for (i, cmd) in pipeline.commands.iter().enumerate() {
+            let mut io = Io { stdin: piped.take(), out: String::new(), err: String::new(), status: None };
+
+            // Variables are filled in now, so they see what earlier commands set
+            let argv: Vec<String> = cmd.argv.iter().map(|w| w.expand(|name| self.env.lookup(name))).collect();
+            let redirects: Vec<(RedirectKind, String)> = cmd.redirects.iter()
+                .map(|r| (r.kind, r.target.expand(|name| self.env.lookup(name))))
+                .collect();
+
+            // `<` replaces the pipe, the last one wins
+            let mut input_ok = true;
+            for (_, target) in redirects.iter().filter(|(kind, _)| *kind == RedirectKind::In) {
+                let content = fs.borrow().read_file(target);
+                match content {
+                    Ok(content) => io.stdin = Some(content),
+                    Err(e) => {
+                        io.err.push_str(&format!("error: {}\n", e));
+                        input_ok = false;
+                    }
+                }
+            }
+
+            result = if input_ok {
+                self.run_command(&argv, &mut io, fs, wasm, ticks, hz)
             } else {
-                // For other commands, borrow events briefly
-                let mut events = events_rc.borrow_mut();
-                self.run_one_command(cmd_str, fs, wasm, &mut events, ticks, hz)
+                CmdResult::Error
             };
-            full_output.push_str(&output);
+            if result == CmdResult::Clear {
+                out.clear();
+                *clear = true;
+            }
 
-            match res {
-                CmdResult::Success => continue,
-                CmdResult::Error => break, 
-                CmdResult::Clear => {}, 
+            // `>` and `>>` take stdout. Like sh, every target is created but only the
+            // last one receives the output.
+            let targets: Vec<&(RedirectKind, String)> = redirects.iter().filter(|(kind, _)| *kind != RedirectKind::In).collect();
+            for (j, (kind, target)) in targets.iter().enumerate() {
+                let data = if j == targets.len() - 1 { io.out.as_bytes() } else { &[] };
+                let res = match kind {
+                    RedirectKind::Append => fs.borrow_mut().append_file(target, data),
+                    _ => fs.borrow_mut().write_file(target, data),
+                };
+                if let Err(e) = res {
+                    io.err.push_str(&format!("error: {}: {}\n", target, e));
+                    result = CmdResult::Error;
+                }
+            }
+
+            if i < last {
+                piped = Some(if targets.is_empty() { io.out.into_bytes() } else { Vec::new() });
+            } else if targets.is_empty() {
+                out.push_str(&io.out);
             }
+            out.push_str(&io.err);
+            self.env.status = match (&result, io.status) {
+                (CmdResult::Error, Some(status)) if status != 0 => status,
+                (CmdResult::Error, _) => 1,
+                (_, status) => status.unwrap_or(0),
+            };
         }
-        (full_output, should_reboot)
+        result != CmdResult::Error
     }
     
-    /// Handles exec command without holding events borrow (allows nested syscalls during wasm load)
-    fn run_one_command_rc(&mut self, cmd_str: &str, fs: &std::rc::Rc<std::cell::RefCell<crate::sys::fs::FileSystem>>, wasm: Option<&crate::sys::wasm::WasmRuntime>, _events_rc: &std::rc::Rc<std::cell::RefCell<std::collections::VecDeque<crate::kernel::SystemEvent>>>, _ticks: u64, _hz: f64) -> (CmdResult, String) {
-        let mut out = String::new();
-        let parts: Vec<&str> = cmd_str.split_whitespace().collect();
-        
-        // This should only be called for exec commands
-        if parts.is_empty() || parts[0] != "exec" {
-            out.push_str("internal error: run_one_command_rc called for non-exec\n");
-            return (CmdResult::Error, out);
-        }
-        
-        if parts.len() < 2 {
-            out.push_str("usage: exec <path>\n");
-            return (CmdResult::Error, out);
+    // "drwxr-xr-x" style type and permission bits
+    fn mode_string(stat: &Stat) -> String {
+        let mut out = String::from(match stat.node_type {
+            NodeType::File => '-',
+            NodeType::Directory => 'd',
+            NodeType::Symlink => 'l',
+        });
+        for shift in [6, 3, 0] {
+            let bits = stat.mode >> shift;
+            out.push(if bits & 4 != 0 { 'r' } else { '-' });
+            out.push(if bits & 2 != 0 { 'w' } else { '-' });
+            out.push(if bits & 1 != 0 { 'x' } else { '-' });
         }
-        
-        if wasm.is_none() {
-            out.push_str("exec not supported in this environment\n");
-            return (CmdResult::Error, out);
-        }
-        
-        let wasm_rt = wasm.unwrap();
-        let path = parts[1];
-        
-        // Get file content without holding borrow during load
-        let file_node_content = {
-            let fs_guard = fs.borrow();
-            fs_guard.resolve_dir(&fs_guard.current_path) 
-                .and_then(|d| d.children.get(path))
-                .or_else(|| {
-                     if path.starts_with("/bin/") {
-                         fs_guard.root.children.get("bin").and_then(|b| b.children.get(&path[5..]))
-                     } else {
-                        None
-                     }
-                }).and_then(|node| {
-                    if let crate::sys::fs::NodeType::File = node.node_type {
-                        Some(node.content.clone())
-                    } else {
-                        None
-                    }
-                })
-        };
+        out
+    }
 
-        if let Some(content) = file_node_content {
-            out.push_str(&format!("loading wasm ({} bytes)...\n", content.len()));
-            // CRITICAL: No events borrow held here - wasm load can safely call syscalls
-            match wasm_rt.load(&content) {
-                Ok(captured_output) => {
-                    out.push_str(&captured_output);
-                    (CmdResult::Success, out)
-                },
-                Err(e) => {
-                    out.push_str(&format!("exec crash: {}\n", e));
-                    (CmdResult::Error, out)
-                }
+    // Wall clock ms as "yyyy-mm-dd hh:mm" (utc)
+    fn format_time(ms: f64) -> String {
+        let secs = (ms / 1000.0) as i64;
+        let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+        // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+        format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, rem / 3600, rem % 3600 / 60)
+    }
+
+    // Octal ("755") or symbolic ("+x", "go-w", "u=rw") mode, applied to `mode`
+    fn parse_mode(spec: &str, mode: u32) -> Option<u32> {
+        if spec.chars().all(|c| c.is_digit(8)) {
+            return u32::from_str_radix(spec, 8).ok().filter(|m| *m <= 0o7777);
+        }
+        let mut mode = mode;
+        for clause in spec.split(',') {
+            let op_at = clause.find(['+', '-', '='])?;
+            let (who, rest) = clause.split_at(op_at);
+            let (op, perms) = rest.split_at(1);
+            let mut mask = 0;
+            for c in if who.is_empty() { "a" } else { who }.chars() {
+                mask |= match c {
+                    'u' => 0o700,
+                    'g' => 0o070,
+                    'o' => 0o007,
+                    'a' => 0o777,
+                    _ => return None,
+                };
             }
-        } else {
-             let is_dir = {
-                 let fs_guard = fs.borrow();
-                 let dir = fs_guard.resolve_dir(&fs_guard.current_path).unwrap_or(&fs_guard.root);
-                 dir.children.get(path).is_some()
-             };
-             
-             if is_dir {
-                 out.push_str(&format!("error: '{}' is a directory\n", path));
-                 (CmdResult::Error, out)
-             } else {
-                 out.push_str(&format!("error: file '{}' not found\n", path));
-                 (CmdResult::Error, out)
-             }
+            let mut bits = 0;
+            for c in perms.chars() {
+                bits |= match c {
+                    'r' => 0o444,
+                    'w' => 0o222,
+                    'x' => 0o111,
+                    _ => return None,
+                };
+            }
+            mode = match op {
+                "+" => mode | (bits & mask),
+                "-" => mode & !(bits & mask),
+                _ => (mode & !mask) | (bits & mask),
+            };
         }
+        Some(mode)
     }
-    
-    fn run_one_command(&mut self, cmd_str: &str, fs: &std::rc::Rc<std::cell::RefCell<crate::sys::fs::FileSystem>>, wasm: Option<&crate::sys::wasm::WasmRuntime>, _events: &mut std::collections::VecDeque<crate::kernel::SystemEvent>, ticks: u64, hz: f64) -> (CmdResult, String) {
-        let mut out = String::new();
-        if cmd_str.is_empty() {
-             return (CmdResult::Success, out);
+
+    // `cp a dir` and `mv a dir` put `a` inside an existing directory
+    fn target_path(fs: &std::rc::Rc<std::cell::RefCell<crate::sys::fs::FileSystem>>, source: &str, dest: &str) -> String {
+        if !fs.borrow().is_dir(dest) {
+            return dest.to_string();
         }
+        let name = source.trim_end_matches('/').rsplit('/').next().unwrap_or(source);
+        format!("{}/{}", dest.trim_end_matches('/'), name)
+    }
 
-        let parts: Vec<&str> = cmd_str.split_whitespace().collect();
+    fn run_command(&mut self, argv: &[String], io: &mut Io, fs: &std::rc::Rc<std::cell::RefCell<crate::sys::fs::FileSystem>>, wasm: Option<&crate::sys::wasm::WasmRuntime>, ticks: u64, hz: f64) -> CmdResult {
+        let parts: Vec<&str> = argv.iter().map(|s| s.as_str()).collect();
         let cmd = parts[0];
 
         match cmd {
             "help" => {
-                out.push_str("available commands:\n");
+                io.out.push_str("available commands:\n");
                 for cmd_def in COMMANDS {
                      let msg = format!("  {:<8} - {}\n", cmd_def.name, cmd_def.desc.to_lowercase());
-                     out.push_str(&msg);
+                     io.out.push_str(&msg);
                 }
-                (CmdResult::Success, out)
+                CmdResult::Success
             },
             "clear" => {
-                (CmdResult::Clear, out)
+                CmdResult::Clear
             },
             "ls" => {
-                let items = fs.borrow().list_dir();
-                for item in items {
-                    out.push_str(&item);
-                    out.push_str("  ");
+                let (flags, operands) = match split_flags(&parts[1..], "l") {
+                    Ok(split) => split,
+                    Err(e) => {
+                        io.err.push_str(&format!("ls: {}\n", e));
+                        return io.usage("ls [-l] [path...]");
+                    },
+                };
+                let long = flags.contains(&'l');
+                let targets = if operands.is_empty() { vec!["."] } else { operands };
+                let mut result = CmdResult::Success;
+                for (i, target) in targets.iter().enumerate() {
+                    // A file (or a link to one) is listed on its own, under the name given
+                    let stat = fs.borrow().lstat(target);
+                    let listing = match stat {
+                        Ok(stat) if !fs.borrow().is_dir(target) => Ok(vec![(target.to_string(), stat, target.to_string())]),
+                        Ok(_) => fs.borrow().read_dir(target).map(|items| {
+                            items.into_iter()
+                                .map(|(name, stat)| {
+                                    let path = format!("{}/{}", target.trim_end_matches('/'), name);
+                                    (name, stat, path)
+                                })
+                                .collect()
+                        }),
+                        Err(e) => Err(e),
+                    };
+                    let items = match listing {
+                        Ok(items) => items,
+                        Err(e) => {
+                            result = io.error(&e);
+                            continue;
+                        },
+                    };
+                    if targets.len() > 1 && fs.borrow().is_dir(target) {
+                        io.out.push_str(&format!("{}{}:\n", if i > 0 { "\n" } else { "" }, target));
+                    }
+                    if long {
+                        for (name, stat, path) in items {
+                            let mut line = format!("{} {:>4} {:>4} {:>8} {} {}", Self::mode_string(&stat), stat.uid, stat.gid, stat.size, Self::format_time(stat.modified), name);
+                            if let NodeType::Symlink = stat.node_type {
+                                if let Ok(dest) = fs.borrow().readlink(&path) {
+                                    line.push_str(&format!(" -> {}", dest));
+                                }
+                            }
+                            io.out.push_str(&line);
+                            io.out.push('\n');
+                        }
+                    } else {
+                        for (name, _, _) in items {
+                            io.out.push_str(&name);
+                            io.out.push_str("  ");
+                        }
+                        io.out.push('\n');
+                    }
                 }
-                out.push('\n');
-                (CmdResult::Success, out)
+                result
             },
-            "mkdir" => {
+            "stat" => {
                 if parts.len() < 2 {
-                    out.push_str("usage: mkdir <name>\n");
-                    (CmdResult::Error, out)
+                    io.usage("stat <path>")
                 } else {
-                    let res = fs.borrow_mut().mkdir(parts[1]);
+                    let res = fs.borrow().lstat(parts[1]);
                     match res {
-                        Ok(_) => (CmdResult::Success, out),
-                        Err(e) => {
-                            out.push_str("error: ");
-                            out.push_str(&e);
-                            out.push('\n');
-                            (CmdResult::Error, out)
+                        Ok(stat) => {
+                            let (kind, name) = match stat.node_type {
+                                NodeType::File => ("regular file", parts[1].to_string()),
+                                NodeType::Directory => ("directory", parts[1].to_string()),
+                                NodeType::Symlink => ("symbolic link", format!("{} -> {}", parts[1], fs.borrow().readlink(parts[1]).unwrap_or_default())),
+                            };
+                            io.out.push_str(&format!("  file: {}\n", name));
+                            io.out.push_str(&format!("  type: {}\n", kind));
+                            io.out.push_str(&format!("  size: {}\n", stat.size));
+                            io.out.push_str(&format!("  mode: {:04o} ({})\n", stat.mode, Self::mode_string(&stat)));
+                            io.out.push_str(&format!(" owner: uid {} gid {}\n", stat.uid, stat.gid));
+                            io.out.push_str(&format!("access: {}\n", Self::format_time(stat.accessed)));
+                            io.out.push_str(&format!("modify: {}\n", Self::format_time(stat.modified)));
+                            io.out.push_str(&format!("create: {}\n", Self::format_time(stat.created)));
+                            CmdResult::Success
+                        },
+                        Err(e) => io.error(&e),
+                    }
+                }
+            },
+            "chmod" => {
+                if parts.len() < 3 {
+                    io.usage("chmod <mode> <path>")
+                } else {
+                    let current = fs.borrow().stat(parts[2]).map(|s| s.mode);
+                    let res = current.and_then(|mode| {
+                        let mode = Self::parse_mode(parts[1], mode).ok_or(format!("invalid mode '{}'", parts[1]))?;
+                        fs.borrow_mut().chmod(parts[2], mode)
+                    });
+                    match res {
+                        Ok(_) => CmdResult::Success,
+                        Err(e) => io.error(&e),
+                    }
+                }
+            },
+            "chown" => {
+                if parts.len() < 3 {
+                    io.usage("chown <uid>[:<gid>] <path>")
+                } else {
+                    let current = fs.borrow().stat(parts[2]);
+                    let res = current.and_then(|stat| {
+                        let (uid, gid) = parts[1].split_once(':').unwrap_or((parts[1], ""));
+                        let parse = |id: &str, keep: u32| if id.is_empty() { Some(keep) } else { id.parse::<u32>().ok() };
+                        match (parse(uid, stat.uid), parse(gid, stat.gid)) {
+                            (Some(uid), Some(gid)) => fs.borrow_mut().chown(parts[2], uid, gid),
+                            _ => Err(format!("invalid owner '{}'", parts[1])),
                         }
+                    });
+                    match res {
+                        Ok(_) => CmdResult::Success,
+                        Err(e) => io.error(&e),
                     }
                 }
             },
+            "ln" => {
+                if parts.len() < 4 || parts[1] != "-s" {
+                    io.usage("ln -s <target> <link>")
+                } else {
+                    let res = fs.borrow_mut().symlink(parts[2], parts[3]);
+                    match res {
+                        Ok(_) => CmdResult::Success,
+                        Err(e) => io.error(&e),
+                    }
+                }
+            },
+            "mkdir" => {
+                match split_flags(&parts[1..], "p") {
+                    Ok((flags, dirs)) if !dirs.is_empty() => {
+                        let parents = flags.contains(&'p');
+                        let mut result = CmdResult::Success;
+                        for dir in dirs {
+                            let res = if parents { fs.borrow_mut().mkdir_all(dir) } else { fs.borrow_mut().mkdir(dir) };
+                            if let Err(e) = res {
+                                result = io.error(&e);
+                            }
+                        }
+                        result
+                    },
+                    Ok(_) => io.usage("mkdir [-p] <dir>..."),
+                    Err(e) => {
+                        io.err.push_str(&format!("mkdir: {}\n", e));
+                        io.usage("mkdir [-p] <dir>...")
+                    },
+                }
+            },
             "touch" => {
                 if parts.len() < 2 {
-                    out.push_str("usage: touch <name>\n");
-                    (CmdResult::Error, out)
+                    io.usage("touch <name>")
                 } else {
                     let res = fs.borrow_mut().create_file(parts[1]);
                     match res {
-                        Ok(_) => (CmdResult::Success, out),
-                        Err(e) => {
-                            out.push_str("error: ");
-                            out.push_str(&e);
-                            out.push('\n');
-                            (CmdResult::Error, out)
-                        }
+                        Ok(_) => CmdResult::Success,
+                        Err(e) => io.error(&e),
                     }
                 }
             },
             "rm" => {
                 if parts.len() < 2 {
-                    out.push_str("usage: rm <name>\n");
-                    (CmdResult::Error, out)
+                    io.usage("rm <name>")
                 } else {
                     let res = fs.borrow_mut().remove_entry(parts[1]);
                     match res {
-                        Ok(_) => (CmdResult::Success, out),
-                        Err(e) => {
-                             out.push_str("error: ");
-                             out.push_str(&e);
-                             out.push('\n');
-                             (CmdResult::Error, out)
+                        Ok(_) => CmdResult::Success,
+                        Err(e) => io.error(&e),
+                    }
+                }
+            },
+            "cat" => {
+                if parts.len() < 2 {
+                    match io.stdin.take() {
+                        Some(data) => {
+                            io.out.push_str(&String::from_utf8_lossy(&data));
+                            CmdResult::Success
+                        },
+                        None => io.usage("cat <file>..."),
+                    }
+                } else {
+                    let mut result = CmdResult::Success;
+                    for path in &parts[1..] {
+                        let res = fs.borrow().read_file(path);
+                        match res {
+                            Ok(data) => io.out.push_str(&String::from_utf8_lossy(&data)),
+                            Err(e) => result = io.error(&e),
                         }
                     }
+                    result
+                }
+            },
+            "cp" => {
+                let recursive = parts.get(1) == Some(&"-r");
+                let args = if recursive { &parts[2..] } else { &parts[1..] };
+                if args.len() != 2 {
+                    io.usage("cp [-r] <source> <dest>")
+                } else {
+                    let dest = Self::target_path(fs, args[0], args[1]);
+                    let res = fs.borrow_mut().copy(args[0], &dest, recursive);
+                    match res {
+                        Ok(_) => CmdResult::Success,
+                        Err(e) => io.error(&e),
+                    }
+                }
+            },
+            "mv" => {
+                if parts.len() != 3 {
+                    io.usage("mv <source> <dest>")
+                } else {
+                    let dest = Self::target_path(fs, parts[1], parts[2]);
+                    let res = fs.borrow_mut().rename(parts[1], &dest);
+                    match res {
+                        Ok(_) => CmdResult::Success,
+                        Err(e) => io.error(&e),
+                    }
+                }
+            },
+            "tar" => {
+                match (parts.get(1).copied(), parts.len()) {
+                    (Some("-c"), 4) => {
+                        let archive = fs.borrow().export_tar(parts[3]);
+                        let res = archive.and_then(|data| fs.borrow_mut().write_file(parts[2], &data));
+                        match res {
+                            Ok(_) => CmdResult::Success,
+                            Err(e) => io.error(&e),
+                        }
+                    },
+                    (Some("-x"), 3 | 4) => {
+                        let dest = parts.get(3).copied().unwrap_or(".");
+                        let data = fs.borrow().read_file(parts[2]);
+                        let res = data.and_then(|data| fs.borrow_mut().import_tar(dest, &data));
+                        match res {
+                            Ok(_) => CmdResult::Success,
+                            Err(e) => io.error(&e),
+                        }
+                    },
+                    _ => io.usage("tar -c <archive> <path> | tar -x <archive> [dir]"),
+                }
+            },
+            "pkg" => {
+                match (parts.get(1).copied(), parts.len()) {
+                    (Some("list"), 2) => {
+                        for (m, _) in pkg::bundled() {
+                            io.out.push_str(&format!("{:<12} {:<8} {:<5} /bin/{}\n", m.name, m.version, m.icon, m.entry));
+                        }
+                        for m in pkg::installed(&fs.borrow()) {
+                            io.out.push_str(&format!("{:<12} {:<8} {:<5} {}/{}/{}\n", m.name, m.version, m.icon, pkg::APPS_DIR, m.name, m.entry));
+                        }
+                        CmdResult::Success
+                    },
+                    (Some("install"), 3) => {
+                        let archive = fs.borrow().read_file(parts[2]);
+                        let res = archive.and_then(|data| pkg::install(&mut fs.borrow_mut(), &data));
+                        match res {
+                            Ok(m) => {
+                                io.out.push_str(&format!("installed {} {}\n", m.name, m.version));
+                                CmdResult::Success
+                            },
+                            Err(e) => io.error(&e),
+                        }
+                    },
+                    (Some("remove"), 3) => {
+                        let res = pkg::remove(&mut fs.borrow_mut(), parts[2]);
+                        match res {
+                            Ok(_) => CmdResult::Success,
+                            Err(e) => io.error(&e),
+                        }
+                    },
+                    _ => io.usage("pkg list | pkg install <archive> | pkg remove <name>"),
+                }
+            },
+            "echo" => {
+                let no_newline = parts.get(1) == Some(&"-n");
+                let words = if no_newline { &parts[2..] } else { &parts[1..] };
+                io.out.push_str(&words.join(" "));
+                if !no_newline {
+                    io.out.push('\n');
+                }
+                CmdResult::Success
+            },
+            "write" => {
+                if parts.len() < 2 {
+                    io.usage("write <file> [text...]")
+                } else {
+                    // Without text the file gets whatever was piped in
+                    let data = if parts.len() > 2 {
+                        format!("{}\n", parts[2..].join(" ")).into_bytes()
+                    } else {
+                        io.stdin.take().unwrap_or_default()
+                    };
+                    let res = fs.borrow_mut().write_file(parts[1], &data);
+                    match res {
+                        Ok(_) => CmdResult::Success,
+                        Err(e) => io.error(&e),
+                    }
+                }
+            },
+            "head" | "tail" => {
+                let (count, file) = match parts[1..] {
+                    ["-n", n] => (n.parse::<usize>().ok(), None),
+                    ["-n", n, file] => (n.parse::<usize>().ok(), Some(file)),
+                    [file] => (Some(10), Some(file)),
+                    [] => (Some(10), None),
+                    _ => (None, None),
+                };
+                let text = match file {
+                    Some(path) => fs.borrow().read_file(path).map(Some),
+                    None => Ok(io.stdin.take()),
+                };
+                match (count, text) {
+                    (Some(n), Ok(Some(data))) => {
+                        let text = String::from_utf8_lossy(&data);
+                        let lines: Vec<&str> = text.lines().collect();
+                        let shown = if cmd == "head" { &lines[..n.min(lines.len())] } else { &lines[lines.len().saturating_sub(n)..] };
+                        for line in shown {
+                            io.out.push_str(line);
+                            io.out.push('\n');
+                        }
+                        CmdResult::Success
+                    },
+                    (Some(_), Err(e)) => io.error(&e),
+                    _ => io.usage(&format!("{} [-n lines] <file>", cmd)),
                 }
             },
             "cd" => {
                 if parts.len() < 2 {
                      let _ = fs.borrow_mut().cd("/");
                      self.update_prompt(fs); 
-                     (CmdResult::Success, out)
+                     CmdResult::Success
                 } else {
                      let target = if let Some(matched) = fs.borrow().match_entry(parts[1]) {
                          matched
@@ -490,126 +914,304 @@ impl Shell {
                      match res {
                         Ok(_) => {
                             self.update_prompt(fs); 
-                            (CmdResult::Success, out)
+                            CmdResult::Success
                         },
-                        Err(e) => {
-                            out.push_str("error: ");
-                            out.push_str(&e);
-                            out.push('\n');
-                            (CmdResult::Error, out)
-                        }
+                        Err(e) => io.error(&e),
                     }
                 }
             },
              "df" => {
                 let fs_guard = fs.borrow();
-                let total_kb = fs_guard.total_space / 1024;
-                let used_kb = fs_guard.used_space / 1024;
-                let msg = format!("disk usage:\n  used: {} kb\n  total: {} kb\n  free: {} kb\n", used_kb, total_kb, total_kb - used_kb);
-                out.push_str(&msg);
-                (CmdResult::Success, out)
+                // With a path, only the mount holding it
+                let only = match parts.get(1) {
+                    Some(path) => fs_guard.mount_of(path).map(Some),
+                    None => Ok(None),
+                };
+                match only {
+                    Ok(only) => {
+                        io.out.push_str("type     size kb  used kb  free kb  use%  mounted on\n");
+                        for m in fs_guard.mounts() {
+                            if only.as_ref().is_some_and(|p| *p != m.path) {
+                                continue;
+                            }
+                            let free = m.capacity.saturating_sub(m.used);
+                            let percent = if m.capacity == 0 { 0 } else { (m.used * 100).div_ceil(m.capacity) };
+                            io.out.push_str(&format!("{:<8} {:>7}  {:>7}  {:>7}  {:>3}%  {}\n",
+                                m.fs_type, m.capacity / 1024, m.used / 1024, free / 1024, percent, m.path));
+                        }
+                        CmdResult::Success
+                    },
+                    Err(e) => io.error(&e),
+                }
+            },
+            "fsck" => {
+                let repair = match parts.get(1) {
+                    None => Some(false),
+                    Some(&"-r") => Some(true),
+                    Some(_) => None,
+                };
+                match repair {
+                    Some(repair) => {
+                        let report = fs.borrow_mut().fsck(repair);
+                        for line in report.lines() {
+                            io.out.push_str(&line);
+                            io.out.push('\n');
+                        }
+                        // Like fsck(8), problems left in place are a failure
+                        if report.problems.iter().all(|p| p.fixed) {
+                            CmdResult::Success
+                        } else {
+                            CmdResult::Error
+                        }
+                    },
+                    None => io.usage("fsck [-r]"),
+                }
+            },
+            "mount" => {
+                match parts.len() {
+                    1 => {
+                        for m in fs.borrow().mounts() {
+                            let mode = if m.read_only { "ro" } else { "rw" };
+                            io.out.push_str(&format!("{} on {} ({})\n", m.fs_type, m.path, mode));
+                        }
+                        CmdResult::Success
+                    },
+                    3 => {
+                        let res = fs.borrow_mut().mount(parts[1], parts[2]);
+                        match res {
+                            Ok(_) => CmdResult::Success,
+                            Err(e) => io.error(&e),
+                        }
+                    },
+                    _ => io.usage("mount [tmpfs|image|localfs <dir>]"),
+                }
+            },
+            "umount" => {
+                if parts.len() < 2 {
+                    io.usage("umount <dir>")
+                } else {
+                    let res = fs.borrow_mut().umount(parts[1]);
+                    match res {
+                        Ok(_) => CmdResult::Success,
+                        Err(e) => io.error(&e),
+                    }
+                }
             },
             "sysinfo" => {
-                out.push_str("system information:\n");
-                out.push_str("  kernel:  rust webos v0.2.0\n");
-                out.push_str("  arch:    wasm32-unknown-unknown\n");
+                io.out.push_str("system information:\n");
+                io.out.push_str("  kernel:  rust webos v0.2.0\n");
+                io.out.push_str("  arch:    wasm32-unknown-unknown\n");
                 let msg_cpu = format!("  cpu:     wasm-32 virtual core @ {:.2} hz\n", hz);
-                out.push_str(&msg_cpu);
-                out.push_str("  vram:    512x512 rgba (1 mb)\n");
-                out.push_str("  ram:     16 mb linear\n");
+                io.out.push_str(&msg_cpu);
+                io.out.push_str("  vram:    512x512 rgba (1 mb)\n");
+                io.out.push_str("  ram:     16 mb linear\n");
                 let msg_ticks = format!("  ticks:   {}\n", ticks);
-                out.push_str(&msg_ticks);
-                (CmdResult::Success, out)
+                io.out.push_str(&msg_ticks);
+                if let Some(wasm_rt) = wasm {
+                    let procs = wasm_rt.process_list();
+                    let load: u32 = procs.iter().map(|p| p.cpu).sum();
+                    io.out.push_str(&format!("  procs:   {} (cpu load {}%)\n", procs.len(), load));
+                }
+                CmdResult::Success
+            },
+            "ps" => {
+                match wasm {
+                    Some(wasm_rt) => {
+                        io.out.push_str("  pid  ppid  state      cpu       fuel  name\n");
+                        for p in wasm_rt.process_list() {
+                            io.out.push_str(&format!("{:>5} {:>5}  {:<9} {:>3}% {:>10}  {}\n", p.pid, p.parent, p.state.name(), p.cpu, p.fuel, p.name));
+                        }
+                        CmdResult::Success
+                    },
+                    None => {
+                        io.err.push_str("ps not supported in this environment\n");
+                        CmdResult::Error
+                    }
+                }
+            },
+            "kill" => {
+                let pid = parts.get(1).and_then(|p| p.parse::<u32>().ok());
+                match (pid, wasm) {
+                    (None, _) => io.usage("kill <pid>"),
+                    (Some(_), None) => {
+                        io.err.push_str("kill not supported in this environment\n");
+                        CmdResult::Error
+                    },
+                    (Some(pid), Some(wasm_rt)) => match wasm_rt.kill(pid) {
+                        Ok(()) => CmdResult::Success,
+                        Err(e) => io.error(&e),
+                    },
+                }
             },
             "uptime" => {
                 let seconds = ticks as f64 / 60.0;
                 let msg = format!("uptime: {:.2} seconds ({} ticks)\n", seconds, ticks);
-                out.push_str(&msg);
-                (CmdResult::Success, out)
+                io.out.push_str(&msg);
+                CmdResult::Success
             },
             "date" => {
-                let date = js_sys::Date::new_0();
-                let msg = format!("{}\n", date.to_string());
-                out.push_str(&msg);
-                (CmdResult::Success, out)
+                let msg = format!("{}\n", self.host.date_string());
+                io.out.push_str(&msg);
+                CmdResult::Success
             },
 
+            "history" => match parts.get(1).copied() {
+                Some("-c") => {
+                    self.history(fs).clear();
+                    match fs.borrow_mut().write_file(HISTORY_FILE, b"") {
+                        Ok(()) => CmdResult::Success,
+                        Err(e) => io.error(&e),
+                    }
+                },
+                arg => {
+                    let entries = self.history(fs).entries();
+                    let count = match arg {
+                        Some(n) => n.parse::<usize>().ok(),
+                        None => Some(entries.len()),
+                    };
+                    match count {
+                        Some(count) => {
+                            let start = entries.len().saturating_sub(count);
+                            for (i, entry) in entries.iter().enumerate().skip(start) {
+                                io.out.push_str(&format!("{:>5}  {}\n", i + 1, entry));
+                            }
+                            CmdResult::Success
+                        },
+                        None => io.usage("history [n] | history -c"),
+                    }
+                },
+            },
             "reset" => {
-                out.push_str("warning: this will wipe all local data.\n");
-                out.push_str("are you sure? (y/n) ");
-                self.input_buffer.clear();
+                io.err.push_str("warning: this will wipe all local data.\n");
+                io.err.push_str("are you sure? (y/n) ");
                 self.waiting_for_reset = true;
-                (CmdResult::Success, out) 
+                CmdResult::Success
             },
             "exec" => {
                 if parts.len() < 2 {
-                    out.push_str("usage: exec <path>\n");
-                    (CmdResult::Error, out)
-                } else if wasm.is_none() {
-                     out.push_str("exec not supported in this environment\n");
-                     (CmdResult::Error, out)
+                    io.usage("exec <path> [args...]")
                 } else {
-                    let wasm_rt = wasm.unwrap();
-                    let path = parts[1];
-                    
-                    // Critical: DO NOT hold FS lock here. wasm_rt.load_from_path will take it.
-                    let file_node_content = {
-                        let fs_guard = fs.borrow();
-                        fs_guard.resolve_dir(&fs_guard.current_path) 
-                            .and_then(|d| d.children.get(path))
-                            .or_else(|| {
-                                 if path.starts_with("/bin/") {
-                                     fs_guard.root.children.get("bin").and_then(|b| b.children.get(&path[5..]))
-                                 } else {
-                                    None
-                                 }
-                            }).and_then(|node| {
-                                if let crate::sys::fs::NodeType::File = node.node_type {
-                                    Some(node.content.clone()) // Clone content to ensure we drop fs borrow
-                                } else {
-                                    None
-                                }
-                            })
-                    };
-
-                    if let Some(content) = file_node_content {
-                        out.push_str(&format!("loading wasm ({} bytes)...\n", content.len()));
-                        match wasm_rt.load(&content) {
-                            Ok(captured_output) => {
-                                out.push_str(&captured_output);
-                                (CmdResult::Success, out)
-                            },
-                            Err(e) => {
-                                out.push_str(&format!("exec crash: {}\n", e));
-                                (CmdResult::Error, out)
-                            }
+                    // Looked up along $PATH like a command, or else a path from here
+                    let found = env::find_program(&fs.borrow(), &self.env, parts[1]);
+                    let path = found.unwrap_or_else(|| parts[1].to_string());
+                    self.run_program(&path, &argv[1..], io, wasm)
+                }
+            },
+            "export" => {
+                if parts.len() < 2 {
+                    for pair in self.env.pairs() {
+                        io.out.push_str(&format!("export {}\n", pair));
+                    }
+                    CmdResult::Success
+                } else {
+                    let mut result = CmdResult::Success;
+                    for arg in &parts[1..] {
+                        let (name, value) = match arg.split_once('=') {
+                            Some((name, value)) => (name, Some(value)),
+                            None => (*arg, None),
+                        };
+                        if !parser::is_name(name) {
+                            result = io.error(&format!("'{}': not a valid name", name));
+                        } else if let Some(value) = value {
+                            self.env.set(name, value);
                         }
-                    } else {
-                         // Check if directory?
-                         let is_dir = {
-                             let fs_guard = fs.borrow();
-                             // Debug path resolution
-                             // web_sys::console::log_1(&format!("exec resolve failed for: {}", path).into());
-                             let dir = fs_guard.resolve_dir(&fs_guard.current_path).unwrap_or(&fs_guard.root);
-                             dir.children.get(path).is_some()
-                         };
-                         
-                         if is_dir {
-                             out.push_str(&format!("error: '{}' is a directory\n", path));
-                             (CmdResult::Error, out)
-                         } else {
-                             out.push_str(&format!("error: file '{}' not found\n", path));
-                             (CmdResult::Error, out)
-                         }
                     }
+                    result
+                }
+            },
+            "unset" => {
+                for name in &parts[1..] {
+                    self.env.unset(name);
                 }
+                CmdResult::Success
+            },
+            "env" => {
+                for pair in self.env.pairs() {
+                    io.out.push_str(&pair);
+                    io.out.push('\n');
+                }
+                CmdResult::Success
             },
             _ => {
-                out.push_str("unknown command: ");
-                out.push_str(cmd);
-                out.push('\n');
-                (CmdResult::Error, out)
+                let found = env::find_program(&fs.borrow(), &self.env, cmd);
+                match found {
+                    Some(path) => self.run_program(&path, argv, io, wasm),
+                    None => {
+                        io.err.push_str(&format!("{}: command not found\n", cmd));
+                        io.status = Some(127);
+                        CmdResult::Error
+                    },
+                }
             }
         }
     }
+
+    // Run a program for the shell with its environment. `args` starts with the name
+    // it was called by.
+    fn run_program(&mut self, path: &str, args: &[String], io: &mut Io, wasm: Option<&crate::sys::wasm::WasmRuntime>) -> CmdResult {
+        let wasm_rt = match wasm {
+            Some(wasm_rt) => wasm_rt,
+            None => {
+                io.err.push_str("exec not supported in this environment\n");
+                return CmdResult::Error;
+            },
+        };
+
+        // Critical: DO NOT hold FS lock here. The program's syscalls will take it.
+        // Clone content to ensure we drop fs borrow
+        match wasm_rt.read_program(path) {
+            // Native images run on the cpu, next to the wasm programs
+            Ok(content) if content.starts_with(crate::kernel::NATIVE_MAGIC) => match wasm_rt.queue_native(content) {
+                Ok(()) => {
+                    io.status = Some(0);
+                    CmdResult::Success
+                },
+                Err(e) => io.error(&e),
+            },
+            Ok(content) => match wasm_rt.run(&content, args, &self.env, io.stdin.take()) {
+                Ok(capture) => {
+                    io.out.push_str(&capture.stdout);
+                    io.err.push_str(&capture.stderr);
+                    io.status = Some(capture.status);
+                    if capture.status == 0 { CmdResult::Success } else { CmdResult::Error }
+                },
+                Err(e) => {
+                    io.err.push_str(&format!("exec crash: {}\n", e));
+                    CmdResult::Error
+                }
+            },
+            Err(e) => io.error(&e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::native::NativeHost;
+    use crate::sys::fs::FileSystem;
+
+    #[test]
+    fn completes_words_as_typed() {
+        let mut fs = FileSystem::new(1, Rc::new(NativeHost::new()));
+        fs.mkdir_all("/tmp/my dir").unwrap();
+        fs.write_file("/tmp/my file.txt", b"").unwrap();
+        fs.write_file("/tmp/it's", b"").unwrap();
+        fs.write_file("/tmp/.hidden", b"").unwrap();
+        fs.current_path = vec!["tmp".to_string()];
+        let mut env = Env::new();
+        env.set("T", "/tmp");
+        let complete = |line: &str| complete(line, &fs, &env);
+
+        assert_eq!(complete("cat my"), ["my\\ dir/", "my\\ file.txt"]);
+        assert_eq!(complete("cat my\\ f"), ["my\\ file.txt"]);
+        assert_eq!(complete("cat \"my f"), ["\"my file.txt\""]);
+        assert_eq!(complete("cat 'my d"), ["'my dir/"]);
+        assert_eq!(complete("cat 'it"), ["'it'\\''s'"]);
+        assert_eq!(complete("cat \"$T/it"), ["\"$T/it's\""]);
+        assert_eq!(complete("cat ."), [".hidden"]);
+        assert_eq!(complete("ls; ech"), ["echo"]);
+        assert_eq!(complete("echo ech"), Vec::<String>::new());
+        assert!(complete("echo ${T").is_empty());
+    }
 }
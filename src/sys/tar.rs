@@ -0,0 +1,339 @@
+//! Tar (ustar) archives of filesystem trees, for moving files in and out of the VM.
+//!
+//! `pack` archives a file or directory under its own name (the root directory's
+//! entries go in bare), and `unpack` extracts an archive into a directory (`read`
+//! and `extract` are its two halves, for callers that look inside first). Files,
+//! directories and symlinks are kept with their mode and owner; other entry types
+//! (devices, pax headers) are skipped. Modification times are written but not
+//! restored.
+//!
+//! Extraction never goes through a symlink: an archive holding `evil -> /local/user`
+//! and then `evil/pwn` would otherwise write outside the destination.
+
+use crate::sys::fs::{FileSystem, NodeType, Stat};
+
+const BLOCK: usize = 512;
+
+const TYPE_FILE: u8 = b'0';
+const TYPE_SYMLINK: u8 = b'2';
+const TYPE_DIR: u8 = b'5';
+
+// Archive the entry at `path` and everything under it
+pub fn pack(fs: &FileSystem, path: &str) -> Result<Vec<u8>, String> {
+    let parts = fs.resolve_path(path);
+    let stat = fs.stat(path).map_err(|_| format!("'{}': no such file or directory", path))?;
+    let name = parts.last().cloned().unwrap_or_default();
+    let mut out = Vec::new();
+    pack_entry(fs, &FileSystem::join_path(&parts), &name, &stat, &mut out)?;
+    // End of archive: two zero blocks
+    out.resize(out.len() + 2 * BLOCK, 0);
+    Ok(out)
+}
+
+fn pack_entry(fs: &FileSystem, path: &str, name: &str, stat: &Stat, out: &mut Vec<u8>) -> Result<(), String> {
+    match stat.node_type {
+        NodeType::Directory => {
+            if !name.is_empty() {
+                out.extend_from_slice(&header(&format!("{}/", name), stat, TYPE_DIR, 0, "")?);
+            }
+            for (child, child_stat) in fs.read_dir(path)? {
+                let child_path = format!("{}/{}", path.trim_end_matches('/'), child);
+                let child_name = if name.is_empty() { child } else { format!("{}/{}", name, child) };
+                pack_entry(fs, &child_path, &child_name, &child_stat, out)?;
+            }
+        },
+        NodeType::File => {
+            let content = fs.read_file(path)?;
+            out.extend_from_slice(&header(name, stat, TYPE_FILE, content.len(), "")?);
+            out.extend_from_slice(&content);
+            out.resize(out.len().next_multiple_of(BLOCK), 0);
+        },
+        NodeType::Symlink => {
+            let target = fs.readlink(path)?;
+            out.extend_from_slice(&header(name, stat, TYPE_SYMLINK, 0, &target)?);
+        },
+    }
+    Ok(())
+}
+
+fn header(name: &str, stat: &Stat, kind: u8, size: usize, link: &str) -> Result<[u8; BLOCK], String> {
+    let mut block = [0u8; BLOCK];
+    let (prefix, name) = split_name(name).ok_or_else(|| format!("'{}': name too long for tar", name))?;
+    if link.len() > 100 {
+        return Err(format!("'{}': link target too long for tar", link));
+    }
+    block[..name.len()].copy_from_slice(name.as_bytes());
+    write_octal(&mut block[100..108], stat.mode as u64);
+    write_octal(&mut block[108..116], stat.uid as u64);
+    write_octal(&mut block[116..124], stat.gid as u64);
+    write_octal(&mut block[124..136], size as u64);
+    write_octal(&mut block[136..148], (stat.modified / 1000.0) as u64);
+    block[156] = kind;
+    block[157..157 + link.len()].copy_from_slice(link.as_bytes());
+    block[257..263].copy_from_slice(b"ustar\0");
+    block[263..265].copy_from_slice(b"00");
+    block[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+
+    let sum = checksum(&block);
+    block[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+    Ok(block)
+}
+
+// Names over 100 bytes are split at a slash into the 155 byte prefix field
+fn split_name(name: &str) -> Option<(&str, &str)> {
+    if name.len() <= 100 {
+        return Some(("", name));
+    }
+    // A directory's trailing slash is not a split point
+    let body = name.strip_suffix('/').unwrap_or(name);
+    body.match_indices('/')
+        .map(|(i, _)| (&name[..i], &name[i + 1..]))
+        .find(|(prefix, rest)| prefix.len() <= 155 && rest.len() <= 100)
+}
+
+fn write_octal(field: &mut [u8], value: u64) {
+    // Zero padded, ending in a NUL
+    let width = field.len() - 1;
+    let digits = format!("{:0width$o}", value);
+    field[..width].copy_from_slice(&digits.as_bytes()[digits.len() - width..]);
+}
+
+// Sum of the header bytes, with the checksum field itself counted as spaces
+fn checksum(block: &[u8]) -> u64 {
+    block.iter().enumerate()
+        .map(|(i, &b)| if (148..156).contains(&i) { b' ' as u64 } else { b as u64 })
+        .sum()
+}
+
+fn read_octal(field: &[u8]) -> Result<u64, String> {
+    let text: String = field.iter()
+        .take_while(|&&b| b != 0)
+        .map(|&b| b as char)
+        .collect();
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(text, 8).map_err(|_| "bad number in tar header".to_string())
+}
+
+fn read_str(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+// One member of an archive
+pub struct Entry {
+    pub path: Vec<String>, // Components, with no "." or ".."
+    pub kind: EntryKind,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+pub enum EntryKind {
+    File(Vec<u8>),
+    Directory,
+    Symlink(String), // Target
+}
+
+// Parse an archive into its entries, in archive order
+pub fn read(data: &[u8]) -> Result<Vec<Entry>, String> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    while offset + BLOCK <= data.len() {
+        let block = &data[offset..offset + BLOCK];
+        if block.iter().all(|&b| b == 0) {
+            break;
+        }
+        if read_octal(&block[148..156]).ok() != Some(checksum(block)) {
+            let msg = if offset == 0 { "not a tar archive" } else { "bad tar header checksum" };
+            return Err(msg.to_string());
+        }
+        let size = read_octal(&block[124..136])? as usize;
+        let start = offset + BLOCK;
+        let end = start.checked_add(size).filter(|&end| end <= data.len()).ok_or("truncated tar archive")?;
+        offset = start + size.next_multiple_of(BLOCK);
+
+        let mut name = read_str(&block[..100]);
+        if &block[257..262] == b"ustar" {
+            let prefix = read_str(&block[345..500]);
+            if !prefix.is_empty() {
+                name = format!("{}/{}", prefix, name);
+            }
+        }
+        let path: Vec<String> = name.split('/')
+            .filter(|p| !p.is_empty() && *p != ".")
+            .map(String::from)
+            .collect();
+        if path.iter().any(|p| p == "..") {
+            return Err(format!("'{}': unsafe path in archive", name));
+        }
+        if path.is_empty() {
+            continue;
+        }
+
+        let kind = match block[156] {
+            TYPE_DIR => EntryKind::Directory,
+            TYPE_SYMLINK => EntryKind::Symlink(read_str(&block[157..257])),
+            TYPE_FILE | 0 | b'7' => EntryKind::File(data[start..end].to_vec()),
+            _ => continue,
+        };
+        entries.push(Entry {
+            path,
+            kind,
+            mode: read_octal(&block[100..108])? as u32,
+            uid: read_octal(&block[108..116])? as u32,
+            gid: read_octal(&block[116..124])? as u32,
+        });
+    }
+    Ok(entries)
+}
+
+// Extract an archive into the directory `dest`, returning how many entries were written
+pub fn unpack(fs: &mut FileSystem, dest: &str, data: &[u8]) -> Result<usize, String> {
+    let entries = read(data)?;
+    extract(fs, dest, &entries)
+}
+
+// Write parsed entries under the directory `dest`
+pub fn extract(fs: &mut FileSystem, dest: &str, entries: &[Entry]) -> Result<usize, String> {
+    if !fs.is_dir(dest) {
+        return Err(format!("'{}': not a directory", dest));
+    }
+    let dest = FileSystem::join_path(&fs.resolve_path(dest));
+    let dest = dest.trim_end_matches('/');
+    for entry in entries {
+        let (name, dirs) = entry.path.split_last().ok_or("empty path in archive")?;
+        let path = match &entry.kind {
+            EntryKind::Directory => mkdir_below(fs, dest, &entry.path)?,
+            EntryKind::File(content) => {
+                let path = format!("{}/{}", mkdir_below(fs, dest, dirs)?, name);
+                make_room(fs, &path)?;
+                fs.write_file(&path, content)?;
+                path
+            },
+            EntryKind::Symlink(target) => {
+                let path = format!("{}/{}", mkdir_below(fs, dest, dirs)?, name);
+                make_room(fs, &path)?;
+                fs.symlink(target, &path)?;
+                path
+            },
+        };
+        // Symlinks keep the defaults, chmod and chown would reach their target
+        if !matches!(entry.kind, EntryKind::Symlink(_)) {
+            fs.chmod(&path, entry.mode)?;
+            fs.chown(&path, entry.uid, entry.gid)?;
+        }
+    }
+    Ok(entries.len())
+}
+
+// Get `path` ready for a new file or link: an existing one is replaced, a directory is not
+fn make_room(fs: &mut FileSystem, path: &str) -> Result<(), String> {
+    match fs.lstat(path) {
+        Ok(Stat { node_type: NodeType::Directory, .. }) => Err(format!("'{}' is a directory", path)),
+        Ok(_) => fs.remove_entry(path),
+        Err(_) => Ok(()),
+    }
+}
+
+// Create the directories `parts` one below the other under `dest`, returning the
+// path of the last. Existing ones must be real directories, not links to one.
+fn mkdir_below(fs: &mut FileSystem, dest: &str, parts: &[String]) -> Result<String, String> {
+    let mut current = dest.to_string();
+    for part in parts {
+        current = format!("{}/{}", current, part);
+        match fs.lstat(&current) {
+            Ok(Stat { node_type: NodeType::Directory, .. }) => {},
+            Ok(Stat { node_type: NodeType::Symlink, .. }) => {
+                return Err(format!("'{}': refusing to extract through a symlink", current));
+            },
+            Ok(_) => return Err(format!("'{}': not a directory", current)),
+            Err(_) => fs.mkdir(&current)?,
+        }
+    }
+    Ok(current)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::native::NativeHost;
+    use std::rc::Rc;
+
+    fn filesystem() -> FileSystem {
+        FileSystem::new(1, Rc::new(NativeHost::new()))
+    }
+
+    // An archive of the given entries, written with default metadata
+    fn archive(entries: &[(&str, u8, &[u8], &str)]) -> Vec<u8> {
+        let stat = Stat { node_type: NodeType::File, size: 0, mode: 0o644, uid: 0, gid: 0, created: 0.0, modified: 0.0, accessed: 0.0 };
+        let mut out = Vec::new();
+        for (name, kind, content, link) in entries {
+            out.extend_from_slice(&header(name, &stat, *kind, content.len(), link).unwrap());
+            out.extend_from_slice(content);
+            out.resize(out.len().next_multiple_of(BLOCK), 0);
+        }
+        out.resize(out.len() + 2 * BLOCK, 0);
+        out
+    }
+
+    #[test]
+    fn round_trips_a_tree() {
+        let mut fs = filesystem();
+        fs.mkdir_all("/tmp/src/sub").unwrap();
+        fs.write_file("/tmp/src/a.txt", b"hello").unwrap();
+        fs.chmod("/tmp/src/a.txt", 0o600).unwrap();
+        let long = "n".repeat(90);
+        fs.mkdir(&format!("/tmp/src/sub/{}", long)).unwrap();
+        fs.write_file(&format!("/tmp/src/sub/{}/{}", long, long), &[7; 1000]).unwrap();
+        fs.symlink("../a.txt", "/tmp/src/sub/link").unwrap();
+
+        let data = pack(&fs, "/tmp/src").unwrap();
+        assert_eq!(data.len() % BLOCK, 0);
+        fs.mkdir("/tmp/out").unwrap();
+        assert_eq!(unpack(&mut fs, "/tmp/out", &data).unwrap(), 6);
+
+        assert_eq!(fs.read_file("/tmp/out/src/a.txt").unwrap(), b"hello");
+        assert_eq!(fs.stat("/tmp/out/src/a.txt").unwrap().mode, 0o600);
+        assert_eq!(fs.read_file(&format!("/tmp/out/src/sub/{}/{}", long, long)).unwrap(), [7; 1000]);
+        assert_eq!(fs.readlink("/tmp/out/src/sub/link").unwrap(), "../a.txt");
+        assert_eq!(fs.read_file("/tmp/out/src/sub/link").unwrap(), b"hello");
+
+        // Extracting again replaces the files
+        fs.write_file("/tmp/out/src/a.txt", b"changed").unwrap();
+        unpack(&mut fs, "/tmp/out", &data).unwrap();
+        assert_eq!(fs.read_file("/tmp/out/src/a.txt").unwrap(), b"hello");
+    }
+
+    #[test]
+    fn rejects_bad_archives() {
+        assert_eq!(read(&[1; BLOCK]).err().unwrap(), "not a tar archive");
+        let data = archive(&[("a.txt", TYPE_FILE, b"hello", "")]);
+        assert_eq!(read(&data[..BLOCK + 2]).err().unwrap(), "truncated tar archive");
+        for name in ["../escape", "a/../../escape", "/a/../.."] {
+            let data = archive(&[(name, TYPE_FILE, b"x", "")]);
+            assert!(read(&data).err().unwrap().contains("unsafe path"), "{}", name);
+        }
+    }
+
+    #[test]
+    fn does_not_extract_through_symlinks() {
+        let mut fs = filesystem();
+        fs.mkdir("/tmp/outside").unwrap();
+        fs.mkdir("/tmp/dest").unwrap();
+
+        let data = archive(&[("evil", TYPE_SYMLINK, b"", "/tmp/outside"), ("evil/pwn", TYPE_FILE, b"x", "")]);
+        assert!(unpack(&mut fs, "/tmp/dest", &data).unwrap_err().contains("symlink"));
+        let data = archive(&[("evil", TYPE_SYMLINK, b"", "/tmp/outside"), ("evil/", TYPE_DIR, b"", ""), ("evil/sub/pwn", TYPE_FILE, b"x", "")]);
+        assert!(unpack(&mut fs, "/tmp/dest", &data).unwrap_err().contains("symlink"));
+        assert!(fs.read_dir("/tmp/outside").unwrap().is_empty());
+
+        // A link in the destination is replaced, not written through
+        let data = archive(&[("evil", TYPE_FILE, b"safe", "")]);
+        unpack(&mut fs, "/tmp/dest", &data).unwrap();
+        assert!(matches!(fs.lstat("/tmp/dest/evil").unwrap().node_type, NodeType::File));
+        assert!(fs.read_dir("/tmp/outside").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,455 @@
+//! In-memory filesystem: a tree of `Node`s that lives as long as its mount.
+//! Mounted at `/` and `/tmp`, and the in-memory side of the image and local drivers.
+
+use std::cell::Cell;
+use std::collections::{BTreeSet, HashMap};
+use std::rc::Rc;
+use serde::{Serialize, Deserialize};
+
+use crate::host::Host;
+use crate::sys::fs::{NodeType, Stat};
+use crate::sys::vfs::{FsckReport, Problem, Vfs};
+
+#[derive(Clone, Serialize, Deserialize)]
+#[allow(dead_code)]
+pub struct Node {
+    pub name: String,
+    #[serde(default)]
+    pub ino: u32, // Key of the node's record on the persistent disk (localfs only)
+    pub node_type: NodeType,
+    pub size: usize, // Bytes
+    pub children: HashMap<String, Node>, // For directories
+    pub content: Vec<u8>, // For files, or a symlink's target path
+    #[serde(default)]
+    pub mode: u32, // Permission bits, e.g. 0o755
+    #[serde(default)]
+    pub uid: u32,
+    #[serde(default)]
+    pub gid: u32,
+    // Wall clock ms. Reads only borrow the tree, so the access time is a Cell.
+    #[serde(default)]
+    pub created: f64,
+    #[serde(default)]
+    pub modified: f64,
+    #[serde(default)]
+    pub accessed: Cell<f64>,
+}
+
+impl Node {
+    pub fn new_dir(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ino: 0,
+            node_type: NodeType::Directory,
+            size: 0,
+            children: HashMap::new(),
+            content: Vec::new(),
+            mode: 0o755,
+            uid: 0,
+            gid: 0,
+            created: 0.0,
+            modified: 0.0,
+            accessed: Cell::new(0.0),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn new_file(name: &str, content: Vec<u8>) -> Self {
+        Self {
+            name: name.to_string(),
+            ino: 0,
+            node_type: NodeType::File,
+            size: content.len(),
+            children: HashMap::new(),
+            content,
+            mode: 0o644,
+            uid: 0,
+            gid: 0,
+            created: 0.0,
+            modified: 0.0,
+            accessed: Cell::new(0.0),
+        }
+    }
+
+    pub fn new_symlink(name: &str, target: &str) -> Self {
+        Self {
+            node_type: NodeType::Symlink,
+            mode: 0o777,
+            ..Self::new_file(name, target.as_bytes().to_vec())
+        }
+    }
+
+    // Bytes of content in this node and everything under it
+    pub fn usage(&self) -> usize {
+        self.content.len() + self.children.values().map(Node::usage).sum::<usize>()
+    }
+
+    // Set all three timestamps, for a node that was just made
+    pub fn stamp(&mut self, now: f64) {
+        self.created = now;
+        self.modified = now;
+        self.accessed.set(now);
+    }
+
+    fn stat(&self) -> Stat {
+        Stat {
+            node_type: self.node_type.clone(),
+            size: self.content.len(),
+            mode: self.mode,
+            uid: self.uid,
+            gid: self.gid,
+            created: self.created,
+            modified: self.modified,
+            accessed: self.accessed.get(),
+        }
+    }
+}
+
+// Size of each tmpfs mount, in bytes of file content
+pub const TMPFS_SIZE: usize = 4 * 1024 * 1024;
+
+pub struct TmpFs {
+    pub root: Node,
+    host: Rc<dyn Host>, // Clock for the timestamps
+    capacity: usize,
+    used: usize, // Kept equal to root.usage()
+}
+
+impl TmpFs {
+    pub fn new(host: Rc<dyn Host>, capacity: usize) -> Self {
+        let mut root = Node::new_dir("/");
+        root.stamp(host.wall_clock_ms());
+        Self { root, host, capacity, used: 0 }
+    }
+
+    // Recount usage after `root` was replaced or filled in directly
+    pub fn recount(&mut self) {
+        self.used = self.root.usage();
+    }
+
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // Fail with ENOSPC unless `grow` more bytes fit
+    fn reserve(&self, grow: usize) -> Result<(), String> {
+        if self.used.checked_add(grow).is_none_or(|used| used > self.capacity) {
+            return Err("disk full".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn now(&self) -> f64 {
+        self.host.wall_clock_ms()
+    }
+
+    pub fn node(&self, path: &[String]) -> Option<&Node> {
+        let mut current_node = &self.root;
+        for part in path {
+            if !matches!(current_node.node_type, NodeType::Directory) {
+                return None;
+            }
+            current_node = current_node.children.get(part)?;
+        }
+        Some(current_node)
+    }
+
+    pub fn node_mut(&mut self, path: &[String]) -> Option<&mut Node> {
+        let mut current_node = &mut self.root;
+        for part in path {
+            current_node = current_node.children.get_mut(part)?;
+        }
+        Some(current_node)
+    }
+
+    fn dir_mut(&mut self, path: &[String]) -> Result<&mut Node, String> {
+        let mut current_node = &mut self.root;
+        for part in path {
+            if let Some(node) = current_node.children.get_mut(part) {
+                if let NodeType::Directory = node.node_type {
+                    current_node = node;
+                } else {
+                    return Err(format!("{} is not a directory", part));
+                }
+            } else {
+                return Err(format!("directory {} not found", part));
+            }
+        }
+        Ok(current_node)
+    }
+
+    fn file_mut(&mut self, path: &[String]) -> Result<&mut Node, String> {
+        let (name, parent) = path.split_last().ok_or("is a directory")?;
+        let dir = self.dir_mut(parent)?;
+        let node = dir.children.get_mut(name).ok_or("file not found")?;
+        match node.node_type {
+            NodeType::File => Ok(node),
+            NodeType::Directory => Err("is a directory".to_string()),
+            NodeType::Symlink => Err("is a symbolic link".to_string()),
+        }
+    }
+
+    // Add a new entry, failing if the name is taken
+    fn insert(&mut self, path: &[String], mut node: Node, exists: &str) -> Result<(), String> {
+        let now = self.now();
+        let (name, parent) = path.split_last().ok_or(exists)?;
+        let dir = self.dir_mut(parent)?;
+        if dir.children.contains_key(name) {
+            return Err(exists.to_string());
+        }
+        node.stamp(now);
+        dir.children.insert(name.clone(), node);
+        dir.modified = now;
+        Ok(())
+    }
+
+    // A directory's entries changed
+    fn touch_dir(&mut self, path: &[String]) {
+        let now = self.now();
+        if let Some(dir) = self.node_mut(path) {
+            dir.modified = now;
+        }
+    }
+
+    // Consistency check of the whole tree. Inodes of the nodes repaired go in
+    // `fixed`, for drivers that have to write them back.
+    pub fn check(&mut self, repair: bool, fixed: &mut BTreeSet<u32>) -> FsckReport {
+        let mut report = FsckReport::default();
+        check_node(&mut self.root, &mut Vec::new(), repair, &mut report, fixed);
+        let usage = self.root.usage();
+        if self.used != usage {
+            report.problems.push(Problem {
+                path: Vec::new(),
+                message: format!("usage count is {} bytes, content adds up to {}", self.used, usage),
+                fixed: repair,
+            });
+            if repair {
+                self.used = usage;
+            }
+        }
+        report
+    }
+}
+
+fn check_node(node: &mut Node, path: &mut Vec<String>, repair: bool, report: &mut FsckReport, fixed: &mut BTreeSet<u32>) {
+    report.checked += 1;
+    let mut problems = Vec::new();
+
+    // The root is not in any directory, so its name is free
+    if let Some(key) = path.last() {
+        if node.name != *key {
+            problems.push(format!("name is '{}', but it is listed as '{}'", node.name, key));
+            if repair {
+                node.name = key.clone();
+            }
+        }
+    }
+    match node.node_type {
+        NodeType::Directory => {
+            if !node.content.is_empty() {
+                problems.push(format!("directory holds {} bytes of content", node.content.len()));
+                if repair {
+                    node.content.clear();
+                }
+            }
+            if node.size != 0 {
+                problems.push(format!("directory has size {}", node.size));
+                if repair {
+                    node.size = 0;
+                }
+            }
+        },
+        NodeType::File | NodeType::Symlink => {
+            if node.size != node.content.len() {
+                problems.push(format!("size is {}, content is {} bytes", node.size, node.content.len()));
+                if repair {
+                    node.size = node.content.len();
+                }
+            }
+            // Only directories list entries; anything under a file is unreachable
+            if !node.children.is_empty() {
+                problems.push("not a directory, but has entries under it".to_string());
+                if repair {
+                    node.children.clear();
+                }
+            }
+        },
+    }
+    if repair && !problems.is_empty() {
+        fixed.insert(node.ino);
+    }
+    for message in problems {
+        report.problems.push(Problem { path: path.clone(), message, fixed: repair });
+    }
+
+    let mut names: Vec<String> = node.children.keys().cloned().collect();
+    names.sort();
+    for name in names {
+        if let Some(child) = node.children.get_mut(&name) {
+            path.push(name);
+            check_node(child, path, repair, report, fixed);
+            path.pop();
+        }
+    }
+}
+
+impl Vfs for TmpFs {
+    fn fs_type(&self) -> &'static str {
+        "tmpfs"
+    }
+
+    fn used(&self) -> usize {
+        self.used
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn lookup(&self, path: &[String]) -> Option<NodeType> {
+        self.node(path).map(|n| n.node_type.clone())
+    }
+
+    fn stat(&self, path: &[String]) -> Result<Stat, String> {
+        self.node(path).map(Node::stat).ok_or_else(|| "file or directory not found".to_string())
+    }
+
+    fn readdir(&self, path: &[String]) -> Result<Vec<(String, Stat)>, String> {
+        let dir = match self.node(path) {
+            Some(node) if matches!(node.node_type, NodeType::Directory) => node,
+            _ => return Err("directory not found".to_string()),
+        };
+        let mut entries: Vec<(String, Stat)> = dir.children.iter()
+            .map(|(name, node)| (name.clone(), node.stat()))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(entries)
+    }
+
+    fn read(&self, path: &[String], offset: usize, len: usize) -> Result<Vec<u8>, String> {
+        let node = self.node(path).ok_or("file not found")?;
+        match node.node_type {
+            NodeType::File => {},
+            NodeType::Directory => return Err("is a directory".to_string()),
+            NodeType::Symlink => return Err("is a symbolic link".to_string()),
+        }
+        node.accessed.set(self.now());
+        let start = offset.min(node.content.len());
+        let end = offset.saturating_add(len).min(node.content.len());
+        Ok(node.content[start..end].to_vec())
+    }
+
+    fn write(&mut self, path: &[String], offset: usize, data: &[u8]) -> Result<usize, String> {
+        let now = self.now();
+        let end = offset.checked_add(data.len()).ok_or("file too large")?;
+        let grow = end.saturating_sub(self.file_mut(path)?.content.len());
+        self.reserve(grow)?;
+        let node = self.file_mut(path)?;
+        if end > node.content.len() {
+            node.content.resize(end, 0);
+        }
+        node.content[offset..end].copy_from_slice(data);
+        node.size = node.content.len();
+        node.modified = now;
+        self.used += grow;
+        Ok(data.len())
+    }
+
+    fn truncate(&mut self, path: &[String], len: usize) -> Result<(), String> {
+        let now = self.now();
+        let old_len = self.file_mut(path)?.content.len();
+        self.reserve(len.saturating_sub(old_len))?;
+        let node = self.file_mut(path)?;
+        node.content.resize(len, 0);
+        node.size = len;
+        node.modified = now;
+        self.used = self.used + len - old_len;
+        Ok(())
+    }
+
+    fn mkdir(&mut self, path: &[String]) -> Result<(), String> {
+        let name = path.last().map(|s| s.as_str()).unwrap_or("/");
+        self.insert(path, Node::new_dir(name), "directory exists")
+    }
+
+    fn create(&mut self, path: &[String]) -> Result<(), String> {
+        let name = path.last().map(|s| s.as_str()).unwrap_or("/");
+        self.insert(path, Node::new_file(name, Vec::new()), "file or directory exists")
+    }
+
+    fn symlink(&mut self, path: &[String], target: &str) -> Result<(), String> {
+        let name = path.last().map(|s| s.as_str()).unwrap_or("/");
+        self.reserve(target.len())?;
+        self.insert(path, Node::new_symlink(name, target), "file or directory exists")?;
+        self.used += target.len();
+        Ok(())
+    }
+
+    fn readlink(&self, path: &[String]) -> Result<String, String> {
+        match self.node(path) {
+            Some(node) if matches!(node.node_type, NodeType::Symlink) => Ok(String::from_utf8_lossy(&node.content).into_owned()),
+            Some(_) => Err("not a symbolic link".to_string()),
+            None => Err("file or directory not found".to_string()),
+        }
+    }
+
+    fn chmod(&mut self, path: &[String], mode: u32) -> Result<(), String> {
+        let node = self.node_mut(path).ok_or("file or directory not found")?;
+        node.mode = mode & 0o7777;
+        Ok(())
+    }
+
+    fn chown(&mut self, path: &[String], uid: u32, gid: u32) -> Result<(), String> {
+        let node = self.node_mut(path).ok_or("file or directory not found")?;
+        node.uid = uid;
+        node.gid = gid;
+        Ok(())
+    }
+
+    fn unlink(&mut self, path: &[String]) -> Result<(), String> {
+        let (name, parent) = path.split_last().ok_or("invalid path")?;
+        match self.dir_mut(parent)?.children.remove(name) {
+            Some(node) => {
+                self.used -= node.usage();
+                self.touch_dir(parent);
+                Ok(())
+            },
+            None => Err("file or directory not found".to_string()),
+        }
+    }
+
+    fn rename(&mut self, from: &[String], to: &[String]) -> Result<(), String> {
+        let (src_name, src_parent) = from.split_last().ok_or("invalid path")?;
+        let (dst_name, dst_parent) = to.split_last().ok_or("invalid path")?;
+        if self.node(from).is_none() {
+            return Err("file or directory not found".to_string());
+        }
+        match self.node(dst_parent) {
+            Some(dir) if matches!(dir.node_type, NodeType::Directory) => {
+                if let Some(existing) = dir.children.get(dst_name) {
+                    if let NodeType::Directory = existing.node_type {
+                        return Err("destination is a directory".to_string());
+                    }
+                }
+            },
+            _ => return Err("destination directory not found".to_string()),
+        }
+
+        let mut node = self.dir_mut(src_parent)?.children.remove(src_name).ok_or("file or directory not found")?;
+        node.name = dst_name.clone();
+        if let Some(replaced) = self.dir_mut(dst_parent)?.children.insert(dst_name.clone(), node) {
+            self.used -= replaced.usage();
+        }
+        self.touch_dir(src_parent);
+        self.touch_dir(dst_parent);
+        Ok(())
+    }
+
+    fn fsck(&mut self, repair: bool) -> FsckReport {
+        self.check(repair, &mut BTreeSet::new())
+    }
+}
@@ -0,0 +1,104 @@
+//! Virtual filesystem layer.
+//!
+//! `FileSystem` keeps a mount table of drivers implementing `Vfs` and sends every
+//! path to the mount with the longest matching prefix. Drivers only see paths
+//! relative to their mount point, as components (`["user", "desktop"]`); the empty
+//! path is the mount's root directory.
+//!
+//! Built-in drivers are `tmpfs` (an in-memory tree), `image` (a read-only tree built
+//! into the kernel) and `localfs` (the persistent store behind `/local`).
+
+use crate::sys::fs::{NodeType, Stat};
+
+pub const READ_ONLY: &str = "read-only file system";
+
+// How a mount came up, once its storage has been read
+#[derive(PartialEq, Debug)]
+pub enum MountStatus {
+    Ready(usize),            // Entries loaded
+    Recovery(usize, String), // Damaged or unreadable: entries recovered, mounted read-only
+    Failed(String),          // Storage unavailable, changes stay in memory
+}
+
+// Something a consistency check found wrong, at `path` inside the mount
+pub struct Problem {
+    pub path: Vec<String>,
+    pub message: String,
+    pub fixed: bool,
+}
+
+// Result of a consistency check
+#[derive(Default)]
+pub struct FsckReport {
+    pub checked: usize, // Entries walked
+    pub problems: Vec<Problem>,
+}
+
+impl FsckReport {
+    // One line per problem and a summary, for reports with absolute paths
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self.problems.iter()
+            .map(|p| format!("/{}: {}{}", p.path.join("/"), p.message, if p.fixed { " (fixed)" } else { "" }))
+            .collect();
+        let fixed = self.problems.iter().filter(|p| p.fixed).count();
+        lines.push(match self.problems.len() {
+            0 => format!("{} entries checked, no problems", self.checked),
+            n => format!("{} entries checked, {} problems, {} fixed", self.checked, n, fixed),
+        });
+        lines
+    }
+}
+
+pub trait Vfs {
+    // Driver name, as shown by `mount`
+    fn fs_type(&self) -> &'static str;
+
+    fn read_only(&self) -> bool {
+        false
+    }
+
+    // Bytes of file content stored, and how many fit. Writes past the capacity fail
+    // with "disk full".
+    fn used(&self) -> usize;
+    fn capacity(&self) -> usize;
+
+    // What kind of entry is at `path`, if any
+    fn lookup(&self, path: &[String]) -> Option<NodeType>;
+    fn stat(&self, path: &[String]) -> Result<Stat, String>;
+    // Entries of a directory, sorted by name
+    fn readdir(&self, path: &[String]) -> Result<Vec<(String, Stat)>, String>;
+
+    // Read up to `len` bytes at `offset`. Reading past the end returns nothing.
+    fn read(&self, path: &[String], offset: usize, len: usize) -> Result<Vec<u8>, String>;
+    // Write at `offset`, growing the file (zero filled) as needed
+    fn write(&mut self, path: &[String], offset: usize, data: &[u8]) -> Result<usize, String>;
+    fn truncate(&mut self, path: &[String], len: usize) -> Result<(), String>;
+
+    fn mkdir(&mut self, path: &[String]) -> Result<(), String>;
+    // Create an empty file
+    fn create(&mut self, path: &[String]) -> Result<(), String>;
+    fn symlink(&mut self, path: &[String], target: &str) -> Result<(), String>;
+    fn readlink(&self, path: &[String]) -> Result<String, String>;
+    fn chmod(&mut self, path: &[String], mode: u32) -> Result<(), String>;
+    fn chown(&mut self, path: &[String], uid: u32, gid: u32) -> Result<(), String>;
+    // Remove a file, or a directory with everything in it
+    fn unlink(&mut self, path: &[String]) -> Result<(), String>;
+    // Move an entry within this filesystem. An existing file at `to` is replaced.
+    fn rename(&mut self, from: &[String], to: &[String]) -> Result<(), String>;
+
+    // Drivers backed by slow storage finish mounting here: `None` while still
+    // loading, otherwise how it went
+    fn poll(&mut self) -> Option<MountStatus> {
+        Some(MountStatus::Ready(0))
+    }
+
+    // Give up on a load that never finished
+    fn abort_load(&mut self, _reason: &str) {}
+
+    // Write pending changes to the backing store
+    fn sync(&mut self) {}
+
+    // Walk the tree looking for entries that contradict themselves, fixing them
+    // when `repair` is set (and the mount is writable)
+    fn fsck(&mut self, repair: bool) -> FsckReport;
+}
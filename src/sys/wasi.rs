@@ -0,0 +1,734 @@
+//! `wasi_snapshot_preview1` imports, so plain `wasm32-wasip1` binaries run under `exec`.
+//!
+//! Descriptors 0-2 are the process `Stdio`: pipes and captures set up by the shell, or
+//! the terminal. There stdout and stderr are printed like `sys_print` and stdin drains
+//! pending `KeyDown` events (reads never block, an empty queue reads as end of
+//! input). Every other descriptor lives in the process `FdTable`, where fd 3 is
+//! the "/" preopen absolute paths resolve against and fd 4 the "." preopen for the
+//! directory the program was started in (also in `$PWD`). A libc that keeps its own
+//! working directory (wasi-libc once `getcwd`/`chdir` are linked, as in Rust's std)
+//! starts it at "/" and sends relative paths through fd 3; such programs can
+//! `chdir($PWD)` first.
+//!
+//! Symlinks in the last component of a path are followed when the lookup flags ask
+//! for it (`path_open`, `path_filestat_get`); the other path calls act on the link.
+//!
+//! Lengths, offsets and iovecs come from the guest and are checked before use: a
+//! buffer must lie inside guest memory, and file positions stay below 2 GiB like
+//! `sys_fs_seek`'s.
+//!
+//! Guests run to completion inside a tick, so anything that would wait (`poll_oneoff`
+//! clocks, `sched_yield`) returns immediately. Those calls and `fd_read`/`fd_write`
+//! are where a `_start` program's fuel slice is renewed (see `process`).
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use wasmi::core::Trap;
+use wasmi::{Caller, Linker};
+
+use crate::kernel::EventType;
+use crate::sys::fd::{self, OpenFile};
+use crate::sys::fs::{FileSystem, NodeType, Stat};
+use crate::sys::wasm::{guest_memory, read_guest_str, read_memory, yield_slice, WasmContext};
+
+const MODULE: &str = "wasi_snapshot_preview1";
+
+// Errno values (the subset the kernel can produce)
+const ESUCCESS: i32 = 0;
+const EBADF: i32 = 8;
+const EEXIST: i32 = 20;
+const EFAULT: i32 = 21;
+const EFBIG: i32 = 22;
+const EINVAL: i32 = 28;
+const EIO: i32 = 29;
+const EISDIR: i32 = 31;
+const ELOOP: i32 = 32;
+const ENOENT: i32 = 44;
+const ENOSPC: i32 = 51;
+const ENOTDIR: i32 = 54;
+const ENOTEMPTY: i32 = 55;
+const ENOTSUP: i32 = 58;
+const EROFS: i32 = 69;
+const ESPIPE: i32 = 70;
+
+// Filetypes
+const FILETYPE_CHARACTER_DEVICE: u8 = 2;
+const FILETYPE_DIRECTORY: u8 = 3;
+const FILETYPE_REGULAR_FILE: u8 = 4;
+const FILETYPE_SYMBOLIC_LINK: u8 = 7;
+
+// Lookup flags of the path_* calls
+const LOOKUP_SYMLINK_FOLLOW: i32 = 1;
+
+// path_open flags
+const OFLAGS_CREAT: i32 = 1;
+const OFLAGS_DIRECTORY: i32 = 2;
+const OFLAGS_EXCL: i32 = 4;
+const OFLAGS_TRUNC: i32 = 8;
+const FDFLAGS_APPEND: i32 = 1;
+const RIGHTS_FD_READ: i64 = 1 << 1;
+const RIGHTS_FD_WRITE: i64 = 1 << 6;
+const RIGHTS_ALL: u64 = (1 << 30) - 1;
+
+// poll_oneoff event types
+const EVENTTYPE_CLOCK: u8 = 0;
+const EVENTTYPE_FD_READ: u8 = 1;
+
+type WasiResult = Result<(), i32>;
+
+fn errno(res: WasiResult) -> i32 {
+    match res {
+        Ok(()) => ESUCCESS,
+        Err(e) => e,
+    }
+}
+
+// FileSystem reports errors as text; map the ones guests care about
+fn fs_errno(msg: &str) -> i32 {
+    let msg = msg.to_lowercase();
+    if msg.contains("disk full") {
+        ENOSPC
+    } else if msg.contains("exists") {
+        EEXIST
+    } else if msg.contains("not a directory") {
+        ENOTDIR
+    } else if msg.contains("is a directory") {
+        EISDIR
+    } else if msg.contains("not found") {
+        ENOENT
+    } else if msg.contains("read-only") {
+        EROFS
+    } else if msg.contains("too large") {
+        EFBIG
+    } else if msg.contains("symbolic link") {
+        // Not a link for readlink, a loop for everything else
+        if msg.contains("levels") { ELOOP } else { EINVAL }
+    } else {
+        EIO
+    }
+}
+
+fn write_bytes(caller: &mut Caller<WasmContext>, ptr: i32, bytes: &[u8]) -> WasiResult {
+    let mem = guest_memory(caller).ok_or(EFAULT)?;
+    mem.write(caller, ptr as u32 as usize, bytes).map_err(|_| EFAULT)
+}
+
+fn read_bytes(caller: &Caller<WasmContext>, ptr: i32, len: usize) -> Result<Vec<u8>, i32> {
+    let mem = guest_memory(caller).ok_or(EFAULT)?;
+    read_memory(caller, &mem, ptr as u32 as usize, len).ok_or(EFAULT)
+}
+
+// Largest file position a guest may use, the same bound as `sys_fs_seek`'s
+const MAX_OFFSET: u64 = i32::MAX as u64;
+
+fn file_offset(offset: u64) -> Result<usize, i32> {
+    if offset > MAX_OFFSET { Err(EINVAL) } else { Ok(offset as usize) }
+}
+
+fn write_u32(caller: &mut Caller<WasmContext>, ptr: i32, val: u32) -> WasiResult {
+    write_bytes(caller, ptr, &val.to_le_bytes())
+}
+
+fn write_u64(caller: &mut Caller<WasmContext>, ptr: i32, val: u64) -> WasiResult {
+    write_bytes(caller, ptr, &val.to_le_bytes())
+}
+
+// Decode an iovec array into (buf, len) pairs. Every buffer must lie in guest memory
+// and together they may not cover more than all of it, so the data they describe
+// can be allocated safely.
+fn read_iovs(caller: &Caller<WasmContext>, iovs: i32, iovs_len: i32) -> Result<Vec<(i32, usize)>, i32> {
+    let size = (iovs_len.max(0) as usize).checked_mul(8).ok_or(EINVAL)?;
+    let raw = read_bytes(caller, iovs, size)?;
+    let memory_size = guest_memory(caller).ok_or(EFAULT)?.data(caller).len();
+    let mut total = 0usize;
+    let mut out = Vec::with_capacity(raw.len() / 8);
+    for c in raw.chunks_exact(8) {
+        let buf = u32::from_le_bytes([c[0], c[1], c[2], c[3]]);
+        let len = u32::from_le_bytes([c[4], c[5], c[6], c[7]]) as usize;
+        if (buf as usize).checked_add(len).is_none_or(|end| end > memory_size) {
+            return Err(EFAULT);
+        }
+        total = total.checked_add(len).filter(|&t| t <= memory_size).ok_or(EINVAL)?;
+        out.push((buf as i32, len));
+    }
+    Ok(out)
+}
+
+fn gather(caller: &Caller<WasmContext>, iovs: i32, iovs_len: i32) -> Result<Vec<u8>, i32> {
+    let mut data = Vec::new();
+    for (buf, len) in read_iovs(caller, iovs, iovs_len)? {
+        data.extend_from_slice(&read_bytes(caller, buf, len)?);
+    }
+    Ok(data)
+}
+
+fn scatter(caller: &mut Caller<WasmContext>, iovs: i32, iovs_len: i32, data: &[u8]) -> WasiResult {
+    let mut rest = data;
+    for (buf, len) in read_iovs(caller, iovs, iovs_len)? {
+        if rest.is_empty() { break; }
+        let n = len.min(rest.len());
+        write_bytes(caller, buf, &rest[..n])?;
+        rest = &rest[n..];
+    }
+    Ok(())
+}
+
+fn iovs_capacity(caller: &Caller<WasmContext>, iovs: i32, iovs_len: i32) -> Result<usize, i32> {
+    Ok(read_iovs(caller, iovs, iovs_len)?.iter().map(|(_, len)| len).sum())
+}
+
+// Write a list of strings as a pointer array plus NUL terminated buffer (args_get, environ_get)
+fn write_string_list(caller: &mut Caller<WasmContext>, list: &[String], ptrs: i32, buf: i32) -> WasiResult {
+    let mut offset = buf as u32;
+    for (i, item) in list.iter().enumerate() {
+        write_u32(caller, ptrs + (i as i32) * 4, offset)?;
+        let mut bytes = item.as_bytes().to_vec();
+        bytes.push(0);
+        write_bytes(caller, offset as i32, &bytes)?;
+        offset += bytes.len() as u32;
+    }
+    Ok(())
+}
+
+fn write_string_sizes(caller: &mut Caller<WasmContext>, list: &[String], count_ptr: i32, size_ptr: i32) -> WasiResult {
+    let size: usize = list.iter().map(|s| s.len() + 1).sum();
+    write_u32(caller, count_ptr, list.len() as u32)?;
+    write_u32(caller, size_ptr, size as u32)
+}
+
+fn filetype(stat: &Stat) -> u8 {
+    match stat.node_type {
+        NodeType::File => FILETYPE_REGULAR_FILE,
+        NodeType::Directory => FILETYPE_DIRECTORY,
+        NodeType::Symlink => FILETYPE_SYMBOLIC_LINK,
+    }
+}
+
+// filestat: dev, ino, filetype, nlink, size, atim, mtim, ctim (64 bytes). There is no
+// status change time, so ctim is the modification time.
+fn write_filestat(caller: &mut Caller<WasmContext>, ptr: i32, filetype: u8, stat: Option<&Stat>) -> WasiResult {
+    let mut buf = [0u8; 64];
+    buf[16] = filetype;
+    buf[24..32].copy_from_slice(&1u64.to_le_bytes());
+    if let Some(stat) = stat {
+        let ns = |ms: f64| ((ms * 1_000_000.0) as u64).to_le_bytes();
+        buf[32..40].copy_from_slice(&(stat.size as u64).to_le_bytes());
+        buf[40..48].copy_from_slice(&ns(stat.accessed));
+        buf[48..56].copy_from_slice(&ns(stat.modified));
+        buf[56..64].copy_from_slice(&ns(stat.modified));
+    }
+    write_bytes(caller, ptr, &buf)
+}
+
+fn is_stdio(fd: i32) -> bool {
+    (0..=2).contains(&fd)
+}
+
+fn open_file(caller: &Caller<WasmContext>, fd: i32) -> Result<(String, usize, i32), i32> {
+    match caller.data().fds.get(fd) {
+        Some(f) if f.is_dir => Err(EISDIR),
+        Some(f) => Ok((f.path.clone(), f.pos, f.flags)),
+        None => Err(EBADF),
+    }
+}
+
+// Resolve a guest path relative to the directory descriptor `dirfd`. A symlink in
+// the last component is followed only with `follow`.
+fn resolve_at(caller: &Caller<WasmContext>, dirfd: i32, path_ptr: i32, path_len: i32, follow: bool) -> Result<String, i32> {
+    let path = read_guest_str(caller, path_ptr, path_len).ok_or(EFAULT)?;
+    let dir = match caller.data().fds.get(dirfd) {
+        Some(f) if f.is_dir => f.path.clone(),
+        Some(_) => return Err(ENOTDIR),
+        None => return Err(EBADF),
+    };
+    let joined = if path.starts_with('/') { path } else { format!("{}/{}", dir, path) };
+    let fs = caller.data().fs.borrow();
+    let parts = if follow { fs.resolve_path(&joined) } else { fs.resolve_entry(&joined) };
+    Ok(FileSystem::join_path(&parts))
+}
+
+// Take up to `max` bytes of piped input, or of typed input from the event queue,
+// leaving other events queued
+fn read_stdin(caller: &mut Caller<WasmContext>, max: usize) -> Vec<u8> {
+    if let Some(piped) = caller.data_mut().stdio.stdin.as_mut() {
+        let n = max.min(piped.len());
+        return piped.drain(..n).collect();
+    }
+    let mut events = caller.data().events.borrow_mut();
+    let mut data = Vec::new();
+    let mut kept = std::collections::VecDeque::new();
+    while let Some(event) = events.pop_front() {
+        let ch = match event.event_type {
+            EventType::KeyDown if data.len() < max => match event.code {
+                10 => Some('\n'),
+                8 => Some('\x08'),
+                9 => Some('\t'),
+                // Keys with Shift or Ctrl held are out of the char range
+                c if c >= 32 && c != crate::kernel::KEY_DELETE => char::from_u32(c),
+                _ => None,
+            },
+            _ => {
+                kept.push_back(event);
+                continue;
+            },
+        };
+        if let Some(ch) = ch {
+            let mut buf = [0u8; 4];
+            data.extend_from_slice(ch.encode_utf8(&mut buf).as_bytes());
+        }
+    }
+    *events = kept;
+    data
+}
+
+fn pending_input(caller: &Caller<WasmContext>) -> u64 {
+    if let Some(piped) = caller.data().stdio.stdin.as_ref() {
+        return piped.len() as u64;
+    }
+    caller.data().events.borrow().iter().filter(|e| matches!(e.event_type, EventType::KeyDown)).count() as u64
+}
+
+fn fd_read(caller: &mut Caller<WasmContext>, fd: i32, iovs: i32, iovs_len: i32, offset: Option<u64>, nread: i32) -> WasiResult {
+    let capacity = iovs_capacity(caller, iovs, iovs_len)?;
+    let data = if fd == 0 {
+        read_stdin(caller, capacity)
+    } else if is_stdio(fd) {
+        return Err(EBADF);
+    } else {
+        let (path, pos, flags) = open_file(caller, fd)?;
+        if flags & fd::O_READ == 0 { return Err(EBADF); }
+        let at = match offset {
+            Some(o) => file_offset(o)?,
+            None => pos,
+        };
+        let data = caller.data().fs.borrow().read_at(&path, at, capacity).map_err(|e| fs_errno(&e))?;
+        if offset.is_none() {
+            if let Some(f) = caller.data_mut().fds.get_mut(fd) { f.pos += data.len(); }
+        }
+        data
+    };
+    scatter(caller, iovs, iovs_len, &data)?;
+    write_u32(caller, nread, data.len() as u32)
+}
+
+fn fd_write(caller: &mut Caller<WasmContext>, fd: i32, iovs: i32, iovs_len: i32, offset: Option<u64>, nwritten: i32) -> WasiResult {
+    let data = gather(caller, iovs, iovs_len)?;
+    if fd == 1 || fd == 2 {
+        caller.data_mut().write_stdio(&String::from_utf8_lossy(&data), fd == 2);
+    } else if is_stdio(fd) {
+        return Err(EBADF);
+    } else {
+        let (path, pos, flags) = open_file(caller, fd)?;
+        if flags & (fd::O_WRITE | fd::O_APPEND) == 0 { return Err(EBADF); }
+        let end = {
+            let mut fs = caller.data().fs.borrow_mut();
+            let at = match offset {
+                Some(o) => file_offset(o)?,
+                None if flags & fd::O_APPEND != 0 => fs.stat(&path).map(|s| s.size).unwrap_or(0),
+                None => pos,
+            };
+            fs.write_at(&path, at, &data).map_err(|e| fs_errno(&e))? + at
+        };
+        if offset.is_none() {
+            if let Some(f) = caller.data_mut().fds.get_mut(fd) { f.pos = end; }
+        }
+    }
+    write_u32(caller, nwritten, data.len() as u32)
+}
+
+fn fd_seek(caller: &mut Caller<WasmContext>, fd: i32, offset: i64, whence: i32, out: i32) -> WasiResult {
+    if is_stdio(fd) { return Err(ESPIPE); }
+    let (path, pos, _) = open_file(caller, fd)?;
+    let base = match whence {
+        fd::SEEK_SET => 0,
+        fd::SEEK_CUR => pos as i64,
+        fd::SEEK_END => caller.data().fs.borrow().stat(&path).map_err(|e| fs_errno(&e))?.size as i64,
+        _ => return Err(EINVAL),
+    };
+    let new_pos = base.checked_add(offset).filter(|p| (0..=MAX_OFFSET as i64).contains(p)).ok_or(EINVAL)?;
+    if let Some(f) = caller.data_mut().fds.get_mut(fd) { f.pos = new_pos as usize; }
+    write_u64(caller, out, new_pos as u64)
+}
+
+// fdstat: filetype u8, flags u16 (at 2), rights base u64 (at 8), rights inheriting u64 (at 16)
+fn fd_fdstat_get(caller: &mut Caller<WasmContext>, fd: i32, out: i32) -> WasiResult {
+    let (filetype, flags) = if is_stdio(fd) {
+        (FILETYPE_CHARACTER_DEVICE, 0)
+    } else {
+        match caller.data().fds.get(fd) {
+            Some(f) if f.is_dir => (FILETYPE_DIRECTORY, 0),
+            Some(f) => (FILETYPE_REGULAR_FILE, if f.flags & fd::O_APPEND != 0 { FDFLAGS_APPEND as u16 } else { 0 }),
+            None => return Err(EBADF),
+        }
+    };
+    let mut buf = [0u8; 24];
+    buf[0] = filetype;
+    buf[2..4].copy_from_slice(&flags.to_le_bytes());
+    buf[8..16].copy_from_slice(&RIGHTS_ALL.to_le_bytes());
+    buf[16..24].copy_from_slice(&RIGHTS_ALL.to_le_bytes());
+    write_bytes(caller, out, &buf)
+}
+
+fn fd_filestat_get(caller: &mut Caller<WasmContext>, fd: i32, out: i32) -> WasiResult {
+    if is_stdio(fd) {
+        return write_filestat(caller, out, FILETYPE_CHARACTER_DEVICE, None);
+    }
+    let path = caller.data().fds.get(fd).ok_or(EBADF)?.path.clone();
+    let stat = caller.data().fs.borrow().stat(&path).map_err(|e| fs_errno(&e))?;
+    write_filestat(caller, out, filetype(&stat), Some(&stat))
+}
+
+// dirent: next cookie u64, ino u64, name length u32, filetype u8, then the name
+fn fd_readdir(caller: &mut Caller<WasmContext>, fd: i32, buf: i32, buf_len: i32, cookie: i64, bufused: i32) -> WasiResult {
+    let path = match caller.data().fds.get(fd) {
+        Some(f) if f.is_dir => f.path.clone(),
+        Some(_) => return Err(ENOTDIR),
+        None => return Err(EBADF),
+    };
+    let mut entries = vec![(".".to_string(), FILETYPE_DIRECTORY), ("..".to_string(), FILETYPE_DIRECTORY)];
+    let children = caller.data().fs.borrow().read_dir(&path).map_err(|e| fs_errno(&e))?;
+    entries.extend(children.iter().map(|(name, stat)| (name.clone(), filetype(stat))));
+
+    let mut out = Vec::new();
+    for (i, (name, filetype)) in entries.iter().enumerate().skip(cookie.max(0) as usize) {
+        out.extend_from_slice(&(i as u64 + 1).to_le_bytes());
+        out.extend_from_slice(&0u64.to_le_bytes());
+        out.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        out.extend_from_slice(&[*filetype, 0, 0, 0]);
+        out.extend_from_slice(name.as_bytes());
+        if out.len() >= buf_len as usize { break; }
+    }
+    // A full buffer tells the guest to call again from the last complete entry
+    out.truncate(buf_len.max(0) as usize);
+    write_bytes(caller, buf, &out)?;
+    write_u32(caller, bufused, out.len() as u32)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn path_open(caller: &mut Caller<WasmContext>, dirfd: i32, dirflags: i32, path_ptr: i32, path_len: i32, oflags: i32, rights: i64, fdflags: i32, out: i32) -> WasiResult {
+    let follow = dirflags & LOOKUP_SYMLINK_FOLLOW != 0;
+    let path = resolve_at(caller, dirfd, path_ptr, path_len, follow)?;
+    let mut flags = 0;
+    if rights & RIGHTS_FD_READ != 0 { flags |= fd::O_READ; }
+    if rights & RIGHTS_FD_WRITE != 0 { flags |= fd::O_WRITE; }
+    if fdflags & FDFLAGS_APPEND != 0 { flags |= fd::O_APPEND; }
+
+    let file = {
+        let mut fs = caller.data().fs.borrow_mut();
+        let stat = if follow { fs.stat(&path) } else { fs.lstat(&path) };
+        match stat {
+            Ok(_) if oflags & OFLAGS_CREAT != 0 && oflags & OFLAGS_EXCL != 0 => return Err(EEXIST),
+            Ok(stat) => match stat.node_type {
+                NodeType::Directory if oflags & OFLAGS_TRUNC != 0 || flags & fd::O_WRITE != 0 => return Err(EISDIR),
+                NodeType::Directory => OpenFile::dir(path),
+                NodeType::File if oflags & OFLAGS_DIRECTORY != 0 => return Err(ENOTDIR),
+                // Opened without SYMLINK_FOLLOW, like O_NOFOLLOW
+                NodeType::Symlink => return Err(ELOOP),
+                NodeType::File => {
+                    if oflags & OFLAGS_TRUNC != 0 {
+                        fs.truncate(&path, 0).map_err(|e| fs_errno(&e))?;
+                    }
+                    OpenFile::file(path, flags)
+                },
+            },
+            Err(_) if oflags & OFLAGS_CREAT == 0 || oflags & OFLAGS_DIRECTORY != 0 => return Err(ENOENT),
+            Err(_) => {
+                fs.create_file(&path).map_err(|e| fs_errno(&e))?;
+                OpenFile::file(path, flags)
+            },
+        }
+    };
+    let new_fd = caller.data_mut().fds.insert(file);
+    write_u32(caller, out, new_fd as u32)
+}
+
+fn path_remove_directory(caller: &mut Caller<WasmContext>, dirfd: i32, path_ptr: i32, path_len: i32) -> WasiResult {
+    let path = resolve_at(caller, dirfd, path_ptr, path_len, false)?;
+    let mut fs = caller.data().fs.borrow_mut();
+    if !matches!(fs.lstat(&path).map_err(|e| fs_errno(&e))?.node_type, NodeType::Directory) {
+        return Err(ENOTDIR);
+    }
+    let entries = fs.read_dir(&path).map_err(|e| fs_errno(&e))?;
+    if !entries.is_empty() { return Err(ENOTEMPTY); }
+    fs.remove_entry(&path).map_err(|e| fs_errno(&e))
+}
+
+fn path_unlink_file(caller: &mut Caller<WasmContext>, dirfd: i32, path_ptr: i32, path_len: i32) -> WasiResult {
+    let path = resolve_at(caller, dirfd, path_ptr, path_len, false)?;
+    let mut fs = caller.data().fs.borrow_mut();
+    if let NodeType::Directory = fs.lstat(&path).map_err(|e| fs_errno(&e))?.node_type {
+        return Err(EISDIR);
+    }
+    fs.remove_entry(&path).map_err(|e| fs_errno(&e))
+}
+
+// subscription: 48 bytes, userdata at 0, tag at 8, fd (for fd_read/fd_write) at 16
+// event: 32 bytes, userdata at 0, error u16 at 8, type u8 at 10, nbytes u64 at 16
+fn poll_oneoff(caller: &mut Caller<WasmContext>, subs: i32, events: i32, nsubs: i32, nevents: i32) -> WasiResult {
+    if nsubs <= 0 { return Err(EINVAL); }
+    let raw = read_bytes(caller, subs, nsubs as usize * 48)?;
+    let mut out = Vec::with_capacity(nsubs as usize * 32);
+    for sub in raw.chunks_exact(48) {
+        let tag = sub[8];
+        let fd = i32::from_le_bytes([sub[16], sub[17], sub[18], sub[19]]);
+        let mut event = [0u8; 32];
+        event[0..8].copy_from_slice(&sub[0..8]);
+        event[10] = tag;
+        if tag != EVENTTYPE_CLOCK {
+            let nbytes = if tag == EVENTTYPE_FD_READ && fd == 0 { pending_input(caller) } else { 0 };
+            event[16..24].copy_from_slice(&nbytes.to_le_bytes());
+        }
+        out.extend_from_slice(&event);
+    }
+    write_bytes(caller, events, &out)?;
+    write_u32(caller, nevents, nsubs as u32)
+}
+
+fn random_get(caller: &mut Caller<WasmContext>, buf: i32, len: i32) -> WasiResult {
+    static STATE: AtomicU64 = AtomicU64::new(0);
+    let mut x = STATE.load(Ordering::Relaxed);
+    if x == 0 {
+        x = (caller.data().host.wall_clock_ms() as u64) ^ ((caller.data().host.now() * 1000.0) as u64) | 1;
+    }
+    // Fill the guest buffer in place, so the length never sizes a kernel allocation
+    let mem = guest_memory(caller).ok_or(EFAULT)?;
+    let start = buf as u32 as usize;
+    let end = start.checked_add(len as u32 as usize).ok_or(EFAULT)?;
+    let out = mem.data_mut(&mut *caller).get_mut(start..end).ok_or(EFAULT)?;
+    for chunk in out.chunks_mut(8) {
+        // xorshift64
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        chunk.copy_from_slice(&x.to_le_bytes()[..chunk.len()]);
+    }
+    STATE.store(x, Ordering::Relaxed);
+    Ok(())
+}
+
+pub fn add_to_linker(linker: &mut Linker<WasmContext>) {
+    // ARGS / ENVIRONMENT
+    linker.func_wrap(MODULE, "args_get", |mut caller: Caller<WasmContext>, argv: i32, argv_buf: i32| -> i32 {
+        let args = caller.data().args.clone();
+        errno(write_string_list(&mut caller, &args, argv, argv_buf))
+    }).unwrap();
+
+    linker.func_wrap(MODULE, "args_sizes_get", |mut caller: Caller<WasmContext>, argc: i32, buf_size: i32| -> i32 {
+        let args = caller.data().args.clone();
+        errno(write_string_sizes(&mut caller, &args, argc, buf_size))
+    }).unwrap();
+
+    linker.func_wrap(MODULE, "environ_get", |mut caller: Caller<WasmContext>, environ: i32, environ_buf: i32| -> i32 {
+        let env = caller.data().env.pairs();
+        errno(write_string_list(&mut caller, &env, environ, environ_buf))
+    }).unwrap();
+
+    linker.func_wrap(MODULE, "environ_sizes_get", |mut caller: Caller<WasmContext>, count: i32, buf_size: i32| -> i32 {
+        let env = caller.data().env.pairs();
+        errno(write_string_sizes(&mut caller, &env, count, buf_size))
+    }).unwrap();
+
+    // CLOCKS (0 = realtime, 1 = monotonic, 2/3 = cpu time, all in nanoseconds)
+    linker.func_wrap(MODULE, "clock_res_get", |mut caller: Caller<WasmContext>, id: i32, out: i32| -> i32 {
+        if !(0..=3).contains(&id) { return EINVAL; }
+        errno(write_u64(&mut caller, out, 1_000))
+    }).unwrap();
+
+    linker.func_wrap(MODULE, "clock_time_get", |mut caller: Caller<WasmContext>, id: i32, _precision: i64, out: i32| -> i32 {
+        let ms = match id {
+            0 => caller.data().host.wall_clock_ms(),
+            1..=3 => caller.data().host.now(),
+            _ => return EINVAL,
+        };
+        errno(write_u64(&mut caller, out, (ms * 1_000_000.0) as u64))
+    }).unwrap();
+
+    // FILE DESCRIPTORS
+    linker.func_wrap(MODULE, "fd_write", |mut caller: Caller<WasmContext>, fd: i32, iovs: i32, iovs_len: i32, nwritten: i32| -> i32 {
+        yield_slice(&mut caller);
+        errno(fd_write(&mut caller, fd, iovs, iovs_len, None, nwritten))
+    }).unwrap();
+
+    linker.func_wrap(MODULE, "fd_pwrite", |mut caller: Caller<WasmContext>, fd: i32, iovs: i32, iovs_len: i32, offset: i64, nwritten: i32| -> i32 {
+        if is_stdio(fd) { return ESPIPE; }
+        errno(fd_write(&mut caller, fd, iovs, iovs_len, Some(offset as u64), nwritten))
+    }).unwrap();
+
+    linker.func_wrap(MODULE, "fd_read", |mut caller: Caller<WasmContext>, fd: i32, iovs: i32, iovs_len: i32, nread: i32| -> i32 {
+        yield_slice(&mut caller);
+        errno(fd_read(&mut caller, fd, iovs, iovs_len, None, nread))
+    }).unwrap();
+
+    linker.func_wrap(MODULE, "fd_pread", |mut caller: Caller<WasmContext>, fd: i32, iovs: i32, iovs_len: i32, offset: i64, nread: i32| -> i32 {
+        if is_stdio(fd) { return ESPIPE; }
+        errno(fd_read(&mut caller, fd, iovs, iovs_len, Some(offset as u64), nread))
+    }).unwrap();
+
+    linker.func_wrap(MODULE, "fd_seek", |mut caller: Caller<WasmContext>, fd: i32, offset: i64, whence: i32, out: i32| -> i32 {
+        errno(fd_seek(&mut caller, fd, offset, whence, out))
+    }).unwrap();
+
+    linker.func_wrap(MODULE, "fd_tell", |mut caller: Caller<WasmContext>, fd: i32, out: i32| -> i32 {
+        errno(fd_seek(&mut caller, fd, 0, fd::SEEK_CUR, out))
+    }).unwrap();
+
+    linker.func_wrap(MODULE, "fd_close", |mut caller: Caller<WasmContext>, fd: i32| -> i32 {
+        if is_stdio(fd) || caller.data_mut().fds.remove(fd).is_some() { ESUCCESS } else { EBADF }
+    }).unwrap();
+
+    linker.func_wrap(MODULE, "fd_renumber", |mut caller: Caller<WasmContext>, from: i32, to: i32| -> i32 {
+        if is_stdio(from) || is_stdio(to) { return ENOTSUP; }
+        if caller.data_mut().fds.renumber(from, to) { ESUCCESS } else { EBADF }
+    }).unwrap();
+
+    linker.func_wrap(MODULE, "fd_fdstat_get", |mut caller: Caller<WasmContext>, fd: i32, out: i32| -> i32 {
+        errno(fd_fdstat_get(&mut caller, fd, out))
+    }).unwrap();
+
+    linker.func_wrap(MODULE, "fd_fdstat_set_flags", |mut caller: Caller<WasmContext>, fd: i32, fdflags: i32| -> i32 {
+        if is_stdio(fd) { return ESUCCESS; }
+        match caller.data_mut().fds.get_mut(fd) {
+            Some(f) => {
+                if fdflags & FDFLAGS_APPEND != 0 { f.flags |= fd::O_APPEND; } else { f.flags &= !fd::O_APPEND; }
+                ESUCCESS
+            },
+            None => EBADF,
+        }
+    }).unwrap();
+
+    linker.func_wrap(MODULE, "fd_fdstat_set_rights", |_: Caller<WasmContext>, _fd: i32, _base: i64, _inheriting: i64| -> i32 {
+        ESUCCESS
+    }).unwrap();
+
+    linker.func_wrap(MODULE, "fd_filestat_get", |mut caller: Caller<WasmContext>, fd: i32, out: i32| -> i32 {
+        errno(fd_filestat_get(&mut caller, fd, out))
+    }).unwrap();
+
+    linker.func_wrap(MODULE, "fd_filestat_set_size", |caller: Caller<WasmContext>, fd: i32, size: i64| -> i32 {
+        let (path, _, _) = match open_file(&caller, fd) { Ok(f) => f, Err(e) => return e };
+        let size = match u64::try_from(size).map_err(|_| EINVAL).and_then(file_offset) { Ok(s) => s, Err(e) => return e };
+        let res = caller.data().fs.borrow_mut().truncate(&path, size);
+        errno(res.map_err(|e| fs_errno(&e)))
+    }).unwrap();
+
+    linker.func_wrap(MODULE, "fd_filestat_set_times", |_: Caller<WasmContext>, _fd: i32, _atim: i64, _mtim: i64, _flags: i32| -> i32 {
+        ESUCCESS
+    }).unwrap();
+
+    linker.func_wrap(MODULE, "fd_advise", |_: Caller<WasmContext>, _fd: i32, _offset: i64, _len: i64, _advice: i32| -> i32 {
+        ESUCCESS
+    }).unwrap();
+
+    linker.func_wrap(MODULE, "fd_allocate", |_: Caller<WasmContext>, _fd: i32, _offset: i64, _len: i64| -> i32 {
+        ENOTSUP
+    }).unwrap();
+
+    // Writes go straight to the filesystem, so there is nothing to flush
+    linker.func_wrap(MODULE, "fd_datasync", |_: Caller<WasmContext>, _fd: i32| -> i32 { ESUCCESS }).unwrap();
+    linker.func_wrap(MODULE, "fd_sync", |_: Caller<WasmContext>, _fd: i32| -> i32 { ESUCCESS }).unwrap();
+
+    // prestat: tag u8 (0 = dir), name length u32 at 4
+    linker.func_wrap(MODULE, "fd_prestat_get", |mut caller: Caller<WasmContext>, fd: i32, out: i32| -> i32 {
+        let name = match fd::preopen_name(fd) { Some(n) => n, None => return EBADF };
+        let mut buf = [0u8; 8];
+        buf[4..8].copy_from_slice(&(name.len() as u32).to_le_bytes());
+        errno(write_bytes(&mut caller, out, &buf))
+    }).unwrap();
+
+    linker.func_wrap(MODULE, "fd_prestat_dir_name", |mut caller: Caller<WasmContext>, fd: i32, path: i32, path_len: i32| -> i32 {
+        let name = match fd::preopen_name(fd) { Some(n) => n, None => return EBADF };
+        if (path_len as usize) < name.len() { return EINVAL; }
+        errno(write_bytes(&mut caller, path, name.as_bytes()))
+    }).unwrap();
+
+    linker.func_wrap(MODULE, "fd_readdir", |mut caller: Caller<WasmContext>, fd: i32, buf: i32, buf_len: i32, cookie: i64, bufused: i32| -> i32 {
+        errno(fd_readdir(&mut caller, fd, buf, buf_len, cookie, bufused))
+    }).unwrap();
+
+    // PATHS
+    linker.func_wrap(MODULE, "path_open", |mut caller: Caller<WasmContext>, dirfd: i32, dirflags: i32, path: i32, path_len: i32, oflags: i32, rights: i64, _inheriting: i64, fdflags: i32, out: i32| -> i32 {
+        errno(path_open(&mut caller, dirfd, dirflags, path, path_len, oflags, rights, fdflags, out))
+    }).unwrap();
+
+    linker.func_wrap(MODULE, "path_create_directory", |caller: Caller<WasmContext>, dirfd: i32, path: i32, path_len: i32| -> i32 {
+        let path = match resolve_at(&caller, dirfd, path, path_len, false) { Ok(p) => p, Err(e) => return e };
+        let res = caller.data().fs.borrow_mut().mkdir(&path);
+        errno(res.map_err(|e| fs_errno(&e)))
+    }).unwrap();
+
+    linker.func_wrap(MODULE, "path_remove_directory", |mut caller: Caller<WasmContext>, dirfd: i32, path: i32, path_len: i32| -> i32 {
+        errno(path_remove_directory(&mut caller, dirfd, path, path_len))
+    }).unwrap();
+
+    linker.func_wrap(MODULE, "path_unlink_file", |mut caller: Caller<WasmContext>, dirfd: i32, path: i32, path_len: i32| -> i32 {
+        errno(path_unlink_file(&mut caller, dirfd, path, path_len))
+    }).unwrap();
+
+    linker.func_wrap(MODULE, "path_rename", |caller: Caller<WasmContext>, old_fd: i32, old_path: i32, old_len: i32, new_fd: i32, new_path: i32, new_len: i32| -> i32 {
+        let from = match resolve_at(&caller, old_fd, old_path, old_len, false) { Ok(p) => p, Err(e) => return e };
+        let to = match resolve_at(&caller, new_fd, new_path, new_len, false) { Ok(p) => p, Err(e) => return e };
+        let res = caller.data().fs.borrow_mut().rename(&from, &to);
+        errno(res.map_err(|e| fs_errno(&e)))
+    }).unwrap();
+
+    // The link itself unless SYMLINK_FOLLOW is set
+    linker.func_wrap(MODULE, "path_filestat_get", |mut caller: Caller<WasmContext>, dirfd: i32, flags: i32, path: i32, path_len: i32, out: i32| -> i32 {
+        let path = match resolve_at(&caller, dirfd, path, path_len, flags & LOOKUP_SYMLINK_FOLLOW != 0) { Ok(p) => p, Err(e) => return e };
+        let stat = {
+            let fs = caller.data().fs.borrow();
+            if flags & LOOKUP_SYMLINK_FOLLOW != 0 { fs.stat(&path) } else { fs.lstat(&path) }
+        };
+        let stat = match stat { Ok(s) => s, Err(e) => return fs_errno(&e) };
+        errno(write_filestat(&mut caller, out, filetype(&stat), Some(&stat)))
+    }).unwrap();
+
+    linker.func_wrap(MODULE, "path_filestat_set_times", |_: Caller<WasmContext>, _dirfd: i32, _flags: i32, _path: i32, _path_len: i32, _atim: i64, _mtim: i64, _fst_flags: i32| -> i32 {
+        ESUCCESS
+    }).unwrap();
+
+    linker.func_wrap(MODULE, "path_link", |_: Caller<WasmContext>, _old_fd: i32, _flags: i32, _old_path: i32, _old_len: i32, _new_fd: i32, _new_path: i32, _new_len: i32| -> i32 {
+        ENOTSUP
+    }).unwrap();
+
+    // The target is stored as given, relative targets resolve against the link's directory
+    linker.func_wrap(MODULE, "path_symlink", |caller: Caller<WasmContext>, old_path: i32, old_len: i32, fd: i32, new_path: i32, new_len: i32| -> i32 {
+        let target = match read_guest_str(&caller, old_path, old_len) { Some(t) => t, None => return EFAULT };
+        let path = match resolve_at(&caller, fd, new_path, new_len, false) { Ok(p) => p, Err(e) => return e };
+        let res = caller.data().fs.borrow_mut().symlink(&target, &path);
+        errno(res.map_err(|e| fs_errno(&e)))
+    }).unwrap();
+
+    // Longer targets are cut to buf_len, as in POSIX
+    linker.func_wrap(MODULE, "path_readlink", |mut caller: Caller<WasmContext>, fd: i32, path: i32, path_len: i32, buf: i32, buf_len: i32, bufused: i32| -> i32 {
+        let path = match resolve_at(&caller, fd, path, path_len, false) { Ok(p) => p, Err(e) => return e };
+        let target = match caller.data().fs.borrow().readlink(&path) { Ok(t) => t, Err(e) => return fs_errno(&e) };
+        let bytes = &target.as_bytes()[..target.len().min(buf_len as u32 as usize)];
+        errno(write_bytes(&mut caller, buf, bytes).and_then(|_| write_u32(&mut caller, bufused, bytes.len() as u32)))
+    }).unwrap();
+
+    // SCHEDULING / PROCESS
+    linker.func_wrap(MODULE, "poll_oneoff", |mut caller: Caller<WasmContext>, subs: i32, events: i32, nsubs: i32, nevents: i32| -> i32 {
+        yield_slice(&mut caller);
+        errno(poll_oneoff(&mut caller, subs, events, nsubs, nevents))
+    }).unwrap();
+
+    linker.func_wrap(MODULE, "sched_yield", |mut caller: Caller<WasmContext>| -> i32 {
+        yield_slice(&mut caller);
+        ESUCCESS
+    }).unwrap();
+
+    linker.func_wrap(MODULE, "proc_exit", |_: Caller<WasmContext>, code: i32| -> Result<(), Trap> {
+        Err(Trap::i32_exit(code))
+    }).unwrap();
+
+    linker.func_wrap(MODULE, "proc_raise", |_: Caller<WasmContext>, _sig: i32| -> i32 { ENOTSUP }).unwrap();
+
+    linker.func_wrap(MODULE, "random_get", |mut caller: Caller<WasmContext>, buf: i32, len: i32| -> i32 {
+        errno(random_get(&mut caller, buf, len))
+    }).unwrap();
+
+    // SOCKETS (none)
+    linker.func_wrap(MODULE, "sock_accept", |_: Caller<WasmContext>, _fd: i32, _flags: i32, _out: i32| -> i32 { ENOTSUP }).unwrap();
+    linker.func_wrap(MODULE, "sock_recv", |_: Caller<WasmContext>, _fd: i32, _iovs: i32, _iovs_len: i32, _flags: i32, _nread: i32, _oflags: i32| -> i32 { ENOTSUP }).unwrap();
+    linker.func_wrap(MODULE, "sock_send", |_: Caller<WasmContext>, _fd: i32, _iovs: i32, _iovs_len: i32, _flags: i32, _nwritten: i32| -> i32 { ENOTSUP }).unwrap();
+    linker.func_wrap(MODULE, "sock_shutdown", |_: Caller<WasmContext>, _fd: i32, _how: i32| -> i32 { ENOTSUP }).unwrap();
+}
@@ -1,7 +1,85 @@
 use std::collections::VecDeque;
 use std::rc::Rc;
 use std::cell::RefCell;
-use wasmi::{Engine, Linker, Module, Store, Caller, Instance, AsContextMut};
+use wasmi::{Config, Engine, Linker, Module, Store, Caller, AsContextMut};
+use wasmi::core::{Trap, TrapCode};
+
+use crate::host::Host;
+use crate::sys::fd::{self, FdTable, OpenFile};
+use crate::sys::env::{self, Env};
+use crate::sys::process::{self, Pid, ProcessTable, Task, WaitResult, KERNEL_PID};
+
+pub(crate) fn guest_memory(caller: &Caller<WasmContext>) -> Option<wasmi::Memory> {
+    caller.get_export("memory").and_then(|e| e.into_memory())
+}
+
+// Copy `len` bytes at `ptr` out of guest memory. Both come from the guest, so the
+// range is checked against the memory before anything is allocated for it.
+pub(crate) fn read_memory(caller: &Caller<WasmContext>, mem: &wasmi::Memory, ptr: usize, len: usize) -> Option<Vec<u8>> {
+    let data = mem.data(caller);
+    let end = ptr.checked_add(len).filter(|&end| end <= data.len())?;
+    Some(data[ptr..end].to_vec())
+}
+
+pub(crate) fn read_guest_str(caller: &Caller<WasmContext>, ptr: i32, len: i32) -> Option<String> {
+    let mem = guest_memory(caller)?;
+    let buf = read_memory(caller, &mem, ptr as usize, len.max(0) as usize)?;
+    String::from_utf8(buf).ok()
+}
+
+// Calls `setup_linker` provides in the `env` module, checked against the syscalls
+// a package manifest asks for. WASI programs ask for "wasi" as a whole.
+pub const SYSCALLS: &[&str] = &[
+    // Console and shell
+    "sys_print", "sys_getenv", "sys_complete", "sys_exec", "sys_time",
+    // Files
+    "sys_fs_list", "sys_fs_getcwd", "sys_fs_open", "sys_fs_read", "sys_fs_write", "sys_fs_seek", "sys_fs_close", "sys_fs_stat",
+    // Processes
+    "sys_getpid", "sys_spawn", "sys_wait", "sys_exit", "sys_kill", "sys_proc_list", "sys_reset", "sys_restart",
+    // Graphics and input
+    "sys_gpu_width", "sys_gpu_height", "sys_gpu_clear", "sys_draw_rect", "sys_draw_text", "sys_enable_gui_mode", "sys_poll_event",
+    // The whole wasi_snapshot_preview1 module
+    "wasi",
+];
+
+// All guests run metered so a runaway loop cannot hang the tick
+fn metered_engine() -> Engine {
+    let mut config = Config::default();
+    config.consume_fuel(true);
+    Engine::new(&config)
+}
+
+// Set the store's fuel to exactly `quota` for the next call. Returns the consumed
+// counter afterwards, to charge the call's usage against.
+fn refuel(store: &mut Store<WasmContext>, quota: u64) -> u64 {
+    let remaining = store.consume_fuel(0).unwrap_or(0);
+    if remaining < quota {
+        store.add_fuel(quota - remaining).ok();
+    } else {
+        store.consume_fuel(remaining - quota).ok();
+    }
+    store.fuel_consumed().unwrap_or(0)
+}
+
+// Start a new slice for a `_start` program at one of its yield points. Other
+// entry points keep the quota they were called with.
+pub(crate) fn yield_slice(caller: &mut Caller<WasmContext>) {
+    if !caller.data().sliced { return; }
+    let remaining = caller.consume_fuel(0).unwrap_or(0);
+    if remaining < process::INIT_FUEL_QUOTA {
+        caller.add_fuel(process::INIT_FUEL_QUOTA - remaining).ok();
+    }
+}
+
+fn end_process(processes: &RefCell<ProcessTable>, gui_mode: &RefCell<bool>, pid: Pid, status: i32) {
+    let mut processes = processes.borrow_mut();
+    let was_foreground = processes.foreground() == Some(pid);
+    processes.exit(pid, status);
+    // Back to the text shell once the last graphical process is gone
+    if was_foreground && processes.foreground().is_none() {
+        *gui_mode.borrow_mut() = false;
+    }
+}
 
 #[derive(Clone)]
 pub struct WasmRuntime {
@@ -13,14 +91,9 @@ pub struct WasmRuntime {
     fs: Rc<RefCell<crate::sys::fs::FileSystem>>,
     should_reset: Rc<RefCell<bool>>,
     shell: Rc<RefCell<crate::sys::shell::Shell>>,
-    
-    active_process: Rc<RefCell<Option<ActiveProcess>>>, 
-    next_process: Rc<RefCell<Option<ActiveProcess>>>,
-}
-
-pub struct ActiveProcess {
-    pub store: Store<WasmContext>,
-    pub instance: Instance,
+    host: Rc<dyn Host>,
+    processes: Rc<RefCell<ProcessTable>>,
+    native: Rc<RefCell<Option<Vec<u8>>>>, // wasmix-32 image waiting for the cpu
 }
 
 pub struct WasmContext {
@@ -31,12 +104,69 @@ pub struct WasmContext {
     pub fs: Rc<RefCell<crate::sys::fs::FileSystem>>,
     pub should_reset: Rc<RefCell<bool>>,
     pub shell: Rc<RefCell<crate::sys::shell::Shell>>,
-    // Add access to global process slot for exec/replacement
-    pub active_process: Rc<RefCell<Option<ActiveProcess>>>,
-    pub next_process: Rc<RefCell<Option<ActiveProcess>>>,
+    pub host: Rc<dyn Host>,
+    pub pid: Pid,
+    pub fds: FdTable,
+    pub args: Vec<String>, // argv, program path first
+    pub env: Env,
+    pub processes: Rc<RefCell<ProcessTable>>,
+    pub native: Rc<RefCell<Option<Vec<u8>>>>,
+    pub stdio: Stdio,
+    pub sliced: bool, // Running `_start`, refuelled at yields (see `yield_slice`)
+}
+
+// Standard streams of a process. A `None` stream is the terminal: stdin reads typed
+// keys, stdout and stderr print to the screen. The shell sets them up while it waits
+// on a program's init, so output can be piped or redirected.
+#[derive(Default)]
+pub struct Stdio {
+    pub stdin: Option<VecDeque<u8>>, // Piped input, empty means end of file
+    pub stdout: Option<String>,
+    pub stderr: Option<String>,
+}
+
+// What a program run by the shell printed, and how it ended
+pub struct Capture {
+    pub stdout: String,
+    pub stderr: String,
+    pub status: i32, // 0 if it is still running in the background
+}
+
+// A process after its init ran
+struct Started {
+    pid: Pid,
+    stdio: Stdio,
+    status: Option<i32>,   // Exit status, if it already exited
+    error: Option<String>, // Why init failed, if it crashed
+}
+
+impl WasmContext {
+    // Whether this process may take input events and draw
+    fn owns_display(&self) -> bool {
+        self.processes.borrow().owns_display(self.pid)
+    }
+
+    // Write to stdout or stderr. False if the terminal is busy and the text was dropped.
+    pub(crate) fn write_stdio(&mut self, text: &str, stderr: bool) -> bool {
+        let capture = if stderr { &mut self.stdio.stderr } else { &mut self.stdio.stdout };
+        match capture {
+            Some(buf) => {
+                buf.push_str(text);
+                true
+            },
+            None => match self.term.try_borrow_mut() {
+                Ok(mut term) => {
+                    term.write_str(text);
+                    true
+                },
+                Err(_) => false,
+            },
+        }
+    }
 }
 
 impl WasmRuntime {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         term: Rc<RefCell<crate::term::Terminal>>,
         gpu: Rc<RefCell<crate::hw::gpu::Gpu>>,
@@ -45,8 +175,9 @@ impl WasmRuntime {
         fs: Rc<RefCell<crate::sys::fs::FileSystem>>,
         should_reset: Rc<RefCell<bool>>,
         shell: Rc<RefCell<crate::sys::shell::Shell>>,
+        host: Rc<dyn Host>,
     ) -> Self {
-        let engine = Engine::default();
+        let engine = metered_engine();
         Self {
             engine,
             term,
@@ -56,32 +187,42 @@ impl WasmRuntime {
             fs,
             should_reset,
             shell,
-            active_process: Rc::new(RefCell::new(None)),
-            next_process: Rc::new(RefCell::new(None)),
+            host,
+            processes: Rc::new(RefCell::new(ProcessTable::new())),
+            native: Rc::new(RefCell::new(None)),
+        }
+    }
+
+    // Runtime for nested loads from inside a syscall, sharing the process table.
+    // Gets its own engine to avoid deadlocking the caller's.
+    fn from_context(ctx: &WasmContext) -> Self {
+        Self {
+            engine: metered_engine(),
+            term: ctx.term.clone(),
+            gpu: ctx.gpu.clone(),
+            gui_mode: ctx.gui_mode.clone(),
+            events: ctx.events.clone(),
+            fs: ctx.fs.clone(),
+            should_reset: ctx.should_reset.clone(),
+            shell: ctx.shell.clone(),
+            host: ctx.host.clone(),
+            processes: ctx.processes.clone(),
+            native: ctx.native.clone(),
         }
     }
 
-    fn setup_linker(engine: &Engine, output_buffer: std::sync::Arc<std::sync::Mutex<String>>) -> Linker<WasmContext> {
+    fn setup_linker(engine: &Engine) -> Linker<WasmContext> {
         let mut linker = Linker::new(engine);
         
-        let output_clone = output_buffer.clone();
+        crate::sys::wasi::add_to_linker(&mut linker);
         
         // ... (sys_print, sys_fs_list, etc unchanged) ...
-        linker.func_wrap("env", "sys_print", move |caller: Caller<WasmContext>, ptr: i32, len: i32| {
+        linker.func_wrap("env", "sys_print", |mut caller: Caller<WasmContext>, ptr: i32, len: i32| {
             if let Some(extern_mem) = caller.get_export("memory").and_then(|e| e.into_memory()) {
-                let mut buffer = vec![0u8; len as usize];
-                if extern_mem.read(&caller, ptr as usize, &mut buffer).is_ok() {
+                if let Some(buffer) = read_memory(&caller, &extern_mem, ptr as usize, len as usize) {
                     if let Ok(msg) = String::from_utf8(buffer) {
-                        // Write to local capture
-                        if let Ok(mut out) = output_clone.lock() {
-                             out.push_str(&msg);
-                             out.push('\n');
-                        }
-
-                        if let Ok(mut term_guard) = caller.data().term.try_borrow_mut() {
-                            term_guard.write_str(&msg);
-                            term_guard.write_char('\n');
-                        } else {
+                        let line = format!("{}\n", msg);
+                        if !caller.data_mut().write_stdio(&line, false) {
                             // Recovery
                             if let Ok(mut gpu) = caller.data().gpu.try_borrow_mut() {
                                 let mut draw_x = 10;
@@ -100,30 +241,25 @@ impl WasmRuntime {
 
         linker.func_wrap("env", "sys_fs_list", |mut caller: Caller<WasmContext>, path_ptr: i32, path_len: i32, out_ptr: i32, out_len: i32| -> i32 {
              if let Some(extern_mem) = caller.get_export("memory").and_then(|e| e.into_memory()) {
-                let mut path_buf = vec![0u8; path_len as usize];
-                if extern_mem.read(&caller, path_ptr as usize, &mut path_buf).is_ok() {
+                if let Some(path_buf) = read_memory(&caller, &extern_mem, path_ptr as usize, path_len as usize) {
                     if let Ok(path_str) = String::from_utf8(path_buf) {
                         let output_data = {
                             let fs = caller.data().fs.borrow();
-                            let path_parts: Vec<String> = path_str.split('/').filter(|s| !s.is_empty()).map(|s| s.to_string()).collect();
-                            let target_node = if path_str == "/" { Some(&fs.root) } else { fs.resolve_dir(&path_parts) };
-                            
-                            if let Some(node) = target_node {
-                                 if let crate::sys::fs::NodeType::Directory = node.node_type {
-                                     let mut output = String::new();
-                                     if !path_parts.is_empty() { output.push_str("D:..\n"); }
-                                     let mut entries: Vec<_> = node.children.iter().collect();
-                                     entries.sort_by_key(|(k,_)| *k);
-                                     for (name, child) in entries {
-                                         let prefix = match child.node_type {
-                                             crate::sys::fs::NodeType::Directory => "D",
-                                             crate::sys::fs::NodeType::File => "F",
-                                         };
-                                         output.push_str(&format!("{}:{}\n", prefix, name));
-                                     }
-                                     Some(output)
-                                 } else { None }
-                            } else { None }
+                            let at_root = fs.resolve_path(&path_str).is_empty();
+                            fs.read_dir(&path_str).ok().map(|entries| {
+                                let mut output = String::new();
+                                if !at_root { output.push_str("D:..\n"); }
+                                for (name, stat) in entries {
+                                    // Symlinks are listed as what they point to
+                                    let is_dir = match stat.node_type {
+                                        crate::sys::fs::NodeType::Symlink => fs.is_dir(&format!("{}/{}", path_str.trim_end_matches('/'), name)),
+                                        node_type => matches!(node_type, crate::sys::fs::NodeType::Directory),
+                                    };
+                                    let prefix = if is_dir { "D" } else { "F" };
+                                    output.push_str(&format!("{}:{}\n", prefix, name));
+                                }
+                                output
+                            })
                         };
 
                         if let Some(output) = output_data {
@@ -141,13 +277,8 @@ impl WasmRuntime {
         linker.func_wrap("env", "sys_fs_getcwd", |mut caller: Caller<WasmContext>, out_ptr: i32, out_len: i32| -> i32 {
             if let Some(extern_mem) = caller.get_export("memory").and_then(|e| e.into_memory()) {
                 let path_str = {
-                    let fs = caller.data().fs.borrow();
-                    if fs.current_path.is_empty() { "~".to_string() } else {
-                        let mut p = String::from("/");
-                        for part in &fs.current_path { p.push_str(part); p.push('/'); }
-                        if p.len() > 1 { p.pop(); }
-                        p
-                    }
+                    let cwd = caller.data().fs.borrow().cwd();
+                    if cwd == "/" { "~".to_string() } else { cwd }
                 };
                 let bytes = path_str.as_bytes();
                 let write_len = bytes.len().min(out_len as usize);
@@ -157,11 +288,131 @@ impl WasmRuntime {
             -1
         }).unwrap();
 
+        // FILE I/O (handle based, per-process descriptor table)
+        linker.func_wrap("env", "sys_fs_open", |mut caller: Caller<WasmContext>, path_ptr: i32, path_len: i32, flags: i32| -> i32 {
+            let path = match read_guest_str(&caller, path_ptr, path_len) { Some(p) => p, None => return -1 };
+            let abs = {
+                let mut fs = caller.data().fs.borrow_mut();
+                let exists = fs.stat(&path).is_ok();
+                if !exists {
+                    if flags & fd::O_CREATE == 0 || fs.create_file(&path).is_err() { return -1; }
+                } else if fs.is_dir(&path) {
+                    return -1;
+                }
+                if flags & fd::O_TRUNC != 0 && fs.truncate(&path, 0).is_err() { return -1; }
+                crate::sys::fs::FileSystem::join_path(&fs.resolve_path(&path))
+            };
+            caller.data_mut().fds.insert(OpenFile::file(abs, flags))
+        }).unwrap();
+
+        linker.func_wrap("env", "sys_fs_read", |mut caller: Caller<WasmContext>, fd: i32, buf_ptr: i32, buf_len: i32| -> i32 {
+            let (path, pos) = match caller.data_mut().fds.get_mut(fd) {
+                Some(f) if f.readable() && !f.is_dir => (f.path.clone(), f.pos),
+                _ => return -1,
+            };
+            let data = match caller.data().fs.borrow().read_at(&path, pos, buf_len.max(0) as usize) {
+                Ok(d) => d,
+                Err(_) => return -1,
+            };
+            let mem = match guest_memory(&caller) { Some(m) => m, None => return -1 };
+            if mem.write(&mut caller, buf_ptr as usize, &data).is_err() { return -1; }
+            if let Some(f) = caller.data_mut().fds.get_mut(fd) { f.pos += data.len(); }
+            data.len() as i32
+        }).unwrap();
+
+        linker.func_wrap("env", "sys_fs_write", |mut caller: Caller<WasmContext>, fd: i32, buf_ptr: i32, buf_len: i32| -> i32 {
+            let (path, pos, append) = match caller.data_mut().fds.get_mut(fd) {
+                Some(f) if f.writable() && !f.is_dir => (f.path.clone(), f.pos, f.flags & fd::O_APPEND != 0),
+                _ => return -1,
+            };
+            let mem = match guest_memory(&caller) { Some(m) => m, None => return -1 };
+            let data = match read_memory(&caller, &mem, buf_ptr as usize, buf_len.max(0) as usize) { Some(d) => d, None => return -1 };
+
+            let res = {
+                let mut fs = caller.data().fs.borrow_mut();
+                let offset = if append { fs.stat(&path).map(|s| s.size).unwrap_or(0) } else { pos };
+                fs.write_at(&path, offset, &data).map(|n| offset + n)
+            };
+            match res {
+                Ok(new_pos) => {
+                    if let Some(f) = caller.data_mut().fds.get_mut(fd) { f.pos = new_pos; }
+                    data.len() as i32
+                },
+                Err(_) => -1,
+            }
+        }).unwrap();
+
+        linker.func_wrap("env", "sys_fs_seek", |mut caller: Caller<WasmContext>, fd: i32, offset: i32, whence: i32| -> i32 {
+            let (path, pos) = match caller.data_mut().fds.get_mut(fd) {
+                Some(f) => (f.path.clone(), f.pos),
+                None => return -1,
+            };
+            let base = match whence {
+                fd::SEEK_SET => 0,
+                fd::SEEK_CUR => pos as i64,
+                fd::SEEK_END => match caller.data().fs.borrow().stat(&path) { Ok(s) => s.size as i64, Err(_) => return -1 },
+                _ => return -1,
+            };
+            let new_pos = base + offset as i64;
+            if new_pos < 0 || new_pos > i32::MAX as i64 { return -1; }
+            if let Some(f) = caller.data_mut().fds.get_mut(fd) { f.pos = new_pos as usize; }
+            new_pos as i32
+        }).unwrap();
+
+        linker.func_wrap("env", "sys_fs_close", |mut caller: Caller<WasmContext>, fd: i32| -> i32 {
+            if caller.data_mut().fds.remove(fd).is_some() { 0 } else { -1 }
+        }).unwrap();
+
+        // Writes { type: u32 (0 = file, 1 = dir, 2 = symlink loop), size: u32 } to out_ptr
+        linker.func_wrap("env", "sys_fs_stat", |mut caller: Caller<WasmContext>, path_ptr: i32, path_len: i32, out_ptr: i32| -> i32 {
+            let path = match read_guest_str(&caller, path_ptr, path_len) { Some(p) => p, None => return -1 };
+            let stat = match caller.data().fs.borrow().stat(&path) { Ok(s) => s, Err(_) => return -1 };
+            let type_val: u32 = match stat.node_type {
+                crate::sys::fs::NodeType::File => 0,
+                crate::sys::fs::NodeType::Directory => 1,
+                crate::sys::fs::NodeType::Symlink => 2,
+            };
+            let bytes = [type_val.to_le_bytes(), (stat.size as u32).to_le_bytes()].concat();
+            let mem = match guest_memory(&caller) { Some(m) => m, None => return -1 };
+            if mem.write(&mut caller, out_ptr as usize, &bytes).is_err() { return -1; }
+            0
+        }).unwrap();
+
+        // Tab completion for the line (up to the cursor) at line_ptr: writes the candidates
+        // for its last word one per line, as many whole ones as fit. Returns the bytes written.
+        linker.func_wrap("env", "sys_complete", |mut caller: Caller<WasmContext>, line_ptr: i32, line_len: i32, out_ptr: i32, out_len: i32| -> i32 {
+            let line = match read_guest_str(&caller, line_ptr, line_len) { Some(l) => l, None => return -1 };
+            let candidates = crate::sys::shell::complete(&line, &caller.data().fs.borrow(), &caller.data().env);
+            let mut out = String::new();
+            for candidate in candidates {
+                if out.len() + candidate.len() + 1 > out_len.max(0) as usize {
+                    break;
+                }
+                out.push_str(&candidate);
+                out.push('\n');
+            }
+            let mem = match guest_memory(&caller) { Some(m) => m, None => return -1 };
+            if mem.write(&mut caller, out_ptr as usize, out.as_bytes()).is_err() { return -1; }
+            out.len() as i32
+        }).unwrap();
+
+        // Value of the caller's environment variable at name_ptr. Returns its length, or -1
+        // if it is not set. Longer values are cut to out_len.
+        linker.func_wrap("env", "sys_getenv", |mut caller: Caller<WasmContext>, name_ptr: i32, name_len: i32, out_ptr: i32, out_len: i32| -> i32 {
+            let name = match read_guest_str(&caller, name_ptr, name_len) { Some(n) => n, None => return -1 };
+            let value = match caller.data().env.get(&name) { Some(v) => v.to_string(), None => return -1 };
+            let bytes = &value.as_bytes()[..value.len().min(out_len.max(0) as usize)];
+            let mem = match guest_memory(&caller) { Some(m) => m, None => return -1 };
+            if mem.write(&mut caller, out_ptr as usize, bytes).is_err() { return -1; }
+            bytes.len() as i32
+        }).unwrap();
+
         // RECURSIVE SYS_EXEC
+        // The command runs with the caller's environment, and `export`, `unset` and the
+        // exit status are kept there for the next one
         linker.func_wrap("env", "sys_exec", move |mut caller: Caller<WasmContext>, cmd_ptr: i32, cmd_len: i32, out_ptr: i32, out_len: i32| -> i32 {
             if let Some(extern_mem) = caller.get_export("memory").and_then(|e| e.into_memory()) {
-                let mut cmd_buf = vec![0u8; cmd_len as usize];
-                if extern_mem.read(&caller, cmd_ptr as usize, &mut cmd_buf).is_ok() {
+                if let Some(cmd_buf) = read_memory(&caller, &extern_mem, cmd_ptr as usize, cmd_len as usize) {
                     if let Ok(cmd_str) = String::from_utf8(cmd_buf) {
                         
                         // Parse command locally to check for empty input
@@ -169,35 +420,24 @@ impl WasmRuntime {
                         if cmd_str_trim.is_empty() { return -1; }
                         
                         let output = {
-                            // Construct transient runtime ISOLATED from parent engine but SHARING process slot
-                            let runtime = WasmRuntime {
-                                engine: Engine::default(), // New Engine to avoid deadlock
-                                term: caller.data().term.clone(),
-                                gpu: caller.data().gpu.clone(),
-                                gui_mode: caller.data().gui_mode.clone(),
-                                events: caller.data().events.clone(),
-                                fs: caller.data().fs.clone(),
-                                should_reset: caller.data().should_reset.clone(),
-                                shell: caller.data().shell.clone(),
-                                // VITAL: Share the global active_process slot with the kernel
-                                active_process: caller.data().active_process.clone(), 
-                                next_process: caller.data().next_process.clone(),
-                            };
+                            // Transient runtime sharing the process table, so programs started
+                            // here run alongside the caller instead of replacing it
+                            let runtime = WasmRuntime::from_context(caller.data());
 
                             // Use a transient Shell to avoid RefCell Double Borrow Panic
                             // The global kernel shell might be active (e.g. in run_one_command -> exec -> sys_exec)
-                            let mut shell = crate::sys::shell::Shell::new(); 
+                            let mut shell = crate::sys::shell::Shell::with_env(caller.data().host.clone(), caller.data().env.clone());
                             
                             let fs_rc = caller.data().fs.clone();
-                            let events_rc = caller.data().events.clone();
                             
-                            // execute_string calls load(), which updates active_process if successful
+                            // exec registers the program in the process table (as a child of the kernel)
                             // CRITICAL: Pass the Rc, not a borrow, to avoid double-borrow panics in nested sys_exec
-                            let (out_str, reboot) = shell.execute_string_rc(cmd_str_trim, &fs_rc, Some(&runtime), &events_rc, 0, 0.0);
+                            let (out_str, reboot) = shell.execute_string(cmd_str_trim, &fs_rc, Some(&runtime), 0, 0.0);
                             
                             if reboot {
                                 *caller.data().should_reset.borrow_mut() = true;
                             }
+                            caller.data_mut().env = shell.env().clone();
                             
                             out_str
                         };
@@ -212,13 +452,75 @@ impl WasmRuntime {
             -1
         }).unwrap();
 
-        // ... (rest unchanged) ...
-        linker.func_wrap("env", "sys_reset", |caller: Caller<WasmContext>| {
-             if let Some(window) = web_sys::window() {
-                if let Ok(Some(storage)) = window.local_storage() {
-                    let _ = storage.remove_item("wasmix_fs_local");
-                }
+        // PROCESSES
+        linker.func_wrap("env", "sys_getpid", |caller: Caller<WasmContext>| -> i32 {
+            caller.data().pid as i32
+        }).unwrap();
+
+        // Start "path [args...]" as a child of the caller, which passes on its environment.
+        // A bare name is looked up along its $PATH. Returns the child's pid, or -1.
+        linker.func_wrap("env", "sys_spawn", |caller: Caller<WasmContext>, cmd_ptr: i32, cmd_len: i32| -> i32 {
+            let cmd = match read_guest_str(&caller, cmd_ptr, cmd_len) { Some(c) => c, None => return -1 };
+            let args: Vec<String> = cmd.split_whitespace().map(|s| s.to_string()).collect();
+            if args.is_empty() { return -1; }
+            let ctx = caller.data();
+            let path = match env::find_program(&ctx.fs.borrow(), &ctx.env, &args[0]) { Some(p) => p, None => return -1 };
+            let runtime = WasmRuntime::from_context(ctx);
+            let bytes = match runtime.read_program(&path) { Ok(b) => b, Err(_) => return -1 };
+            match runtime.spawn(&bytes, &args, &ctx.env, ctx.pid) {
+                Ok(pid) => pid as i32,
+                Err(_) => -1,
             }
+        }).unwrap();
+
+        // Reap an exited child (pid -1 = any). Returns its pid and writes its status,
+        // 0 if it is still running (the caller sleeps until a child exits), -1 if no such child.
+        linker.func_wrap("env", "sys_wait", |mut caller: Caller<WasmContext>, pid: i32, status_ptr: i32| -> i32 {
+            let res = caller.data().processes.borrow_mut().wait(caller.data().pid, pid);
+            match res {
+                WaitResult::Exited(child, status) => {
+                    if let Some(mem) = guest_memory(&caller) {
+                        mem.write(&mut caller, status_ptr as usize, &status.to_le_bytes()).ok();
+                    }
+                    child as i32
+                },
+                WaitResult::Pending => 0,
+                WaitResult::NoChild => -1,
+            }
+        }).unwrap();
+
+        linker.func_wrap("env", "sys_exit", |_: Caller<WasmContext>, status: i32| -> Result<(), Trap> {
+            Err(Trap::i32_exit(status))
+        }).unwrap();
+
+        // Returns 0, or -1 if there is no such process. Killing yourself does not return.
+        linker.func_wrap("env", "sys_kill", |caller: Caller<WasmContext>, pid: i32| -> Result<i32, Trap> {
+            let ctx = caller.data();
+            if pid as Pid == ctx.pid {
+                return Err(Trap::i32_exit(process::EXIT_KILLED));
+            }
+            if pid <= KERNEL_PID as i32 || ctx.processes.borrow().name(pid as Pid).is_none() {
+                return Ok(-1);
+            }
+            end_process(&ctx.processes, &ctx.gui_mode, pid as Pid, process::EXIT_KILLED);
+            Ok(0)
+        }).unwrap();
+
+        // One line per process: "pid ppid state cpu% name"
+        linker.func_wrap("env", "sys_proc_list", |mut caller: Caller<WasmContext>, out_ptr: i32, out_len: i32| -> i32 {
+            let mut output = String::new();
+            for p in caller.data().processes.borrow().list() {
+                output.push_str(&format!("{} {} {} {} {}\n", p.pid, p.parent, p.state.name(), p.cpu, p.name));
+            }
+            let bytes = output.as_bytes();
+            let write_len = bytes.len().min(out_len.max(0) as usize);
+            let mem = match guest_memory(&caller) { Some(m) => m, None => return -1 };
+            if mem.write(&mut caller, out_ptr as usize, &bytes[..write_len]).is_err() { return -1; }
+            write_len as i32
+        }).unwrap();
+
+        linker.func_wrap("env", "sys_reset", |caller: Caller<WasmContext>| {
+            caller.data().host.disk_clear();
             *caller.data().should_reset.borrow_mut() = true;
         }).unwrap();
 
@@ -234,18 +536,21 @@ impl WasmRuntime {
             caller.data().gpu.borrow().height as i32
         }).unwrap();
 
+        // Drawing is ignored for background processes
         linker.func_wrap("env", "sys_gpu_clear", |caller: Caller<WasmContext>, r: i32, g: i32, b: i32| {
+            if !caller.data().owns_display() { return; }
             caller.data().gpu.borrow_mut().clear(r as u8, g as u8, b as u8);
         }).unwrap();
 
         linker.func_wrap("env", "sys_draw_rect", |caller: Caller<WasmContext>, x: i32, y: i32, w: i32, h: i32, color: i32| {
+             if !caller.data().owns_display() { return; }
              caller.data().gpu.borrow_mut().fill_rect(x, y, w, h, color as u32);
         }).unwrap();
 
         linker.func_wrap("env", "sys_draw_text", |caller: Caller<WasmContext>, ptr: i32, len: i32, x: i32, y: i32, color: i32| {
+            if !caller.data().owns_display() { return; }
             if let Some(extern_mem) = caller.get_export("memory").and_then(|e| e.into_memory()) {
-                let mut buffer = vec![0u8; len as usize];
-                if extern_mem.read(&caller, ptr as usize, &mut buffer).is_ok() {
+                if let Some(buffer) = read_memory(&caller, &extern_mem, ptr as usize, len as usize) {
                     if let Ok(msg) = String::from_utf8(buffer) {
                         let mut gpu = caller.data().gpu.borrow_mut();
                         let mut draw_x = x;
@@ -259,11 +564,14 @@ impl WasmRuntime {
             }
         }).unwrap();
 
+        // Also makes the caller the foreground process
         linker.func_wrap("env", "sys_enable_gui_mode", |caller: Caller<WasmContext>| {
+            caller.data().processes.borrow_mut().set_foreground(caller.data().pid);
             *caller.data().gui_mode.borrow_mut() = true;
         }).unwrap();
 
         linker.func_wrap("env", "sys_poll_event", |mut caller: Caller<WasmContext>, ptr: i32| -> i32 {
+             if !caller.data().owns_display() { return 0; }
              let events_rc = caller.data().events.clone();
              let mut events_guard = events_rc.borrow_mut();
              if let Some(event) = events_guard.pop_front() {
@@ -279,21 +587,58 @@ impl WasmRuntime {
              0
         }).unwrap();
 
-        linker.func_wrap("env", "sys_time", |_caller: Caller<WasmContext>| -> i32 {
-            if let Some(window) = web_sys::window() {
-                if let Some(perf) = window.performance() { return perf.now() as i32; }
-            }
-            0
+        linker.func_wrap("env", "sys_time", |caller: Caller<WasmContext>| -> i32 {
+            caller.data().host.now() as i32
         }).unwrap();
         
         linker
     }
 
-    pub fn load(&self, wasm_bytes: &[u8]) -> Result<String, String> {
+    // Run a program for the shell as a child of the kernel, capturing what it prints
+    // during init. `stdin` is piped input, `None` reads the keyboard.
+    pub fn run(&self, wasm_bytes: &[u8], args: &[String], env: &Env, stdin: Option<Vec<u8>>) -> Result<Capture, String> {
+        let stdio = Stdio {
+            stdin: stdin.map(VecDeque::from),
+            stdout: Some(String::new()),
+            stderr: Some(String::new()),
+        };
+        let started = self.start(wasm_bytes, args, env, KERNEL_PID, stdio)?;
+        let mut stderr = started.stdio.stderr.unwrap_or_default();
+        if let Some(error) = started.error {
+            stderr.push_str(&format!("exec crash: {}\n", error));
+        }
+        Ok(Capture {
+            stdout: started.stdio.stdout.unwrap_or_default(),
+            stderr,
+            status: started.status.unwrap_or(0),
+        })
+    }
+
+    // Start a program as a child of `parent`, printing straight to the terminal.
+    // Failures in init are only reported as errors to the kernel, guest parents see
+    // them through sys_wait.
+    pub fn spawn(&self, wasm_bytes: &[u8], args: &[String], env: &Env, parent: Pid) -> Result<Pid, String> {
+        let started = self.start(wasm_bytes, args, env, parent, Stdio::default())?;
+        match started.status {
+            Some(status) if parent == KERNEL_PID && status != 0 => {
+                Err(started.error.unwrap_or_else(|| format!("exited with status {}", status)))
+            },
+            _ => Ok(started.pid),
+        }
+    }
+
+    // Instantiate a program and run its `init` (or `_start`). Programs exporting `step`
+    // stay in the process table; the rest exit once init returns. The streams are
+    // handed back afterwards and later steps print to the terminal.
+    fn start(&self, wasm_bytes: &[u8], args: &[String], env: &Env, parent: Pid, stdio: Stdio) -> Result<Started, String> {
         let module = Module::new(&self.engine, wasm_bytes)
             .map_err(|e| format!("failed to create module: {}", e))?;
-        
-        let output_buffer = std::sync::Arc::new(std::sync::Mutex::new(String::new()));
+
+        // Programs start where the shell is, as the "." preopen and $PWD
+        let cwd = self.fs.borrow().cwd();
+        let mut env = env.clone();
+        env.set("PWD", &cwd);
+
         let ctx = WasmContext {
             term: self.term.clone(),
             gpu: self.gpu.clone(),
@@ -302,98 +647,160 @@ impl WasmRuntime {
             fs: self.fs.clone(),
             should_reset: self.should_reset.clone(),
             shell: self.shell.clone(),
-            active_process: self.active_process.clone(),
-            next_process: self.next_process.clone(),
+            host: self.host.clone(),
+            pid: KERNEL_PID,
+            fds: FdTable::new(&cwd),
+            args: args.to_vec(),
+            env,
+            processes: self.processes.clone(),
+            native: self.native.clone(),
+            stdio,
+            sliced: false,
         };
 
         let mut store = Store::new(&self.engine, ctx);
-        let linker = Self::setup_linker(&self.engine, output_buffer.clone());
-
-        let instance = linker.instantiate(&mut store, &module)
-            .map_err(|e| format!("failed to instantiate: {}", e))?
-            .start(&mut store)
-            .map_err(|e| format!("failed to start: {}", e))?;
+        let linker = Self::setup_linker(&self.engine);
+
+        let pre = linker.instantiate(&mut store, &module)
+            .map_err(|e| format!("failed to instantiate: {}", e))?;
+
+        let name = args.first().map(|a| a.rsplit('/').next().unwrap_or(a)).unwrap_or("wasm");
+        let pid = self.processes.borrow_mut().create(parent, name);
+        store.data_mut().pid = pid;
+
+        // Start section and init share one init quota
+        let baseline = refuel(&mut store, process::INIT_FUEL_QUOTA);
+        let instance = match pre.start(&mut store) {
+            Ok(instance) => instance,
+            Err(e) => {
+                self.exit_process(pid, -1);
+                return Err(format!("failed to start: {}", e));
+            },
+        };
 
-        if let Ok(init_func) = instance.get_typed_func::<(), ()>(&store, "init") {
-             init_func.call(&mut store, ())
-                .map_err(|e| format!("init error: {}", e))?;
+        let res = if let Ok(init_func) = instance.get_typed_func::<(), ()>(&store, "init") {
+            init_func.call(&mut store, ()).map_err(|e| ("init", e))
         } else if let Ok(start_func) = instance.get_typed_func::<(), ()>(&store, "_start") {
-             start_func.call(&mut store, ())
-                .map_err(|e| format!("start error: {}", e))?;
-        }
+            store.data_mut().sliced = true;
+            let res = start_func.call(&mut store, ()).map_err(|e| ("start", e));
+            store.data_mut().sliced = false;
+            res
+        } else {
+            Ok(())
+        };
 
-        if let Ok(_) = instance.get_typed_func::<(), ()>(&store, "step") {
-            
-            // Try explicit borrow first (works for Boot)
-            if let Ok(mut guard) = self.active_process.try_borrow_mut() {
-                *guard = Some(ActiveProcess {
-                    store,
-                    instance,
-                });
-            } else {
-                // If active_process is busy (e.g. inside tick -> sys_exec), schedule switch
-                if let Ok(mut guard) = self.next_process.try_borrow_mut() {
-                    *guard = Some(ActiveProcess {
-                        store,
-                        instance,
-                    });
+        let used = store.fuel_consumed().unwrap_or(0) - baseline;
+        self.processes.borrow_mut().charge(pid, used);
+        let stdio = std::mem::take(&mut store.data_mut().stdio);
+
+        let mut started = Started { pid, stdio, status: None, error: None };
+        match res {
+            Ok(()) if instance.get_typed_func::<(), ()>(&store, "step").is_ok() => {
+                self.processes.borrow_mut().attach(pid, Task { store, instance });
+            },
+            Ok(()) => {
+                self.exit_process(pid, 0);
+                started.status = Some(0);
+            },
+            // A wasi `proc_exit` or `sys_exit` unwinds as a trap carrying the exit status
+            Err((stage, trap)) => {
+                let status = self.trap_status(pid, &trap);
+                self.exit_process(pid, status);
+                started.status = Some(status);
+                if trap.i32_exit_status().is_none() && status != process::EXIT_KILLED {
+                    started.error = Some(format!("{} error: {}", stage, trap));
                 }
+            },
+        }
+        Ok(started)
+    }
+
+    fn exit_process(&self, pid: Pid, status: i32) {
+        end_process(&self.processes, &self.gui_mode, pid, status);
+    }
+
+    // Exit status for a call that trapped: the guest's own if it exited, otherwise it
+    // either ran out of fuel (killed) or crashed
+    fn trap_status(&self, pid: Pid, trap: &Trap) -> i32 {
+        if let Some(status) = trap.i32_exit_status() {
+            return status;
+        }
+        let name = self.processes.borrow().name(pid).unwrap_or("?").to_string();
+        if matches!(trap.trap_code(), Some(TrapCode::OutOfFuel)) {
+            let msg = format!("process {} ({}) killed: cpu quota exceeded", pid, name);
+            self.host.log(&msg);
+            if let Ok(mut term) = self.term.try_borrow_mut() {
+                term.write_str(&msg);
+                term.write_char('\n');
             }
+            process::EXIT_KILLED
+        } else {
+            self.host.log(&format!("process {} ({}) crashed: {}", pid, name, trap));
+            -1
         }
-        
-        let res = output_buffer.lock().unwrap().clone();
-        Ok(res)
     }
 
-    pub fn load_from_path(&self, path: &str) -> Result<String, String> {
-        let content = {
-            let fs = self.fs.borrow();
-            let node = if let Some(node) = fs.resolve_dir(&path.split('/').filter(|s| !s.is_empty()).map(|s| s.to_string()).collect::<Vec<_>>()) {
-                 Some(node)
-            } else {
-                 if let Some(bin) = fs.root.children.get("bin") {
-                     if let Some(node) = bin.children.get(path) {
-                         Some(node)
-                     } else if path.starts_with("/bin/") {
-                          if let Some(node) = bin.children.get(&path[5..]) { Some(node) } else { None }
-                     } else { None }
-                 } else { None }
-            };
+    pub fn kill(&self, pid: Pid) -> Result<(), String> {
+        if pid == KERNEL_PID || self.processes.borrow().name(pid).is_none() {
+            return Err(format!("no such process: {}", pid));
+        }
+        self.exit_process(pid, process::EXIT_KILLED);
+        Ok(())
+    }
 
-            if let Some(node) = node {
-                if let crate::sys::fs::NodeType::File = node.node_type {
-                    Some(node.content.clone())
-                } else { None }
-            } else { None }
-        }; 
+    pub fn process_list(&self) -> Vec<crate::sys::process::ProcessInfo> {
+        self.processes.borrow().list()
+    }
 
-        if let Some(bytes) = content {
-             self.load(&bytes)
-        } else {
-             Err("file not found".to_string())
+    // Read a program to run. Names are resolved by the caller (see `env::find_program`).
+    pub fn read_program(&self, path: &str) -> Result<Vec<u8>, String> {
+        self.fs.borrow().read_executable(path)
+    }
+
+    // Hand a native wasmix-32 image to the machine, which loads it into the cpu on
+    // its next step. Fails while another one is waiting.
+    pub fn queue_native(&self, image: Vec<u8>) -> Result<(), String> {
+        let mut native = self.native.borrow_mut();
+        if native.is_some() {
+            return Err("a native program is already starting".to_string());
         }
+        *native = Some(image);
+        Ok(())
     }
 
+    pub fn take_native(&self) -> Option<Vec<u8>> {
+        self.native.borrow_mut().take()
+    }
+
+    // Start a program from the kernel, with the default environment
+    pub fn load_from_path(&self, path: &str) -> Result<Pid, String> {
+        let bytes = self.read_program(path)?;
+        self.spawn(&bytes, &[path.to_string()], &Env::new(), KERNEL_PID)
+    }
+
+    // Step every runnable process once, round-robin. Each task is taken out of the
+    // table while it runs so its syscalls can spawn, wait on or kill other processes.
     pub fn tick(&self) {
-        {
-            let mut process_guard = self.active_process.borrow_mut();
-            if let Some(process) = process_guard.as_mut() {
-                 if let Ok(step_func) = process.instance.get_typed_func::<(), ()>(&process.store, "step") {
-                     let res = step_func.call(&mut process.store, ());
-                     if let Err(e) = res {
-                         web_sys::console::log_1(&format!("process crashed: {}", e).into());
-                         *process_guard = None;
-                         *self.gui_mode.borrow_mut() = false;
-                     }
-                 }
+        let order = self.processes.borrow_mut().schedule();
+        for pid in order {
+            let task = self.processes.borrow_mut().take_task(pid);
+            let mut task = match task { Some(t) => t, None => continue };
+
+            let baseline = refuel(&mut task.store, process::STEP_FUEL_QUOTA);
+            let res = match task.instance.get_typed_func::<(), ()>(&task.store, "step") {
+                Ok(step_func) => step_func.call(&mut task.store, ()),
+                Err(_) => Ok(()),
+            };
+            let used = task.store.fuel_consumed().unwrap_or(0) - baseline;
+            self.processes.borrow_mut().charge(pid, used);
+
+            match res {
+                Ok(()) => self.processes.borrow_mut().attach(pid, task),
+                Err(trap) => {
+                    let status = self.trap_status(pid, &trap);
+                    self.exit_process(pid, status);
+                },
             }
-        } // Drop active lock
-
-        // Apply pending Process Switch
-        if let Ok(mut next_guard) = self.next_process.try_borrow_mut() {
-             if next_guard.is_some() {
-                 *self.active_process.borrow_mut() = next_guard.take();
-             }
         }
     }
 }
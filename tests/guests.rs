@@ -0,0 +1,252 @@
+// Small guest programs, written in wat, run through `exec` to check the syscall
+// layers: what a guest hands the kernel is never trusted.
+
+use std::rc::Rc;
+
+use wasmix::host::NativeHost;
+use wasmix::kernel::{Machine, MachineState};
+
+fn boot() -> Machine {
+    let mut machine = Machine::new(Rc::new(NativeHost::new()));
+    while !matches!(machine.state, MachineState::Kernel) {
+        machine.step(None);
+    }
+    machine
+}
+
+fn run(machine: &Machine, line: &str) -> String {
+    let mut shell = machine.shell.borrow_mut();
+    shell.execute_string(line, &machine.fs, Some(&machine.wasm), machine.tick_count, 0.0).0
+}
+
+// Install `wat` as an executable at `path`
+fn install(machine: &Machine, path: &str, wat: &str) {
+    let bytes = wat::parse_str(wat).unwrap();
+    let mut fs = machine.fs.borrow_mut();
+    fs.write_file(path, &bytes).unwrap();
+    fs.chmod(path, 0o755).unwrap();
+}
+
+#[test]
+fn rejects_lengths_past_guest_memory() {
+    let machine = boot();
+    install(&machine, "/tmp/bad.wasm", r#"
+        (module
+            (import "env" "sys_print" (func $print (param i32 i32)))
+            (import "env" "sys_fs_open" (func $open (param i32 i32 i32) (result i32)))
+            (import "env" "sys_fs_write" (func $write (param i32 i32 i32) (result i32)))
+            (import "env" "sys_exec" (func $exec (param i32 i32 i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "/tmp/out.txt")
+            (func (export "_start")
+                (call $print (i32.const 0) (i32.const -1))
+                (call $print (i32.const 65000) (i32.const 1000))
+                (drop (call $exec (i32.const 0) (i32.const 0x7fffffff) (i32.const 0) (i32.const 0)))
+                (if (i32.ne (call $write (call $open (i32.const 0) (i32.const 12) (i32.const 6))
+                                         (i32.const 16) (i32.const 0x7fffffff))
+                            (i32.const -1))
+                    (then unreachable))
+                (call $print (i32.const 0) (i32.const 4))))
+    "#);
+    assert_eq!(run(&machine, "exec /tmp/bad.wasm; echo $?"), "/tmp\n0\n");
+}
+
+#[test]
+fn opens_relative_paths_in_the_shell_directory() {
+    let machine = boot();
+    install(&machine, "/tmp/touch.wasm", r#"
+        (module
+            (import "wasi_snapshot_preview1" "fd_prestat_get" (func $prestat (param i32 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "fd_prestat_dir_name" (func $dir_name (param i32 i32 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "path_open"
+                (func $open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "fd_write" (func $write (param i32 i32 i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "rel.txt")
+            (data (i32.const 16) "hi")
+            (data (i32.const 32) "\10\00\00\00\02\00\00\00")
+            (func (export "_start")
+                (if (call $prestat (i32.const 4) (i32.const 100)) (then unreachable))
+                (if (i32.ne (i32.load (i32.const 104)) (i32.const 1)) (then unreachable))
+                (if (call $dir_name (i32.const 4) (i32.const 108) (i32.const 1)) (then unreachable))
+                (if (i32.ne (i32.load8_u (i32.const 108)) (i32.const 46)) (then unreachable))
+                (if (call $open (i32.const 4) (i32.const 0) (i32.const 0) (i32.const 7)
+                                (i32.const 1) (i64.const 64) (i64.const 0) (i32.const 0) (i32.const 200))
+                    (then unreachable))
+                (if (call $write (i32.load (i32.const 200)) (i32.const 32) (i32.const 1) (i32.const 204))
+                    (then unreachable))))
+    "#);
+    assert_eq!(run(&machine, "mkdir -p /tmp/here; cd /tmp/here; exec /tmp/touch.wasm; echo $?"), "0\n");
+    assert_eq!(machine.fs.borrow_mut().read_file("/tmp/here/rel.txt").unwrap(), b"hi");
+}
+
+#[test]
+fn rejects_offsets_and_iovecs_out_of_range() {
+    let machine = boot();
+    install(&machine, "/tmp/seek.wasm", r#"
+        (module
+            (import "wasi_snapshot_preview1" "path_open"
+                (func $open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "fd_seek" (func $seek (param i32 i64 i32 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "fd_write" (func $write (param i32 i32 i32 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "fd_pwrite" (func $pwrite (param i32 i32 i32 i64 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "random_get" (func $random (param i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "tmp/big.txt")
+            (data (i32.const 16) "hi")
+            (data (i32.const 32) "\10\00\00\00\02\00\00\00")
+            (data (i32.const 40) "\00\00\00\00\ff\ff\ff\ff")
+            (data (i32.const 48) "\10\00\00\00\00\c0\00\00\10\00\00\00\00\c0\00\00")
+            (func (export "_start")
+                (local $fd i32)
+                (if (call $open (i32.const 3) (i32.const 0) (i32.const 0) (i32.const 11)
+                                (i32.const 1) (i64.const 64) (i64.const 0) (i32.const 0) (i32.const 200))
+                    (then unreachable))
+                (local.set $fd (i32.load (i32.const 200)))
+                ;; EINVAL for positions past 2 GiB
+                (if (i32.ne (call $seek (local.get $fd) (i64.const 0x80000000) (i32.const 0) (i32.const 208)) (i32.const 28))
+                    (then unreachable))
+                (if (i32.ne (call $pwrite (local.get $fd) (i32.const 32) (i32.const 1) (i64.const -1) (i32.const 208)) (i32.const 28))
+                    (then unreachable))
+                ;; EFAULT for a buffer past the end of memory
+                (if (i32.ne (call $write (local.get $fd) (i32.const 40) (i32.const 1) (i32.const 208)) (i32.const 21))
+                    (then unreachable))
+                ;; EINVAL for buffers that together cover more than memory
+                (if (i32.ne (call $write (local.get $fd) (i32.const 48) (i32.const 2) (i32.const 208)) (i32.const 28))
+                    (then unreachable))
+                ;; EFAULT for random bytes past the end of memory, which fit otherwise
+                (if (i32.ne (call $random (i32.const 0) (i32.const 0x7fffffff)) (i32.const 21))
+                    (then unreachable))
+                (if (call $random (i32.const 0xfff0) (i32.const 16))
+                    (then unreachable))
+                ;; the largest position still works
+                (if (call $seek (local.get $fd) (i64.const 0x7ffffffe) (i32.const 0) (i32.const 208))
+                    (then unreachable))))
+    "#);
+    assert_eq!(run(&machine, "exec /tmp/seek.wasm; echo $?"), "0\n");
+    assert!(machine.fs.borrow_mut().read_file("/tmp/big.txt").unwrap().is_empty());
+}
+
+#[test]
+fn start_runs_in_slices_between_yields() {
+    let machine = boot();
+    // Twice the init quota in total, yielding every 2^20 iterations
+    install(&machine, "/tmp/busy.wasm", r#"
+        (module
+            (import "wasi_snapshot_preview1" "sched_yield" (func $yield (result i32)))
+            (memory (export "memory") 1)
+            (func (export "_start")
+                (local $i i32)
+                (loop $spin
+                    (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                    (if (i32.eqz (i32.and (local.get $i) (i32.const 0xfffff)))
+                        (then (drop (call $yield))))
+                    (br_if $spin (i32.lt_u (local.get $i) (i32.const 10000000))))))
+    "#);
+    assert_eq!(run(&machine, "exec /tmp/busy.wasm; echo $?"), "0\n");
+
+    install(&machine, "/tmp/spin.wasm", r#"
+        (module
+            (memory (export "memory") 1)
+            (func (export "_start") (loop $spin (br $spin))))
+    "#);
+    assert!(run(&machine, "exec /tmp/spin.wasm; echo $?").ends_with("137\n"));
+}
+
+// A package `p` whose entry prints `text`, packed to /tmp/p.tar with `extra` bytes
+// of padding
+fn package(machine: &Machine, text: &str, extra: usize) {
+    let wat = format!(r#"
+        (module
+            (import "env" "sys_print" (func $print (param i32 i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "{}")
+            (func (export "_start") (call $print (i32.const 0) (i32.const {}))))
+    "#, text, text.len());
+    let mut fs = machine.fs.borrow_mut();
+    let _ = fs.remove_entry("/tmp/p");
+    fs.mkdir("/tmp/p").unwrap();
+    fs.write_file("/tmp/p/manifest.json",
+                  br#"{ "name": "p", "version": "1", "entry": "p.wasm", "syscalls": ["sys_print"] }"#).unwrap();
+    fs.write_file("/tmp/p/p.wasm", &wat::parse_str(wat).unwrap()).unwrap();
+    fs.write_file("/tmp/p/padding", &vec![0; extra]).unwrap();
+    drop(fs);
+    assert_eq!(run(machine, "tar -c /tmp/p.tar /tmp/p; echo $?"), "0\n");
+}
+
+#[test]
+fn keeps_the_old_package_when_an_upgrade_fails() {
+    let machine = boot();
+    package(&machine, "old", 0);
+    assert_eq!(run(&machine, "pkg install /tmp/p.tar"), "installed p 1\n");
+    assert_eq!(run(&machine, "p"), "old\n");
+
+    // Fill the disk up to 64 KiB short of its quota, then upgrade with 128 KiB
+    let local = machine.fs.borrow().mounts().into_iter().find(|m| m.path == "/local").unwrap();
+    let filler = local.capacity - local.used - 64 * 1024;
+    machine.fs.borrow_mut().write_file("/local/filler", &vec![0; filler]).unwrap();
+    package(&machine, "new", 128 * 1024);
+    assert!(run(&machine, "pkg install /tmp/p.tar; echo $?").ends_with("1\n"));
+
+    assert_eq!(run(&machine, "p"), "old\n");
+    assert_eq!(machine.fs.borrow().list_dir("/local/apps").unwrap(), ["p"]);
+
+    // With the room back the upgrade goes through
+    machine.fs.borrow_mut().remove_entry("/local/filler").unwrap();
+    assert_eq!(run(&machine, "pkg install /tmp/p.tar"), "installed p 1\n");
+    assert_eq!(run(&machine, "p"), "new\n");
+    assert_eq!(machine.fs.borrow().list_dir("/local/apps").unwrap(), ["p"]);
+}
+
+#[test]
+fn follows_symlinks_only_when_asked() {
+    let machine = boot();
+    machine.fs.borrow_mut().write_file("/tmp/target.txt", b"data").unwrap();
+    install(&machine, "/tmp/links.wasm", r#"
+        (module
+            (import "wasi_snapshot_preview1" "path_symlink" (func $symlink (param i32 i32 i32 i32 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "path_readlink" (func $readlink (param i32 i32 i32 i32 i32 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "path_filestat_get" (func $stat (param i32 i32 i32 i32 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "path_open"
+                (func $open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "path_unlink_file" (func $unlink (param i32 i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "target.txt")
+            (data (i32.const 16) "link")
+            (func (export "_start")
+                (if (call $symlink (i32.const 0) (i32.const 10) (i32.const 4) (i32.const 16) (i32.const 4))
+                    (then unreachable))
+                ;; the target as stored, cut to the buffer
+                (if (call $readlink (i32.const 4) (i32.const 16) (i32.const 4) (i32.const 100) (i32.const 64) (i32.const 96))
+                    (then unreachable))
+                (if (i32.ne (i32.load (i32.const 96)) (i32.const 10)) (then unreachable))
+                (if (i64.ne (i64.load (i32.const 100)) (i64.load (i32.const 0))) (then unreachable))
+                (if (call $readlink (i32.const 4) (i32.const 16) (i32.const 4) (i32.const 100) (i32.const 3) (i32.const 96))
+                    (then unreachable))
+                (if (i32.ne (i32.load (i32.const 96)) (i32.const 3)) (then unreachable))
+                ;; EINVAL for a file that is not a link
+                (if (i32.ne (call $readlink (i32.const 4) (i32.const 0) (i32.const 10) (i32.const 100) (i32.const 64) (i32.const 96))
+                            (i32.const 28))
+                    (then unreachable))
+                ;; the link itself, then with SYMLINK_FOLLOW what it points to
+                (if (call $stat (i32.const 4) (i32.const 0) (i32.const 16) (i32.const 4) (i32.const 200)) (then unreachable))
+                (if (i32.ne (i32.load8_u (i32.const 216)) (i32.const 7)) (then unreachable))
+                (if (call $stat (i32.const 4) (i32.const 1) (i32.const 16) (i32.const 4) (i32.const 200)) (then unreachable))
+                (if (i32.ne (i32.load8_u (i32.const 216)) (i32.const 4)) (then unreachable))
+                (if (i64.ne (i64.load (i32.const 232)) (i64.const 4)) (then unreachable))
+                ;; ELOOP opening the link without SYMLINK_FOLLOW
+                (if (i32.ne (call $open (i32.const 4) (i32.const 0) (i32.const 16) (i32.const 4)
+                                        (i32.const 0) (i64.const 2) (i64.const 0) (i32.const 0) (i32.const 300))
+                            (i32.const 32))
+                    (then unreachable))
+                (if (call $open (i32.const 4) (i32.const 1) (i32.const 16) (i32.const 4)
+                                (i32.const 0) (i64.const 2) (i64.const 0) (i32.const 0) (i32.const 300))
+                    (then unreachable))
+                ;; unlinking removes the link, not the target
+                (if (call $unlink (i32.const 4) (i32.const 16) (i32.const 4)) (then unreachable))))
+    "#);
+    assert_eq!(run(&machine, "cd /tmp; exec /tmp/links.wasm; echo $?"), "0\n");
+    let fs = machine.fs.borrow();
+    assert!(fs.lstat("/tmp/link").is_err());
+    assert_eq!(fs.read_file("/tmp/target.txt").unwrap(), b"data");
+}
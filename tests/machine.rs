@@ -0,0 +1,139 @@
+// Boots the machine on the native host and drives it the way the browser does:
+// BIOS, shell commands and programs along $PATH.
+
+use std::rc::Rc;
+
+use wasmix::host::NativeHost;
+use wasmix::kernel::{Machine, MachineState};
+
+// Step through the BIOS until the kernel takes over
+fn boot() -> Machine {
+    let mut machine = Machine::new(Rc::new(NativeHost::new()));
+    for _ in 0..2000 {
+        machine.step(None);
+        if matches!(machine.state, MachineState::Kernel) {
+            return machine;
+        }
+    }
+    panic!("the bios did not hand over to the kernel");
+}
+
+// Run a command line in the kernel shell, returning what it printed
+fn run(machine: &Machine, line: &str) -> String {
+    let mut shell = machine.shell.borrow_mut();
+    shell.execute_string(line, &machine.fs, Some(&machine.wasm), machine.tick_count, 0.0).0
+}
+
+fn screen(machine: &Machine) -> String {
+    let term = machine.term.borrow();
+    let mut text = String::new();
+    for row in term.buffer.chunks(term.cols) {
+        let line: String = row.iter().map(|cell| cell.c).collect();
+        text.push_str(line.trim_end());
+        text.push('\n');
+    }
+    text
+}
+
+#[test]
+fn boots_into_the_kernel() {
+    let machine = boot();
+    assert!(machine.fs.borrow().is_dir("/bin"));
+    assert!(machine.fs.borrow().is_dir("/local/user"));
+}
+
+#[test]
+fn runs_a_pipeline() {
+    let machine = boot();
+    let out = run(&machine, "mkdir /tmp/t && echo one two > /tmp/t/a.txt; echo three >> /tmp/t/a.txt");
+    assert_eq!(out, "");
+    assert_eq!(run(&machine, "cat /tmp/t/a.txt | tail -n 1"), "three\n");
+    assert_eq!(run(&machine, "cd /tmp/t; ls").trim_end(), "a.txt");
+    assert_eq!(run(&machine, "cat missing.txt || echo failed"), "error: file 'missing.txt' not found\nfailed\n");
+}
+
+#[test]
+fn expands_variables() {
+    let machine = boot();
+    assert_eq!(run(&machine, "export GREETING=hi; echo \"$GREETING there\" '$GREETING'"), "hi there $GREETING\n");
+    assert_eq!(run(&machine, "nosuchcommand; echo $?"), "nosuchcommand: command not found\n127\n");
+}
+
+#[test]
+fn runs_programs_from_path() {
+    let machine = boot();
+    let greet = wat::parse_str(r#"
+        (module
+            (import "wasi_snapshot_preview1" "fd_write" (func $write (param i32 i32 i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "greetings from /local/bin\n")
+            (data (i32.const 32) "\00\00\00\00\1a\00\00\00")
+            (func (export "_start")
+                (drop (call $write (i32.const 1) (i32.const 32) (i32.const 1) (i32.const 40)))))
+    "#).unwrap();
+    {
+        let mut fs = machine.fs.borrow_mut();
+        fs.mkdir_all("/local/bin").unwrap();
+        fs.write_file("/local/bin/greet.wasm", &greet).unwrap();
+        fs.chmod("/local/bin/greet.wasm", 0o755).unwrap();
+    }
+    assert_eq!(run(&machine, "greet; echo $?"), "greetings from /local/bin\n0\n");
+    assert_eq!(run(&machine, "exec /local/bin/greet.wasm | cat"), "greetings from /local/bin\n");
+}
+
+#[test]
+fn runs_native_images() {
+    use wasmix::kernel::NATIVE_MAGIC;
+
+    let mut machine = boot();
+    // r4 = 'A'; putchar; exit
+    let mut image = NATIVE_MAGIC.to_vec();
+    for word in [(0x10u32 << 26) | (4 << 21) | 0x41, (0x3F << 26) | 1, 0x3F << 26] {
+        image.extend_from_slice(&word.to_le_bytes());
+    }
+    machine.fs.borrow_mut().write_file("/tmp/a.bin", &image).unwrap();
+    assert_eq!(run(&machine, "exec /tmp/a.bin; echo $?"), "error: '/tmp/a.bin': permission denied\n1\n");
+
+    run(&machine, "chmod +x /tmp/a.bin");
+    assert_eq!(run(&machine, "exec /tmp/a.bin"), "");
+    machine.step(None);
+    machine.step(None);
+    assert!(machine.cpu.halted);
+    assert!(screen(&machine).contains('A'));
+}
+
+#[test]
+fn takes_flags_before_operands() {
+    let machine = boot();
+    assert_eq!(run(&machine, "mkdir -p /tmp/a/b/c && mkdir -p /tmp/a/b; echo $?"), "0\n");
+    assert!(machine.fs.borrow().is_dir("/tmp/a/b/c"));
+    assert!(!machine.fs.borrow().is_dir("-p"));
+    assert_eq!(run(&machine, "mkdir /tmp/x/y"), "error: directory x not found\n");
+    assert_eq!(run(&machine, "mkdir -q /tmp/x"), "mkdir: invalid option -- 'q'\nusage: mkdir [-p] <dir>...\n");
+
+    run(&machine, "echo hi > /tmp/a/file.txt");
+    assert_eq!(run(&machine, "ls /tmp/a/file.txt"), "/tmp/a/file.txt  \n");
+    let long = run(&machine, "ls -l /tmp/a/file.txt");
+    assert!(long.starts_with("-rw-r--r--"), "{}", long);
+    assert!(long.ends_with(" /tmp/a/file.txt\n"), "{}", long);
+    assert_eq!(run(&machine, "cd /tmp/a; ls"), "b  file.txt  \n");
+}
+
+#[test]
+fn sizes_the_scrollback_from_the_environment() {
+    let machine = boot();
+    let lines: String = (0..40).map(|i| format!("{}\n", i)).collect();
+    machine.fs.borrow_mut().write_file("/tmp/lines.txt", lines.as_bytes()).unwrap();
+    let console = |line: &str| {
+        machine.shell.borrow_mut().execute_command(line, &machine.term, &machine.fs, Some(&machine.wasm), machine.tick_count, 0.0);
+        let mut term = machine.term.borrow_mut();
+        term.scroll_view(-1000);
+        let saved = term.view_offset();
+        term.scroll_view(1000);
+        saved
+    };
+    assert!(console("cat /tmp/lines.txt") > 2);
+    assert_eq!(console("export SCROLLBACK=2"), 2);
+    assert_eq!(console("cat /tmp/lines.txt"), 2);
+    assert_eq!(console("unset SCROLLBACK; cat /tmp/lines.txt"), 2 + 40);
+}